@@ -0,0 +1,7 @@
+fn main() {
+    // Expose the target triple so `--version-json` can report what the binary was built for
+    println!(
+        "cargo:rustc-env=BWLAT_TARGET={}",
+        std::env::var("TARGET").unwrap()
+    );
+}
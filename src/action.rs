@@ -1,5 +1,14 @@
 use std::time::Duration;
 
+use crate::{
+    collector::CollectedTarget,
+    network::{
+        bandwidth::BandwidthSample,
+        echo::ClientActivity,
+        latency::{Event, Percentiles, SendRate},
+    },
+};
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Action {
     Quit,
@@ -7,8 +16,38 @@ pub enum Action {
     Render,
 
     ToggleShowHelp,
+    ToggleLatencyLines,
+    ToggleHistogram,
+    ChartZoomIn,
+    ChartZoomOut,
+    /// Start the next phase, named by the phase marker file or `--phases`
+    MarkPhase(Option<String>),
+
+    // Latency actions are tagged with the index of the target they belong to
+    LatencyTarget(usize, String),
+    LatencyPacketTotal(usize, u32),
+    LatencyPacketsSent(usize, u32),
+    LatencyPacketsSkipped(usize, u32),
+    LatencyPacketsReceived(usize, u32, Duration, Duration, Duration),
+    /// Bytes sent and received on the wire so far
+    LatencyTraffic(usize, u64, u64),
+    LatencyEvent(usize, Duration, Event),
+    /// Latency of a single received packet and when it was received
+    LatencySample(usize, Duration, Duration),
+    /// Send time of a packet that stayed unanswered for a while
+    LatencyLost(usize, Duration),
+    LatencyPercentiles(usize, Percentiles),
+    /// Estimated bytes of memory the state of a target takes
+    LatencyMemory(usize, usize),
+    LatencySendRate(usize, SendRate),
+    /// The `--stop-when` condition of the target was met
+    LatencyConverged(usize),
+
+    BandwidthSample(BandwidthSample),
+
+    /// Server uptime and the clients it echoed for recently
+    ServerActivity(Duration, Vec<ClientActivity>),
 
-    LatencyPacketTotal(u32),
-    LatencyPacketsSent(u32),
-    LatencyPacketsReceived(u32, Duration, Duration, Duration),
+    /// Latest report of every target the collector heard about
+    CollectorTargets(Vec<CollectedTarget>),
 }
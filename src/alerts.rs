@@ -0,0 +1,435 @@
+use std::{collections::HashSet, ops::Range, sync::Arc, time::Duration};
+
+use color_eyre::eyre::{bail, eyre, Result};
+use serde::Deserialize;
+use tokio::{process::Command, sync::Mutex, time};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::{
+    http,
+    metadata::Identity,
+    network::latency::{PacketStatus, State},
+    test_plan::{duration, optional_duration},
+    units::DisplayFormat,
+};
+
+/// Exec hooks and notification commands still running after this long are
+/// killed, so they cannot hold up the alerts that follow.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Settings under `[alerts]` in the config file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct AlertsConfig {
+    /// Window the rules are evaluated over
+    #[serde(default = "default_interval", deserialize_with = "duration")]
+    pub interval: Duration,
+    #[serde(default)]
+    pub sinks: Vec<SinkConfig>,
+}
+
+fn default_interval() -> Duration {
+    Duration::from_secs(10)
+}
+
+/// Where alerts go and which ones.
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct SinkConfig {
+    #[serde(flatten)]
+    pub kind: SinkKind,
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub(crate) enum SinkKind {
+    /// JSON POST to an `http://` URL
+    Webhook { url: String },
+    /// The local syslog daemon (Unix)
+    Syslog,
+    /// A desktop notification
+    Desktop,
+    /// A shell command, the alert is passed in `BWLAT_ALERT_*` variables
+    Exec { command: String },
+}
+
+/// Limits of one evaluation window, an alert is raised once any is exceeded
+/// and resolved once none is.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Rule {
+    #[serde(default)]
+    pub severity: Severity,
+    /// Packet loss in percent
+    pub max_loss: Option<f64>,
+    #[serde(default, deserialize_with = "optional_duration")]
+    pub max_average_latency: Option<Duration>,
+    #[serde(default, deserialize_with = "optional_duration")]
+    pub max_latency: Option<Duration>,
+    /// Fire while the target is down, which needs `--down-after`
+    #[serde(default)]
+    pub down: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Severity {
+    Info,
+    #[default]
+    Warning,
+    Critical,
+}
+
+impl Severity {
+    fn name(&self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+impl AlertsConfig {
+    /// Rejects sinks that could never deliver or rules that could never fire.
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.interval.is_zero() {
+            bail!("alerts.interval has to be longer than zero");
+        }
+        for (i, sink) in self.sinks.iter().enumerate() {
+            if let SinkKind::Webhook { ref url } = sink.kind {
+                if !url.starts_with("http://") || url.len() == "http://".len() {
+                    bail!("alerts.sinks[{}]: only http:// webhooks are supported", i);
+                }
+            }
+            if sink.rules.is_empty() {
+                bail!("alerts.sinks[{}] has no rules", i);
+            }
+            for (j, rule) in sink.rules.iter().enumerate() {
+                let limits = [
+                    rule.max_loss.is_some(),
+                    rule.max_average_latency.is_some(),
+                    rule.max_latency.is_some(),
+                    rule.down,
+                ];
+                if !limits.contains(&true) {
+                    bail!("alerts.sinks[{}].rules[{}] sets no limit", i, j);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Probes of one target whose outcome became final during a window.
+#[derive(Debug, Default)]
+struct Window {
+    sent: u32,
+    received: u32,
+    average_latency: Duration,
+    max_latency: Duration,
+    /// Whether the target was down at the end of the window
+    down: bool,
+}
+
+impl Window {
+    fn of(state: &State, packets: Range<usize>) -> Self {
+        let mut window = Window::default();
+        let mut total = Duration::ZERO;
+        for (_, packet) in state.packets.range(packets) {
+            match *packet {
+                PacketStatus::Skipped(_) | PacketStatus::Invalid(_) | PacketStatus::Warmup(_) => {
+                    continue
+                }
+                PacketStatus::Sent(_) => {}
+                PacketStatus::Received { latency, .. } => {
+                    window.received += 1;
+                    total += latency;
+                    window.max_latency = window.max_latency.max(latency);
+                }
+            }
+            window.sent += 1;
+        }
+        window.average_latency = total / window.received.max(1);
+        window.down = state.down_since.is_some();
+        window
+    }
+
+    fn loss(&self) -> f64 {
+        1.0 - self.received as f64 / self.sent.max(1) as f64
+    }
+}
+
+impl Rule {
+    /// Limits the window exceeds, described for the alert.
+    fn breaches(&self, window: &Window, format: &DisplayFormat) -> Vec<String> {
+        let mut breaches = Vec::new();
+        if let Some(max) = self.max_loss {
+            if window.loss() * 100.0 > max {
+                breaches.push(format!(
+                    "loss {} above {}",
+                    format.percent(window.loss()),
+                    format.percent(max / 100.0)
+                ));
+            }
+        }
+        if let Some(max) = self.max_average_latency {
+            if window.received > 0 && window.average_latency > max {
+                breaches.push(format!(
+                    "average latency {} above {}",
+                    format.duration(window.average_latency),
+                    format.duration(max)
+                ));
+            }
+        }
+        if let Some(max) = self.max_latency {
+            if window.max_latency > max {
+                breaches.push(format!(
+                    "latency {} above {}",
+                    format.duration(window.max_latency),
+                    format.duration(max)
+                ));
+            }
+        }
+        if self.down && window.down {
+            breaches.push("target down".to_string());
+        }
+        breaches
+    }
+}
+
+/// A rule that started or stopped firing for a target.
+struct Alert<'a> {
+    severity: Severity,
+    target: &'a str,
+    resolved: bool,
+    message: String,
+}
+
+impl Alert<'_> {
+    fn state(&self) -> &'static str {
+        match self.resolved {
+            true => "resolved",
+            false => "firing",
+        }
+    }
+}
+
+/// Evaluates the rules of every sink on the probes of each window and delivers
+/// alerts when a rule starts or stops firing for a target.
+pub(crate) struct Alerting {
+    config: AlertsConfig,
+    targets: Vec<(String, Arc<Mutex<State>>)>,
+    format: DisplayFormat,
+    identity: Identity,
+    quit: CancellationToken,
+}
+
+impl Alerting {
+    pub(crate) fn new(
+        config: AlertsConfig,
+        targets: Vec<(String, Arc<Mutex<State>>)>,
+        format: DisplayFormat,
+        quit: CancellationToken,
+    ) -> Self {
+        Self {
+            config,
+            targets,
+            format,
+            identity: Identity::default(),
+            quit,
+        }
+    }
+
+    /// Names the probe in every alert.
+    pub(crate) fn with_identity(mut self, identity: Identity) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    pub(crate) async fn run(self) -> Result<()> {
+        info!("Alerting to {} sink(s)", self.config.sinks.len());
+
+        let interval = self.config.interval;
+        let mut ticker = time::interval_at(time::Instant::now() + interval, interval);
+        let mut evaluated = vec![0; self.targets.len()];
+        // Sink, rule and target of the rules that are firing
+        let mut firing: HashSet<(usize, usize, usize)> = HashSet::new();
+
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = self.quit.cancelled() => break,
+            }
+
+            for (t, (target, state)) in self.targets.iter().enumerate() {
+                let window = {
+                    let state = state.lock().await;
+                    let settled = state.settled();
+                    let window = Window::of(&state, evaluated[t]..settled);
+                    evaluated[t] = settled;
+                    window
+                };
+                if window.sent == 0 {
+                    continue;
+                }
+
+                for (s, sink) in self.config.sinks.iter().enumerate() {
+                    for (r, rule) in sink.rules.iter().enumerate() {
+                        let breaches = rule.breaches(&window, &self.format);
+                        let was_firing = firing.contains(&(s, r, t));
+                        let alert = match (breaches.is_empty(), was_firing) {
+                            (false, false) => {
+                                firing.insert((s, r, t));
+                                Alert {
+                                    severity: rule.severity,
+                                    target,
+                                    resolved: false,
+                                    message: breaches.join(", "),
+                                }
+                            }
+                            (true, true) => {
+                                firing.remove(&(s, r, t));
+                                Alert {
+                                    severity: rule.severity,
+                                    target,
+                                    resolved: true,
+                                    message: "back within limits".to_string(),
+                                }
+                            }
+                            _ => continue,
+                        };
+
+                        // A sink that is down should not end the run
+                        if let Err(e) = deliver(&sink.kind, &alert, &self.identity).await {
+                            warn!("Could not deliver alert to {:?}: {}", sink.kind, e);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+async fn deliver(sink: &SinkKind, alert: &Alert<'_>, identity: &Identity) -> Result<()> {
+    let source = match identity.probe {
+        Some(ref probe) => format!("{} to {}", probe, alert.target),
+        None => alert.target.to_string(),
+    };
+    let text = format!("{} {}: {}", alert.severity.name(), source, alert.message);
+    match sink {
+        SinkKind::Webhook { url } => {
+            let url = url.trim_start_matches("http://");
+            let (host, path) = match url.split_once('/') {
+                Some((host, path)) => (host, format!("/{}", path)),
+                None => (url, "/".to_string()),
+            };
+            let labels: serde_json::Map<_, _> = identity
+                .labels
+                .iter()
+                .map(|(k, v)| (k.clone(), v.as_str().into()))
+                .collect();
+            let body = serde_json::json!({
+                "severity": alert.severity.name(),
+                "state": alert.state(),
+                "target": alert.target,
+                "message": alert.message,
+                "probe": identity.probe,
+                "labels": labels,
+            });
+            http::post(host, &path, "application/json", "", &body.to_string()).await
+        }
+        SinkKind::Syslog => syslog(alert.severity, &text),
+        SinkKind::Desktop => desktop(alert, &text).await,
+        SinkKind::Exec {
+            command: command_line,
+        } => {
+            let mut command = Command::new("sh");
+            command.arg("-c").arg(command_line);
+            if let Some(ref probe) = identity.probe {
+                command.env("BWLAT_ALERT_PROBE", probe);
+            }
+            for (key, value) in &identity.labels {
+                command.env(format!("BWLAT_LABEL_{}", key.to_uppercase()), value);
+            }
+            command
+                .env("BWLAT_ALERT_SEVERITY", alert.severity.name())
+                .env("BWLAT_ALERT_STATE", alert.state())
+                .env("BWLAT_ALERT_TARGET", alert.target)
+                .env("BWLAT_ALERT_MESSAGE", &alert.message);
+            let status = run(command, command_line).await?;
+            if !status.success() {
+                bail!("{} exited with {}", command_line, status);
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(unix)]
+fn syslog(severity: Severity, text: &str) -> Result<()> {
+    use std::os::unix::net::UnixDatagram;
+
+    // Facility user, severity as in RFC 5424
+    let priority = 8 + match severity {
+        Severity::Info => 6,
+        Severity::Warning => 4,
+        Severity::Critical => 2,
+    };
+    let socket = UnixDatagram::unbound()?;
+    let message = format!("<{}>bwlat: {}", priority, text);
+    ["/dev/log", "/var/run/syslog"]
+        .iter()
+        .find_map(|path| socket.send_to(message.as_bytes(), path).ok())
+        .ok_or_else(|| eyre!("No syslog socket"))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn syslog(_: Severity, _: &str) -> Result<()> {
+    bail!("syslog is only supported on Unix")
+}
+
+async fn desktop(alert: &Alert<'_>, text: &str) -> Result<()> {
+    let command = match std::env::consts::OS {
+        "macos" => {
+            let mut command = Command::new("osascript");
+            let script = format!(
+                "display notification {:?} with title \"bwlat\"",
+                text.replace('"', "'")
+            );
+            command.arg("-e").arg(script);
+            command
+        }
+        _ => {
+            let urgency = match alert.severity {
+                Severity::Info => "low",
+                Severity::Warning => "normal",
+                Severity::Critical => "critical",
+            };
+            let mut command = Command::new("notify-send");
+            command.args(["-u", urgency, "bwlat", text]);
+            command
+        }
+    };
+    let status = run(command, "notification command").await?;
+    if !status.success() {
+        bail!("notification command exited with {}", status);
+    }
+    Ok(())
+}
+
+/// Runs `command` to completion, killing it after [`COMMAND_TIMEOUT`].
+async fn run(mut command: Command, name: &str) -> Result<std::process::ExitStatus> {
+    let mut child = command.kill_on_drop(true).spawn()?;
+    match time::timeout(COMMAND_TIMEOUT, child.wait()).await {
+        Ok(status) => Ok(status?),
+        Err(_) => {
+            child.kill().await?;
+            bail!("{} did not finish within {:?}", name, COMMAND_TIMEOUT)
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::net::IpAddr;
+
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    network::{icmp_error::IcmpError, latency::Event},
+    signing::to_hex,
+};
+
+/// Replaces identifying network information in exports with salted hashes.
+///
+/// The salt is random per run, so the same address maps to the same token within
+/// one report (keeping results comparable) but cannot be brute-forced back from the
+/// relatively small IPv4 address space.
+pub(crate) struct Anonymizer {
+    salt: [u8; 16],
+}
+
+impl Anonymizer {
+    pub(crate) fn new() -> Self {
+        let mut salt = [0; 16];
+        rand::thread_rng().fill_bytes(&mut salt);
+        Self { salt }
+    }
+
+    pub(crate) fn address(&self, address: &IpAddr) -> String {
+        let prefix = match address {
+            IpAddr::V4(_) => "ipv4",
+            IpAddr::V6(_) => "ipv6",
+        };
+
+        format!("{}-{}", prefix, self.token(address.to_string().as_bytes()))
+    }
+
+    /// Free-form names like the probe name, prefixed with what they name.
+    pub(crate) fn name(&self, kind: &str, name: &str) -> String {
+        format!("{}-{}", kind, self.token(name.as_bytes()))
+    }
+
+    /// Hostnames that are literal addresses are anonymized like addresses.
+    pub(crate) fn hostname(&self, host: &str) -> String {
+        match host.parse::<IpAddr>() {
+            Ok(address) => self.address(&address),
+            Err(_) => format!("host-{}", self.token(host.to_ascii_lowercase().as_bytes())),
+        }
+    }
+
+    pub(crate) fn event(&self, event: &Event) -> String {
+        match event {
+            Event::Route(route) => {
+                let mut s = match route.gateway {
+                    Some(ref gateway) => format!("route via {}", self.address(gateway)),
+                    None => "route direct".to_string(),
+                };
+                if let Some(ref interface) = route.interface {
+                    s += &format!(" dev {}", interface);
+                }
+                if let Some(ref source) = route.source {
+                    s += &format!(" src {}", self.address(source));
+                }
+                if route.tunnel {
+                    s += " (tunnel)";
+                }
+                s
+            }
+            Event::IcmpError(probe, error) => {
+                format!("probe {} rejected: {}", probe, self.icmp_error(error))
+            }
+            event => event.to_string(),
+        }
+    }
+
+    pub(crate) fn icmp_error(&self, error: &IcmpError) -> String {
+        match error.from {
+            Some(ref from) => format!("{} from {}", error.kind, self.address(from)),
+            None => error.kind.to_string(),
+        }
+    }
+
+    fn token(&self, value: &[u8]) -> String {
+        let digest = Sha256::new()
+            .chain_update(self.salt)
+            .chain_update(value)
+            .finalize();
+
+        to_hex(&digest[..6])
+    }
+}
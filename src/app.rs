@@ -0,0 +1,180 @@
+use color_eyre::eyre::Result;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::prelude::Rect;
+use tokio::{
+    sync::mpsc::{UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
+};
+use tracing::{debug, error, trace_span};
+
+use crate::{
+    action::Action,
+    components::Component,
+    tui::{Tui, TuiEvent},
+};
+
+/// Keys of a mode, on top of quitting with `q` and Ctrl-C.
+pub(crate) type KeyMap = fn(&KeyEvent) -> Option<Action>;
+
+/// Event, action and render loop shared by the TUI modes. Terminal events become
+/// actions, every action goes to all components, and `Render` draws the
+/// components over each other in the order they were added.
+pub(crate) struct App {
+    tui: Tui,
+    components: Vec<Box<dyn Component>>,
+    keys: KeyMap,
+    tasks: Vec<JoinHandle<Result<()>>>,
+}
+
+impl App {
+    /// Takes over the terminal, rates are per second.
+    pub(crate) fn new(tick_rate: f64, frame_rate: f64) -> Result<Self> {
+        let mut tui = Tui::new()?;
+        tui.tick_rate(tick_rate);
+        tui.frame_rate(frame_rate);
+        tui.enter()?;
+
+        Ok(Self {
+            tui,
+            components: Vec::new(),
+            keys: |_| None,
+            tasks: Vec::new(),
+        })
+    }
+
+    pub(crate) fn with_component(mut self, component: Box<dyn Component>) -> Self {
+        self.components.push(component);
+        self
+    }
+
+    pub(crate) fn with_components(
+        mut self,
+        components: impl IntoIterator<Item = Box<dyn Component>>,
+    ) -> Self {
+        self.components.extend(components);
+        self
+    }
+
+    pub(crate) fn with_keys(mut self, keys: KeyMap) -> Self {
+        self.keys = keys;
+        self
+    }
+
+    /// Work the app runs alongside. A task that fails ends the app with its error,
+    /// the tasks still running when the app quits are aborted.
+    pub(crate) fn with_task(mut self, task: JoinHandle<Result<()>>) -> Self {
+        self.tasks.push(task);
+        self
+    }
+
+    /// Runs until a `Quit` action, or until `on_action` returns true. It sees every
+    /// action before the components do. Leaves the terminal either way.
+    pub(crate) async fn run(
+        mut self,
+        action_tx: UnboundedSender<Action>,
+        mut action_rx: UnboundedReceiver<Action>,
+        mut on_action: impl FnMut(&Action) -> bool,
+    ) -> Result<()> {
+        let result = self
+            .run_loop(&action_tx, &mut action_rx, &mut on_action)
+            .await;
+        for task in self.tasks.iter() {
+            task.abort();
+        }
+        self.tui.exit()?;
+        result
+    }
+
+    async fn run_loop(
+        &mut self,
+        action_tx: &UnboundedSender<Action>,
+        action_rx: &mut UnboundedReceiver<Action>,
+        on_action: &mut impl FnMut(&Action) -> bool,
+    ) -> Result<()> {
+        for component in self.components.iter_mut() {
+            component.init()?;
+        }
+
+        loop {
+            if let Some(e) = self.tui.next().await {
+                self.handle_event(&e, action_tx)?;
+
+                for component in self.components.iter_mut() {
+                    if let Some(action) = component.handle_events(Some(e.clone()))? {
+                        action_tx.send(action)?;
+                    }
+                }
+            }
+
+            while let Ok(action) = action_rx.try_recv() {
+                if action != Action::Render {
+                    debug!("{action:?}");
+                }
+                if action == Action::Quit || on_action(&action) {
+                    return Ok(());
+                }
+                self.handle_action(action, action_tx)?;
+            }
+
+            if let Some(i) = self.tasks.iter().position(|t| t.is_finished()) {
+                self.tasks.swap_remove(i).await??;
+            }
+        }
+    }
+
+    fn handle_event(&self, e: &TuiEvent, action_tx: &UnboundedSender<Action>) -> Result<()> {
+        match e {
+            TuiEvent::Render => action_tx.send(Action::Render)?,
+            TuiEvent::Resize(x, y) => action_tx.send(Action::Resize(*x, *y))?,
+            TuiEvent::Key(key) => match key.code {
+                KeyCode::Char('q') => action_tx.send(Action::Quit)?,
+                KeyCode::Char('c') if key.modifiers == KeyModifiers::CONTROL => {
+                    action_tx.send(Action::Quit)?
+                }
+                _ => {
+                    if let Some(action) = (self.keys)(key) {
+                        action_tx.send(action)?;
+                    }
+                }
+            },
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn handle_action(&mut self, action: Action, action_tx: &UnboundedSender<Action>) -> Result<()> {
+        match action {
+            Action::Resize(w, h) => {
+                self.tui.resize(Rect::new(0, 0, w, h))?;
+                self.draw()?;
+            }
+            Action::Render => {
+                let _span = trace_span!("render").entered();
+                self.draw()?;
+            }
+            _ => {}
+        }
+
+        for component in self.components.iter_mut() {
+            if let Some(action) = component.update(action.clone())? {
+                action_tx.send(action)?
+            };
+        }
+
+        Ok(())
+    }
+
+    fn draw(&mut self) -> Result<()> {
+        let components = &mut self.components;
+        self.tui.draw(|f| {
+            for component in components.iter_mut() {
+                if let Err(e) = component.draw(f, f.size()) {
+                    error!("Failed to draw: {:?}", e);
+                }
+            }
+        })?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,233 @@
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    time::Duration,
+};
+
+use color_eyre::eyre::{bail, eyre, Result};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use crate::{
+    network::{
+        echo::Echo,
+        latency::{Latency, PacketStatus},
+        protocol::HEADER_LEN,
+    },
+    units::DisplayFormat,
+};
+
+/// Fraction of the requested rate a step has to reach to count as sustainable.
+const SUSTAINABLE_RATE: f64 = 0.95;
+
+/// Result of probing loopback at one packet size and interval.
+struct Step {
+    size: usize,
+    interval: Duration,
+    sent: u32,
+    lost: u32,
+    skipped: u32,
+    packets_per_second: f64,
+    min_latency: Duration,
+    avg_latency: Duration,
+    /// Process CPU time, client and echo together, per probe
+    cpu_per_packet: Duration,
+}
+
+impl Step {
+    fn sustainable(&self) -> bool {
+        let requested = 1.0 / self.interval.as_secs_f64();
+        self.lost == 0
+            && self.skipped == 0
+            && self.packets_per_second >= requested * SUSTAINABLE_RATE
+    }
+}
+
+/// Measures the overhead floor of this host by running the echo server and the
+/// latency engine in-process over loopback at increasing rates.
+pub(crate) struct Bench {
+    sizes: Vec<usize>,
+    intervals: Vec<Duration>,
+    duration: Duration,
+    format: DisplayFormat,
+}
+
+impl Bench {
+    pub(crate) fn new(sizes: Vec<usize>, intervals: Vec<Duration>, duration: Duration) -> Self {
+        Self {
+            sizes,
+            intervals,
+            duration,
+            format: DisplayFormat::default(),
+        }
+    }
+
+    pub(crate) fn with_display_format(mut self, format: DisplayFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub(crate) async fn run(&self) -> Result<()> {
+        if self.sizes.iter().any(|&s| s < HEADER_LEN) {
+            bail!(
+                "Packet sizes must be at least {} bytes, the probe header",
+                HEADER_LEN
+            );
+        }
+        if self.intervals.iter().any(|i| i.is_zero()) {
+            bail!("Intervals must be longer than zero");
+        }
+
+        // Grab a free port for the echo server
+        let port = std::net::UdpSocket::bind("127.0.0.1:0")?
+            .local_addr()?
+            .port();
+        let mut echo = Echo::new(port).with_bind_address(Ipv4Addr::LOCALHOST.into());
+        let server = tokio::spawn(async move { echo.run().await });
+
+        let mut steps = Vec::new();
+        for &size in self.sizes.iter() {
+            let mut intervals = self.intervals.clone();
+            intervals.sort_by(|a, b| b.cmp(a));
+
+            for interval in intervals {
+                let step = self.step(port, size, interval).await?;
+                self.report_step(&step);
+                steps.push(step);
+            }
+        }
+
+        server.abort();
+
+        self.summarize(&steps);
+
+        Ok(())
+    }
+
+    async fn step(&self, port: u16, size: usize, interval: Duration) -> Result<Step> {
+        let count = (self.duration.as_nanos() / interval.as_nanos()).clamp(1, u32::MAX as u128);
+
+        // The engine reports progress for the TUI, nothing listens here
+        let (notify, mut actions) = mpsc::unbounded_channel();
+        let drain = tokio::spawn(async move { while actions.recv().await.is_some() {} });
+
+        let mut latency = Latency::new_with_count(
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port,
+            count as u32,
+            notify,
+            CancellationToken::new(),
+        )
+        .with_packet_size(
+            u16::try_from(size)
+                .map_err(|_| eyre!("Packet size {} is larger than a datagram", size))?,
+        )
+        .with_interval(interval);
+
+        let cpu = cpu_time();
+        let state = latency.run().await?;
+        let cpu = cpu_time().saturating_sub(cpu);
+
+        drop(latency);
+        drain.await?;
+
+        let state = state.lock().await;
+        let sent = state.sent_packets();
+
+        // Rate over the send span, the engine lingers for late echoes after the last probe
+        let span = match (state.packets.first(), state.packets.last()) {
+            (Some(first), Some(last)) => sent_at(last).saturating_sub(sent_at(first)),
+            _ => Duration::ZERO,
+        };
+        let packets_per_second = match span.is_zero() {
+            true => 0.0,
+            false => (sent.saturating_sub(1)) as f64 / span.as_secs_f64(),
+        };
+
+        Ok(Step {
+            size,
+            interval,
+            sent,
+            lost: state.packet_loss,
+            skipped: state.skipped_packets,
+            packets_per_second,
+            min_latency: state.min_latency,
+            avg_latency: state.average_latency,
+            cpu_per_packet: cpu / sent.max(1),
+        })
+    }
+
+    fn report_step(&self, step: &Step) {
+        info!(
+            "{:>5} B every {:>9}: {:>9} pkt/s, latency min {} avg {}, cpu {}/probe, lost {} of {}, skipped {}{}",
+            step.size,
+            self.format.duration(step.interval),
+            self.format.number(step.packets_per_second, 0),
+            self.format.duration(step.min_latency),
+            self.format.duration(step.avg_latency),
+            self.format.duration(step.cpu_per_packet),
+            step.lost,
+            step.sent,
+            step.skipped,
+            if step.sustainable() { "" } else { " (not sustained)" }
+        );
+    }
+
+    fn summarize(&self, steps: &[Step]) {
+        let Some(floor) = steps
+            .iter()
+            .map(|s| s.min_latency)
+            .filter(|l| !l.is_zero())
+            .min()
+        else {
+            info!("No probe was answered over loopback");
+            return;
+        };
+        info!(
+            "Measurement floor: {} round trip over loopback",
+            self.format.duration(floor)
+        );
+
+        for &size in self.sizes.iter() {
+            let best = steps
+                .iter()
+                .filter(|s| s.size == size && s.sustainable())
+                .max_by(|a, b| a.packets_per_second.total_cmp(&b.packets_per_second));
+
+            match best {
+                Some(step) => info!(
+                    "{} B probes: sustained {} pkt/s, use an interval of at least {}",
+                    size,
+                    self.format.number(step.packets_per_second, 0),
+                    self.format.duration(step.interval)
+                ),
+                None => info!("{} B probes: no tested interval was sustained", size),
+            }
+        }
+    }
+}
+
+fn sent_at(packet: &PacketStatus) -> Duration {
+    match *packet {
+        PacketStatus::Skipped(at)
+        | PacketStatus::Sent(at)
+        | PacketStatus::Invalid(at)
+        | PacketStatus::Warmup(at) => at,
+        PacketStatus::Received { start, .. } => start,
+    }
+}
+
+/// CPU time consumed by this process so far.
+#[cfg(unix)]
+fn cpu_time() -> Duration {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) };
+
+    let to_duration = |tv: libc::timeval| Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000);
+    to_duration(usage.ru_utime) + to_duration(usage.ru_stime)
+}
+
+#[cfg(not(unix))]
+fn cpu_time() -> Duration {
+    Duration::ZERO
+}
@@ -0,0 +1,286 @@
+use std::{
+    fs::{self, File},
+    io,
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{eyre, Result};
+use directories::ProjectDirs;
+use flate2::{write::GzEncoder, Compression};
+use serde::Serialize;
+use tracing::{debug, info, warn};
+
+use crate::{
+    anonymize::Anonymizer, doctor::Doctor, metadata, metadata::RunMetadata, profile::SpanTiming,
+    version::VersionInfo,
+};
+
+/// Metadata and self-profile of the last client run.
+const RUN_FILE: &str = "last-run.json";
+
+/// Modes that keep the log of their last run.
+const LOGGED_MODES: [&str; 2] = ["client", "server"];
+
+/// Lines of each log that go into the bundle, counted from its end.
+const MAX_LOG_LINES: usize = 5000;
+
+/// Directory in the bundle everything is put in.
+const BUNDLE_DIR: &str = "bwlat-bugreport";
+
+/// Where runs leave what a bug report needs.
+fn state_dir() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "bwlat").map(|dirs| dirs.data_local_dir().to_path_buf())
+}
+
+fn log_name(mode: &str) -> String {
+    format!("last-{}.log", mode)
+}
+
+/// The log of the run of `mode`, emptied on every run. `None` without a
+/// writable data directory.
+pub(crate) fn log_file(mode: &str) -> Option<File> {
+    let dir = state_dir()?;
+    fs::create_dir_all(&dir).ok()?;
+    File::create(dir.join(log_name(mode))).ok()
+}
+
+#[derive(Serialize)]
+struct LastRun<'a> {
+    metadata: &'a RunMetadata,
+    /// Microseconds per span, with `--profile-self`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    self_profile: Option<Vec<SpanTiming>>,
+}
+
+/// Keeps the metadata of a finished run for the next bug report. Failing to
+/// does not fail the run.
+pub(crate) fn record_run(metadata: &RunMetadata, self_profile: Option<Vec<SpanTiming>>) {
+    let Some(dir) = state_dir() else { return };
+    let run = LastRun {
+        metadata,
+        self_profile,
+    };
+    let written = fs::create_dir_all(&dir).and_then(|_| {
+        let file = File::create(dir.join(RUN_FILE))?;
+        serde_json::to_writer_pretty(file, &run).map_err(io::Error::from)
+    });
+    if let Err(e) = written {
+        debug!("Cannot keep the run for bug reports: {}", e);
+    }
+}
+
+/// Bundles the last run's metadata and logs, the `doctor` checks and the build
+/// into a gzipped tarball at `output`, redacted to attach to an issue.
+pub(crate) fn create(output: &Path, ports: Vec<u16>) -> Result<()> {
+    let dir = state_dir();
+    let last_run = dir
+        .as_ref()
+        .and_then(|dir| fs::read_to_string(dir.join(RUN_FILE)).ok());
+    let redactor = Redactor::new(last_run.as_deref());
+
+    let mut files = vec![
+        (
+            "version.json".to_string(),
+            serde_json::to_string_pretty(&VersionInfo::detect())?,
+        ),
+        ("doctor.txt".to_string(), Doctor::new(ports).report()),
+    ];
+    let mut missing = Vec::new();
+    match last_run {
+        Some(run) => files.push((RUN_FILE.to_string(), run)),
+        None => missing.push(RUN_FILE.to_string()),
+    }
+    for mode in LOGGED_MODES {
+        let name = log_name(mode);
+        match dir.as_ref().and_then(|dir| read_tail(&dir.join(&name))) {
+            Some(log) => files.push((name, log)),
+            None => missing.push(name),
+        }
+    }
+    files.push(("README.txt".to_string(), readme(&missing)));
+
+    let file =
+        File::create(output).map_err(|e| eyre!("Cannot create {}: {}", output.display(), e))?;
+    let mut archive = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    for (name, contents) in files.iter() {
+        let contents = redactor.redact(contents);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+        );
+        header.set_cksum();
+        archive.append_data(
+            &mut header,
+            format!("{}/{}", BUNDLE_DIR, name),
+            contents.as_bytes(),
+        )?;
+    }
+    archive.into_inner()?.finish()?;
+
+    info!("Bug report written to {}", output.display());
+    for name in missing.iter() {
+        warn!("No {} yet, run the client or server first", name);
+    }
+    info!("Addresses and host names are redacted, still look it over before attaching it");
+
+    Ok(())
+}
+
+/// The last `MAX_LOG_LINES` lines of the log at `path`.
+fn read_tail(path: &Path) -> Option<String> {
+    let log = fs::read_to_string(path).ok()?;
+    let lines: Vec<&str> = log.lines().collect();
+    let start = lines.len().saturating_sub(MAX_LOG_LINES);
+    Some(lines[start..].join("\n") + "\n")
+}
+
+fn readme(missing: &[String]) -> String {
+    let mut readme = format!(
+        "Bug report of {} {}\n\n\
+         version.json   build and capabilities of this binary\n\
+         doctor.txt     checks of this host, as `bwlat doctor` prints them\n\
+         {}  metadata of the last client run, with its self-profile if\n\
+         \x20              it ran with --profile-self\n\
+         last-*.log     end of the log of the last client and server run\n\n\
+         Addresses other than loopback and unspecified ones are replaced with\n\
+         tokens that are consistent within this report, as are the names of this\n\
+         host and the last target, the probe name and the label values of the\n\
+         last run. The home directory is replaced with ~.\n",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        RUN_FILE,
+    );
+    if !missing.is_empty() {
+        readme += &format!("\nNot available: {}\n", missing.join(", "));
+    }
+    readme
+}
+
+/// Takes what identifies the user out of the bundle.
+struct Redactor {
+    anonymizer: Anonymizer,
+    /// Literal strings and what replaces them
+    names: Vec<(String, String)>,
+}
+
+impl Redactor {
+    fn new(last_run: Option<&str>) -> Self {
+        let anonymizer = Anonymizer::new();
+        let mut names = Vec::new();
+        if let Some(home) = std::env::var_os("HOME").filter(|h| !h.is_empty()) {
+            names.push((home.to_string_lossy().into_owned(), "~".to_string()));
+        }
+
+        let run = last_run
+            .and_then(|run| serde_json::from_str::<serde_json::Value>(run).ok())
+            .unwrap_or_default();
+        let metadata = &run["metadata"];
+        let target = metadata["parameters"]["host"].as_str().map(String::from);
+        for host in metadata::hostname().into_iter().chain(target) {
+            // Addresses are redacted as addresses
+            if !host.is_empty() && host != "localhost" && host.parse::<IpAddr>().is_err() {
+                names.push((host.clone(), anonymizer.hostname(&host)));
+            }
+        }
+
+        // Probe names and labels often name the site or customer
+        let probe = metadata["probe"].as_str().map(|p| ("probe", p));
+        let labels = metadata["labels"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter_map(|(_, value)| Some(("label", value.as_str()?)));
+        for (kind, name) in probe.into_iter().chain(labels) {
+            // Numbers would take timings and counts along
+            if !name.is_empty() && name.parse::<f64>().is_err() {
+                names.push((name.to_string(), anonymizer.name(kind, name)));
+            }
+        }
+
+        Self { anonymizer, names }
+    }
+
+    fn redact(&self, text: &str) -> String {
+        let mut text = self.redact_addresses(text);
+        for (name, replacement) in self.names.iter() {
+            text = replace_words(&text, name, replacement);
+        }
+        text
+    }
+
+    fn redact_addresses(&self, text: &str) -> String {
+        let mut redacted = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find(is_address_char) {
+            redacted.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let end = rest.find(|c| !is_address_char(c)).unwrap_or(rest.len());
+            // Not part of a word, like the `::c` of `bwlat::client`
+            let bounded =
+                !redacted.ends_with(is_word_char) && !rest[end..].starts_with(is_word_char);
+            match bounded {
+                true => redacted.push_str(&self.redact_token(&rest[..end])),
+                false => redacted.push_str(&rest[..end]),
+            }
+            rest = &rest[end..];
+        }
+        redacted.push_str(rest);
+        redacted
+    }
+
+    /// Redacts `token` if it is an address, also with a port or at the end of a
+    /// sentence.
+    fn redact_token(&self, token: &str) -> String {
+        let trimmed = token.trim_end_matches(['.', ':']);
+        let address = match trimmed.rsplit_once(':') {
+            Some((v4, port))
+                if v4.parse::<Ipv4Addr>().is_ok() && port.bytes().all(|b| b.is_ascii_digit()) =>
+            {
+                v4
+            }
+            _ => trimmed,
+        };
+        match address.parse::<IpAddr>() {
+            Ok(ip) if !ip.is_loopback() && !ip.is_unspecified() => {
+                format!(
+                    "{}{}",
+                    self.anonymizer.address(&ip),
+                    &token[address.len()..]
+                )
+            }
+            _ => token.to_string(),
+        }
+    }
+}
+
+fn is_address_char(c: char) -> bool {
+    c.is_ascii_hexdigit() || c == '.' || c == ':'
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
+/// Replaces `name` where it is not part of a longer word, so short host names
+/// leave the rest of the text alone.
+fn replace_words(text: &str, name: &str, replacement: &str) -> String {
+    let mut replaced = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(name) {
+        let end = start + name.len();
+        replaced.push_str(&rest[..start]);
+        let bounded = !replaced.ends_with(is_word_char) && !rest[end..].starts_with(is_word_char);
+        replaced.push_str(match bounded {
+            true => replacement,
+            false => name,
+        });
+        rest = &rest[end..];
+    }
+    replaced.push_str(rest);
+    replaced
+}
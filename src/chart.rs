@@ -0,0 +1,228 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use color_eyre::eyre::{bail, eyre, Result};
+use plotters::{coord::Shift, prelude::*};
+
+use crate::network::latency::{PacketStatus, State};
+
+/// Size of the image, large enough to stay readable when a chat client scales
+/// it down.
+const SIZE: (u32, u32) = (1200, 800);
+
+/// Bars of the latency histogram.
+const HISTOGRAM_BINS: usize = 60;
+
+/// Quantile the histogram ends at, slower probes are added to the last bar so
+/// a few outliers do not squeeze the rest into one bar.
+const HISTOGRAM_END: f64 = 0.999;
+
+const FONT: &str = "sans-serif";
+
+/// Where the labels' font is looked for, the renderer bundles none.
+const FONT_PATHS: &[&str] = &[
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu-sans-fonts/DejaVuSans.ttf",
+    "/usr/share/fonts/truetype/liberation/LiberationSans-Regular.ttf",
+    "/usr/share/fonts/liberation-sans/LiberationSans-Regular.ttf",
+    "/usr/share/fonts/noto/NotoSans-Regular.ttf",
+    "/usr/share/fonts/truetype/noto/NotoSans-Regular.ttf",
+    "/System/Library/Fonts/Supplemental/Arial.ttf",
+    "/Library/Fonts/Arial.ttf",
+    "C:\\Windows\\Fonts\\arial.ttf",
+];
+
+const NANOS_PER_MS: f64 = 1_000_000.0;
+
+/// Checks that a chart path ends in a format the renderer writes.
+pub(crate) fn parse_path(s: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(s);
+    match extension(&path).as_deref() {
+        Some("svg" | "png") => Ok(path),
+        _ => Err("charts are written as .svg or .png".to_string()),
+    }
+}
+
+/// Draws the latency of a run over time above its latency histogram, as SVG or
+/// PNG by the extension of `path`.
+pub(crate) fn render(path: &Path, title: &str, state: &State) -> Result<()> {
+    register_font()?;
+
+    let drawn = match extension(path).as_deref() {
+        Some("svg") => draw(
+            SVGBackend::new(path, SIZE).into_drawing_area(),
+            title,
+            state,
+        )
+        .map_err(|e| e.to_string()),
+        Some("png") => draw(
+            BitMapBackend::new(path, SIZE).into_drawing_area(),
+            title,
+            state,
+        )
+        .map_err(|e| e.to_string()),
+        _ => bail!("Charts are written as .svg or .png, not {}", path.display()),
+    };
+    drawn.map_err(|e| eyre!("Could not draw the chart {}: {}", path.display(), e))
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase)
+}
+
+/// Loads the first font found for the labels, once per process.
+fn register_font() -> Result<()> {
+    static REGISTERED: OnceLock<bool> = OnceLock::new();
+    let registered = *REGISTERED.get_or_init(|| {
+        FONT_PATHS
+            .iter()
+            .filter_map(|path| fs::read(path).ok())
+            .any(|font| {
+                plotters::style::register_font(
+                    FONT,
+                    FontStyle::Normal,
+                    Box::leak(font.into_boxed_slice()),
+                )
+                .is_ok()
+            })
+    });
+    if !registered {
+        bail!(
+            "Charts need a TrueType font for their labels, install DejaVu Sans or Liberation Sans"
+        );
+    }
+    Ok(())
+}
+
+fn draw<DB: DrawingBackend>(
+    root: DrawingArea<DB, Shift>,
+    title: &str,
+    state: &State,
+) -> Result<(), DrawingAreaErrorKind<DB::ErrorType>> {
+    root.fill(&WHITE)?;
+    let root = root.titled(title, (FONT, 24))?;
+    let (upper, lower) = root.split_vertically(60.percent());
+
+    draw_timeline(&upper, state)?;
+    draw_histogram(&lower, state)?;
+
+    root.present()
+}
+
+/// Round trip of every received probe over the time it was sent, lost probes
+/// as marks along the top.
+fn draw_timeline<DB: DrawingBackend>(
+    area: &DrawingArea<DB, Shift>,
+    state: &State,
+) -> Result<(), DrawingAreaErrorKind<DB::ErrorType>> {
+    let mut samples = Vec::new();
+    let mut lost = Vec::new();
+    for (_, packet) in state.packets.numbered() {
+        match *packet {
+            PacketStatus::Received { start, latency, .. } => samples.push((
+                start.as_secs_f64(),
+                latency.as_nanos() as f64 / NANOS_PER_MS,
+            )),
+            PacketStatus::Sent(at) => lost.push(at.as_secs_f64()),
+            PacketStatus::Skipped(_) | PacketStatus::Invalid(_) | PacketStatus::Warmup(_) => {}
+        }
+    }
+
+    let end = state
+        .packets
+        .last()
+        .map_or(0.0, |p| p.sent_at().as_secs_f64())
+        .max(1.0);
+    let top = samples
+        .iter()
+        .map(|&(_, ms)| ms)
+        .fold(0.0, f64::max)
+        .max(1.0)
+        * 1.1;
+
+    let mut chart = ChartBuilder::on(area)
+        .caption("Latency over time", (FONT, 18))
+        .margin(15)
+        .x_label_area_size(40)
+        .y_label_area_size(70)
+        .build_cartesian_2d(0.0..end, 0.0..top)?;
+    chart
+        .configure_mesh()
+        .x_desc("Time (s)")
+        .y_desc("Latency (ms)")
+        .label_style((FONT, 14))
+        .draw()?;
+
+    chart
+        .draw_series(LineSeries::new(samples, BLUE.stroke_width(1)))?
+        .label("round trip")
+        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], BLUE));
+    chart
+        .draw_series(
+            lost.iter()
+                .map(|&at| Circle::new((at, top * 0.97), 3, RED.filled())),
+        )?
+        .label(format!("lost ({})", lost.len()))
+        .legend(|(x, y)| Circle::new((x + 10, y), 3, RED.filled()));
+    chart
+        .configure_series_labels()
+        .position(SeriesLabelPosition::UpperRight)
+        .label_font((FONT, 14))
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()
+}
+
+/// Latency distribution of the whole run.
+fn draw_histogram<DB: DrawingBackend>(
+    area: &DrawingArea<DB, Shift>,
+    state: &State,
+) -> Result<(), DrawingAreaErrorKind<DB::ErrorType>> {
+    let histogram = &state.histogram;
+    let (low, high) = match histogram.is_empty() {
+        true => (0.0, 1.0),
+        false => (
+            histogram.min() as f64 / NANOS_PER_MS,
+            histogram.value_at_quantile(HISTOGRAM_END) as f64 / NANOS_PER_MS,
+        ),
+    };
+    let width = ((high - low) / HISTOGRAM_BINS as f64).max(0.001);
+
+    let mut bins = [0u64; HISTOGRAM_BINS];
+    for value in histogram.iter_recorded() {
+        let ms = value.value_iterated_to() as f64 / NANOS_PER_MS;
+        let bin = (((ms - low) / width) as usize).min(HISTOGRAM_BINS - 1);
+        bins[bin] += value.count_at_value();
+    }
+    let most = bins.iter().copied().max().unwrap_or(0).max(1);
+
+    let mut chart = ChartBuilder::on(area)
+        .caption("Latency distribution", (FONT, 18))
+        .margin(15)
+        .x_label_area_size(40)
+        .y_label_area_size(70)
+        .build_cartesian_2d(
+            low..low + width * HISTOGRAM_BINS as f64,
+            0..most + most / 10,
+        )?;
+    chart
+        .configure_mesh()
+        .x_desc("Latency (ms)")
+        .y_desc("Probes")
+        .label_style((FONT, 14))
+        .draw()?;
+
+    chart.draw_series(bins.iter().enumerate().map(|(i, &count)| {
+        let start = low + width * i as f64;
+        Rectangle::new([(start, 0), (start + width, count)], BLUE.mix(0.6).filled())
+    }))?;
+
+    Ok(())
+}
@@ -1,14 +1,45 @@
-use std::{net::IpAddr, path::PathBuf};
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+};
 
 use clap::{Parser, Subcommand};
 use clap_verbosity_flag::{InfoLevel, Verbosity};
+use color_eyre::eyre::{bail, eyre, Result};
 use humantime::Duration;
 
+use crate::{
+    chart,
+    collector::{self, CollectorAddress},
+    influx::InfluxDestination,
+    metadata,
+    network::{
+        flow_label::MAX_FLOW_LABEL,
+        latency::{
+            CatchUp, DownPolicy, Payload, Protocol, StopCondition, StreamProfile, TunnelChange,
+            Warmup, UDP_IPV4_OVERHEAD,
+        },
+        link::LinkType,
+        metered::MeteredPolicy,
+        mmsg, mtu,
+        passive::{Filter, Sequence},
+        protocol,
+        quiet::{self, QuietPolicy, QuietWindow},
+        timestamping::Timestamping,
+        udplite,
+    },
+    units::Units,
+};
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None, infer_subcommands = true)]
 pub(crate) struct CliOptions {
     #[command(subcommand)]
-    pub mode: Modes,
+    pub mode: Option<Modes>,
+
+    /// Print version, build and system capabilities as JSON
+    #[arg(long, exclusive = true)]
+    pub version_json: bool,
 
     #[command(flatten)]
     pub verbose: Verbosity<InfoLevel>,
@@ -17,32 +48,1038 @@ pub(crate) struct CliOptions {
 #[derive(Subcommand, Debug)]
 pub(crate) enum Modes {
     Server(ServerOptions),
-    Client(ClientOptions),
+    Client(Box<ClientOptions>),
+    /// Verify the signature of an exported report
+    Verify(VerifyOptions),
+    /// Measure the overhead floor of this host over loopback
+    Bench(BenchOptions),
+    /// Run the steps of a YAML test plan and check their assertions
+    TestPlan(TestPlanOptions),
+    /// Watch an existing UDP flow without sending any traffic (Linux)
+    Monitor(MonitorOptions),
+    /// Run the concurrent flows of a YAML scenario and report how they interact
+    Scenario(ScenarioOptions),
+    /// Rate the results of a latency JSON export against typical links
+    Rate(RateOptions),
+    /// Replay a latency JSON export in the TUI, with --compare over a second run
+    /// to see the differences
+    View(ViewOptions),
+    /// Find the path MTU to a server by binary-searching the largest probe it
+    /// echoes with the don't fragment bit set (Linux)
+    Pmtu(PmtuOptions),
+    /// Check this host for problems that skew measurements, like a slow clock
+    /// source, coarse timers or small socket buffers, and print fixes
+    Doctor(DoctorOptions),
+    /// Accept the reports of many clients sent with --collector and serve them
+    /// combined as Prometheus metrics and in a TUI
+    Collector(CollectorOptions),
+    /// Bundle the last run's metadata and logs with the doctor checks into a
+    /// redacted archive to attach to an issue
+    Bugreport(BugreportOptions),
 }
 
 #[derive(Parser, Debug)]
 pub(crate) struct ServerOptions {
     #[arg(short, long)]
     pub port: u16,
+
+    /// Address to listen on, all IPv6 and IPv4 addresses by default
+    #[arg(long, value_name = "ADDR")]
+    pub bind: Option<IpAddr>,
+
+    /// Log echo rate, byte rate and client count this often, 0s disables
+    #[arg(long, default_value = "10s")]
+    pub stats_interval: Duration,
+
+    /// Append the per-interval statistics to a CSV file
+    #[arg(long, value_name = "PATH")]
+    pub stats_csv: Option<PathBuf>,
+
+    /// Print a one-time code clients can pair with
+    #[arg(long)]
+    pub pair: bool,
+
+    /// Only serve clients that paired with this server
+    #[arg(long)]
+    pub require_auth: bool,
+
+    /// Send clients that did not pair the larger and repeated replies they ask
+    /// for, e.g. with --profile game, rate limited per address. Without it only
+    /// paired clients get them, as replies to spoofed probes would amplify
+    /// traffic toward a third party
+    #[arg(long)]
+    pub allow_amplification: bool,
+
+    /// Log statistics instead of showing the TUI of connected clients. Without a
+    /// terminal, e.g. as a service, the server always logs
+    #[arg(long)]
+    pub no_tui: bool,
+
+    /// Serve Prometheus metrics on /metrics at this address, e.g. 0.0.0.0:9101
+    #[arg(long, value_name = "ADDR")]
+    pub metrics_listen: Option<SocketAddr>,
+
+    /// Also echo UDP-Lite probes on the port (experimental, Linux only)
+    #[arg(long)]
+    pub udplite: bool,
+
+    /// Accept IPv6 probes of clients with --no-udp-checksum, which the stack
+    /// drops otherwise (Linux, expert)
+    #[cfg(feature = "udp-no-checksum")]
+    #[arg(long)]
+    pub no_udp_checksum: bool,
+
+    /// Also accept QUIC latency and bandwidth tests, on this UDP port as the
+    /// echo takes the main one
+    #[arg(long, value_name = "PORT")]
+    pub quic_port: Option<u16>,
+
+    /// Datagrams read per system call with recvmmsg (Linux), 1 reads each on
+    /// its own
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = mmsg::DEFAULT_BATCH as u16,
+        value_parser = clap::value_parser!(u16).range(1..=mmsg::MAX_BATCH as i64)
+    )]
+    pub batch: u16,
+
+    /// Kernel receive buffer of the UDP sockets, e.g. 4MB, instead of the OS
+    /// default that drops datagrams at high rates
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub rcvbuf: Option<u64>,
+
+    /// Kernel send buffer of the UDP sockets
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub sndbuf: Option<u64>,
+
+    /// Run with SCHED_FIFO real-time priority, so other work on a busy host
+    /// delays the probes less. Needs root or CAP_SYS_NICE (Linux)
+    #[arg(long)]
+    pub realtime: bool,
+
+    /// Pin bwlat to this CPU core (Linux)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(0..1024))]
+    pub cpu: Option<u16>,
+
+    /// Config file with options under [server], defaults to config.toml in the
+    /// config directory
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
 }
 
 #[derive(Parser, Debug)]
 pub(crate) struct ClientOptions {
-    pub address: IpAddr,
-    pub port: u16,
+    /// Server hostname or IP address
+    pub address: String,
+    /// Server port, not needed with --protocol icmp
+    pub port: Option<u16>,
+
+    /// Probe every address the hostname resolves to as a separate target
+    #[arg(long)]
+    pub all_addresses: bool,
+
+    /// Underlay address of the tunnel the target is reached through, e.g. the
+    /// WireGuard endpoint. Both are probed at the same time and the latency and
+    /// loss the tunnel adds are reported. Needs a bwlat server on both
+    #[arg(
+        long,
+        value_name = "ADDR",
+        conflicts_with_all = ["all_addresses", "bandwidth", "bidirectional"]
+    )]
+    pub underlay: Option<IpAddr>,
 
     #[arg(long, default_value = "0")]
     pub client_port: u16,
 
-    #[arg(short, long, default_value = "20ms")]
+    /// Source address to send from, also picks the address family of the target
+    #[arg(long, value_name = "ADDR")]
+    pub bind: Option<IpAddr>,
+
+    /// Send probes round-robin from these local addresses, e.g. several IPv6
+    /// addresses of one interface, and compare the results per source to expose
+    /// source-based routing or policing. All have to be of one family
+    #[arg(
+        long,
+        value_name = "ADDRS",
+        value_delimiter = ',',
+        conflicts_with_all = ["bind", "bandwidth", "streams"]
+    )]
+    pub source_addresses: Vec<IpAddr>,
+
+    /// Cycle probes through a pool of sockets with different source ports
+    #[arg(long)]
+    pub randomize_source_port: bool,
+
+    /// Number of source ports used by --randomize-source-port
+    #[arg(long, default_value = "8", requires = "randomize_source_port")]
+    pub port_pool: usize,
+
+    /// Concurrent probe flows per target, each from its own source port, to see
+    /// whether paths or queues treat flows differently
+    #[arg(
+        long,
+        default_value = "1",
+        value_parser = clap::value_parser!(u16).range(1..=64),
+        conflicts_with = "bandwidth"
+    )]
+    pub streams: u16,
+
+    /// Also echo probes the server sends back, measuring from both ends at once
+    #[arg(long, conflicts_with_all = ["bandwidth", "all_addresses"])]
+    pub bidirectional: bool,
+
+    /// Only echo probes the server sends, so all probe traffic originates from
+    /// the server's side
+    #[arg(long, conflicts_with_all = ["bandwidth", "all_addresses", "bidirectional"])]
+    pub reverse: bool,
+
+    #[arg(short, long, default_value = "20ms", value_parser = parse_interval)]
     pub interval: Duration,
 
+    /// How to handle send slots missed because the sender fell behind
+    #[arg(long, value_enum, default_value_t)]
+    pub catch_up: CatchUp,
+
+    /// Clock divergence treated as a system suspend, 0 disables detection
+    #[arg(long, default_value = "2s")]
+    pub suspend_threshold: Duration,
+
+    /// Ask NetworkManager whether the connection is metered, and warn about it or
+    /// pause probing and hold bandwidth tests back until it is unmetered (Linux)
+    #[arg(long, value_enum, value_name = "POLICY")]
+    pub on_metered: Option<MeteredPolicy>,
+
+    /// Daily windows of local time, e.g. a backup window, during which bandwidth
+    /// tests are skipped and probing pauses, comma-delimited as 02:00-03:00.
+    /// Pauses are recorded as events and skipped probes in the exports
+    #[arg(long, value_name = "HH:MM-HH:MM", value_delimiter = ',', value_parser = quiet::parse_window)]
+    pub quiet_hours: Vec<QuietWindow>,
+
+    /// What quiet hours hold back
+    #[arg(long, value_enum, default_value_t, requires = "quiet_hours")]
+    pub quiet_policy: QuietPolicy,
+
+    /// Mark the target down once no reply arrived for this long while probes
+    /// were sent, an event of its own rather than creeping loss
+    #[arg(long, value_name = "DURATION", conflicts_with = "bandwidth")]
+    pub down_after: Option<Duration>,
+
+    /// What to do when the target goes down
+    #[arg(long, value_enum, default_value_t, requires = "down_after")]
+    pub on_down: DownPolicy,
+
     #[arg(short = 'z', long, default_value = "64")]
     pub packet_size: usize,
 
+    /// Transport of the latency probes, TCP where UDP is blocked, websocket where
+    /// only HTTP gets through, ICMP for hosts without a bwlat server. With
+    /// --bandwidth only quic applies
+    #[arg(
+        long,
+        value_enum,
+        default_value_t,
+        conflicts_with = "randomize_source_port"
+    )]
+    pub protocol: Protocol,
+
+    /// UDP port of the server's QUIC listener, for --protocol quic
+    #[arg(long, value_name = "PORT")]
+    pub quic_port: Option<u16>,
+
+    /// Send the probes without UDP checksums, so ones damaged on the path reach
+    /// the server instead of being dropped. Over IPv6 the server needs
+    /// --no-udp-checksum as well. For lab studies of corruption only (Linux,
+    /// expert)
+    #[cfg(feature = "udp-no-checksum")]
+    #[arg(long, conflicts_with = "bandwidth")]
+    pub no_udp_checksum: bool,
+
+    /// HTTP proxy to tunnel --protocol websocket through with CONNECT, e.g.
+    /// proxy.example.com:3128
+    #[arg(long, value_name = "HOST:PORT")]
+    pub proxy: Option<String>,
+
+    /// Probe contents, `alternate` compares compressible and random payloads.
+    /// Bandwidth tests switch between both every report interval
+    #[arg(long, value_enum, default_value_t)]
+    pub payload: Payload,
+
+    /// Seed of the random payloads, recorded in the metadata of the results. Runs
+    /// with the same seed and version send the same bytes. Source ports are still
+    /// picked by the OS
+    #[arg(long)]
+    pub seed: Option<u64>,
+
     #[arg(short, long, default_value = "100")]
     pub count: u32,
 
+    /// Keep only the last N probes in memory so long runs stay bounded. Totals,
+    /// percentiles, phases and ports still cover the whole run. Runs with
+    /// --count 0 keep 100000 unless they export every probe
+    #[arg(long, value_name = "N", conflicts_with_all = ["csv", "json"])]
+    pub retain: Option<usize>,
+
+    /// Cap the memory the probes take, e.g. 200MB. Once reached only the most
+    /// recent probes are kept and the totals carry on, so unattended runs never
+    /// run out of memory
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_memory: Option<u64>,
+
+    /// Kernel receive buffer of the UDP probe sockets, e.g. 4MB, instead of the
+    /// OS default that drops datagrams at high rates
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, conflicts_with = "bandwidth")]
+    pub rcvbuf: Option<u64>,
+
+    /// Kernel send buffer of the UDP probe sockets
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, conflicts_with = "bandwidth")]
+    pub sndbuf: Option<u64>,
+
+    /// Probe like a VoIP stream or game, with its packet sizes, interval and DSCP,
+    /// and rate the experience it would get. The game snapshots need a paired
+    /// server or one started with --allow-amplification
+    #[arg(
+        long,
+        value_enum,
+        value_name = "PROFILE",
+        conflicts_with_all = ["packet_size", "interval", "auto_interval", "protocol", "bandwidth"]
+    )]
+    pub profile: Option<StreamProfile>,
+
+    /// Mark the probes with this DSCP, a number up to 63 or a name like EF, AF41
+    /// or CS1
+    #[arg(long, value_parser = parse_dscp, conflicts_with = "bandwidth")]
+    pub dscp: Option<u8>,
+
+    /// Set the whole type of service byte of the probes, ECN bits included, e.g.
+    /// 0xb8
+    #[arg(long, value_parser = parse_tos, conflicts_with_all = ["dscp", "bandwidth"])]
+    pub tos: Option<u8>,
+
+    /// Send the probes with this TTL, or hop limit over IPv6. Routers where it
+    /// runs out answer with ICMP errors, which are reported per probe
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..), conflicts_with = "bandwidth")]
+    pub ttl: Option<u8>,
+
+    /// IPv6 flow label of the probes, e.g. 0x12345, so flow-label based ECMP
+    /// keeps them on one path
+    #[arg(long, value_name = "LABEL", value_parser = parse_flow_label, conflicts_with = "bandwidth")]
+    pub flow_label: Option<u32>,
+
+    /// Give every stream of --streams its own flow label, counting up from
+    /// --flow-label, to spread them over the ECMP paths
+    #[arg(long, requires = "flow_label")]
+    pub vary_flow_label: bool,
+
+    /// Bytes of the --protocol udplite probes covered by the checksum, counted
+    /// from the start of the 8 byte UDP-Lite header. Damage past them is
+    /// delivered instead of dropped
+    #[arg(
+        long,
+        value_name = "BYTES",
+        value_parser = clap::value_parser!(u16).range(udplite::HEADER as i64..)
+    )]
+    pub checksum_coverage: Option<u16>,
+
+    /// Attach this text, up to 64 bytes, to every probe. The server echoes it
+    /// back untouched and it is recorded with each probe in the exports
+    #[arg(long, value_name = "TEXT", value_parser = protocol::parse_tag)]
+    pub probe_tag: Option<String>,
+
+    /// Take send and receive times of the UDP probes in the kernel or network
+    /// card instead of after the scheduler woke the client (Linux)
+    #[arg(long, value_enum, conflicts_with = "bandwidth")]
+    pub timestamping: Option<Timestamping>,
+
+    /// Spin for UDP echoes instead of sleeping until woken up, and have the
+    /// kernel busy poll the device for up to USEC (default 50) with SO_BUSY_POLL.
+    /// Keeps a core busy; for sub-100µs LAN latencies where the wakeup counts
+    #[arg(
+        long,
+        value_name = "USEC",
+        num_args = 0..=1,
+        default_missing_value = "50",
+        value_parser = clap::value_parser!(u32).range(1..=100_000),
+        conflicts_with = "bandwidth"
+    )]
+    pub busy_poll: Option<u32>,
+
+    /// Run with SCHED_FIFO real-time priority, so other work on a busy host
+    /// delays the probes less. Needs root or CAP_SYS_NICE (Linux)
+    #[arg(long)]
+    pub realtime: bool,
+
+    /// Pin bwlat to this CPU core (Linux)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(0..1024))]
+    pub cpu: Option<u16>,
+
+    /// Tune loss timeouts and the bandwidth ramp for the latency of this kind of
+    /// link, so normal satellite round trips do not count as losses
+    #[arg(long, value_enum)]
+    pub link_type: Option<LinkType>,
+
+    /// Declare probes lost after SRTT + K·RTTVAR of the round trips so far, like
+    /// the TCP retransmission timeout, instead of a fixed timeout. K defaults to 4
+    #[arg(
+        long,
+        value_name = "K",
+        num_args = 0..=1,
+        default_missing_value = "4",
+        value_parser = clap::value_parser!(u32).range(1..=64)
+    )]
+    pub adaptive_loss_timeout: Option<u32>,
+
+    /// Depth of the fixed jitter buffer assumed by the RTP profiles
+    #[arg(long, default_value = "40ms", requires = "profile")]
+    pub jitter_buffer: Duration,
+
+    /// Probes, e.g. 10, or time, e.g. 2s, at the start that prime ARP/ND, NAT
+    /// state and route caches. They come on top of --count, are left out of the
+    /// statistics and marked as warmup in the exports
+    #[arg(
+        long,
+        value_name = "COUNT|DURATION",
+        value_parser = parse_warmup,
+        conflicts_with_all = ["bandwidth", "replay_schedule"]
+    )]
+    pub warmup: Option<Warmup>,
+
+    /// End the run once a percentile settles, e.g. "p99 stable within 2% over 60s"
+    #[arg(long, value_name = "CONDITION", value_parser = parse_stop_condition)]
+    pub stop_when: Option<StopCondition>,
+
+    /// Retry reaching the server with backoff for this long before starting
+    #[arg(long, value_name = "DURATION")]
+    pub wait_for_server: Option<Duration>,
+
+    /// Pair with the server using the code it printed, the secret is remembered
+    #[arg(long, value_name = "CODE")]
+    pub pair: Option<String>,
+
+    /// Latency that triggers a burst of high-resolution probing
+    #[arg(long, value_name = "LATENCY")]
+    pub burst_threshold: Option<Duration>,
+
+    /// Consecutive lost probes that trigger a burst of high-resolution probing
+    #[arg(long, value_name = "PROBES")]
+    pub burst_loss: Option<u32>,
+
+    /// Probe interval during a burst, shorter than --interval
+    #[arg(long, default_value = "10ms", value_parser = parse_interval)]
+    pub burst_interval: Duration,
+
+    /// How long a burst lasts before returning to --interval
+    #[arg(long, default_value = "10s")]
+    pub burst_duration: Duration,
+
+    /// Measure throughput instead of latency
+    #[arg(long)]
+    pub bandwidth: bool,
+
+    /// TCP congestion control for the bandwidth test, e.g. bbr or cubic (Linux)
+    #[arg(long, requires = "bandwidth", conflicts_with = "rate")]
+    pub congestion: Option<String>,
+
+    /// Send UDP at this bitrate instead of a TCP bulk transfer, e.g. 50M
+    #[arg(long, requires = "bandwidth", value_parser = parse_bitrate)]
+    pub rate: Option<u64>,
+
+    /// Raise the UDP rate in this many steps up to --rate to find the path's limit
+    #[arg(long, value_name = "STEPS", requires = "rate")]
+    pub ramp: Option<u32>,
+
+    /// Datagrams of the UDP bandwidth test sent per system call with sendmmsg
+    /// (Linux), 1 sends each on its own [default: 32]
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "rate",
+        value_parser = clap::value_parser!(u16).range(1..=mmsg::MAX_BATCH as i64)
+    )]
+    pub batch: Option<u16>,
+
+    /// Run for this long instead of a fixed packet count
+    #[arg(short, long)]
+    pub duration: Option<Duration>,
+
+    /// Send the probes at the times recorded in a latency JSON export, to compare
+    /// runs under the same offered pattern. The export references the schedule
+    #[arg(
+        long,
+        value_name = "JSON",
+        conflicts_with_all = ["count", "interval", "duration", "profile", "bandwidth", "burst_threshold", "burst_loss"]
+    )]
+    pub replay_schedule: Option<PathBuf>,
+
+    /// Pick the interval so the run stays within --max-packets/--max-bytes
+    #[arg(long, requires = "duration")]
+    pub auto_interval: bool,
+
+    /// Packet budget for --auto-interval, across all targets
+    #[arg(long)]
+    pub max_packets: Option<u64>,
+
+    /// Stop after this many bytes in both directions including headers, also
+    /// the budget for --auto-interval
+    #[arg(long)]
+    pub max_bytes: Option<u64>,
+
     #[arg(long)]
     pub csv: Option<PathBuf>,
+
+    /// Write parameters, summary and per-packet records as a JSON document
+    #[arg(long, value_name = "PATH")]
+    pub json: Option<PathBuf>,
+
+    /// Write the latency distribution as HdrHistogram percentile output (.hgrm)
+    #[arg(long, value_name = "PATH", conflicts_with = "bandwidth")]
+    pub hgrm: Option<PathBuf>,
+
+    /// Draw the latency over time and its histogram to an .svg or .png image
+    #[arg(long, value_name = "PATH", value_parser = chart::parse_path, conflicts_with = "bandwidth")]
+    pub chart_out: Option<PathBuf>,
+
+    /// Unit for latencies in the TUI and summary, numbers follow the locale
+    #[arg(long, value_enum, default_value_t)]
+    pub units: Units,
+
+    /// Smoothing factor (0-1] of the latency trend line, lower is smoother
+    #[arg(long, default_value = "0.1", value_parser = parse_alpha)]
+    pub ewma_alpha: f64,
+
+    /// Bars of the latency histogram panel, toggled with `g`
+    #[arg(long, default_value = "20", value_parser = clap::value_parser!(u16).range(1..=200))]
+    pub histogram_buckets: u16,
+
+    /// Track the kernel's route to the target and report changes (Linux)
+    #[arg(long)]
+    pub track_route: bool,
+
+    /// Read the server's clock during the run and report how far the clocks drift
+    #[arg(long)]
+    pub clock_drift: bool,
+
+    /// Have the server timestamp its echoes and report upstream and downstream
+    /// delay separately. The offset between the clocks is estimated before the
+    /// run, which assumes a symmetric path like NTP does
+    #[arg(long)]
+    pub one_way: bool,
+
+    /// Take the clocks of client and server as in sync, e.g. through PTP or GPS,
+    /// instead of estimating their offset. Keeps a constant asymmetry of the path
+    /// visible in the one-way delays
+    #[arg(long, requires = "one_way")]
+    pub trust_clocks: bool,
+
+    /// Names of the phases of the run, `p` or the phase marker starts the next one
+    #[arg(long, value_name = "NAMES", value_delimiter = ',')]
+    pub phases: Vec<String>,
+
+    /// Start a phase named after every line appended to this file
+    #[arg(long, value_name = "PATH")]
+    pub phase_marker: Option<PathBuf>,
+
+    /// What to do when the route moves onto or off a VPN or other tunnel
+    #[arg(long, value_enum, default_value_t, requires = "track_route")]
+    pub on_tunnel_change: TunnelChange,
+
+    /// Name of this probe, added to every export, metric and upload so results of
+    /// many probes can be told apart
+    #[arg(long, value_name = "NAME")]
+    pub probe_name: Option<String>,
+
+    /// Label added to every export, metric and upload, can be repeated
+    #[arg(long, value_name = "KEY=VALUE", value_parser = metadata::parse_label)]
+    pub label: Vec<(String, String)>,
+
+    /// Serve a live web dashboard on this address, e.g. 0.0.0.0:8088
+    #[arg(long, value_name = "ADDR")]
+    pub web: Option<SocketAddr>,
+
+    /// Serve Prometheus metrics on /metrics at this address, e.g. 0.0.0.0:9101
+    #[arg(long, value_name = "ADDR")]
+    pub metrics_listen: Option<SocketAddr>,
+
+    /// Stream every probe in InfluxDB line protocol to a file, udp://host:port or
+    /// an HTTP write endpoint like http://localhost:8086/write?db=bwlat. The
+    /// INFLUX_TOKEN environment variable is sent as API token
+    #[arg(long, value_name = "DEST")]
+    pub influx: Option<InfluxDestination>,
+
+    /// How often probes are written to the InfluxDB output, each write adds a
+    /// summary point of the probes it contains
+    #[arg(long, default_value = "10s", requires = "influx", value_parser = parse_interval)]
+    pub influx_interval: Duration,
+
+    /// Report the totals of every target to a `bwlat collector` at HOST:PORT.
+    /// Reports are queued while it is unreachable and sent once it is back
+    #[arg(long, value_name = "HOST:PORT")]
+    pub collector: Option<CollectorAddress>,
+
+    /// How often the totals are reported to the collector
+    #[arg(long, default_value = "10s", requires = "collector", value_parser = parse_interval)]
+    pub collector_interval: Duration,
+
+    /// Config file with options under [client] and alert sinks under [alerts],
+    /// defaults to config.toml in the config directory
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// MaxMind database (ASN, Country or City) used to tag targets, can be repeated
+    #[arg(long, value_name = "MMDB")]
+    pub geoip_db: Vec<PathBuf>,
+
+    /// Replace IP addresses in reports and summaries with salted hashes
+    #[arg(long)]
+    pub anonymize: bool,
+
+    /// Sign exported reports with an Ed25519 private key (PKCS#8 PEM)
+    #[arg(long, value_name = "KEY")]
+    pub sign: Option<PathBuf>,
+
+    /// Log progress instead of showing the TUI, for cron jobs and CI without a
+    /// terminal. The run ends with the test or on Ctrl-C
+    #[arg(long)]
+    pub no_tui: bool,
+
+    /// Plain-text progress for screen readers and braille displays: no TUI,
+    /// colors or timestamps, every number labeled and units spelled out
+    #[arg(long)]
+    pub accessible: bool,
+
+    /// Run the test again this often, from start to start, until interrupted.
+    /// Each test is summarized with its start time and the TUI shows the history
+    /// in between. Exports are rewritten by every test
+    #[arg(long, value_name = "DURATION")]
+    pub repeat_every: Option<Duration>,
+
+    /// Time the tool's own send, receive, lock and render paths and report them
+    /// at exit, to tell when bwlat itself is the bottleneck
+    #[arg(long)]
+    pub profile_self: bool,
+}
+
+#[derive(Parser, Debug)]
+pub(crate) struct VerifyOptions {
+    pub report: PathBuf,
+
+    /// Detached signature, defaults to `<report>.sig`
+    #[arg(long)]
+    pub signature: Option<PathBuf>,
+
+    /// Only accept signatures made with this Ed25519 public key (PEM)
+    #[arg(long)]
+    pub key: Option<PathBuf>,
+}
+
+#[derive(Parser, Debug)]
+pub(crate) struct RateOptions {
+    /// Latency results written with `client --json`
+    pub results: PathBuf,
+
+    /// Link type to rate against, the one recorded in the results by default,
+    /// otherwise the results are matched to the link types they are typical for
+    #[arg(long, value_enum)]
+    pub link_type: Option<LinkType>,
+
+    #[arg(long, value_enum, default_value_t)]
+    pub units: Units,
+}
+
+#[derive(Parser, Debug)]
+pub(crate) struct ViewOptions {
+    /// Latency results written with `client --json`
+    pub results: PathBuf,
+
+    /// Second export to draw dimmed behind the run and compare its figures with,
+    /// e.g. the run before a change
+    #[arg(long, value_name = "PATH")]
+    pub compare: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value_t)]
+    pub units: Units,
+}
+
+#[derive(Parser, Debug)]
+pub(crate) struct PmtuOptions {
+    /// Server hostname or IP address
+    pub address: String,
+    /// Server port
+    pub port: u16,
+
+    /// Largest MTU to try, e.g. 9000 on jumbo frame networks
+    #[arg(
+        long,
+        default_value = "1500",
+        value_parser = clap::value_parser!(u16).range(mtu::MIN_IPV4_MTU as i64..=u16::MAX as i64)
+    )]
+    pub max: u16,
+}
+
+#[derive(Parser, Debug)]
+pub(crate) struct CollectorOptions {
+    /// Address to accept reports and serve /metrics on, :PORT for all addresses
+    #[arg(long, value_name = "ADDR", default_value = ":7443", value_parser = collector::parse_listen)]
+    pub listen: SocketAddr,
+
+    /// Log new and silent targets instead of showing the TUI
+    #[arg(long)]
+    pub no_tui: bool,
+
+    #[arg(long, value_enum, default_value_t)]
+    pub units: Units,
+}
+
+#[derive(Parser, Debug)]
+pub(crate) struct DoctorOptions {
+    /// Port a server should listen on, to check it is free and print how to open
+    /// it in the firewall. Repeat for several
+    #[arg(short, long)]
+    pub port: Vec<u16>,
+}
+
+#[derive(Parser, Debug)]
+pub(crate) struct BugreportOptions {
+    /// Where to write the archive
+    #[arg(short, long, default_value = "bwlat-bugreport.tar.gz")]
+    pub output: PathBuf,
+
+    /// Port the server listens on, checked like with doctor. Repeat for several
+    #[arg(short, long)]
+    pub port: Vec<u16>,
+}
+
+#[derive(Parser, Debug)]
+pub(crate) struct TestPlanOptions {
+    pub plan: PathBuf,
+
+    /// Write a JUnit XML report of the results
+    #[arg(long, value_name = "PATH")]
+    pub junit: Option<PathBuf>,
+}
+
+#[derive(Parser, Debug)]
+pub(crate) struct ScenarioOptions {
+    pub scenario: PathBuf,
+
+    #[arg(long, value_enum, default_value_t)]
+    pub units: Units,
+}
+
+#[derive(Parser, Debug)]
+pub(crate) struct BenchOptions {
+    /// Probe sizes to test
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "64,512,1400",
+        value_parser = parse_probe_size
+    )]
+    pub sizes: Vec<usize>,
+
+    /// Probe intervals to test
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "10ms,1ms,200us,50us",
+        value_parser = parse_interval
+    )]
+    pub intervals: Vec<Duration>,
+
+    /// How long to probe at each size and interval
+    #[arg(short, long, default_value = "2s")]
+    pub duration: Duration,
+
+    #[arg(long, value_enum, default_value_t)]
+    pub units: Units,
+}
+
+#[derive(Parser, Debug)]
+pub(crate) struct MonitorOptions {
+    /// Datagrams to watch, e.g. "udp and dst port 5004" or "host 10.0.0.2"
+    #[arg(default_value = "")]
+    pub filter: Filter,
+
+    /// Interface to capture on, all of them by default
+    #[arg(short, long)]
+    pub interface: Option<String>,
+
+    /// Read sequence numbers from the payload to count lost datagrams
+    #[arg(long, value_enum, default_value_t)]
+    pub sequence: Sequence,
+
+    /// Log per-flow progress this often, 0s disables it
+    #[arg(long, default_value = "5s")]
+    pub stats_interval: Duration,
+
+    /// Stop after this long instead of on Ctrl-C
+    #[arg(short, long)]
+    pub duration: Option<Duration>,
+
+    #[arg(long, value_enum, default_value_t)]
+    pub units: Units,
+}
+
+/// Shortest interval `--auto-interval` will select.
+const MIN_AUTO_INTERVAL: std::time::Duration = std::time::Duration::from_millis(1);
+
+impl ClientOptions {
+    /// Resolves the target host. Without `--all-addresses` only the first
+    /// address is kept, with `--bind` or `--source-addresses` only those of the
+    /// same family.
+    pub(crate) async fn resolve_targets(&self) -> Result<Vec<IpAddr>> {
+        let mut addresses: Vec<IpAddr> = Vec::new();
+        for address in
+            tokio::net::lookup_host((self.address.as_str(), self.port.unwrap_or(0))).await?
+        {
+            if !addresses.contains(&address.ip()) {
+                addresses.push(address.ip());
+            }
+        }
+
+        if addresses.is_empty() {
+            return Err(eyre!("{} did not resolve to any address", self.address));
+        }
+
+        if let Some(first) = self.source_addresses.first() {
+            if self
+                .source_addresses
+                .iter()
+                .any(|a| a.is_ipv4() != first.is_ipv4())
+            {
+                bail!("--source-addresses have to be all IPv4 or all IPv6, run once per family");
+            }
+        }
+
+        if let Some(bind) = self.bind.or_else(|| self.source_addresses.first().copied()) {
+            addresses.retain(|a| a.is_ipv4() == bind.is_ipv4());
+            if addresses.is_empty() {
+                bail!(
+                    "{} has no {} address to reach from {}",
+                    self.address,
+                    if bind.is_ipv4() { "IPv4" } else { "IPv6" },
+                    bind
+                );
+            }
+        }
+
+        if !self.all_addresses {
+            addresses.truncate(1);
+        }
+
+        Ok(addresses)
+    }
+
+    /// Port of the bwlat server, ICMP probes need none.
+    pub(crate) fn server_port(&self) -> Result<u16> {
+        match (self.port, self.protocol) {
+            (Some(port), _) => Ok(port),
+            (None, Protocol::Icmp) => Ok(0),
+            (None, _) => bail!("The server port is required unless probing with --protocol icmp"),
+        }
+    }
+
+    /// Effective send interval and packet count per target after applying
+    /// `--duration` and `--auto-interval`.
+    pub(crate) fn schedule(&self, targets: u32) -> Result<(std::time::Duration, u32)> {
+        let mut interval: std::time::Duration = self.interval.into();
+
+        let Some(duration) = self.duration else {
+            return Ok((interval, self.count));
+        };
+        let duration: std::time::Duration = duration.into();
+
+        if self.auto_interval {
+            // Every probe is echoed, so it crosses the link twice.
+            let bytes_per_packet = (self.packet_size as u64 + UDP_IPV4_OVERHEAD) * 2;
+            let budget = match (self.max_packets, self.max_bytes) {
+                (Some(p), Some(b)) => p.min(b / bytes_per_packet),
+                (Some(p), None) => p,
+                (None, Some(b)) => b / bytes_per_packet,
+                (None, None) => bail!("--auto-interval needs --max-packets or --max-bytes"),
+            };
+
+            let per_target = budget / targets.max(1) as u64;
+            if per_target == 0 {
+                bail!("Packet budget is too small for {} target(s)", targets);
+            }
+
+            interval = (duration / per_target.min(u32::MAX as u64) as u32).max(MIN_AUTO_INTERVAL);
+        }
+
+        let count = (duration.as_nanos() / interval.as_nanos()).clamp(1, u32::MAX as u128) as u32;
+
+        Ok((interval, count))
+    }
+}
+
+fn parse_alpha(s: &str) -> std::result::Result<f64, String> {
+    let alpha: f64 = s.parse().map_err(|e| format!("{}", e))?;
+    if alpha <= 0.0 || alpha > 1.0 {
+        return Err("must be in the range (0, 1]".to_string());
+    }
+    Ok(alpha)
+}
+
+/// A differentiated services code point by number or name.
+fn parse_dscp(s: &str) -> std::result::Result<u8, String> {
+    let name = s.to_ascii_uppercase();
+    let dscp = match name.as_str() {
+        "BE" | "DEFAULT" => 0,
+        "EF" => 46,
+        "VA" | "VOICE-ADMIT" => 44,
+        "LE" => 1,
+        _ => {
+            if let Some(class) = name.strip_prefix("CS") {
+                match class.parse::<u8>() {
+                    Ok(class) if class <= 7 => class << 3,
+                    _ => return Err(format!("{} is not a class selector, CS0 to CS7", s)),
+                }
+            } else if let Some(af) = name.strip_prefix("AF") {
+                let mut digits = af.chars().map(|c| c.to_digit(10));
+                match (digits.next(), digits.next(), digits.next()) {
+                    (Some(Some(class @ 1..=4)), Some(Some(drop @ 1..=3)), None) => {
+                        (class << 3 | drop << 1) as u8
+                    }
+                    _ => return Err(format!("{} is not an assured forwarding class", s)),
+                }
+            } else {
+                s.parse()
+                    .map_err(|_| format!("{} is neither a DSCP number nor a name", s))?
+            }
+        }
+    };
+    if dscp > 63 {
+        return Err("a DSCP is at most 63".to_string());
+    }
+    Ok(dscp)
+}
+
+/// A type of service byte, decimal or hexadecimal with 0x.
+fn parse_tos(s: &str) -> std::result::Result<u8, String> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|e| format!("{}: {}", s, e))
+}
+
+/// A non-zero IPv6 flow label, decimal or hexadecimal with 0x.
+fn parse_flow_label(s: &str) -> std::result::Result<u32, String> {
+    let label = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|e| format!("{}: {}", s, e))?;
+    if label == 0 || label > MAX_FLOW_LABEL {
+        return Err(format!(
+            "a flow label is between 1 and {:#x}",
+            MAX_FLOW_LABEL
+        ));
+    }
+    Ok(label)
+}
+
+/// Parses a condition like "p99 stable within 2% over 30s", the tolerance
+/// relative to the percentile.
+fn parse_stop_condition(s: &str) -> std::result::Result<StopCondition, String> {
+    const FORMAT: &str = "expected \"p<PERCENTILE> stable within <PERCENT>% over <DURATION>\"";
+
+    let words: Vec<&str> = s.split_whitespace().collect();
+    let [percentile, "stable", "within", tolerance, "over", window] = words[..] else {
+        return Err(FORMAT.to_string());
+    };
+
+    let percentile: f64 = percentile
+        .strip_prefix(['p', 'P'])
+        .and_then(|p| p.parse().ok())
+        .ok_or_else(|| format!("invalid percentile {}, {}", percentile, FORMAT))?;
+    if percentile <= 0.0 || percentile >= 100.0 {
+        return Err("the percentile must be between 0 and 100".to_string());
+    }
+    let tolerance: f64 = tolerance
+        .strip_suffix('%')
+        .and_then(|t| t.parse().ok())
+        .ok_or_else(|| format!("invalid tolerance {}, {}", tolerance, FORMAT))?;
+    if tolerance <= 0.0 {
+        return Err("the tolerance must be positive".to_string());
+    }
+    let window = humantime::parse_duration(window).map_err(|e| format!("{}: {}", window, e))?;
+
+    Ok(StopCondition {
+        percentile,
+        tolerance: tolerance / 100.0,
+        window,
+    })
+}
+
+/// A duration between probes or reports, which has to be longer than zero.
+fn parse_interval(s: &str) -> std::result::Result<Duration, String> {
+    let interval: Duration = s.parse().map_err(|e| format!("{}", e))?;
+    if interval.is_zero() {
+        return Err("must be longer than zero".to_string());
+    }
+    Ok(interval)
+}
+
+/// A probe size in bytes, from the probe header up to the largest datagram.
+fn parse_probe_size(s: &str) -> std::result::Result<usize, String> {
+    let size: usize = s.parse().map_err(|e| format!("{}", e))?;
+    if !(protocol::HEADER_LEN..=u16::MAX as usize).contains(&size) {
+        return Err(format!(
+            "must be between {} and {} bytes",
+            protocol::HEADER_LEN,
+            u16::MAX
+        ));
+    }
+    Ok(size)
+}
+
+/// A number of probes, or a duration with its unit.
+fn parse_warmup(s: &str) -> std::result::Result<Warmup, String> {
+    if let Ok(probes) = s.parse() {
+        return Ok(Warmup::Probes(probes));
+    }
+    humantime::parse_duration(s)
+        .map(Warmup::Duration)
+        .map_err(|e| format!("{} is neither a number of probes nor a duration: {}", s, e))
+}
+
+/// Parses a size in bytes with an optional kB, MB or GB suffix, or KiB, MiB or
+/// GiB.
+fn parse_size(s: &str) -> std::result::Result<u64, String> {
+    let units = [
+        ("KiB", 1 << 10),
+        ("MiB", 1 << 20),
+        ("GiB", 1 << 30),
+        ("kB", 1_000),
+        ("KB", 1_000),
+        ("MB", 1_000_000),
+        ("GB", 1_000_000_000),
+        ("B", 1),
+    ];
+    let (number, factor) = units
+        .iter()
+        .find_map(|&(unit, factor)| s.strip_suffix(unit).map(|n| (n, factor)))
+        .unwrap_or((s, 1));
+    let size: f64 = number.trim().parse().map_err(|e| format!("{}", e))?;
+    if size <= 0.0 {
+        return Err("must be positive".to_string());
+    }
+    Ok((size * factor as f64) as u64)
+}
+
+/// Bits per second with an optional k, M or G suffix.
+pub(crate) fn parse_bitrate(s: &str) -> std::result::Result<u64, String> {
+    let (number, factor) = match s.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&s[..i], 1e3),
+        Some((i, 'M')) => (&s[..i], 1e6),
+        Some((i, 'G')) => (&s[..i], 1e9),
+        _ => (s, 1.0),
+    };
+    let rate: f64 = number.parse().map_err(|e| format!("{}", e))?;
+    if rate <= 0.0 {
+        return Err("must be positive".to_string());
+    }
+    Ok((rate * factor) as u64)
 }
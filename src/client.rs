@@ -1,53 +1,377 @@
-use std::{net::IpAddr, path::PathBuf, time::Duration};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use crate::{
     action::Action,
-    components::{client_view::ClientView, Component},
-    network::latency::{Latency, PacketStatus},
-    tui::{Tui, TuiEvent},
+    alerts::{Alerting, AlertsConfig},
+    anonymize::Anonymizer,
+    app::App,
+    bugreport, chart,
+    collector::{CollectorAddress, Uploader},
+    components::{client_view::ClientView, history_view::HistoryView, Component},
+    geoip::GeoIp,
+    influx::{InfluxDestination, InfluxSink},
+    metadata::{self, Identity, RunMetadata},
+    metrics::{MetricsExporter, Source},
+    network::{
+        bandwidth::{
+            format_bitrate, format_bytes, BandwidthComparison, BandwidthSample, BandwidthState,
+            QuicBandwidth, TcpBandwidth, UdpBandwidth,
+        },
+        bidir::{Bidirectional, ReverseReport},
+        buffers::SocketBuffers,
+        flow_label::MAX_FLOW_LABEL,
+        game::GameQuality,
+        handshake::{self, Handshake},
+        icmp_error::IcmpError,
+        latency::{
+            self, BurstCapture, CatchUp, DownPolicy, Event, Latency, LossTimeout, PacketStatus,
+            Payload, PayloadComparison, PhaseStatistics, Protocol, SendRate, SourceStatistics,
+            State, StopCondition, StreamProfile, TunnelChange, Warmup,
+        },
+        link::LinkType,
+        metered::{self, MeteredPolicy},
+        quiet::{self, QuietPolicy, QuietWindow},
+        rtp::CallQuality,
+        shaping::RateLimit,
+        timestamping::Timestamping,
+    },
+    pairing,
+    preferences::Preferences,
+    profile, recommend,
+    replay::SendSchedule,
+    results::{BandwidthResults, LatencyResults},
+    signing,
+    streams::{StreamRelay, StreamsSummary},
+    units::DisplayFormat,
+    web::Dashboard,
 };
-use color_eyre::eyre::Result;
-use crossterm::event::KeyCode;
+use clap::ValueEnum;
+use color_eyre::eyre::{bail, Result};
+use crossterm::event::{KeyCode, KeyEvent};
 use csv::Writer;
-use ratatui::prelude::Rect;
-use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use ed25519_dalek::SigningKey;
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt},
+    sync::{
+        broadcast,
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        Mutex,
+    },
+    task::JoinHandle,
+    time,
+};
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, warn};
 
-pub(crate) struct Client {
+/// How often the route to each target is looked up with `--track-route`.
+const ROUTE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Percentile steps per halving of the remaining distance to 100% in `--hgrm`
+/// output, the value HdrHistogram's own tools use.
+const HGRM_TICKS_PER_HALF_DISTANCE: u32 = 5;
+
+/// How often progress is logged with `--no-tui`.
+const HEADLESS_PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often each target's server clock is read with `--clock-drift`.
+const CLOCK_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often the phase marker file is checked for new lines.
+const PHASE_MARKER_POLL: Duration = Duration::from_millis(200);
+
+type LatencyTask = JoinHandle<Result<Arc<Mutex<State>>>>;
+
+enum Tasks {
+    Latency(Vec<LatencyTask>),
+    Bandwidth(JoinHandle<Result<BandwidthState>>),
+}
+
+impl Tasks {
+    fn finished(&self) -> bool {
+        match self {
+            Tasks::Latency(tasks) => tasks.iter().all(|t| t.is_finished()),
+            Tasks::Bandwidth(task) => task.is_finished(),
+        }
+    }
+}
+
+/// Latest numbers of a target for headless progress lines.
+#[derive(Debug, Default, Clone)]
+struct Progress {
+    sent: u32,
+    received: u32,
+    minimum: Duration,
+    average: Duration,
+    maximum: Duration,
+}
+
+/// Results of one target, for comparing targets after the run.
+struct TargetSummary {
     address: IpAddr,
+    average_latency: Duration,
+    /// Share of the probes lost
+    loss: f64,
+}
+
+/// Summary of one test of a `--repeat-every` run.
+#[derive(Debug, Clone)]
+pub(crate) struct Round {
+    pub started: SystemTime,
+    pub outcome: RoundOutcome,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum RoundOutcome {
+    /// Of the first target, or of the server's probes in a reverse test
+    Latency {
+        average: Duration,
+        loss: f64,
+    },
+    /// Throughput in bits per second
+    Bandwidth(f64),
+    Skipped(String),
+    Failed(String),
+}
+
+impl RoundOutcome {
+    pub(crate) fn describe(&self, format: &DisplayFormat) -> String {
+        match self {
+            RoundOutcome::Latency { average, loss } => format!(
+                "average latency {}, {} loss",
+                format.duration(*average),
+                format.percent(*loss)
+            ),
+            RoundOutcome::Bandwidth(bits_per_second) => {
+                format!("throughput {}", format_bitrate(*bits_per_second))
+            }
+            RoundOutcome::Skipped(reason) => format!("skipped, {}", reason),
+            RoundOutcome::Failed(error) => format!("failed: {}", error),
+        }
+    }
+}
+
+pub(crate) struct Client {
+    host: String,
+    targets: Vec<IpAddr>,
+    /// The last target is the underlay address of the tunnel the first is
+    /// reached through
+    tunnel_comparison: bool,
 
     server_port: u16,
     client_port: u16,
+    bind_address: Option<IpAddr>,
+    source_addresses: Vec<IpAddr>,
+    buffers: SocketBuffers,
 
     packet_size: usize,
     count: u32,
     period: Duration,
 
     csv: Option<PathBuf>,
+    json: Option<PathBuf>,
+    hgrm: Option<PathBuf>,
+    chart: Option<PathBuf>,
+    signing_key: Option<SigningKey>,
+    anonymizer: Option<Anonymizer>,
+    geoip: Option<GeoIp>,
+    web: Option<SocketAddr>,
+    metrics: Option<SocketAddr>,
+    influx: Option<(InfluxDestination, Duration)>,
+    influx_task: Option<JoinHandle<()>>,
+    collector: Option<(CollectorAddress, Duration)>,
+    collector_task: Option<JoinHandle<()>>,
+    alerts: Option<AlertsConfig>,
+    identity: Identity,
+    track_route: bool,
+    track_clock: bool,
+    one_way_delay: bool,
+    trust_clocks: bool,
+    tunnel_change: TunnelChange,
+    stop_condition: Option<StopCondition>,
+    warmup: Option<Warmup>,
+    down_after: Option<Duration>,
+    on_down: DownPolicy,
+    /// Targets whose stop condition was met, the run ends once all were
+    converged: usize,
+    phase_names: Vec<String>,
+    phase_marker: Option<PathBuf>,
+    phase_marks: broadcast::Sender<String>,
+    phase_count: usize,
+    source_ports: usize,
+    /// Concurrent engines per target
+    streams: usize,
+    bidirectional: bool,
+    /// Only the server sends probes
+    reverse: bool,
+    reverse_task: Option<JoinHandle<Result<Option<ReverseReport>>>>,
+    catch_up: CatchUp,
+    suspend_threshold: Duration,
+    metered: Option<MeteredPolicy>,
+    quiet_hours: Vec<QuietWindow>,
+    quiet_policy: QuietPolicy,
+    payload: Payload,
+    seed: u64,
+    protocol: Protocol,
+    stream_profile: Option<StreamProfile>,
+    tos: Option<u8>,
+    ttl: Option<u8>,
+    flow_label: Option<u32>,
+    vary_flow_label: bool,
+    checksum_coverage: Option<u16>,
+    probe_tag: Option<String>,
+    timestamping: Option<Timestamping>,
+    busy_poll: Option<u32>,
+    jitter_buffer: Duration,
+    link_type: Option<LinkType>,
+    adaptive_loss_timeout: Option<u32>,
+    burst_capture: Option<BurstCapture>,
+    replay: Option<SendSchedule>,
+    quic_port: Option<u16>,
+    proxy: Option<String>,
+    #[cfg(feature = "udp-no-checksum")]
+    no_udp_checksum: bool,
+    max_bytes: Option<u64>,
+    retention: Option<usize>,
+    max_memory: Option<u64>,
+
+    bandwidth: Option<Duration>,
+    bandwidth_rate: Option<u64>,
+    ramp_steps: Option<u32>,
+    batch: Option<usize>,
+    congestion: Option<String>,
+
+    ewma_alpha: f64,
+    histogram_buckets: usize,
+    format: DisplayFormat,
+
+    wait_for_server: Option<Duration>,
+    handshake: Option<Handshake>,
+    pairing_code: Option<String>,
+
+    started_at: SystemTime,
+    finished_at: SystemTime,
+    profile_self: bool,
+    headless: bool,
+    accessible: bool,
+    repeat_every: Option<Duration>,
+    history: Vec<Round>,
+    /// The user ended the run rather than the test
+    interrupted: bool,
 
     pub components: Vec<Box<dyn Component>>,
-    should_exit: bool,
 }
 
 impl Client {
     pub(crate) fn new(
-        address: IpAddr,
+        host: String,
+        targets: Vec<IpAddr>,
         port: u16,
         client_port: u16,
         packet_size: usize,
         count: u32,
     ) -> Self {
         Self {
-            address,
+            host,
+            targets,
             server_port: port,
             client_port,
+            tunnel_comparison: false,
+            bind_address: None,
+            source_addresses: Vec::new(),
+            buffers: SocketBuffers::default(),
             packet_size,
             count,
             period: Duration::from_millis(20),
             csv: None,
-            components: vec![Box::new(ClientView::new())],
-            should_exit: false,
+            json: None,
+            hgrm: None,
+            chart: None,
+            signing_key: None,
+            anonymizer: None,
+            geoip: None,
+            web: None,
+            metrics: None,
+            influx: None,
+            influx_task: None,
+            collector: None,
+            collector_task: None,
+            alerts: None,
+            identity: Identity::default(),
+            track_route: false,
+            track_clock: false,
+            one_way_delay: false,
+            trust_clocks: false,
+            tunnel_change: TunnelChange::default(),
+            stop_condition: None,
+            warmup: None,
+            down_after: None,
+            on_down: DownPolicy::default(),
+            converged: 0,
+            phase_names: Vec::new(),
+            phase_marker: None,
+            phase_marks: broadcast::channel(16).0,
+            phase_count: 0,
+            source_ports: 1,
+            streams: 1,
+            bidirectional: false,
+            reverse: false,
+            reverse_task: None,
+            catch_up: CatchUp::default(),
+            suspend_threshold: Duration::from_secs(2),
+            metered: None,
+            quiet_hours: Vec::new(),
+            quiet_policy: QuietPolicy::default(),
+            payload: Payload::default(),
+            seed: rand::random(),
+            protocol: Protocol::default(),
+            stream_profile: None,
+            tos: None,
+            ttl: None,
+            flow_label: None,
+            vary_flow_label: false,
+            checksum_coverage: None,
+            probe_tag: None,
+            timestamping: None,
+            busy_poll: None,
+            jitter_buffer: Duration::ZERO,
+            link_type: None,
+            adaptive_loss_timeout: None,
+            burst_capture: None,
+            replay: None,
+            quic_port: None,
+            proxy: None,
+            #[cfg(feature = "udp-no-checksum")]
+            no_udp_checksum: false,
+            max_bytes: None,
+            retention: None,
+            max_memory: None,
+            bandwidth: None,
+            bandwidth_rate: None,
+            ramp_steps: None,
+            batch: None,
+            congestion: None,
+            ewma_alpha: 0.1,
+            histogram_buckets: 20,
+            format: DisplayFormat::default(),
+            wait_for_server: None,
+            handshake: None,
+            pairing_code: None,
+            started_at: SystemTime::now(),
+            finished_at: SystemTime::now(),
+            profile_self: false,
+            headless: false,
+            accessible: false,
+            repeat_every: None,
+            history: Vec::new(),
+            interrupted: false,
+            components: Vec::new(),
         }
     }
 
@@ -56,180 +380,2258 @@ impl Client {
         self
     }
 
+    /// Treats the second target as the underlay of the tunnel the first is
+    /// reached through, and reports what the tunnel adds.
+    pub(crate) fn enable_tunnel_comparison(&mut self) {
+        self.tunnel_comparison = true;
+    }
+
+    /// Send probes and bandwidth tests from this address.
+    pub(crate) fn set_bind_address(&mut self, address: IpAddr) {
+        self.bind_address = Some(address);
+    }
+
+    /// Send the latency probes round-robin from these addresses and compare
+    /// the results per source.
+    pub(crate) fn set_source_addresses(&mut self, addresses: Vec<IpAddr>) {
+        self.source_addresses = addresses;
+    }
+
+    /// Sizes the kernel buffers of the UDP probe sockets.
+    pub(crate) fn set_socket_buffers(&mut self, buffers: SocketBuffers) {
+        self.buffers = buffers;
+    }
+
     pub(crate) fn enable_output_csv(&mut self, path: PathBuf) {
         self.csv = Some(path);
     }
 
-    pub(crate) async fn run(&mut self) -> Result<()> {
-        let cancel = CancellationToken::new();
-        let (mut action_tx, mut action_rx) = mpsc::unbounded_channel();
+    /// Write the results of every target as JSON at the end.
+    pub(crate) fn enable_output_json(&mut self, path: PathBuf) {
+        self.json = Some(path);
+    }
 
-        let mut tui = Tui::new()?;
-        tui.tick_rate(1.0);
-        tui.frame_rate(60.0);
-        tui.enter()?;
+    /// Write the latency distribution of every target as HdrHistogram output at the end.
+    pub(crate) fn enable_output_hgrm(&mut self, path: PathBuf) {
+        self.hgrm = Some(path);
+    }
 
-        for component in self.components.iter_mut() {
-            component.init()?;
-        }
+    /// Draw the latency chart of every target to an SVG or PNG image at the end.
+    pub(crate) fn enable_output_chart(&mut self, path: PathBuf) {
+        self.chart = Some(path);
+    }
 
-        let mut latency = Latency::new_with_count(
-            self.address,
-            self.server_port,
-            self.count,
-            action_tx.clone(),
-            cancel.child_token(),
-        )
-        .with_packet_size(self.packet_size as u16)
-        .with_interval(self.period)
-        .with_client_port(self.client_port);
+    /// Run a TCP bandwidth test of the given duration instead of measuring latency.
+    pub(crate) fn enable_bandwidth(&mut self, duration: Duration) {
+        self.bandwidth = Some(duration);
+    }
 
-        let latency_task = tokio::spawn(async move { latency.run().await });
+    /// Send UDP at this many bits per second in the bandwidth test instead of a
+    /// TCP bulk transfer.
+    pub(crate) fn set_bandwidth_rate(&mut self, rate: u64) {
+        self.bandwidth_rate = Some(rate);
+    }
 
-        loop {
-            if let Some(e) = tui.next().await {
-                self.handle_events(&e, &mut action_tx)?;
+    /// Raise the UDP rate in this many steps, to find where the path limits it.
+    pub(crate) fn set_bandwidth_ramp(&mut self, steps: u32) {
+        self.ramp_steps = Some(steps);
+    }
 
-                for component in self.components.iter_mut() {
-                    if let Some(action) = component.handle_events(Some(e.clone()))? {
-                        action_tx.send(action)?;
-                    }
-                }
-            }
+    /// Sends up to `size` datagrams of the UDP bandwidth test per system call.
+    pub(crate) fn set_bandwidth_batch(&mut self, size: usize) {
+        self.batch = Some(size);
+    }
 
-            self.handle_actions(&mut tui, &mut action_rx, &mut action_tx)?;
+    pub(crate) fn set_congestion(&mut self, algorithm: String) {
+        self.congestion = Some(algorithm);
+    }
 
-            if self.should_exit {
-                break;
-            }
-        }
+    /// Smoothing factor of the latency trend line on the chart.
+    pub(crate) fn set_ewma_alpha(&mut self, alpha: f64) {
+        self.ewma_alpha = alpha;
+    }
 
-        tui.exit()?;
+    /// Number of bars in the latency histogram panel.
+    pub(crate) fn set_histogram_buckets(&mut self, buckets: usize) {
+        self.histogram_buckets = buckets;
+    }
 
-        cancel.cancel();
-        let latency_result = latency_task.await;
-        let latency_result = latency_result??;
+    pub(crate) fn set_display_format(&mut self, format: DisplayFormat) {
+        self.format = format;
+    }
+
+    pub(crate) fn set_catch_up(&mut self, catch_up: CatchUp) {
+        self.catch_up = catch_up;
+    }
+
+    pub(crate) fn set_suspend_threshold(&mut self, threshold: Duration) {
+        self.suspend_threshold = threshold;
+    }
+
+    pub(crate) fn set_metered_policy(&mut self, policy: MeteredPolicy) {
+        self.metered = Some(policy);
+    }
+
+    pub(crate) fn set_quiet_hours(&mut self, windows: Vec<QuietWindow>, policy: QuietPolicy) {
+        self.quiet_hours = windows;
+        self.quiet_policy = policy;
+    }
+
+    pub(crate) fn set_payload(&mut self, payload: Payload) {
+        self.payload = payload;
+    }
+
+    pub(crate) fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+    }
+
+    /// Type of service byte of the probes, overriding the marking of a profile.
+    pub(crate) fn set_tos(&mut self, tos: u8) {
+        self.tos = Some(tos);
+    }
+
+    /// TTL, or hop limit, of the probes.
+    pub(crate) fn set_ttl(&mut self, ttl: u8) {
+        self.ttl = Some(ttl);
+    }
+
+    /// IPv6 flow label of the probes, with `vary` every stream gets the next one.
+    pub(crate) fn set_flow_label(&mut self, label: u32, vary: bool) {
+        self.flow_label = Some(label);
+        self.vary_flow_label = vary;
+    }
+
+    /// Checksum coverage of the UDP-Lite probes, header included.
+    pub(crate) fn set_checksum_coverage(&mut self, coverage: u16) {
+        self.checksum_coverage = Some(coverage);
+    }
+
+    /// Text attached to every probe and echoed back by the server.
+    pub(crate) fn set_probe_tag(&mut self, tag: String) {
+        self.probe_tag = Some(tag);
+    }
+
+    /// Measures UDP probes with kernel or network card timestamps.
+    pub(crate) fn set_timestamping(&mut self, timestamping: Timestamping) {
+        self.timestamping = Some(timestamping);
+    }
+
+    /// Spins for UDP echoes with the kernel busy polling for up to `micros`.
+    pub(crate) fn set_busy_poll(&mut self, micros: u32) {
+        self.busy_poll = Some(micros);
+    }
+
+    /// Shape the traffic like `profile` and rate the experience, voice calls with a
+    /// fixed jitter buffer of this depth. Size and interval are set as usual.
+    pub(crate) fn set_stream_profile(&mut self, profile: StreamProfile, jitter_buffer: Duration) {
+        self.stream_profile = Some(profile);
+        self.jitter_buffer = jitter_buffer;
+    }
+
+    /// Waits for echoes and ramps bandwidth tests as suits this kind of link.
+    pub(crate) fn set_link_type(&mut self, link_type: LinkType) {
+        self.link_type = Some(link_type);
+    }
+
+    /// Declares probes lost after SRTT + k·RTTVAR instead of a fixed grace.
+    pub(crate) fn set_adaptive_loss_timeout(&mut self, k: u32) {
+        self.adaptive_loss_timeout = Some(k);
+    }
+
+    pub(crate) fn enable_burst_capture(&mut self, burst: BurstCapture) {
+        self.burst_capture = Some(burst);
+    }
+
+    /// UDP port of the server's QUIC listener, for `--protocol quic`. The
+    /// server port still serves authentication and `--wait-for-server`.
+    pub(crate) fn set_quic_port(&mut self, port: u16) {
+        self.quic_port = Some(port);
+    }
+
+    /// Send the UDP probes without checksums, for lab studies of corruption.
+    #[cfg(feature = "udp-no-checksum")]
+    pub(crate) fn disable_udp_checksum(&mut self) {
+        self.no_udp_checksum = true;
+    }
+
+    /// HTTP proxy, as `host:port`, the WebSocket of `--protocol websocket` is
+    /// tunneled through.
+    pub(crate) fn set_proxy(&mut self, proxy: String) {
+        self.proxy = Some(proxy);
+    }
+
+    /// Sends the probes at the times of `schedule`, the count and interval are
+    /// expected to be set to its own.
+    pub(crate) fn set_send_schedule(&mut self, schedule: SendSchedule) {
+        self.replay = Some(schedule);
+    }
+
+    /// Data budget for the whole run, split evenly between the targets.
+    pub(crate) fn set_max_bytes(&mut self, bytes: u64) {
+        self.max_bytes = Some(bytes);
+    }
+
+    /// Keep only the last `retention` probes of each stream in memory.
+    pub(crate) fn set_retention(&mut self, retention: usize) {
+        self.retention = Some(retention);
+    }
+
+    /// Keeps only the most recent probes once the probes of all streams take
+    /// more than `bytes` of memory.
+    pub(crate) fn set_max_memory(&mut self, bytes: u64) {
+        self.max_memory = Some(bytes);
+    }
+
+    /// Seed of the random payloads, recorded in the metadata so the run can be
+    /// reproduced. A random one is picked otherwise.
+    pub(crate) fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    pub(crate) fn set_source_ports(&mut self, ports: usize) {
+        self.source_ports = ports;
+    }
+
+    /// Probe each target with this many concurrent flows, each from its own
+    /// source port, and report them together.
+    pub(crate) fn set_streams(&mut self, streams: usize) {
+        self.streams = streams;
+    }
+
+    /// Also echo probes the server sends back to measure from both ends at once.
+    pub(crate) fn enable_bidirectional(&mut self) {
+        self.bidirectional = true;
+    }
+
+    /// Only echo probes the server sends, for servers that cannot be probed
+    /// from outside but can send out.
+    pub(crate) fn enable_reverse(&mut self) {
+        self.reverse = true;
+    }
+
+    pub(crate) fn enable_route_tracking(&mut self) {
+        self.track_route = true;
+    }
+
+    pub(crate) fn enable_clock_tracking(&mut self) {
+        self.track_clock = true;
+    }
+
+    /// Split round trips into upstream and downstream delay with the server's
+    /// receive time. With `trust_clocks` the clocks are taken to be in sync
+    /// rather than corrected by an estimated offset.
+    pub(crate) fn enable_one_way_delay(&mut self, trust_clocks: bool) {
+        self.one_way_delay = true;
+        self.trust_clocks = trust_clocks;
+    }
+
+    pub(crate) fn set_tunnel_change(&mut self, policy: TunnelChange) {
+        self.tunnel_change = policy;
+    }
+
+    pub(crate) fn set_warmup(&mut self, warmup: Warmup) {
+        self.warmup = Some(warmup);
+    }
+
+    pub(crate) fn set_down_detection(&mut self, after: Duration, policy: DownPolicy) {
+        self.down_after = Some(after);
+        self.on_down = policy;
+    }
+
+    pub(crate) fn set_stop_condition(&mut self, condition: StopCondition) {
+        self.stop_condition = Some(condition);
+    }
+
+    /// Names of the phases, in the order they are marked.
+    pub(crate) fn set_phase_names(&mut self, names: Vec<String>) {
+        self.phase_names = names;
+    }
+
+    pub(crate) fn enable_phase_marker(&mut self, path: PathBuf) {
+        self.phase_marker = Some(path);
+    }
+
+    /// Name of the `n`th phase from `--phases`, or its number.
+    fn phase_name(&self, n: usize) -> String {
+        self.phase_names
+            .get(n)
+            .cloned()
+            .unwrap_or_else(|| format!("phase {}", n + 1))
+    }
+
+    pub(crate) fn enable_web_dashboard(&mut self, listen: SocketAddr) {
+        self.web = Some(listen);
+    }
+
+    /// Serve Prometheus metrics of all targets on this address.
+    pub(crate) fn enable_metrics(&mut self, listen: SocketAddr) {
+        self.metrics = Some(listen);
+    }
+
+    /// Stream the probes of all targets in InfluxDB line protocol, flushing every
+    /// `interval`.
+    pub(crate) fn enable_influx(&mut self, destination: InfluxDestination, interval: Duration) {
+        self.influx = Some((destination, interval));
+    }
+
+    /// Report the totals of all targets to a collector every `interval`.
+    pub(crate) fn enable_collector(&mut self, collector: CollectorAddress, interval: Duration) {
+        self.collector = Some((collector, interval));
+    }
+
+    /// Name and labels of this probe for exports, metrics and uploads.
+    pub(crate) fn set_identity(&mut self, identity: Identity) {
+        self.identity = identity;
+    }
+
+    /// Deliver alerts to the sinks of the config file while the targets are probed.
+    pub(crate) fn enable_alerts(&mut self, alerts: AlertsConfig) {
+        self.alerts = Some(alerts);
+    }
+
+    /// Pair with the server using the code it printed before authenticating.
+    pub(crate) fn set_pairing_code(&mut self, code: String) {
+        self.pairing_code = Some(code);
+    }
+
+    /// Wait up to `patience` for the server to answer before starting the run.
+    pub(crate) fn set_wait_for_server(&mut self, patience: Duration) {
+        self.wait_for_server = Some(patience);
+    }
+
+    /// Tag targets with the ASN and country found in local MaxMind databases.
+    pub(crate) fn enable_geoip(&mut self, geoip: GeoIp) {
+        self.geoip = Some(geoip);
+    }
+
+    pub(crate) fn enable_anonymize(&mut self) {
+        self.anonymizer = Some(Anonymizer::new());
+    }
+
+    pub(crate) fn enable_headless(&mut self) {
+        self.headless = true;
+    }
+
+    /// Headless progress as self-contained lines that label every number, for
+    /// screen readers and braille displays.
+    pub(crate) fn enable_accessible(&mut self) {
+        self.headless = true;
+        self.accessible = true;
+    }
+
+    /// Runs the test again every `interval`, from start to start, until the user
+    /// interrupts, turning the client into a connection monitor.
+    pub(crate) fn set_repeat_every(&mut self, interval: Duration) {
+        self.repeat_every = Some(interval);
+    }
+
+    pub(crate) fn enable_self_profile(&mut self) {
+        self.profile_self = true;
+    }
+
+    pub(crate) fn enable_signing(&mut self, key: SigningKey) {
+        self.signing_key = Some(key);
+    }
+
+    pub(crate) async fn run(&mut self) -> Result<()> {
+        let Some(interval) = self.repeat_every else {
+            return self.run_round().await.map(|_| ());
+        };
 
-        // Print statistics
-        let state = latency_result.lock().await;
+        loop {
+            let start = time::Instant::now();
+            let round = Round {
+                started: SystemTime::now(),
+                // A monitor keeps going while the server is unreachable
+                outcome: self
+                    .run_round()
+                    .await
+                    .unwrap_or_else(|e| RoundOutcome::Failed(e.to_string())),
+            };
+            info!(
+                "Test {} at {}: {}",
+                self.history.len() + 1,
+                humantime::format_rfc3339_seconds(round.started),
+                round.outcome.describe(&self.format)
+            );
+            self.history.push(round);
+            if self.interrupted || !self.wait_for_next_round(start + interval).await? {
+                return Ok(());
+            }
+        }
+    }
 
-        info!("Min latency: {:?}", state.min_latency);
-        info!("Average latency: {:?}", state.average_latency);
-        info!("Max latency: {:?}", state.max_latency);
+    /// Waits for the next test of a `--repeat-every` run, showing the results so
+    /// far in the TUI. False when the user quit instead.
+    async fn wait_for_next_round(&self, next: time::Instant) -> Result<bool> {
+        let wait = next.saturating_duration_since(time::Instant::now());
+        if wait.is_zero() {
+            warn!("The test took longer than --repeat-every, starting the next one right away");
+            return Ok(true);
+        }
         info!(
-            "Packet loss: {:.2}% ({}/{})",
-            state.packet_loss as f32 / state.packets.len() as f32 * 100.0,
-            state.packet_loss,
-            state.packets.len()
+            "Next test at {}",
+            humantime::format_rfc3339_seconds(SystemTime::now() + wait)
         );
 
-        if self.csv.is_some() {
-            self.write_csv(&state.packets)?;
+        if self.headless {
+            return Ok(tokio::select! {
+                _ = time::sleep_until(next) => true,
+                _ = tokio::signal::ctrl_c() => false,
+            });
         }
 
-        Ok(())
+        let (action_tx, action_rx) = mpsc::unbounded_channel();
+        let view = HistoryView::new(self.history.clone(), next, self.format);
+        App::new(1.0, 4.0)?
+            .with_component(Box::new(view))
+            .run(action_tx, action_rx, |_| time::Instant::now() >= next)
+            .await?;
+        Ok(time::Instant::now() >= next)
     }
 
-    fn handle_events(&self, e: &TuiEvent, action_tx: &mut UnboundedSender<Action>) -> Result<()> {
-        match e {
-            TuiEvent::Render => action_tx.send(Action::Render)?,
-            TuiEvent::Resize(x, y) => action_tx.send(Action::Resize(*x, *y))?,
-            TuiEvent::Key(key) => {
-                if key.code == KeyCode::Char('c')
-                    && key.modifiers == crossterm::event::KeyModifiers::CONTROL
-                {
-                    action_tx.send(Action::Quit)?;
-                };
+    /// Runs the test once and reports on it.
+    async fn run_round(&mut self) -> Result<RoundOutcome> {
+        if let (Some(_), Some(window)) = (self.bandwidth, quiet::active(&self.quiet_hours)) {
+            warn!("Skipping the bandwidth test during quiet hours {}", window);
+            return Ok(RoundOutcome::Skipped(format!("quiet hours {}", window)));
+        }
+        self.converged = 0;
+        self.phase_count = 0;
+        if let Some(patience) = self.wait_for_server {
+            self.handshake = Some(self.wait_for_server(patience).await?);
+        }
+        self.authenticate().await?;
+
+        let cancel = CancellationToken::new();
+        let (action_tx, mut action_rx) = mpsc::unbounded_channel();
+
+        let app = match self.headless {
+            true => None,
+            false => {
+                let mut view = ClientView::new(
+                    self.ewma_alpha,
+                    self.histogram_buckets,
+                    self.format,
+                    Preferences::load(),
+                );
+                if self.bidirectional {
+                    view = view.with_side_by_side();
+                }
+                if let Some(max) = self.max_memory {
+                    view = view.with_max_memory(max);
+                }
+                self.components.push(Box::new(view));
+                Some(
+                    App::new(1.0, 60.0)?
+                        .with_components(self.components.drain(..))
+                        .with_keys(keys),
+                )
+            }
+        };
+
+        if let (Some(_), Some(policy)) = (self.bandwidth, self.metered) {
+            metered::hold_off(policy).await;
+        }
+
+        self.started_at = SystemTime::now();
+        let tasks = match self.bandwidth {
+            Some(duration) => Tasks::Bandwidth(self.start_bandwidth(duration, &action_tx, &cancel)),
+            None => Tasks::Latency(self.start_latency(&action_tx, &cancel)?),
+        };
 
-                match key.code {
-                    KeyCode::Char('q') => action_tx.send(Action::Quit)?,
-                    KeyCode::Char('h') => action_tx.send(Action::ToggleShowHelp)?,
-                    _ => (),
+        if let Some(ref path) = self.phase_marker {
+            let path = path.clone();
+            let action_tx = action_tx.clone();
+            let cancel = cancel.child_token();
+            tokio::spawn(async move {
+                if let Err(e) = watch_phase_marker(&path, action_tx, cancel).await {
+                    error!("Phase marker {} failed: {:?}", path.display(), e);
                 }
+            });
+        }
+
+        match app {
+            Some(app) => {
+                // Repeated tests leave the TUI once they are done, to show the history
+                let repeating = self.repeat_every.is_some();
+                let mut ended = false;
+                app.run(action_tx, action_rx, |action| {
+                    ended =
+                        self.handle_action(action) || (repeating && self.round_finished(&tasks));
+                    ended
+                })
+                .await?;
+                self.interrupted = !ended;
             }
-            _ => {}
+            None => self.run_headless(&mut action_rx, &tasks).await?,
         }
 
-        Ok(())
+        cancel.cancel();
+        self.finished_at = SystemTime::now();
+
+        info!("Target: {}", self.display_host());
+
+        let mut outcome = match tasks {
+            // Only the server's probes were measured
+            Tasks::Latency(_) if self.reverse => None,
+            Tasks::Latency(tasks) => {
+                self.report_latency(tasks)
+                    .await?
+                    .first()
+                    .map(|s| RoundOutcome::Latency {
+                        average: s.average_latency,
+                        loss: s.loss,
+                    })
+            }
+            Tasks::Bandwidth(task) => {
+                Some(RoundOutcome::Bandwidth(self.report_bandwidth(task).await?))
+            }
+        };
+        if let Some(task) = self.reverse_task.take() {
+            let result = task.await?;
+            if let (None, Ok(Some(report))) = (&outcome, &result) {
+                outcome = Some(RoundOutcome::Latency {
+                    average: report.average_latency,
+                    loss: report.loss(),
+                });
+            }
+            self.report_reverse(result);
+        }
+
+        // The last probes are written once the engines drained them
+        if let Some(task) = self.influx_task.take() {
+            task.await?;
+        }
+        if let Some(task) = self.collector_task.take() {
+            task.await?;
+        }
+
+        if self.profile_self {
+            profile::report(&self.format);
+        }
+        bugreport::record_run(&self.metadata(0), self.profile_self.then(profile::timings));
+
+        Ok(
+            outcome
+                .unwrap_or_else(|| RoundOutcome::Failed("the server did not report".to_string())),
+        )
+    }
+
+    /// Whether the tests ended on their own, including the server's probes.
+    fn round_finished(&self, tasks: &Tasks) -> bool {
+        tasks.finished() && self.reverse_task.as_ref().is_none_or(|t| t.is_finished())
     }
 
-    fn handle_actions(
+    /// Logs progress instead of drawing the TUI until the tests finish on their
+    /// own, the stop condition is met or the user interrupts.
+    async fn run_headless(
         &mut self,
-        tui: &mut Tui,
         action_rx: &mut UnboundedReceiver<Action>,
-        action_tx: &mut UnboundedSender<Action>,
+        tasks: &Tasks,
     ) -> Result<()> {
-        while let Ok(action) = action_rx.try_recv() {
-            if action != Action::Render {
-                debug!("{action:?}");
-            }
-
-            match action {
-                Action::Quit => self.should_exit = true,
-                Action::Resize(w, h) => {
-                    tui.resize(Rect::new(0, 0, w, h))?;
-                    tui.draw(|f| {
-                        for component in self.components.iter_mut() {
-                            let r = component.draw(f, f.size());
-                            if let Err(e) = r {
-                                error!("Failed to draw: {:?}", e);
-                            }
+        let panes = self.targets.len() + self.bidirectional as usize;
+        let mut progress = vec![Progress::default(); panes];
+        let mut bandwidth: Option<BandwidthSample> = None;
+        let mut ticker = time::interval(HEADLESS_PROGRESS_INTERVAL);
+        ticker.tick().await;
+
+        loop {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {
+                    self.interrupted = true;
+                    break;
+                }
+                action = action_rx.recv() => {
+                    let Some(action) = action else { break };
+                    if self.handle_action(&action) {
+                        break;
+                    }
+                    match action {
+                        Action::LatencyPacketsSent(t, sent) => progress[t].sent = sent,
+                        Action::LatencyPacketsReceived(t, received, minimum, average, maximum) => {
+                            progress[t] = Progress { received, minimum, average, maximum, ..progress[t] };
                         }
-                    })?;
-                }
-                Action::Render => {
-                    tui.draw(|f| {
-                        for component in self.components.iter_mut() {
-                            let r = component.draw(f, f.size());
-                            if let Err(e) = r {
-                                error!("Failed to draw: {:?}", e);
-                            }
+                        Action::LatencyEvent(t, at, event) => {
+                            info!("{}Event at {:.1?}: {}", self.target_prefix(t), at, self.display_event(&event));
                         }
-                    })?;
+                        Action::BandwidthSample(sample) => bandwidth = Some(sample),
+                        _ => {}
+                    }
                 }
-                _ => {}
-            }
+                _ = ticker.tick() => {
+                    if let Some(sample) = bandwidth.take() {
+                        match self.accessible {
+                            true => info!(
+                                "Bandwidth after {} seconds: {}",
+                                sample.at.as_secs(),
+                                format_bitrate(sample.bits_per_second as f64)
+                            ),
+                            false => info!(
+                                "{:.0?}: {}",
+                                sample.at,
+                                format_bitrate(sample.bits_per_second as f64)
+                            ),
+                        }
+                    }
+                    for (t, p) in progress.iter().enumerate() {
+                        if p.sent == 0 {
+                            continue;
+                        }
+                        if self.accessible {
+                            self.log_accessible_progress(t, p);
+                            continue;
+                        }
+                        info!(
+                            "{}Sent {}, received {} ({} loss), average latency {}",
+                            self.target_prefix(t),
+                            p.sent,
+                            p.received,
+                            self.format.percent(1.0 - p.received as f64 / p.sent as f64),
+                            self.format.duration(p.average)
+                        );
+                    }
 
-            for component in self.components.iter_mut() {
-                if let Some(action) = component.update(action.clone())? {
-                    action_tx.send(action)?
-                };
+                    if self.round_finished(tasks) {
+                        break;
+                    }
+                }
             }
         }
 
         Ok(())
     }
 
-    fn write_csv(&self, packets: &[PacketStatus]) -> Result<()> {
-        let csv = match self.csv {
-            Some(ref path) => path,
-            _ => {
-                warn!("CSV output is disabled");
-                return Ok(());
-            }
+    /// One progress line of a target that names every figure, so it is read out
+    /// the same whether or not the previous line was heard.
+    fn log_accessible_progress(&self, target: usize, p: &Progress) {
+        let target = match (self.bidirectional, target) {
+            (true, 0) => "from this client".to_string(),
+            (true, _) => "from the server".to_string(),
+            (false, t) => format!("to {}", self.display_address(&self.targets[t])),
         };
-
-        let mut wtr = Writer::from_path(csv)?;
-        wtr.write_record(["packet", "sent", "received", "latency"])?;
-
-        for (i, packet) in packets.iter().enumerate() {
-            match packet {
-                PacketStatus::Sent(s) => {
-                    wtr.write_record([&format!("{}", i), &format!("{}", s.as_micros()), "", ""])?;
-                }
-                PacketStatus::Received {
-                    start,
-                    stop,
-                    latency,
-                } => {
-                    wtr.write_record([
-                        &format!("{}", i),
-                        &format!("{}", start.as_micros()),
-                        &format!("{}", stop.as_micros()),
-                        &format!("{}", latency.as_micros()),
-                    ])?;
-                }
-            }
+        let loss = 1.0 - p.received as f64 / p.sent as f64;
+        match p.received {
+            0 => info!(
+                "Latency {}: sent {}, received none, loss {}.",
+                target,
+                p.sent,
+                self.format.percent(loss)
+            ),
+            _ => info!(
+                "Latency {}: sent {}, received {}, loss {}, minimum {}, average {}, maximum {}.",
+                target,
+                p.sent,
+                p.received,
+                self.format.percent(loss),
+                self.format.duration(p.minimum),
+                self.format.duration(p.average),
+                self.format.duration(p.maximum)
+            ),
+        }
+    }
+
+    /// Names the target in headless progress when probing several.
+    fn target_prefix(&self, target: usize) -> String {
+        if self.bidirectional {
+            return match target {
+                0 => "From this client: ".to_string(),
+                _ => "From the server: ".to_string(),
+            };
+        }
+        match self.targets.len() {
+            1 => String::new(),
+            _ => format!("{}: ", self.display_address(&self.targets[target])),
+        }
+    }
+
+    /// Waits until every target answers, the slowest one decides the start delay.
+    async fn wait_for_server(&self, patience: Duration) -> Result<Handshake> {
+        let mut result = Handshake {
+            attempts: 0,
+            delay: Duration::ZERO,
+        };
+
+        for address in self.targets.iter() {
+            let handshake = handshake::wait_for_server(
+                *address,
+                self.server_port,
+                (self.bandwidth.is_some() && self.bandwidth_rate.is_none())
+                    || self.protocol == Protocol::Tcp,
+                patience.saturating_sub(result.delay),
+            )
+            .await?;
+
+            result.attempts += handshake.attempts;
+            result.delay += handshake.delay;
+        }
+
+        if result.attempts > self.targets.len() as u32 {
+            info!(
+                "Server answered after {:.1?} ({} attempts)",
+                result.delay, result.attempts
+            );
+        }
+
+        Ok(result)
+    }
+
+    /// Pairs with the server if a code was given, then proves the remembered
+    /// secret to every target so a server requiring authentication serves us.
+    async fn authenticate(&self) -> Result<()> {
+        if self.protocol == Protocol::Icmp {
+            return Ok(());
+        }
+        let server = format!("{}:{}", self.host, self.server_port);
+
+        let mut targets = self.targets.iter();
+        let paired = match self.pairing_code {
+            Some(ref code) => {
+                let Some(&first) = targets.next() else {
+                    return Ok(());
+                };
+                let paired =
+                    pairing::pair(SocketAddr::new(first, self.server_port), &server, code).await?;
+                info!("Paired with {}", server);
+                paired
+            }
+            None => match pairing::paired_server(&server)? {
+                Some(paired) => paired,
+                None => return Ok(()),
+            },
+        };
+
+        for &address in targets {
+            pairing::authenticate(SocketAddr::new(address, self.server_port), &paired).await?;
+        }
+
+        Ok(())
+    }
+
+    fn start_latency(
+        &mut self,
+        action_tx: &UnboundedSender<Action>,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<LatencyTask>> {
+        if self.reverse {
+            action_tx.send(Action::LatencyTarget(
+                0,
+                "probes from the server".to_string(),
+            ))?;
+            self.start_reverse(0, action_tx, cancel);
+            return Ok(Vec::new());
+        }
+
+        let flows = self.targets.len() * self.streams;
+        // Each flow sends from the port after the one of the previous flow
+        if self.client_port != 0
+            && u16::try_from(flows.saturating_sub(1))
+                .ok()
+                .and_then(|last| self.client_port.checked_add(last))
+                .is_none()
+        {
+            bail!(
+                "--client-port {} leaves no room for the ports of {} flows",
+                self.client_port,
+                flows
+            );
+        }
+        let mut latency_tasks = Vec::with_capacity(flows);
+        let mut states = Vec::with_capacity(flows);
+        for (i, address) in self.targets.iter().enumerate() {
+            let location = self.geoip.as_ref().and_then(|g| g.lookup(*address));
+            if self.targets.len() > 1 || location.is_some() {
+                let label = match location {
+                    Some(location) => format!("{} ({})", self.display_address(address), location),
+                    None => self.display_address(address),
+                };
+                action_tx.send(Action::LatencyTarget(i, label))?;
+            }
+
+            // Streams report to a relay that combines them into the target
+            let (stream_tx, stream_rx) = mpsc::unbounded_channel();
+            let mut stream_states = Vec::with_capacity(self.streams);
+
+            for s in 0..self.streams {
+                let flow = i * self.streams + s;
+                let client_port = match self.client_port {
+                    0 => 0,
+                    // Checked above to stay within the port range
+                    port => port + flow as u16,
+                };
+                let (notify, tag) = match self.streams {
+                    1 => (action_tx.clone(), i),
+                    _ => (stream_tx.clone(), s),
+                };
+
+                let mut latency = Latency::new_with_count(
+                    *address,
+                    self.probe_port(),
+                    self.count,
+                    notify,
+                    cancel.child_token(),
+                )
+                .with_target(tag)
+                .with_packet_size(self.packet_size as u16)
+                .with_interval(self.period)
+                .with_client_port(client_port)
+                .with_source_ports(self.source_ports)
+                .with_catch_up(self.catch_up)
+                .with_suspend_threshold(self.suspend_threshold)
+                .with_payload(self.payload)
+                .with_seed(self.seed.wrapping_add(s as u64))
+                .with_protocol(self.protocol)
+                .with_phases(self.phase_name(0), self.phase_marks.subscribe());
+
+                if let Some(label) = self.stream_flow_label(flow) {
+                    latency = latency.with_flow_label(label);
+                }
+                if let Some(policy) = self.metered {
+                    latency = latency.with_metered_policy(policy);
+                }
+                if let Some(ref proxy) = self.proxy {
+                    latency = latency.with_proxy(proxy.clone());
+                }
+                #[cfg(feature = "udp-no-checksum")]
+                if self.no_udp_checksum {
+                    latency = latency.with_udp_checksum_disabled();
+                }
+                if !self.quiet_hours.is_empty() {
+                    latency = latency.with_quiet_hours(self.quiet_hours.clone(), self.quiet_policy);
+                }
+                if let Some(warmup) = self.warmup {
+                    latency = latency.with_warmup(warmup);
+                }
+                if let Some(after) = self.down_after {
+                    latency = latency.with_down_detection(after, self.on_down);
+                }
+                if let Some(address) = self.bind_address {
+                    latency = latency.with_bind_address(address);
+                }
+                if !self.source_addresses.is_empty() {
+                    latency = latency.with_source_addresses(self.source_addresses.clone());
+                }
+                if !self.buffers.is_default() {
+                    latency = latency.with_socket_buffers(self.buffers);
+                }
+                if self.track_route {
+                    latency = latency
+                        .with_route_tracking(ROUTE_CHECK_INTERVAL)
+                        .with_tunnel_change(self.tunnel_change);
+                }
+                if self.track_clock {
+                    latency = latency.with_clock_tracking(CLOCK_CHECK_INTERVAL);
+                }
+                if self.one_way_delay {
+                    latency = latency.with_one_way_delay(!self.trust_clocks);
+                }
+                if let Some(burst) = self.burst_capture {
+                    latency = latency.with_burst_capture(burst);
+                }
+                if let Some(ref schedule) = self.replay {
+                    latency = latency.with_send_schedule(schedule.offsets.clone());
+                }
+                if self.link_type.is_some() || self.adaptive_loss_timeout.is_some() {
+                    let mut timeout = self
+                        .link_type
+                        .map_or_else(LossTimeout::default, |l| l.loss_timeout());
+                    timeout.adaptive = self.adaptive_loss_timeout;
+                    latency = latency.with_loss_timeout(timeout);
+                }
+                if let Some(condition) = self.stop_condition {
+                    latency = latency.with_stop_condition(condition);
+                }
+                if let Some(ttl) = self.ttl {
+                    latency = latency.with_ttl(ttl);
+                }
+                if let Some(coverage) = self.checksum_coverage {
+                    latency = latency.with_checksum_coverage(coverage);
+                }
+                if let Some(ref tag) = self.probe_tag {
+                    latency = latency.with_tag(tag.clone());
+                }
+                if let Some(timestamping) = self.timestamping {
+                    latency = latency.with_timestamping(timestamping);
+                }
+                if let Some(micros) = self.busy_poll {
+                    latency = latency.with_busy_poll(micros);
+                }
+                if let Some(retention) = self.retention {
+                    latency = latency.with_retention(retention);
+                }
+                if let Some(max) = self.max_memory {
+                    // Each flow gets an equal share of the cap
+                    latency = latency.with_max_memory((max / flows as u64) as usize);
+                }
+                if let Some(tos) = self.tos {
+                    latency = latency.with_tos(tos);
+                } else if let Some(dscp) = self.stream_profile.and_then(|p| p.dscp()) {
+                    latency = latency.with_dscp(dscp);
+                }
+                if let Some(reply) = self.stream_profile.and_then(|p| p.reply()) {
+                    latency = latency.with_reply(reply);
+                }
+                if let Some(max) = self.max_bytes {
+                    latency = latency.with_max_bytes(max / flows as u64);
+                }
+
+                let label = match self.streams {
+                    1 => self.display_address(address),
+                    _ => format!("{} stream {}", self.display_address(address), s),
+                };
+                states.push((label, latency.state()));
+                stream_states.push(latency.state());
+                latency_tasks.push(tokio::spawn(async move { latency.run().await }));
+            }
+
+            if self.streams > 1 {
+                let relay = StreamRelay::new(i, stream_states);
+                let action_tx = action_tx.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = relay.run(stream_rx, action_tx).await {
+                        error!("Stream relay failed: {:?}", e);
+                    }
+                });
+            }
+        }
+
+        if let Some(listen) = self.web {
+            let dashboard = Dashboard::new(listen, states.clone(), cancel.child_token());
+            tokio::spawn(async move {
+                if let Err(e) = dashboard.run().await {
+                    error!("Dashboard failed: {:?}", e);
+                }
+            });
+        }
+
+        if self.bidirectional {
+            // The server's probes get a pane after the targets
+            let reverse = self.targets.len();
+            action_tx.send(Action::LatencyTarget(
+                0,
+                "probes from this client".to_string(),
+            ))?;
+            action_tx.send(Action::LatencyTarget(
+                reverse,
+                "probes from the server".to_string(),
+            ))?;
+
+            self.start_reverse(reverse, action_tx, cancel);
+        }
+
+        if let Some((ref destination, interval)) = self.influx {
+            let sink = InfluxSink::new(
+                destination.clone(),
+                states.clone(),
+                interval,
+                cancel.child_token(),
+            )
+            .with_identity(self.identity.clone());
+            self.influx_task = Some(tokio::spawn(async move {
+                if let Err(e) = sink.run().await {
+                    error!("InfluxDB output failed: {:?}", e);
+                }
+            }));
+        }
+
+        if let Some((ref collector, interval)) = self.collector {
+            let uploader = Uploader::new(
+                collector.clone(),
+                states.clone(),
+                interval,
+                cancel.child_token(),
+            )
+            .with_identity(self.identity.clone());
+            self.collector_task = Some(tokio::spawn(async move {
+                if let Err(e) = uploader.run().await {
+                    error!("Collector reports failed: {:?}", e);
+                }
+            }));
+        }
+
+        if let Some(alerts) = self.alerts.clone() {
+            let alerting = Alerting::new(alerts, states.clone(), self.format, cancel.child_token())
+                .with_identity(self.identity.clone());
+            tokio::spawn(async move {
+                if let Err(e) = alerting.run().await {
+                    error!("Alerting failed: {:?}", e);
+                }
+            });
+        }
+
+        if let Some(listen) = self.metrics {
+            let exporter =
+                MetricsExporter::new(listen, Source::Client(states), cancel.child_token())
+                    .with_identity(self.identity.clone());
+            tokio::spawn(async move {
+                if let Err(e) = exporter.run().await {
+                    error!("Metrics exporter failed: {:?}", e);
+                }
+            });
+        }
+
+        Ok(latency_tasks)
+    }
+
+    /// Has the server probe a socket of ours, its results are tagged with `target`.
+    fn start_reverse(
+        &mut self,
+        target: usize,
+        action_tx: &UnboundedSender<Action>,
+        cancel: &CancellationToken,
+    ) {
+        let mut reverse = Bidirectional::new(
+            SocketAddr::new(self.targets[0], self.server_port),
+            self.count,
+            self.period,
+            self.packet_size as u16,
+            action_tx.clone(),
+            cancel.child_token(),
+        )
+        .with_target(target);
+        if let Some(address) = self.bind_address {
+            reverse = reverse.with_bind_address(address);
+        }
+        self.reverse_task = Some(tokio::spawn(reverse.run()));
+    }
+
+    async fn report_latency(&self, latency_tasks: Vec<LatencyTask>) -> Result<Vec<TargetSummary>> {
+        let mut summaries = Vec::with_capacity(self.targets.len());
+        let mut latency_tasks = latency_tasks.into_iter();
+        for (i, &address) in self.targets.iter().enumerate() {
+            let mut states = Vec::with_capacity(self.streams);
+            for latency_task in latency_tasks.by_ref().take(self.streams) {
+                states.push(latency_task.await??);
+            }
+
+            // Print statistics
+            match (self.tunnel_comparison, i) {
+                (true, 0) => info!("Tunnel address: {}", self.display_address(&address)),
+                (true, _) => info!("Underlay address: {}", self.display_address(&address)),
+                _ if self.targets.len() > 1 => {
+                    info!("Address: {}", self.display_address(&address))
+                }
+                _ => {}
+            }
+            if let Some(location) = self.geoip.as_ref().and_then(|g| g.lookup(address)) {
+                info!("Network: {}", location);
+            }
+            if self.bidirectional {
+                info!("Probes from this client:");
+            }
+            let average_latency = match self.streams {
+                1 => {
+                    let state = states[0].lock().await;
+                    self.report_state(&state);
+                    state.average_latency
+                }
+                _ => self.report_streams(&states).await,
+            };
+
+            let (mut sent, mut lost) = (0, 0);
+            for (s, state) in states.iter().enumerate() {
+                let state = state.lock().await;
+                sent += state.sent_packets();
+                lost += state.packet_loss;
+                self.export_latency(i * self.streams + s, &state)?;
+            }
+
+            summaries.push(TargetSummary {
+                address,
+                average_latency,
+                loss: lost as f64 / sent.max(1) as f64,
+            });
+        }
+
+        let exported = self.csv.is_some()
+            || self.json.is_some()
+            || self.hgrm.is_some()
+            || self.chart.is_some();
+        if !exported && self.signing_key.is_some() {
+            warn!("Signing is enabled but there is no export to sign");
+        }
+
+        match self.tunnel_comparison {
+            true => self.report_tunnel_overhead(&summaries[0], &summaries[1]),
+            false if summaries.len() > 1 => self.compare_targets(&summaries),
+            false => {}
+        }
+
+        Ok(summaries)
+    }
+
+    /// Prints what the server measured of the probes it sent in a bidirectional
+    /// test.
+    fn report_reverse(&self, result: Result<Option<ReverseReport>>) {
+        let report = match result {
+            Ok(Some(report)) => report,
+            Ok(None) => {
+                warn!("The server did not report on its probes");
+                return;
+            }
+            Err(e) => {
+                warn!("Bidirectional test failed: {}", e);
+                return;
+            }
+        };
+
+        info!("Probes from the server:");
+        info!("Min latency: {}", self.format.duration(report.min_latency));
+        info!(
+            "Average latency: {}",
+            self.format.duration(report.average_latency)
+        );
+        info!("Max latency: {}", self.format.duration(report.max_latency));
+        if let Some(p) = report.percentiles {
+            info!(
+                "Percentiles: p50 {}, p90 {}, p99 {}, p99.9 {}",
+                self.format.duration(p.p50),
+                self.format.duration(p.p90),
+                self.format.duration(p.p99),
+                self.format.duration(p.p999)
+            );
+        }
+        info!(
+            "Packet loss: {} ({}/{})",
+            self.format.percent(report.loss()),
+            report.sent - report.received,
+            report.sent
+        );
+        if !report.finished {
+            warn!("The server was still probing when the run ended");
+        }
+    }
+
+    /// Prints the results of a single engine.
+    fn report_state(&self, state: &State) {
+        info!("Min latency: {}", self.format.duration(state.min_latency));
+        info!(
+            "Average latency: {}",
+            self.format.duration(state.average_latency)
+        );
+        info!("Max latency: {}", self.format.duration(state.max_latency));
+        if let Some(p) = state.percentiles() {
+            info!(
+                "Percentiles: p50 {}, p90 {}, p99 {}, p99.9 {}",
+                self.format.duration(p.p50),
+                self.format.duration(p.p90),
+                self.format.duration(p.p99),
+                self.format.duration(p.p999)
+            );
+        }
+        if let Some(handshake) = state.handshake {
+            info!("QUIC handshake: {}", self.format.duration(handshake));
+        }
+        if let Some(kernel) = state.kernel_rtt() {
+            let (sign, difference) = match state.average_latency.checked_sub(kernel.rtt) {
+                Some(difference) => ("+", difference),
+                None => ("-", kernel.rtt - state.average_latency),
+            };
+            info!(
+                "Kernel RTT (TCP_INFO): avg {}, rttvar {}, application RTT avg {} ({}{}), {} retransmit(s), delivery rate {}",
+                self.format.duration(kernel.rtt),
+                self.format.duration(kernel.rttvar),
+                self.format.duration(state.average_latency),
+                sign,
+                self.format.duration(difference),
+                kernel.retransmits,
+                format_bitrate(kernel.delivery_rate as f64)
+            );
+        }
+        #[cfg(feature = "udp-no-checksum")]
+        if self.no_udp_checksum {
+            warn!("Probes were sent without UDP checksums, damaged ones count as received");
+        }
+        let (outages, downtime) = state.outages();
+        if outages > 0 {
+            warn!(
+                "Target down {} time(s), {} in total",
+                outages,
+                self.format.duration(downtime)
+            );
+        }
+        info!(
+            "Packet loss: {} ({}/{})",
+            self.format
+                .percent(state.packet_loss as f64 / state.sent_packets() as f64),
+            state.packet_loss,
+            state.sent_packets()
+        );
+        for (error, probes) in state.icmp_error_counts() {
+            warn!(
+                "  {} lost probe(s) rejected: {}",
+                probes,
+                self.display_icmp_error(&error)
+            );
+        }
+        info!(
+            "Data sent: {} ({} on the wire), received: {} ({} on the wire)",
+            format_bytes(state.traffic_sent.payload),
+            format_bytes(state.traffic_sent.wire),
+            format_bytes(state.traffic_received.payload),
+            format_bytes(state.traffic_received.wire)
+        );
+        let rate = state.send_rate();
+        if let (Some(pps), Some(bps)) = (rate.packets_per_second(), rate.bits_per_second()) {
+            let text = format!(
+                "Send rate: {:.1} pkt/s of {:.1} configured, {} offered",
+                pps,
+                rate.expected_packets_per_second(),
+                format_bitrate(bps)
+            );
+            match rate.on_schedule() {
+                true => info!("{}", text),
+                false => warn!("{}, the sender fell behind the interval", text),
+            }
+        }
+        if state.skipped_packets > 0 {
+            info!("Skipped send slots: {}", state.skipped_packets);
+        }
+        if state.invalid_packets > 0 {
+            info!(
+                "Invalidated by system suspend: {} packet(s)",
+                state.invalid_packets
+            );
+        }
+        if state.warmup_packets > 0 {
+            info!(
+                "Warm-up: {} packet(s), left out of the statistics",
+                state.warmup_packets
+            );
+        }
+        if state.truncated_packets > 0 {
+            warn!(
+                "Truncated echoes: {}, larger than the probes sent",
+                state.truncated_packets
+            );
+        }
+        if state.corrupted_packets > 0 {
+            warn!(
+                "Corrupted echoes: {}, payload differs from the probe sent",
+                state.corrupted_packets
+            );
+        }
+        if state.duplicate_packets > 0 {
+            warn!(
+                "Duplicate echoes: {}, of probes already answered or never sent",
+                state.duplicate_packets
+            );
+        }
+        self.report_kernel_timestamps(state.kernel_timestamped, state.received_packets);
+
+        if self.one_way_delay {
+            match state.one_way_delay_ranges() {
+                Some((up, down)) => {
+                    for (direction, range) in [("Upstream", up), ("Downstream", down)] {
+                        info!(
+                            "{} delay: min {}, avg {}, max {}",
+                            direction,
+                            self.format.signed_duration(range.min),
+                            self.format.signed_duration(range.average),
+                            self.format.signed_duration(range.max)
+                        );
+                    }
+                    match state.clock_offset {
+                        Some(offset) => info!(
+                            "  Corrected for a clock offset of {} ± {}, assuming a symmetric path",
+                            self.format.signed_duration(offset.offset),
+                            self.format.duration(offset.rtt / 2)
+                        ),
+                        None => info!(
+                            "  One-way delays assume the client's and server's clocks are in sync"
+                        ),
+                    }
+                }
+                None => warn!(
+                    "The server did not timestamp its echoes, it may be too old for one-way delays"
+                ),
+            }
+        }
+
+        if self.track_clock {
+            match state.clock_drift() {
+                Some(drift) => {
+                    info!(
+                        "Clock drift: {:+.2} ± {:.2} ppm, {} over the run",
+                        drift.ppm,
+                        drift.error,
+                        self.format.duration(drift.accumulated)
+                    );
+                    if drift.invalidates_one_way_delay(state.min_latency) {
+                        warn!(
+                            "The clocks drifted apart by {}, one-way delays of this run are unreliable",
+                            self.format.duration(drift.accumulated)
+                        );
+                    }
+                }
+                None => warn!("Too few answers from the server to estimate clock drift"),
+            }
+        }
+
+        if !state.bursts.is_empty() {
+            info!(
+                "High-resolution captures: {} ({} probes)",
+                state.bursts.len(),
+                state.bursts.iter().map(|b| b.len()).sum::<usize>()
+            );
+        }
+
+        if state.phases.len() > 1 {
+            report_phase_statistics(&state.phase_statistics(), &self.format);
+        }
+
+        if state.sources.len() > 1 {
+            report_source_statistics(&state.source_statistics(), &self.format);
+        }
+
+        if self.payload == Payload::Alternate {
+            report_payload_comparison(&state.payload_comparison(), &self.format);
+        }
+
+        let call_quality = self
+            .stream_profile
+            .and_then(|profile| CallQuality::estimate(state, profile, self.jitter_buffer));
+        if let Some(ref quality) = call_quality {
+            report_call_quality(quality, self.jitter_buffer, &self.format);
+        }
+        let game_quality = match self.stream_profile {
+            Some(StreamProfile::Game) => GameQuality::estimate(state),
+            _ => None,
+        };
+        if let Some(ref quality) = game_quality {
+            report_game_quality(quality, &self.format);
+        }
+
+        for (at, event) in state.events.iter() {
+            info!("Event at {:.1?}: {}", at, self.display_event(event));
+        }
+
+        let recommendations = recommend::recommend(&recommend::Run {
+            state,
+            probe_size: self.packet_size,
+            payload: self.payload,
+            format: self.format,
+        });
+        if !recommendations.is_empty() {
+            info!("Recommendations:");
+            for recommendation in recommendations {
+                info!("  {}: {}", recommendation.finding, recommendation.action);
+            }
+        }
+    }
+
+    /// Prints the results of all streams to a target taken together, then each
+    /// stream by its source port. Returns the average latency over all streams.
+    async fn report_streams(&self, states: &[Arc<Mutex<State>>]) -> Duration {
+        let mut guards = Vec::with_capacity(states.len());
+        for state in states {
+            guards.push(state.lock().await);
+        }
+        let states: Vec<&State> = guards.iter().map(|s| &**s).collect();
+        let summary = StreamsSummary::of(&states);
+
+        info!("Streams: {}", states.len());
+        info!("Min latency: {}", self.format.duration(summary.min_latency));
+        info!(
+            "Average latency: {}",
+            self.format.duration(summary.average_latency)
+        );
+        info!("Max latency: {}", self.format.duration(summary.max_latency));
+        if let Some(p) = summary.percentiles() {
+            info!(
+                "Percentiles: p50 {}, p90 {}, p99 {}, p99.9 {}",
+                self.format.duration(p.p50),
+                self.format.duration(p.p90),
+                self.format.duration(p.p99),
+                self.format.duration(p.p999)
+            );
+        }
+        info!(
+            "Packet loss: {} ({}/{})",
+            self.format
+                .percent(summary.lost as f64 / summary.sent.max(1) as f64),
+            summary.lost,
+            summary.sent
+        );
+        info!(
+            "Data sent: {} ({} on the wire), received: {} ({} on the wire)",
+            format_bytes(summary.traffic_sent.payload),
+            format_bytes(summary.traffic_sent.wire),
+            format_bytes(summary.traffic_received.payload),
+            format_bytes(summary.traffic_received.wire)
+        );
+        let truncated: u32 = states.iter().map(|s| s.truncated_packets).sum();
+        if truncated > 0 {
+            warn!(
+                "Truncated echoes: {}, larger than the probes sent",
+                truncated
+            );
+        }
+        let corrupted: u32 = states.iter().map(|s| s.corrupted_packets).sum();
+        if corrupted > 0 {
+            warn!(
+                "Corrupted echoes: {}, payload differs from the probe sent",
+                corrupted
+            );
+        }
+        self.report_kernel_timestamps(
+            states.iter().map(|s| s.kernel_timestamped).sum(),
+            states.iter().map(|s| s.received_packets).sum(),
+        );
+
+        report_source_statistics(&summary.streams, &self.format);
+
+        for (s, state) in states.iter().enumerate() {
+            for (at, event) in state.events.iter() {
+                info!(
+                    "Stream {} event at {:.1?}: {}",
+                    s,
+                    at,
+                    self.display_event(event)
+                );
+            }
+        }
+
+        summary.average_latency
+    }
+
+    /// Tells how many echoes were measured with kernel timestamps, the rest fell
+    /// back to the client's own clock.
+    fn report_kernel_timestamps(&self, timestamped: u32, received: u32) {
+        if self.timestamping.is_none() || received == 0 {
+            return;
+        }
+        match timestamped {
+            0 => {
+                warn!("The kernel stamped none of the echoes, latencies were measured in userspace")
+            }
+            _ => info!("Kernel timestamps: {} of {} echoes", timestamped, received),
+        }
+    }
+
+    /// Writes the enabled exports of one flow.
+    fn export_latency(&self, flow: usize, state: &State) -> Result<()> {
+        let call_quality = self
+            .stream_profile
+            .and_then(|profile| CallQuality::estimate(state, profile, self.jitter_buffer));
+        let game_quality = match self.stream_profile {
+            Some(StreamProfile::Game) => GameQuality::estimate(state),
+            _ => None,
+        };
+
+        if let Some(ref csv) = self.csv {
+            let path = self.target_export_path(csv, flow);
+            self.write_csv(&path, flow, state)?;
+            self.sign_export(&path)?;
+        }
+        if let Some(ref json) = self.json {
+            let path = self.target_export_path(json, flow);
+            let events = state
+                .events
+                .iter()
+                .map(|(at, event)| (*at, self.display_event(event)))
+                .collect();
+            let mut results = LatencyResults::new(
+                self.metadata(flow),
+                self.display_address(&self.targets[flow / self.streams]),
+                state,
+                events,
+            );
+            if let Some(ref quality) = call_quality {
+                results = results.with_call_quality(quality);
+            }
+            if let Some(ref quality) = game_quality {
+                results = results.with_game_quality(quality);
+            }
+            results = results.with_icmp_errors(state.icmp_error_counts().into_iter().map(
+                |(error, probes)| (error, error.from.map(|a| self.display_address(&a)), probes),
+            ));
+            write_json(&path, &results)?;
+            self.sign_export(&path)?;
+        }
+        if let Some(ref hgrm) = self.hgrm {
+            let path = self.target_export_path(hgrm, flow);
+            write_hgrm(&path, state)?;
+            self.sign_export(&path)?;
+        }
+        if let Some(ref chart) = self.chart {
+            let path = self.target_export_path(chart, flow);
+            let title = format!(
+                "Latency to {}",
+                self.display_address(&self.targets[flow / self.streams])
+            );
+            chart::render(&path, &title, state)?;
+            self.sign_export(&path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Port the probes go to, the QUIC listener's over QUIC.
+    fn probe_port(&self) -> u16 {
+        match (self.protocol, self.quic_port) {
+            (Protocol::Quic, Some(port)) => port,
+            _ => self.server_port,
+        }
+    }
+
+    fn start_bandwidth(
+        &self,
+        duration: Duration,
+        action_tx: &UnboundedSender<Action>,
+        cancel: &CancellationToken,
+    ) -> JoinHandle<Result<BandwidthState>> {
+        if let Some(rate) = self.bandwidth_rate {
+            let mut bandwidth = UdpBandwidth::new(
+                self.targets[0],
+                self.server_port,
+                duration,
+                rate,
+                action_tx.clone(),
+                cancel.child_token(),
+            );
+            if let Some(max) = self.max_bytes {
+                bandwidth = bandwidth.with_max_bytes(max);
+            }
+            if let Some(size) = self.batch {
+                bandwidth = bandwidth.with_batch(size);
+            }
+            if let Some(steps) = self.ramp_steps {
+                bandwidth = bandwidth.with_ramp(steps);
+            }
+            if let Some(link_type) = self.link_type {
+                bandwidth = bandwidth.with_min_step(link_type.ramp_step());
+            }
+            if let Some(address) = self.bind_address {
+                bandwidth = bandwidth.with_bind_address(address);
+            }
+            bandwidth = bandwidth.with_payload(self.payload, self.seed);
+
+            return tokio::spawn(async move { bandwidth.run().await });
+        }
+
+        if self.protocol == Protocol::Quic {
+            let mut bandwidth = QuicBandwidth::new(
+                self.targets[0],
+                self.probe_port(),
+                duration,
+                action_tx.clone(),
+                cancel.child_token(),
+            );
+            if let Some(max) = self.max_bytes {
+                bandwidth = bandwidth.with_max_bytes(max);
+            }
+            if let Some(address) = self.bind_address {
+                bandwidth = bandwidth.with_bind_address(address);
+            }
+            bandwidth = bandwidth.with_payload(self.payload, self.seed);
+
+            return tokio::spawn(async move { bandwidth.run().await });
+        }
+
+        let mut bandwidth = TcpBandwidth::new(
+            self.targets[0],
+            self.server_port,
+            duration,
+            action_tx.clone(),
+            cancel.child_token(),
+        );
+
+        if let Some(ref congestion) = self.congestion {
+            bandwidth = bandwidth.with_congestion(congestion.clone());
+        }
+        if let Some(max) = self.max_bytes {
+            bandwidth = bandwidth.with_max_bytes(max);
+        }
+        if let Some(address) = self.bind_address {
+            bandwidth = bandwidth.with_bind_address(address);
+        }
+        bandwidth = bandwidth.with_payload(self.payload, self.seed);
+
+        tokio::spawn(async move { bandwidth.run().await })
+    }
+
+    /// Returns the throughput in bits per second.
+    async fn report_bandwidth(&self, task: JoinHandle<Result<BandwidthState>>) -> Result<f64> {
+        let state = task.await??;
+
+        if let Some(ref congestion) = state.congestion {
+            info!("Congestion control: {}", congestion);
+        }
+        info!(
+            "Transferred {} bytes in {:.2?}",
+            state.total_bytes, state.elapsed
+        );
+        info!("Throughput: {}", format_bitrate(state.bits_per_second()));
+        match state.received {
+            Some(received) => {
+                if let Some(rate) = state.target_rate {
+                    info!("Target rate: {}", format_bitrate(rate as f64));
+                }
+                info!(
+                    "Datagrams: {} sent, {} received, {} lost ({}), {} reordered",
+                    state.datagrams_sent,
+                    received.packets,
+                    received.lost(),
+                    self.format
+                        .percent(received.lost() as f64 / received.expected.max(1) as f64),
+                    received.reordered
+                );
+                if let Some(limit) = RateLimit::detect(&state.samples) {
+                    info!("Rate limit: {}", limit);
+                }
+            }
+            None => info!("Retransmissions: {}", state.retransmits),
+        }
+
+        if self.payload == Payload::Alternate {
+            report_bandwidth_comparison(&state.payload_comparison(), &self.format);
+        }
+
+        if let Some(ref csv) = self.csv {
+            self.write_bandwidth_csv(csv, &state.samples)?;
+            self.sign_export(csv)?;
+        }
+        if let Some(ref json) = self.json {
+            write_json(json, &BandwidthResults::new(self.metadata(0), &state))?;
+            self.sign_export(json)?;
+        }
+        if self.csv.is_none() && self.json.is_none() && self.signing_key.is_some() {
+            warn!("Signing is enabled but there is no export to sign");
+        }
+
+        Ok(state.bits_per_second())
+    }
+
+    /// Reacts to the actions that steer the run, true when the run should end.
+    fn handle_action(&mut self, action: &Action) -> bool {
+        match action {
+            Action::Quit => true,
+            Action::LatencyConverged(_) => {
+                self.converged += 1;
+                self.converged == self.targets.len()
+            }
+            Action::MarkPhase(name) => {
+                self.phase_count += 1;
+                let name = name
+                    .clone()
+                    .unwrap_or_else(|| self.phase_name(self.phase_count));
+                // Fails when no engine listens, as in bandwidth tests
+                let _ = self.phase_marks.send(name);
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Host as it should appear in summaries and exports.
+    fn display_host(&self) -> String {
+        match self.anonymizer {
+            Some(ref anonymizer) => anonymizer.hostname(&self.host),
+            None => self.host.clone(),
+        }
+    }
+
+    /// Address as it should appear in summaries and exports.
+    fn display_address(&self, address: &IpAddr) -> String {
+        match self.anonymizer {
+            Some(ref anonymizer) => anonymizer.address(address),
+            None => address.to_string(),
+        }
+    }
+
+    fn display_icmp_error(&self, error: &IcmpError) -> String {
+        match self.anonymizer {
+            Some(ref anonymizer) => anonymizer.icmp_error(error),
+            None => error.to_string(),
+        }
+    }
+
+    fn display_event(&self, event: &Event) -> String {
+        match self.anonymizer {
+            Some(ref anonymizer) => anonymizer.event(event),
+            None => event.to_string(),
+        }
+    }
+
+    fn sign_export(&self, path: &Path) -> Result<()> {
+        if let Some(ref key) = self.signing_key {
+            let signature = signing::sign_report(path, key)?;
+            info!("Signature written to {}", signature.display());
+        }
+
+        Ok(())
+    }
+
+    /// Export path of a flow, numbered by target and stream when there are several.
+    fn target_export_path(&self, path: &Path, flow: usize) -> PathBuf {
+        if self.targets.len() * self.streams == 1 {
+            return path.to_path_buf();
+        }
+
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let name = match path.extension() {
+            Some(ext) => format!("{}.{}.{}", stem, flow, ext.to_string_lossy()),
+            None => format!("{}.{}", stem, flow),
+        };
+
+        path.with_file_name(name)
+    }
+
+    fn compare_targets(&self, summaries: &[TargetSummary]) {
+        let fastest = summaries
+            .iter()
+            .map(|s| s.average_latency)
+            .min()
+            .unwrap_or_default();
+
+        info!("Address comparison (average latency):");
+        for summary in summaries {
+            info!(
+                "  {:<40} {:>12} (+{})",
+                self.display_address(&summary.address),
+                self.format.duration(summary.average_latency),
+                self.format.duration(summary.average_latency - fastest)
+            );
+        }
+    }
+
+    /// Prints the latency and loss the tunnel adds to its underlay, both probed
+    /// over the same time.
+    fn report_tunnel_overhead(&self, tunnel: &TargetSummary, underlay: &TargetSummary) {
+        let delta =
+            tunnel.average_latency.as_nanos() as i64 - underlay.average_latency.as_nanos() as i64;
+        let delta = match delta < 0 {
+            true => self.format.signed_duration(delta),
+            false => format!("+{}", self.format.signed_duration(delta)),
+        };
+        info!(
+            "Tunnel overhead: latency {} ({} through {}, {} to {}), loss {:+.2} pp",
+            delta,
+            self.format.duration(tunnel.average_latency),
+            self.display_address(&tunnel.address),
+            self.format.duration(underlay.average_latency),
+            self.display_address(&underlay.address),
+            (tunnel.loss - underlay.loss) * 100.0
+        );
+    }
+
+    /// Flow label of the probes of a flow, only IPv6 carries one.
+    fn stream_flow_label(&self, flow: usize) -> Option<u32> {
+        let label = self.flow_label?;
+        if !self.targets[flow / self.streams].is_ipv6() {
+            return None;
+        }
+        match self.vary_flow_label {
+            // Labels wrap around without ever becoming 0, which means unlabeled
+            true => Some((label - 1 + (flow % self.streams) as u32) % MAX_FLOW_LABEL + 1),
+            false => Some(label),
+        }
+    }
+
+    /// Metadata of the exports of a flow, the `flow`th stream over all targets.
+    fn metadata(&self, flow: usize) -> RunMetadata {
+        let target = flow / self.streams;
+        let mut parameters = vec![
+            ("host", self.display_host()),
+            ("target", self.display_address(&self.targets[target])),
+            ("server_port", self.server_port.to_string()),
+        ];
+        if self.streams > 1 {
+            parameters.push(("stream", (flow % self.streams).to_string()));
+        }
+        if self.tunnel_comparison {
+            let role = match target {
+                0 => "tunnel",
+                _ => "underlay",
+            };
+            parameters.push(("tunnel_role", role.to_string()));
+        }
+        if let Some(address) = self.bind_address {
+            parameters.push(("bind", self.display_address(&address)));
+        }
+        if let Some(interval) = self.repeat_every {
+            parameters.push(("repeat_every", format!("{:?}", interval)));
+            parameters.push(("repetition", (self.history.len() + 1).to_string()));
+        }
+
+        let location = self
+            .geoip
+            .as_ref()
+            .and_then(|g| g.lookup(self.targets[target]));
+        if let Some(location) = location {
+            if let Some(asn) = location.asn {
+                parameters.push(("asn", asn.to_string()));
+            }
+            if let Some(organization) = location.organization {
+                parameters.push(("as_organization", organization));
+            }
+            if let Some(country) = location.country {
+                parameters.push(("country", country));
+            }
+        }
+
+        match self.bandwidth {
+            Some(duration) => {
+                parameters.push(("mode", "bandwidth".to_string()));
+                parameters.push(("duration", format!("{:?}", duration)));
+                let payload = self
+                    .payload
+                    .to_possible_value()
+                    .map_or_else(String::new, |v| v.get_name().to_string());
+                parameters.push(("payload", payload));
+                parameters.push(("seed", self.seed.to_string()));
+                match self.bandwidth_rate {
+                    Some(rate) => {
+                        parameters.push(("protocol", "udp".to_string()));
+                        parameters.push(("rate", rate.to_string()));
+                        if let Some(steps) = self.ramp_steps {
+                            parameters.push(("ramp_steps", steps.to_string()));
+                        }
+                        if let Some(size) = self.batch {
+                            parameters.push(("batch", size.to_string()));
+                        }
+                    }
+                    None if self.protocol == Protocol::Quic => {
+                        parameters.push(("protocol", "quic".to_string()));
+                        parameters.push(("quic_port", self.probe_port().to_string()));
+                    }
+                    None => {
+                        parameters.push(("protocol", "tcp".to_string()));
+                        parameters.push((
+                            "congestion",
+                            self.congestion
+                                .clone()
+                                .unwrap_or_else(|| "default".to_string()),
+                        ));
+                    }
+                }
+            }
+            None => {
+                let catch_up = self
+                    .catch_up
+                    .to_possible_value()
+                    .map_or_else(String::new, |v| v.get_name().to_string());
+                let payload = self
+                    .payload
+                    .to_possible_value()
+                    .map_or_else(String::new, |v| v.get_name().to_string());
+                let protocol = self
+                    .protocol
+                    .to_possible_value()
+                    .map_or_else(String::new, |v| v.get_name().to_string());
+
+                parameters.extend([
+                    ("mode", "latency".to_string()),
+                    ("protocol", protocol),
+                    ("client_port", self.client_port.to_string()),
+                    ("source_ports", self.source_ports.to_string()),
+                    ("streams", self.streams.to_string()),
+                    ("packet_size", self.packet_size.to_string()),
+                    ("count", self.count.to_string()),
+                    ("interval", format!("{:?}", self.period)),
+                    ("catch_up", catch_up),
+                    ("payload", payload),
+                    ("seed", self.seed.to_string()),
+                    ("suspend_threshold", format!("{:?}", self.suspend_threshold)),
+                    ("track_route", self.track_route.to_string()),
+                    ("clock_drift", self.track_clock.to_string()),
+                    ("one_way_delay", self.one_way_delay.to_string()),
+                    ("trust_clocks", self.trust_clocks.to_string()),
+                ]);
+                if let Some(condition) = self.stop_condition {
+                    parameters.push(("stop_when", condition.to_string()));
+                }
+                if let Some(profile) = self.stream_profile {
+                    let name = profile
+                        .to_possible_value()
+                        .map_or_else(String::new, |v| v.get_name().to_string());
+                    parameters.push(("profile", name));
+                    if let Some(dscp) = profile.dscp().filter(|_| self.tos.is_none()) {
+                        parameters.push(("dscp", dscp.to_string()));
+                    }
+                    if let Some(reply) = profile.reply() {
+                        parameters.push(("reply_size", reply.size.to_string()));
+                        parameters.push(("reply_count", reply.count.to_string()));
+                    }
+                    if profile != StreamProfile::Game {
+                        parameters.push(("jitter_buffer", format!("{:?}", self.jitter_buffer)));
+                    }
+                }
+                if let Some(tos) = self.tos {
+                    parameters.push(("dscp", (tos >> 2).to_string()));
+                    parameters.push(("tos", format!("{:#04x}", tos)));
+                }
+                if let Some(ttl) = self.ttl {
+                    parameters.push(("ttl", ttl.to_string()));
+                }
+                if let Some(label) = self.stream_flow_label(flow) {
+                    parameters.push(("flow_label", format!("{:#07x}", label)));
+                }
+                if let Some(coverage) = self.checksum_coverage {
+                    parameters.push(("checksum_coverage", coverage.to_string()));
+                }
+                if let Some(ref tag) = self.probe_tag {
+                    parameters.push(("probe_tag", tag.clone()));
+                }
+                if let Some(timestamping) = self.timestamping {
+                    let name = timestamping
+                        .to_possible_value()
+                        .map_or_else(String::new, |v| v.get_name().to_string());
+                    parameters.push(("timestamping", name));
+                }
+                if let Some(micros) = self.busy_poll {
+                    parameters.push(("busy_poll", micros.to_string()));
+                }
+                if let Some(retention) = self.retention {
+                    parameters.push(("retain", retention.to_string()));
+                }
+                if let Some(max) = self.max_memory {
+                    parameters.push(("max_memory", max.to_string()));
+                }
+                if let Some(port) = self.quic_port {
+                    parameters.push(("quic_port", port.to_string()));
+                }
+                if let Some(ref proxy) = self.proxy {
+                    let proxy = match self.anonymizer {
+                        Some(ref anonymizer) => anonymizer.hostname(proxy),
+                        None => proxy.clone(),
+                    };
+                    parameters.push(("proxy", proxy));
+                }
+                #[cfg(feature = "udp-no-checksum")]
+                if self.no_udp_checksum {
+                    parameters.push(("udp_checksum", "disabled".to_string()));
+                }
+                if let Some(ref schedule) = self.replay {
+                    parameters.push(("replay_schedule", schedule.path.display().to_string()));
+                    parameters.push(("replay_schedule_sha256", schedule.digest.clone()));
+                    parameters.push(("replay_schedule_started", schedule.started.clone()));
+                }
+                if !self.source_addresses.is_empty() {
+                    let addresses: Vec<String> = self
+                        .source_addresses
+                        .iter()
+                        .map(|a| self.display_address(a))
+                        .collect();
+                    parameters.push(("source_addresses", addresses.join(",")));
+                }
+                if let Some(size) = self.buffers.receive {
+                    parameters.push(("rcvbuf", size.to_string()));
+                }
+                if let Some(size) = self.buffers.send {
+                    parameters.push(("sndbuf", size.to_string()));
+                }
+                if let Some(policy) = self.metered {
+                    let policy = policy
+                        .to_possible_value()
+                        .map_or_else(String::new, |v| v.get_name().to_string());
+                    parameters.push(("on_metered", policy));
+                }
+                if !self.quiet_hours.is_empty() {
+                    let windows: Vec<_> = self.quiet_hours.iter().map(|w| w.to_string()).collect();
+                    parameters.push(("quiet_hours", windows.join(",")));
+                    let policy = self
+                        .quiet_policy
+                        .to_possible_value()
+                        .map_or_else(String::new, |v| v.get_name().to_string());
+                    parameters.push(("quiet_policy", policy));
+                }
+                if let Some(warmup) = self.warmup {
+                    parameters.push(("warmup", warmup.to_string()));
+                }
+                if let Some(after) = self.down_after {
+                    parameters.push(("down_after", format!("{:?}", after)));
+                    let policy = self
+                        .on_down
+                        .to_possible_value()
+                        .map_or_else(String::new, |v| v.get_name().to_string());
+                    parameters.push(("on_down", policy));
+                }
+                if !self.phase_names.is_empty() {
+                    parameters.push(("phases", self.phase_names.join(",")));
+                }
+                if self.track_route {
+                    let policy = self
+                        .tunnel_change
+                        .to_possible_value()
+                        .map_or_else(String::new, |v| v.get_name().to_string());
+                    parameters.push(("on_tunnel_change", policy));
+                }
+
+                if let Some(burst) = self.burst_capture {
+                    if let Some(threshold) = burst.latency_threshold {
+                        parameters.push(("burst_threshold", format!("{:?}", threshold)));
+                    }
+                    if let Some(lost) = burst.loss_threshold {
+                        parameters.push(("burst_loss", lost.to_string()));
+                    }
+                    parameters.push(("burst_interval", format!("{:?}", burst.interval)));
+                    parameters.push(("burst_duration", format!("{:?}", burst.duration)));
+                }
+            }
+        }
+        if let Some(link_type) = self.link_type {
+            let name = link_type
+                .to_possible_value()
+                .map_or_else(String::new, |v| v.get_name().to_string());
+            parameters.push(("link_type", name));
+        }
+        if let Some(k) = self.adaptive_loss_timeout {
+            parameters.push(("adaptive_loss_timeout", k.to_string()));
+        }
+        if let Some(max) = self.max_bytes {
+            parameters.push(("max_bytes", max.to_string()));
+        }
+        if let Some(handshake) = self.handshake {
+            parameters.push(("start_delay", format!("{:?}", handshake.delay)));
+            parameters.push(("handshake_attempts", handshake.attempts.to_string()));
+        }
+        parameters.push(("anonymized", self.anonymizer.is_some().to_string()));
+
+        let hostname = metadata::hostname().map(|name| match self.anonymizer {
+            Some(ref anonymizer) => anonymizer.hostname(&name),
+            None => name,
+        });
+
+        RunMetadata::new(
+            hostname,
+            self.started_at,
+            self.finished_at,
+            parameters
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect(),
+        )
+        .with_identity(&self.identity)
+    }
+
+    /// Opens an export for writing, starting with the run metadata.
+    fn create_export(
+        &self,
+        path: &Path,
+        metadata: &RunMetadata,
+    ) -> Result<Writer<BufWriter<File>>> {
+        let mut file = BufWriter::new(File::create(path)?);
+        metadata.write_comments(&mut file)?;
+
+        Ok(Writer::from_writer(file))
+    }
+
+    fn write_bandwidth_csv(&self, csv: &Path, samples: &[BandwidthSample]) -> Result<()> {
+        let mut wtr = self.create_export(csv, &self.metadata(0))?;
+        wtr.write_record([
+            "time",
+            "bytes",
+            "bits_per_second",
+            "retransmits",
+            "lost",
+            "cwnd",
+            "rtt",
+            "rttvar",
+            "delivery_rate",
+        ])?;
+
+        for sample in samples {
+            wtr.write_record([
+                &format!("{}", sample.at.as_micros()),
+                &format!("{}", sample.bytes),
+                &format!("{}", sample.bits_per_second),
+                &format!("{}", sample.retransmits),
+                &sample.lost.map_or_else(String::new, |l| l.to_string()),
+                &format!("{}", sample.cwnd),
+                &format!("{}", sample.rtt.as_micros()),
+                &format!("{}", sample.rttvar.as_micros()),
+                &format!("{}", sample.delivery_rate),
+            ])?;
+        }
+
+        wtr.flush()?;
+
+        Ok(())
+    }
+
+    fn write_csv(&self, csv: &Path, flow: usize, state: &State) -> Result<()> {
+        let mut metadata = self.metadata(flow);
+        metadata.measured = measured_send_rate(&state.send_rate());
+        let mut wtr = self.create_export(csv, &metadata)?;
+        wtr.write_record([
+            "packet",
+            "sent",
+            "received",
+            "latency",
+            "status",
+            "burst",
+            "phase",
+            "clock_offset",
+            "clock_drift",
+            "icmp_error",
+            "upstream",
+            "downstream",
+            "tag",
+            "loss_timeout",
+            "kernel_rtt",
+            "kernel_rttvar",
+            "kernel_retransmits",
+            "delivery_rate",
+        ])?;
+
+        let mut clock = state.clock_samples.iter().peekable();
+        let mut clock_columns = [String::new(), String::new()];
+        let mut tcp_info = state.tcp_info.iter().peekable();
+        let mut tcp_info_columns = [String::new(), String::new(), String::new(), String::new()];
+        for (i, packet) in state.packets.numbered() {
+            let phase = state.phase_of(i).unwrap_or_default();
+            // Latest reading of the server's clock before the packet was sent
+            let sent = match packet {
+                PacketStatus::Skipped(s)
+                | PacketStatus::Invalid(s)
+                | PacketStatus::Warmup(s)
+                | PacketStatus::Sent(s) => *s,
+                PacketStatus::Received { start, .. } => *start,
+            };
+            while let Some(sample) = clock.next_if(|s| s.at <= sent) {
+                clock_columns = [
+                    (sample.offset / 1000).to_string(),
+                    sample
+                        .drift
+                        .map_or_else(String::new, |d| format!("{:.3}", d)),
+                ];
+            }
+            let [clock_offset, clock_drift] = &clock_columns;
+            // Latest TCP_INFO of the connection, with --protocol tcp
+            while let Some(sample) = tcp_info.next_if(|s| s.at <= sent) {
+                tcp_info_columns = [
+                    sample.rtt.as_micros().to_string(),
+                    sample.rttvar.as_micros().to_string(),
+                    sample.retransmits.to_string(),
+                    sample.delivery_rate.to_string(),
+                ];
+            }
+            let [kernel_rtt, kernel_rttvar, kernel_retransmits, delivery_rate] = &tcp_info_columns;
+            // Index of the high-resolution capture the packet belongs to
+            let burst = state
+                .bursts
+                .iter()
+                .position(|b| b.contains(&i))
+                .map_or_else(String::new, |b| b.to_string());
+
+            let tag = state.tag(i).unwrap_or_default();
+            let loss_timeout = state.grace(i).as_micros().to_string();
+            match packet {
+                PacketStatus::Skipped(s) => {
+                    wtr.write_record([
+                        &format!("{}", i),
+                        &format!("{}", s.as_micros()),
+                        "",
+                        "",
+                        "skipped",
+                        &burst,
+                        phase,
+                        clock_offset,
+                        clock_drift,
+                        "",
+                        "",
+                        "",
+                        &tag,
+                        "",
+                        kernel_rtt,
+                        kernel_rttvar,
+                        kernel_retransmits,
+                        delivery_rate,
+                    ])?;
+                }
+                PacketStatus::Invalid(s) | PacketStatus::Warmup(s) => {
+                    let status = match packet {
+                        PacketStatus::Warmup(_) => "warmup",
+                        _ => "invalid",
+                    };
+                    wtr.write_record([
+                        &format!("{}", i),
+                        &format!("{}", s.as_micros()),
+                        "",
+                        "",
+                        status,
+                        &burst,
+                        phase,
+                        clock_offset,
+                        clock_drift,
+                        "",
+                        "",
+                        "",
+                        &tag,
+                        &loss_timeout,
+                        kernel_rtt,
+                        kernel_rttvar,
+                        kernel_retransmits,
+                        delivery_rate,
+                    ])?;
+                }
+                PacketStatus::Sent(s) => {
+                    wtr.write_record([
+                        &format!("{}", i),
+                        &format!("{}", s.as_micros()),
+                        "",
+                        "",
+                        "lost",
+                        &burst,
+                        phase,
+                        clock_offset,
+                        clock_drift,
+                        &state
+                            .icmp_error(i)
+                            .map_or_else(String::new, |e| e.kind.to_string()),
+                        "",
+                        "",
+                        &tag,
+                        &loss_timeout,
+                        kernel_rtt,
+                        kernel_rttvar,
+                        kernel_retransmits,
+                        delivery_rate,
+                    ])?;
+                }
+                PacketStatus::Received {
+                    start,
+                    stop,
+                    latency,
+                } => {
+                    let (upstream, downstream) =
+                        state
+                            .one_way_delays
+                            .get(&i)
+                            .map_or_else(Default::default, |d| {
+                                (
+                                    (d.upstream / 1000).to_string(),
+                                    (d.downstream / 1000).to_string(),
+                                )
+                            });
+                    let status = match state.corrupted.contains(&i) {
+                        true => "corrupted",
+                        false => "received",
+                    };
+                    wtr.write_record([
+                        &format!("{}", i),
+                        &format!("{}", start.as_micros()),
+                        &format!("{}", stop.as_micros()),
+                        &format!("{}", latency.as_micros()),
+                        status,
+                        &burst,
+                        phase,
+                        clock_offset,
+                        clock_drift,
+                        "",
+                        &upstream,
+                        &downstream,
+                        &tag,
+                        &loss_timeout,
+                        kernel_rtt,
+                        kernel_rttvar,
+                        kernel_retransmits,
+                        delivery_rate,
+                    ])?;
+                }
+            }
         }
 
         wtr.flush()?;
@@ -237,3 +2639,349 @@ impl Client {
         Ok(())
     }
 }
+
+/// Keys of the client view.
+fn keys(key: &KeyEvent) -> Option<Action> {
+    match key.code {
+        KeyCode::Char('h') => Some(Action::ToggleShowHelp),
+        KeyCode::Char('t') => Some(Action::ToggleLatencyLines),
+        KeyCode::Char('g') => Some(Action::ToggleHistogram),
+        KeyCode::Char('+') => Some(Action::ChartZoomIn),
+        KeyCode::Char('-') => Some(Action::ChartZoomOut),
+        KeyCode::Char('p') => Some(Action::MarkPhase(None)),
+        _ => None,
+    }
+}
+
+/// Achieved send rate for the measured part of export metadata.
+fn measured_send_rate(rate: &SendRate) -> Vec<(String, String)> {
+    let mut measured = Vec::new();
+    if let (Some(pps), Some(bps)) = (rate.packets_per_second(), rate.bits_per_second()) {
+        measured.push(("packets_per_second".to_string(), format!("{:.3}", pps)));
+        measured.push(("offered_bits_per_second".to_string(), format!("{:.0}", bps)));
+    }
+    measured
+}
+
+fn write_json(path: &Path, results: &impl serde::Serialize) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut file, results)?;
+    writeln!(file)?;
+    file.flush()?;
+
+    Ok(())
+}
+
+/// Writes the latency distribution in the percentile format of HdrHistogram's
+/// `outputPercentileDistribution`, with values in milliseconds.
+fn write_hgrm(path: &Path, state: &State) -> Result<()> {
+    const NANOS_PER_MS: f64 = 1_000_000.0;
+    let histogram = &state.histogram;
+    let mut file = BufWriter::new(File::create(path)?);
+
+    writeln!(
+        file,
+        "{:>12} {:>14} {:>10} {:>14}\n",
+        "Value", "Percentile", "TotalCount", "1/(1-Percentile)"
+    )?;
+
+    let mut total = 0;
+    for v in histogram.iter_quantiles(HGRM_TICKS_PER_HALF_DISTANCE) {
+        total += v.count_since_last_iteration();
+        let value = v.value_iterated_to() as f64 / NANOS_PER_MS;
+        let quantile = v.quantile_iterated_to();
+        if quantile < 1.0 {
+            writeln!(
+                file,
+                "{:12.3} {:2.12} {:10} {:14.2}",
+                value,
+                quantile,
+                total,
+                1.0 / (1.0 - quantile)
+            )?;
+        } else {
+            writeln!(file, "{:12.3} {:2.12} {:10}", value, quantile, total)?;
+        }
+    }
+
+    let sub_buckets = (2 * 10u64.pow(histogram.sigfig() as u32)).next_power_of_two();
+    writeln!(
+        file,
+        "#[Mean    = {:12.3}, StdDeviation   = {:12.3}]",
+        histogram.mean() / NANOS_PER_MS,
+        histogram.stdev() / NANOS_PER_MS
+    )?;
+    writeln!(
+        file,
+        "#[Max     = {:12.3}, Total count    = {:12}]",
+        histogram.max() as f64 / NANOS_PER_MS,
+        histogram.len()
+    )?;
+    writeln!(
+        file,
+        "#[Buckets = {:12}, SubBuckets     = {:12}]",
+        histogram.buckets(),
+        sub_buckets
+    )?;
+    file.flush()?;
+
+    Ok(())
+}
+
+/// Starts a phase named after every line appended to `path`, so a script making
+/// the change under test can mark it. Lines already in the file are ignored.
+async fn watch_phase_marker(
+    path: &Path,
+    action_tx: UnboundedSender<Action>,
+    cancel: CancellationToken,
+) -> Result<()> {
+    let mut offset = match tokio::fs::metadata(path).await {
+        Ok(metadata) => metadata.len(),
+        Err(_) => {
+            tokio::fs::File::create(path).await?;
+            0
+        }
+    };
+    let mut pending = String::new();
+    let mut interval = tokio::time::interval(PHASE_MARKER_POLL);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = cancel.cancelled() => return Ok(()),
+        }
+
+        let len = tokio::fs::metadata(path).await.map_or(0, |m| m.len());
+        if len < offset {
+            // Truncated or replaced, start over
+            offset = 0;
+            pending.clear();
+        }
+        if len == offset {
+            continue;
+        }
+
+        let mut file = tokio::fs::File::open(path).await?;
+        file.seek(std::io::SeekFrom::Start(offset)).await?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).await?;
+        offset += data.len() as u64;
+
+        pending.push_str(&String::from_utf8_lossy(&data));
+        while let Some(end) = pending.find('\n') {
+            let name = pending[..end].trim().to_string();
+            pending.drain(..=end);
+            if !name.is_empty() {
+                action_tx.send(Action::MarkPhase(Some(name)))?;
+            }
+        }
+    }
+}
+
+/// Results per phase, each compared with the first phase.
+fn report_phase_statistics(phases: &[PhaseStatistics], format: &DisplayFormat) {
+    let Some(first) = phases.first() else {
+        return;
+    };
+    let width = phases
+        .iter()
+        .map(|p| p.name.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    info!("Results by phase:");
+    for phase in phases {
+        let comparison = if std::ptr::eq(phase, first) || phase.received == 0 || first.received == 0
+        {
+            String::new()
+        } else {
+            let first_average = first.average_latency.as_secs_f64();
+            format!(
+                ", vs \"{}\": avg {:+.1}%, loss {:+.2} pp",
+                first.name,
+                (phase.average_latency.as_secs_f64() - first_average) / first_average * 100.0,
+                (phase.loss() - first.loss()) * 100.0
+            )
+        };
+
+        info!(
+            "  {:<width$} probes {}-{}: avg {}, min {}, max {}, loss {} ({}/{}){}",
+            phase.name,
+            phase.packets.start,
+            phase.packets.end.saturating_sub(1),
+            format.duration(phase.average_latency),
+            format.duration(phase.min_latency),
+            format.duration(phase.max_latency),
+            format.percent(phase.loss()),
+            phase.sent - phase.received,
+            phase.sent,
+            comparison,
+            width = width
+        );
+    }
+}
+
+/// Logs per-source results, at info level only when the sources behave
+/// noticeably different, which hints at per-flow policing, NAT limits or
+/// source-based routing.
+fn report_source_statistics(ports: &[SourceStatistics], format: &DisplayFormat) {
+    // Sources on one address are told apart by their port
+    let by_address = ports.iter().any(|p| p.source.ip() != ports[0].source.ip());
+    let significant = latency::sources_differ(ports);
+
+    // Addresses were chosen to be compared, they are always shown
+    match (significant, by_address) {
+        (true, true) => info!("Results differ between source addresses:"),
+        (true, false) => info!("Results differ between source ports:"),
+        (false, true) => info!("Results by source address:"),
+        (false, false) => {}
+    }
+    for p in ports {
+        let source = match by_address {
+            true => format!("source {}", p.source),
+            false => format!("port {:>5}", p.source.port()),
+        };
+        let line = format!(
+            "  {}: avg {}, loss {} ({}/{})",
+            source,
+            format.duration(p.average_latency),
+            format.percent(p.loss()),
+            p.sent - p.received,
+            p.sent
+        );
+        if significant || by_address {
+            info!("{}", line);
+        } else {
+            debug!("{}", line);
+        }
+    }
+}
+
+fn report_payload_comparison(comparison: &PayloadComparison, format: &DisplayFormat) {
+    for (name, group) in [
+        ("compressible", &comparison.compressible),
+        ("incompressible", &comparison.incompressible),
+    ] {
+        info!(
+            "  {:>14} payloads: avg {} ± {}, loss {} ({}/{})",
+            name,
+            format.duration(group.mean),
+            format.duration(group.std_dev),
+            format.percent(group.loss()),
+            group.sent - group.received,
+            group.sent
+        );
+    }
+
+    if comparison.pairs > 0 {
+        let difference = Duration::from_secs_f64(comparison.median_difference.abs());
+        info!(
+            "  incompressible slower in {} and faster in {} of {} pairs, median {}{}",
+            comparison.incompressible_slower,
+            comparison.incompressible_faster,
+            comparison.pairs,
+            if comparison.median_difference < 0.0 {
+                "-"
+            } else {
+                "+"
+            },
+            format.duration(difference)
+        );
+    }
+
+    match (comparison.latency_differs(), comparison.loss_differs()) {
+        (false, false) => info!("Payload contents make no significant difference"),
+        (latency, loss) => {
+            let differs: Vec<_> = [(latency, "latency"), (loss, "loss")]
+                .into_iter()
+                .filter_map(|(differs, what)| differs.then_some(what))
+                .collect();
+            warn!(
+                "Payload contents change {}, the path likely compresses or inspects traffic",
+                differs.join(" and ")
+            );
+        }
+    }
+}
+
+fn report_bandwidth_comparison(comparison: &BandwidthComparison, format: &DisplayFormat) {
+    info!(
+        "  {:>14} payloads: {}",
+        "compressible",
+        format_bitrate(comparison.compressible)
+    );
+    info!(
+        "  {:>14} payloads: {}",
+        "incompressible",
+        format_bitrate(comparison.incompressible)
+    );
+
+    if comparison.pairs == 0 {
+        info!("Too few report intervals to compare the payloads");
+        return;
+    }
+    info!(
+        "  incompressible lower in {} and higher in {} of {} interval pairs, median {}{}",
+        comparison.incompressible_lower,
+        comparison.incompressible_higher,
+        comparison.pairs,
+        if comparison.median_difference < 0.0 {
+            "-"
+        } else {
+            "+"
+        },
+        format.percent(comparison.median_difference.abs())
+    );
+
+    if comparison.differs() {
+        warn!("Payload contents change the throughput, the path likely compresses or inspects traffic");
+    } else {
+        info!("Payload contents make no significant difference");
+    }
+}
+
+fn report_call_quality(quality: &CallQuality, buffer: Duration, format: &DisplayFormat) {
+    info!(
+        "Call quality: MOS {:.2}, R-factor {:.1} ({})",
+        quality.mos,
+        quality.r_factor,
+        quality.rating()
+    );
+    info!(
+        "  jitter {}, mouth-to-ear delay {}",
+        format.duration(quality.jitter),
+        format.duration(quality.mouth_to_ear)
+    );
+    let text = format!(
+        "  {} jitter buffer: {} late and {} lost of {} frames ({}), {} underrun(s)",
+        format.duration(buffer),
+        quality.late,
+        quality.lost,
+        quality.frames,
+        format.percent(quality.effective_loss()),
+        quality.underruns
+    );
+    match quality.late {
+        0 => info!("{}", text),
+        _ => warn!("{}", text),
+    }
+}
+
+fn report_game_quality(quality: &GameQuality, format: &DisplayFormat) {
+    info!(
+        "Game: ping {}, jitter {}, loss {}, {} spike(s) ({:.1}/min)",
+        format.duration(quality.ping),
+        format.duration(quality.jitter),
+        format.percent(quality.loss),
+        quality.spikes,
+        quality.spikes_per_minute
+    );
+    match quality.limited_by.is_empty() {
+        true => info!("Verdict: {}", quality.verdict),
+        false => info!(
+            "Verdict: {}, held back by {}",
+            quality.verdict,
+            quality.limited_by.join(" and ")
+        ),
+    }
+}
@@ -0,0 +1,524 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt::Write as _,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use color_eyre::eyre::{bail, Result};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc::UnboundedSender, Mutex},
+    time::{self, Instant},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use crate::{
+    action::Action,
+    http,
+    metadata::{self, Identity},
+    metrics::{escape, metric},
+    network::latency::State,
+};
+
+/// Path clients post their reports to.
+const REPORT_PATH: &str = "/report";
+
+/// Largest request the collector reads, reports of a few targets are far smaller.
+const MAX_REQUEST: usize = 1024 * 1024;
+
+/// Reports kept while the collector is unreachable, the oldest are dropped first.
+const MAX_QUEUED_REPORTS: usize = 360;
+
+/// Targets without a report for this long are shown as stale.
+pub(crate) const STALE_AFTER: Duration = Duration::from_secs(60);
+
+/// Targets without a report for this long are dropped.
+const EXPIRE_AFTER: Duration = Duration::from_secs(600);
+
+/// How often the TUI gets the collected targets.
+const VIEW_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where a client sends its reports, `host:port` of a collector.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CollectorAddress(String);
+
+impl FromStr for CollectorAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("https://") {
+            return Err("HTTPS is not supported, the collector speaks plain HTTP".into());
+        }
+        let host = s.strip_prefix("http://").unwrap_or(s).trim_end_matches('/');
+        match host.rsplit_once(':') {
+            Some((name, port)) if !name.is_empty() && port.parse::<u16>().is_ok() => {
+                Ok(CollectorAddress(host.to_string()))
+            }
+            _ => Err("expected the collector as host:port, e.g. collector.lan:7443".into()),
+        }
+    }
+}
+
+/// Parses the address a collector listens on, `:port` listens on all addresses.
+pub(crate) fn parse_listen(s: &str) -> Result<SocketAddr, String> {
+    match s.strip_prefix(':') {
+        Some(port) => port
+            .parse::<u16>()
+            .map(|port| SocketAddr::new(IpAddr::from([0u8; 16]), port))
+            .map_err(|e| format!("invalid port {}: {}", port, e)),
+        None => s.parse().map_err(|e| format!("{}: {}", s, e)),
+    }
+}
+
+/// What a client reports about its targets, the totals of the run so far.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Report {
+    /// Name of the probe, the collector names it by its address otherwise
+    pub probe: Option<String>,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    pub targets: Vec<TargetReport>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct TargetReport {
+    pub target: String,
+    pub sent: u32,
+    pub received: u32,
+    pub min_latency_us: Option<u64>,
+    pub average_latency_us: Option<u64>,
+    pub max_latency_us: Option<u64>,
+    pub p50_us: Option<u64>,
+    pub p90_us: Option<u64>,
+    pub p99_us: Option<u64>,
+    /// The run ended, no further reports follow
+    #[serde(default)]
+    pub finished: bool,
+}
+
+impl TargetReport {
+    fn of(target: &str, state: &State, finished: bool) -> Self {
+        let micros = |d: Duration| d.as_micros() as u64;
+        let answered = state.received_packets > 0;
+        let percentiles = state.percentiles();
+        Self {
+            target: target.to_string(),
+            sent: state.sent_packets(),
+            received: state.received_packets,
+            min_latency_us: answered.then(|| micros(state.min_latency)),
+            average_latency_us: answered.then(|| micros(state.average_latency)),
+            max_latency_us: answered.then(|| micros(state.max_latency)),
+            p50_us: percentiles.map(|p| micros(p.p50)),
+            p90_us: percentiles.map(|p| micros(p.p90)),
+            p99_us: percentiles.map(|p| micros(p.p99)),
+            finished,
+        }
+    }
+
+    pub(crate) fn loss(&self) -> f64 {
+        1.0 - self.received as f64 / self.sent.max(1) as f64
+    }
+}
+
+/// Latest report of one target of one probe, as the TUI shows it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CollectedTarget {
+    pub probe: String,
+    pub report: TargetReport,
+    /// Time since the last report
+    pub age: Duration,
+}
+
+/// Sends the totals of every target to a collector in intervals and once more
+/// when the run ends. Reports that could not be delivered are queued and sent
+/// first once the collector is reachable again.
+pub(crate) struct Uploader {
+    collector: CollectorAddress,
+    targets: Vec<(String, Arc<Mutex<State>>)>,
+    interval: Duration,
+    identity: Identity,
+    queue: VecDeque<String>,
+    quit: CancellationToken,
+}
+
+impl Uploader {
+    pub(crate) fn new(
+        collector: CollectorAddress,
+        targets: Vec<(String, Arc<Mutex<State>>)>,
+        interval: Duration,
+        quit: CancellationToken,
+    ) -> Self {
+        Self {
+            collector,
+            targets,
+            interval,
+            identity: Identity::default(),
+            queue: VecDeque::new(),
+            quit,
+        }
+    }
+
+    pub(crate) fn with_identity(mut self, identity: Identity) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    pub(crate) async fn run(mut self) -> Result<()> {
+        info!("Reporting to the collector at {}", self.collector.0);
+
+        let mut ticker = time::interval_at(Instant::now() + self.interval, self.interval);
+        loop {
+            let finished = tokio::select! {
+                _ = ticker.tick() => false,
+                _ = self.quit.cancelled() => true,
+            };
+
+            // A collector that is down should not end the run
+            if let Err(e) = self.upload(finished).await {
+                warn!(
+                    "Could not report to the collector, {} report(s) queued: {}",
+                    self.queue.len(),
+                    e
+                );
+            }
+            if finished {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn upload(&mut self, finished: bool) -> Result<()> {
+        let mut targets = Vec::with_capacity(self.targets.len());
+        for (target, state) in self.targets.iter() {
+            let state = state.lock().await;
+            targets.push(TargetReport::of(target, &state, finished));
+        }
+        let report = Report {
+            probe: self.identity.probe.clone(),
+            labels: self.identity.labels.iter().cloned().collect(),
+            targets,
+        };
+
+        self.queue.push_back(serde_json::to_string(&report)?);
+        if self.queue.len() > MAX_QUEUED_REPORTS {
+            self.queue.pop_front();
+        }
+
+        // Oldest first, so the collector ends up with the latest totals
+        let queued = self.queue.len();
+        while let Some(body) = self.queue.front() {
+            http::post(&self.collector.0, REPORT_PATH, "application/json", "", body).await?;
+            self.queue.pop_front();
+        }
+        if queued > 1 {
+            info!("Collector reachable again, sent {} queued reports", queued);
+        }
+
+        Ok(())
+    }
+}
+
+struct Entry {
+    labels: Vec<(String, String)>,
+    report: TargetReport,
+    received_at: Instant,
+}
+
+/// Reports by probe and target.
+type Entries = HashMap<(String, String), Entry>;
+
+/// Accepts the reports of many clients and serves them combined as Prometheus
+/// metrics on `/metrics`.
+pub(crate) struct Collector {
+    listen: SocketAddr,
+    entries: Arc<Mutex<Entries>>,
+    notify: Option<UnboundedSender<Action>>,
+    quit: CancellationToken,
+}
+
+impl Collector {
+    pub(crate) fn new(listen: SocketAddr, quit: CancellationToken) -> Self {
+        Self {
+            listen,
+            entries: Arc::default(),
+            notify: None,
+            quit,
+        }
+    }
+
+    /// Send the collected targets to the TUI every second.
+    pub(crate) fn with_notify(mut self, notify: UnboundedSender<Action>) -> Self {
+        self.notify = Some(notify);
+        self
+    }
+
+    pub(crate) async fn run(self) -> Result<()> {
+        let listener = TcpListener::bind(self.listen).await?;
+        info!(
+            "Collecting reports on http://{}{}, metrics on /metrics",
+            listener.local_addr()?,
+            REPORT_PATH
+        );
+
+        let mut ticker = time::interval(VIEW_UPDATE_INTERVAL);
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let (stream, peer) = accepted?;
+                    let entries = self.entries.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_connection(stream, peer, entries).await {
+                            debug!("Collector connection from {} failed: {:?}", peer, e);
+                        }
+                    });
+                }
+                _ = ticker.tick() => self.expire().await?,
+                _ = self.quit.cancelled() => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drops targets that stopped reporting and updates the TUI.
+    async fn expire(&self) -> Result<()> {
+        let mut entries = self.entries.lock().await;
+        entries.retain(|(probe, target), entry| {
+            let expired = entry.received_at.elapsed() >= EXPIRE_AFTER;
+            if expired {
+                info!("{} stopped reporting {}", probe, target);
+            }
+            !expired
+        });
+
+        if let Some(ref notify) = self.notify {
+            let mut targets: Vec<_> = entries
+                .iter()
+                .map(|((probe, _), entry)| CollectedTarget {
+                    probe: probe.clone(),
+                    report: entry.report.clone(),
+                    age: entry.received_at.elapsed(),
+                })
+                .collect();
+            targets.sort_by(|a, b| (&a.probe, &a.report.target).cmp(&(&b.probe, &b.report.target)));
+            notify.send(Action::CollectorTargets(targets))?;
+        }
+
+        Ok(())
+    }
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    entries: Arc<Mutex<Entries>>,
+) -> Result<()> {
+    let (request, body) = read_request(&mut stream).await?;
+    let mut words = request.split_whitespace();
+
+    let (status, content_type, body) = match (words.next(), words.next()) {
+        (Some("POST"), Some(REPORT_PATH)) => match accept_report(&body, peer, &entries).await {
+            Ok(()) => ("204 No Content", "text/plain", String::new()),
+            Err(e) => ("400 Bad Request", "text/plain", e.to_string()),
+        },
+        (Some("GET"), Some("/metrics")) => (
+            "200 OK",
+            "text/plain; version=0.0.4",
+            metrics(&*entries.lock().await),
+        ),
+        _ => ("404 Not Found", "text/plain", "Not found".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+
+    Ok(())
+}
+
+/// Reads the request line and the body of a request with a Content-Length.
+async fn read_request(stream: &mut TcpStream) -> Result<(String, Vec<u8>)> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 4096];
+    let header_end = loop {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            bail!("Connection closed before the end of the headers");
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end;
+        }
+        if buf.len() > MAX_REQUEST {
+            bail!("Headers too large");
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buf[..header_end]).into_owned();
+    let length = head
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    if length > MAX_REQUEST {
+        bail!("Body of {} bytes too large", length);
+    }
+
+    let mut body = buf.split_off(header_end + 4);
+    while body.len() < length {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            bail!("Connection closed before the end of the body");
+        }
+        body.extend_from_slice(&chunk[..n]);
+    }
+    body.truncate(length);
+
+    let request = head.lines().next().unwrap_or_default().to_string();
+    Ok((request, body))
+}
+
+async fn accept_report(body: &[u8], peer: SocketAddr, entries: &Mutex<Entries>) -> Result<()> {
+    let report: Report = serde_json::from_slice(body)?;
+    for key in report.labels.keys() {
+        if let Err(e) = metadata::check_label_name(key) {
+            bail!(e);
+        }
+    }
+    let probe = report.probe.unwrap_or_else(|| peer.ip().to_string());
+    let labels: Vec<_> = report.labels.into_iter().collect();
+
+    let mut entries = entries.lock().await;
+    for target in report.targets {
+        let key = (probe.clone(), target.target.clone());
+        if !entries.contains_key(&key) {
+            info!("{} started reporting {}", probe, target.target);
+        }
+        entries.insert(
+            key,
+            Entry {
+                labels: labels.clone(),
+                report: target,
+                received_at: Instant::now(),
+            },
+        );
+    }
+
+    Ok(())
+}
+
+fn metrics(entries: &Entries) -> String {
+    let mut sent = Vec::new();
+    let mut received = Vec::new();
+    let mut min = Vec::new();
+    let mut average = Vec::new();
+    let mut max = Vec::new();
+    let mut quantiles = Vec::new();
+    let mut age = Vec::new();
+
+    let mut keys: Vec<_> = entries.keys().collect();
+    keys.sort();
+    for key in keys {
+        let (probe, target) = key;
+        let entry = &entries[key];
+        let report = &entry.report;
+
+        let mut label = format!("probe=\"{}\",", escape(probe));
+        for (key, value) in entry.labels.iter() {
+            let _ = write!(label, "{}=\"{}\",", key, escape(value));
+        }
+        let _ = write!(label, "target=\"{}\"", escape(target));
+
+        let seconds = |us: u64| us as f64 / 1e6;
+        sent.push((label.clone(), report.sent as f64));
+        received.push((label.clone(), report.received as f64));
+        for (values, latency) in [
+            (&mut min, report.min_latency_us),
+            (&mut average, report.average_latency_us),
+            (&mut max, report.max_latency_us),
+        ] {
+            if let Some(latency) = latency {
+                values.push((label.clone(), seconds(latency)));
+            }
+        }
+        for (quantile, latency) in [
+            ("0.5", report.p50_us),
+            ("0.9", report.p90_us),
+            ("0.99", report.p99_us),
+        ] {
+            if let Some(latency) = latency {
+                quantiles.push((
+                    format!("{},quantile=\"{}\"", label, quantile),
+                    seconds(latency),
+                ));
+            }
+        }
+        age.push((label, entry.received_at.elapsed().as_secs_f64()));
+    }
+
+    let mut body = String::new();
+    metric(
+        &mut body,
+        "bwlat_probes_sent_total",
+        "counter",
+        "Probes sent",
+        &sent,
+    );
+    metric(
+        &mut body,
+        "bwlat_probes_received_total",
+        "counter",
+        "Probes answered",
+        &received,
+    );
+    metric(
+        &mut body,
+        "bwlat_latency_min_seconds",
+        "gauge",
+        "Fastest round trip",
+        &min,
+    );
+    metric(
+        &mut body,
+        "bwlat_latency_average_seconds",
+        "gauge",
+        "Mean round trip",
+        &average,
+    );
+    metric(
+        &mut body,
+        "bwlat_latency_max_seconds",
+        "gauge",
+        "Slowest round trip",
+        &max,
+    );
+    metric(
+        &mut body,
+        "bwlat_latency_quantile_seconds",
+        "gauge",
+        "Round-trip percentiles of the whole run",
+        &quantiles,
+    );
+    metric(
+        &mut body,
+        "bwlat_collector_report_age_seconds",
+        "gauge",
+        "Time since the probe last reported the target",
+        &age,
+    );
+    body
+}
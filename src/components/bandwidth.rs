@@ -0,0 +1,110 @@
+use color_eyre::eyre::Result;
+use ratatui::{
+    prelude::*,
+    widgets::{Block, Borders, Paragraph, Sparkline},
+};
+
+use super::{Component, Frame};
+use crate::{
+    action::Action,
+    network::bandwidth::{format_bitrate, BandwidthSample},
+    units::DisplayFormat,
+};
+
+#[derive(Default)]
+pub struct BandwidthComponent {
+    pub samples: Vec<BandwidthSample>,
+    pub retransmits: u32,
+    pub lost: Option<u64>,
+    pub format: DisplayFormat,
+}
+
+impl Component for BandwidthComponent {
+    fn update(&mut self, action: Action) -> Result<Option<Action>> {
+        if let Action::BandwidthSample(sample) = action {
+            self.retransmits += sample.retransmits;
+            if let Some(lost) = sample.lost {
+                *self.lost.get_or_insert(0) += lost;
+            }
+            self.samples.push(sample);
+        }
+        Ok(None)
+    }
+
+    fn draw(&mut self, f: &mut Frame<'_>, rect: Rect) -> Result<()> {
+        let block = Block::new().title("Bandwidth").borders(Borders::ALL);
+        let inner = block.inner(rect);
+        f.render_widget(block, rect);
+
+        let layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![Constraint::Length(6), Constraint::Min(0)])
+            .split(inner);
+
+        let current = self.samples.last();
+        let average = match self.samples.len() {
+            0 => 0.0,
+            n => {
+                self.samples
+                    .iter()
+                    .map(|s| s.bits_per_second as f64)
+                    .sum::<f64>()
+                    / n as f64
+            }
+        };
+
+        let lines = vec![
+            Line::from(
+                format!(
+                    "Throughput: {}",
+                    format_bitrate(current.map_or(0.0, |s| s.bits_per_second as f64))
+                )
+                .blue(),
+            ),
+            Line::from(format!("Average: {}", format_bitrate(average)).green()),
+            Line::from(match (self.lost, self.retransmits) {
+                (Some(0), _) => "Lost datagrams: 0".to_string().green(),
+                (Some(lost), _) => format!("Lost datagrams: {}", lost).yellow(),
+                (None, 0) => "Retransmissions: 0".to_string().green(),
+                (None, r) => format!("Retransmissions: {}", r).yellow(),
+            }),
+            Line::from(format!("Cwnd: {} segments", current.map_or(0, |s| s.cwnd)).dim()),
+            Line::from(
+                format!(
+                    "RTT: {} ± {}",
+                    self.format
+                        .duration(current.map_or(Default::default(), |s| s.rtt)),
+                    self.format
+                        .duration(current.map_or(Default::default(), |s| s.rttvar))
+                )
+                .dim(),
+            ),
+            Line::from(
+                format!(
+                    "Delivery rate: {}",
+                    format_bitrate(current.map_or(0.0, |s| s.delivery_rate as f64))
+                )
+                .dim(),
+            ),
+        ];
+        f.render_widget(Paragraph::new(lines), layout[0]);
+
+        // Most recent samples that fit the width of the chart
+        let history: Vec<u64> = self
+            .samples
+            .iter()
+            .rev()
+            .take(layout[1].width as usize)
+            .rev()
+            .map(|s| s.bits_per_second)
+            .collect();
+
+        let sparkline = Sparkline::default()
+            .block(Block::default().title("Throughput history".dim()))
+            .data(&history)
+            .style(Style::default().fg(Color::Blue));
+        f.render_widget(sparkline, layout[1]);
+
+        Ok(())
+    }
+}
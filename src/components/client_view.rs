@@ -1,34 +1,222 @@
+use std::time::Duration;
+
 use color_eyre::eyre::Result;
-use ratatui::prelude::*;
+use ratatui::{prelude::*, widgets::Paragraph};
+use tracing::debug;
 
-use super::{latency::LatencyComponent, Component, Frame};
-use crate::action::Action;
+use super::{
+    bandwidth::BandwidthComponent,
+    latency::{LatencyComponent, MAX_CHART_WINDOW, MIN_CHART_WINDOW},
+    Component, Frame,
+};
+use crate::{
+    action::Action, network::bandwidth::format_bytes, preferences::Preferences,
+    units::DisplayFormat,
+};
 
-#[derive(Default)]
 pub struct ClientView {
-    pub show_help: bool,
-    latency: LatencyComponent,
+    ewma_alpha: f64,
+    histogram_buckets: usize,
+    format: DisplayFormat,
+    preferences: Preferences,
+    latency: Vec<LatencyComponent>,
+    /// Lay the latency panes out next to each other instead of stacked
+    side_by_side: bool,
+    bandwidth: Option<BandwidthComponent>,
+    /// Bytes the state of each target takes, as last reported
+    memory: Vec<usize>,
+    /// The `--max-memory` cap shown next to the usage
+    max_memory: Option<u64>,
 }
 
 impl ClientView {
-    pub fn new() -> Self {
-        Self::default()
+    pub fn new(
+        ewma_alpha: f64,
+        histogram_buckets: usize,
+        format: DisplayFormat,
+        preferences: Preferences,
+    ) -> Self {
+        Self {
+            ewma_alpha,
+            histogram_buckets,
+            format,
+            preferences,
+            latency: Vec::new(),
+            side_by_side: false,
+            bandwidth: None,
+            memory: Vec::new(),
+            max_memory: None,
+        }
+    }
+
+    /// Show the latency panes next to each other, as for the two directions of
+    /// a bidirectional test.
+    pub fn with_side_by_side(mut self) -> Self {
+        self.side_by_side = true;
+        self
+    }
+
+    /// Show the memory usage against the cap the probes are held to.
+    pub fn with_max_memory(mut self, bytes: u64) -> Self {
+        self.max_memory = Some(bytes);
+        self
+    }
+
+    fn new_latency(&self) -> LatencyComponent {
+        let mut latency = LatencyComponent::new(self.ewma_alpha);
+        latency.format = self.format;
+        latency.chart_lines = self.preferences.chart_lines;
+        latency.chart_window = self.preferences.chart_window;
+        latency.show_histogram = self.preferences.show_histogram;
+        latency.histogram_buckets = self.histogram_buckets;
+        latency
+    }
+
+    /// Applies a changed view setting to all panes and remembers it for the next run.
+    fn preferences_changed(&mut self) {
+        for latency in self.latency.iter_mut() {
+            latency.chart_lines = self.preferences.chart_lines;
+            latency.chart_window = self.preferences.chart_window;
+            latency.show_histogram = self.preferences.show_histogram;
+        }
+
+        if let Err(e) = self.preferences.save() {
+            debug!("Could not save preferences: {}", e);
+        }
+    }
+
+    fn zoom(&mut self, window: Duration) {
+        self.preferences.chart_window = window.clamp(MIN_CHART_WINDOW, MAX_CHART_WINDOW);
+        self.preferences_changed();
     }
 }
 
 impl Component for ClientView {
     fn update(&mut self, action: Action) -> Result<Option<Action>> {
-        if let Action::ToggleShowHelp = action {
-            self.show_help = !self.show_help
+        match action {
+            Action::ToggleShowHelp => {
+                self.preferences.show_help = !self.preferences.show_help;
+                self.preferences_changed();
+                return Ok(None);
+            }
+            Action::ToggleLatencyLines => {
+                self.preferences.chart_lines = self.preferences.chart_lines.next();
+                self.preferences_changed();
+                return Ok(None);
+            }
+            Action::ToggleHistogram => {
+                self.preferences.show_histogram = !self.preferences.show_histogram;
+                self.preferences_changed();
+                return Ok(None);
+            }
+            Action::ChartZoomIn => {
+                self.zoom(self.preferences.chart_window / 2);
+                return Ok(None);
+            }
+            Action::ChartZoomOut => {
+                self.zoom(self.preferences.chart_window * 2);
+                return Ok(None);
+            }
+            _ => {}
         }
 
-        self.latency.update(action)?;
+        if let Action::LatencyMemory(target, usage) = action {
+            if target >= self.memory.len() {
+                self.memory.resize(target + 1, 0);
+            }
+            self.memory[target] = usage;
+            return Ok(None);
+        }
+
+        if let Action::BandwidthSample(_) = action {
+            let format = self.format;
+            return self
+                .bandwidth
+                .get_or_insert_with(|| BandwidthComponent {
+                    format,
+                    ..Default::default()
+                })
+                .update(action);
+        }
+
+        let target = match action {
+            Action::LatencyTarget(t, _)
+            | Action::LatencyPacketTotal(t, _)
+            | Action::LatencyPacketsSent(t, _)
+            | Action::LatencyPacketsSkipped(t, _)
+            | Action::LatencyPacketsReceived(t, ..)
+            | Action::LatencyTraffic(t, ..)
+            | Action::LatencyEvent(t, ..)
+            | Action::LatencyPercentiles(t, _)
+            | Action::LatencySendRate(t, _)
+            | Action::LatencySample(t, ..)
+            | Action::LatencyLost(t, _) => t,
+            _ => return Ok(None),
+        };
+
+        while target >= self.latency.len() {
+            self.latency.push(self.new_latency());
+        }
+        self.latency[target].update(action)?;
 
         Ok(None)
     }
 
     fn draw(&mut self, f: &mut Frame<'_>, rect: Rect) -> Result<()> {
-        self.latency.draw(f, rect)?;
+        let rect = if self.preferences.show_help || !self.memory.is_empty() {
+            let layout = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints(vec![Constraint::Min(0), Constraint::Length(1)])
+                .split(rect);
+
+            if self.preferences.show_help {
+                let help =
+                    "q quit  t chart lines  g histogram  +/- zoom  p next phase  h hide help";
+                f.render_widget(Paragraph::new(help.dim()), layout[1]);
+            }
+            if !self.memory.is_empty() {
+                let usage = self.memory.iter().sum::<usize>() as u64;
+                let memory = match self.max_memory {
+                    Some(max) => format!("memory {} of {}", format_bytes(usage), format_bytes(max)),
+                    None => format!("memory {}", format_bytes(usage)),
+                };
+                let memory = match self.max_memory {
+                    Some(max) if usage >= max => memory.yellow(),
+                    _ => memory.dim(),
+                };
+                f.render_widget(
+                    Paragraph::new(memory).alignment(Alignment::Right),
+                    layout[1],
+                );
+            }
+            layout[0]
+        } else {
+            rect
+        };
+
+        if let Some(ref mut bandwidth) = self.bandwidth {
+            return bandwidth.draw(f, rect);
+        }
+
+        if self.latency.is_empty() {
+            self.latency.push(self.new_latency());
+        }
+
+        let direction = match self.side_by_side {
+            true => Direction::Horizontal,
+            false => Direction::Vertical,
+        };
+        let layout = Layout::default()
+            .direction(direction)
+            .constraints(vec![
+                Constraint::Ratio(1, self.latency.len() as u32);
+                self.latency.len()
+            ])
+            .split(rect);
+
+        for (latency, rect) in self.latency.iter_mut().zip(layout.iter()) {
+            latency.draw(f, *rect)?;
+        }
 
         Ok(())
     }
@@ -0,0 +1,132 @@
+use std::{net::SocketAddr, time::Duration};
+
+use color_eyre::eyre::Result;
+use ratatui::{
+    prelude::*,
+    widgets::{Block, Borders, Paragraph, Row, Table},
+};
+
+use super::{Component, Frame};
+use crate::{
+    action::Action,
+    collector::{CollectedTarget, STALE_AFTER},
+    units::DisplayFormat,
+};
+
+pub struct CollectorView {
+    listen: SocketAddr,
+    format: DisplayFormat,
+    targets: Vec<CollectedTarget>,
+}
+
+impl CollectorView {
+    pub fn new(listen: SocketAddr, format: DisplayFormat) -> Self {
+        Self {
+            listen,
+            format,
+            targets: Vec::new(),
+        }
+    }
+
+    fn latency(&self, micros: Option<u64>) -> String {
+        micros.map_or_else(
+            || "-".to_string(),
+            |us| self.format.duration(Duration::from_micros(us)),
+        )
+    }
+}
+
+impl Component for CollectorView {
+    fn update(&mut self, action: Action) -> Result<Option<Action>> {
+        if let Action::CollectorTargets(targets) = action {
+            self.targets = targets;
+        }
+        Ok(None)
+    }
+
+    fn draw(&mut self, f: &mut Frame<'_>, rect: Rect) -> Result<()> {
+        let layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![Constraint::Min(0), Constraint::Length(1)])
+            .split(rect);
+        f.render_widget(Paragraph::new("q quit".dim()), layout[1]);
+
+        let block = Block::new().title("Collector").borders(Borders::ALL);
+        let inner = block.inner(layout[0]);
+        f.render_widget(block, layout[0]);
+
+        let layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![Constraint::Length(3), Constraint::Min(0)])
+            .split(inner);
+
+        let mut probes: Vec<&str> = self.targets.iter().map(|t| t.probe.as_str()).collect();
+        probes.dedup();
+        let reporting = self.targets.iter().filter(|t| t.age < STALE_AFTER);
+        let (sent, received) = reporting.fold((0u64, 0u64), |(sent, received), t| {
+            (
+                sent + t.report.sent as u64,
+                received + t.report.received as u64,
+            )
+        });
+        let lines = vec![
+            Line::from(format!("Collecting on {}", self.listen)),
+            Line::from(
+                format!(
+                    "{} probe(s), {} target(s), {} loss overall",
+                    probes.len(),
+                    self.targets.len(),
+                    self.format
+                        .percent(1.0 - received as f64 / sent.max(1) as f64)
+                )
+                .blue(),
+            ),
+        ];
+        f.render_widget(Paragraph::new(lines), layout[0]);
+
+        let rows: Vec<Row> = self
+            .targets
+            .iter()
+            .map(|t| {
+                let cells = vec![
+                    t.probe.clone(),
+                    t.report.target.clone(),
+                    t.report.sent.to_string(),
+                    self.format.percent(t.report.loss()),
+                    self.latency(t.report.average_latency_us),
+                    self.latency(t.report.p99_us),
+                    match t.report.finished {
+                        true => "finished".to_string(),
+                        false => format!("{}s ago", t.age.as_secs()),
+                    },
+                ];
+                match t.age < STALE_AFTER && !t.report.finished {
+                    true => Row::new(cells),
+                    false => Row::new(cells).dim(),
+                }
+            })
+            .collect();
+
+        let widths = [
+            Constraint::Min(16),
+            Constraint::Min(16),
+            Constraint::Length(10),
+            Constraint::Length(8),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Length(10),
+        ];
+        let table = Table::new(rows)
+            .header(
+                Row::new(vec![
+                    "Probe", "Target", "Sent", "Loss", "Average", "p99", "Report",
+                ])
+                .bold()
+                .bottom_margin(1),
+            )
+            .widths(&widths);
+        f.render_widget(table, layout[1]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+use color_eyre::eyre::Result;
+use ratatui::{
+    prelude::*,
+    widgets::{Block, Borders, Paragraph, Row, Table},
+};
+use tokio::time::Instant;
+
+use super::{Component, Frame};
+use crate::{
+    client::{Round, RoundOutcome},
+    units::DisplayFormat,
+};
+
+/// Results of the tests of a `--repeat-every` run so far, shown while waiting
+/// for the next one.
+pub struct HistoryView {
+    rounds: Vec<Round>,
+    next: Instant,
+    format: DisplayFormat,
+}
+
+impl HistoryView {
+    pub fn new(rounds: Vec<Round>, next: Instant, format: DisplayFormat) -> Self {
+        Self {
+            rounds,
+            next,
+            format,
+        }
+    }
+}
+
+impl Component for HistoryView {
+    fn draw(&mut self, f: &mut Frame<'_>, rect: Rect) -> Result<()> {
+        let layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![Constraint::Min(0), Constraint::Length(1)])
+            .split(rect);
+        f.render_widget(Paragraph::new("q quit".dim()), layout[1]);
+
+        let block = Block::new().title("History").borders(Borders::ALL);
+        let inner = block.inner(layout[0]);
+        f.render_widget(block, layout[0]);
+
+        let layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![Constraint::Length(2), Constraint::Min(0)])
+            .split(inner);
+
+        let wait = self.next.saturating_duration_since(Instant::now());
+        let wait = Duration::from_secs(wait.as_secs_f64().ceil() as u64);
+        f.render_widget(
+            Paragraph::new(
+                format!(
+                    "{} test(s), the next one in {}",
+                    self.rounds.len(),
+                    humantime::format_duration(wait)
+                )
+                .blue(),
+            ),
+            layout[0],
+        );
+
+        // Latest first, older ones scroll off the bottom
+        let rows: Vec<Row> = self
+            .rounds
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, round)| {
+                let cells = vec![
+                    (i + 1).to_string(),
+                    humantime::format_rfc3339_seconds(round.started).to_string(),
+                    round.outcome.describe(&self.format),
+                ];
+                match round.outcome {
+                    RoundOutcome::Failed(_) => Row::new(cells).red(),
+                    RoundOutcome::Skipped(_) => Row::new(cells).dim(),
+                    _ => Row::new(cells),
+                }
+            })
+            .collect();
+
+        let widths = [
+            Constraint::Length(6),
+            Constraint::Length(22),
+            Constraint::Min(20),
+        ];
+        let table = Table::new(rows)
+            .header(
+                Row::new(vec!["Test", "Started", "Result"])
+                    .bold()
+                    .bottom_margin(1),
+            )
+            .widths(&widths);
+        f.render_widget(table, layout[1]);
+
+        Ok(())
+    }
+}
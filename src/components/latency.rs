@@ -1,32 +1,300 @@
-use std::time::Duration;
+use std::{borrow::Cow, collections::VecDeque, time::Duration};
 
 use color_eyre::eyre::Result;
+use hdrhistogram::Histogram;
 use ratatui::{
     prelude::*,
-    widgets::{block::Title, Block, Borders, LineGauge, Paragraph},
+    symbols::Marker,
+    widgets::{
+        block::Title, Axis, Bar, BarChart, BarGroup, Block, Borders, Chart, Dataset, GraphType,
+        LineGauge, Paragraph,
+    },
 };
+use serde::{Deserialize, Serialize};
 
 use super::{Component, Frame};
-use crate::action::Action;
+use crate::{
+    action::Action,
+    network::{
+        bandwidth::{format_bitrate, format_bytes},
+        latency::{Event, Percentiles, SendRate},
+    },
+    units::DisplayFormat,
+};
+
+/// How much history is kept for the latency chart, the widest it can zoom out.
+pub const MAX_CHART_WINDOW: Duration = Duration::from_secs(600);
+pub const MIN_CHART_WINDOW: Duration = Duration::from_secs(5);
+
+/// Slowest latency the histogram panel can tell apart.
+const HISTOGRAM_MAX: Duration = Duration::from_secs(3600);
+/// Quantile the histogram panel spans up to, slower samples share the last bucket.
+const HISTOGRAM_RANGE: f64 = 0.999;
+/// Share of the pane width taken by the histogram panel.
+const HISTOGRAM_WIDTH_PERCENT: u16 = 35;
+
+/// Chart coordinates, seconds since the start and milliseconds.
+type Point = (f64, f64);
+
+/// Which latency lines are drawn on the chart, cycled with a keybinding.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChartLines {
+    #[default]
+    Both,
+    Trend,
+    Raw,
+}
+
+impl ChartLines {
+    pub fn next(self) -> Self {
+        match self {
+            ChartLines::Both => ChartLines::Trend,
+            ChartLines::Trend => ChartLines::Raw,
+            ChartLines::Raw => ChartLines::Both,
+        }
+    }
+}
 
-#[derive(Default)]
 pub struct LatencyComponent {
+    pub target: Option<String>,
+
     pub packets_total: Option<u32>,
     pub packets_sent: u32,
+    pub packets_skipped: u32,
     pub packets_received: u32,
     pub packet_loss: f32,
 
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+
     pub min_latency: Duration,
     pub avg_latency: Duration,
     pub max_latency: Duration,
+    pub percentiles: Option<Percentiles>,
+    pub send_rate: Option<SendRate>,
+
+    pub events: Vec<(Duration, Event)>,
+
+    /// Smoothing factor of the trend line, weight of the newest sample
+    pub ewma_alpha: f64,
+    pub chart_lines: ChartLines,
+    pub format: DisplayFormat,
+    /// Time span shown by the chart
+    pub chart_window: Duration,
+    pub show_histogram: bool,
+    pub histogram_buckets: usize,
+    /// Latencies of the whole run in microseconds
+    histogram: Histogram<u64>,
+    /// (seconds, milliseconds) points for the raw and smoothed lines
+    raw: VecDeque<(f64, f64)>,
+    trend: VecDeque<(f64, f64)>,
+    /// Send times in seconds of the packets that went unanswered
+    lost: VecDeque<f64>,
+}
+
+impl LatencyComponent {
+    pub fn new(ewma_alpha: f64) -> Self {
+        Self {
+            target: None,
+            packets_total: None,
+            packets_sent: 0,
+            packets_skipped: 0,
+            packets_received: 0,
+            packet_loss: 0.0,
+            bytes_sent: 0,
+            bytes_received: 0,
+            min_latency: Duration::ZERO,
+            avg_latency: Duration::ZERO,
+            max_latency: Duration::ZERO,
+            percentiles: None,
+            send_rate: None,
+            events: Vec::new(),
+            ewma_alpha,
+            chart_lines: ChartLines::default(),
+            format: DisplayFormat::default(),
+            chart_window: Duration::from_secs(60),
+            show_histogram: true,
+            histogram_buckets: 20,
+            histogram: Histogram::new_with_bounds(1, HISTOGRAM_MAX.as_micros() as u64, 2)
+                .expect("valid histogram bounds"),
+            raw: VecDeque::new(),
+            trend: VecDeque::new(),
+            lost: VecDeque::new(),
+        }
+    }
+
+    fn push_sample(&mut self, at: Duration, latency: Duration) {
+        let x = at.as_secs_f64();
+        let y = latency.as_secs_f64() * 1000.0;
+
+        // Both lines share the x coordinates, so only the smoothed value is derived
+        let smoothed = match self.trend.back() {
+            Some(&(_, previous)) => self.ewma_alpha * y + (1.0 - self.ewma_alpha) * previous,
+            None => y,
+        };
+        self.raw.push_back((x, y));
+        self.trend.push_back((x, smoothed));
+        self.histogram
+            .saturating_record(latency.as_micros().clamp(1, u64::MAX as u128) as u64);
+
+        let cutoff = x - MAX_CHART_WINDOW.as_secs_f64();
+        while self.raw.front().is_some_and(|&(t, _)| t < cutoff) {
+            self.raw.pop_front();
+            self.trend.pop_front();
+        }
+        while self.lost.front().is_some_and(|&t| t < cutoff) {
+            self.lost.pop_front();
+        }
+    }
+
+    fn draw_chart(&mut self, f: &mut Frame<'_>, rect: Rect) {
+        if rect.height < 3 || self.raw.is_empty() {
+            return;
+        }
+
+        let end = self.raw.back().map_or(0.0, |&(t, _)| t);
+        let start = (end - self.chart_window.as_secs_f64()).max(0.0);
+
+        let raw = self.raw.make_contiguous();
+        let first = raw.partition_point(|&(t, _)| t < start);
+        let raw = &raw[first..];
+        let trend = &self.trend.make_contiguous()[first..];
+
+        let ceiling = raw.iter().map(|&(_, y)| y).fold(0.0, f64::max) * 1.1;
+
+        // Braille draws two dots per cell, more points than that only cost time
+        let columns = rect.width as usize * 2;
+        let step = (end - start).max(1.0) / columns.max(1) as f64;
+        let (raw, trend): (Cow<[Point]>, Cow<[Point]>) = match raw.len() > columns * 2 {
+            true => {
+                let (envelope, average) = downsample(raw, trend, start, step);
+                (envelope.into(), average.into())
+            }
+            false => (raw.into(), trend.into()),
+        };
+
+        // Lost packets have no latency, they are marked along the top edge
+        let mut lost: Vec<(f64, f64)> = self
+            .lost
+            .iter()
+            .filter(|&&t| t >= start)
+            .map(|&t| (t, ceiling))
+            .collect();
+        lost.dedup_by_key(|&mut (t, _)| ((t - start) / step) as usize);
+
+        let mut datasets = Vec::new();
+        if self.chart_lines != ChartLines::Trend {
+            datasets.push(
+                Dataset::default()
+                    .name("raw")
+                    .marker(Marker::Braille)
+                    .graph_type(GraphType::Line)
+                    .style(Style::default().fg(Color::DarkGray))
+                    .data(&raw),
+            );
+        }
+        if self.chart_lines != ChartLines::Raw {
+            datasets.push(
+                Dataset::default()
+                    .name(format!("ewma α={}", self.ewma_alpha))
+                    .marker(Marker::Braille)
+                    .graph_type(GraphType::Line)
+                    .style(Style::default().fg(Color::Cyan))
+                    .data(&trend),
+            );
+        }
+        if !lost.is_empty() {
+            datasets.push(
+                Dataset::default()
+                    .name("lost")
+                    .marker(Marker::Dot)
+                    .graph_type(GraphType::Scatter)
+                    .style(Style::default().fg(Color::Red))
+                    .data(&lost),
+            );
+        }
+
+        let chart = Chart::new(datasets)
+            .x_axis(
+                Axis::default()
+                    .bounds([start, end.max(start + 1.0)])
+                    .labels(vec![
+                        format!("{:.0}s", start).dim(),
+                        format!("{:.0}s", end).dim(),
+                    ]),
+            )
+            .y_axis(
+                Axis::default()
+                    .bounds([0.0, ceiling.max(0.001)])
+                    .labels(vec![
+                        "0".dim(),
+                        self.format
+                            .duration(Duration::from_secs_f64(ceiling / 1000.0))
+                            .dim(),
+                    ]),
+            );
+
+        f.render_widget(chart, rect);
+    }
+
+    /// Distribution of all latencies so far in equally wide buckets, one bar each.
+    fn draw_histogram(&self, f: &mut Frame<'_>, rect: Rect) {
+        if rect.height < 3 || self.histogram.is_empty() {
+            return;
+        }
+
+        let block = Block::new()
+            .title("Distribution".dim())
+            .borders(Borders::LEFT);
+        let buckets = self
+            .histogram_buckets
+            .min(block.inner(rect).height as usize)
+            .max(1) as u64;
+
+        let low = self.histogram.min();
+        let high = self.histogram.value_at_quantile(HISTOGRAM_RANGE).max(low);
+        let width = ((high - low) / buckets + 1).max(1);
+
+        let bars: Vec<Bar> = (0..buckets)
+            .map(|i| {
+                let start = low + i * width;
+                // The last bucket also holds everything beyond the range
+                let end = match i + 1 == buckets {
+                    true => self.histogram.max(),
+                    false => start + width - 1,
+                };
+                let count = self.histogram.count_between(start, end);
+                Bar::default()
+                    .value(count)
+                    .text_value(count.to_string())
+                    .label(Line::from(
+                        self.format.duration(Duration::from_micros(start)),
+                    ))
+            })
+            .collect();
+
+        let chart = BarChart::default()
+            .block(block)
+            .direction(Direction::Horizontal)
+            .bar_width(1)
+            .bar_gap(0)
+            .bar_style(Style::default().fg(Color::Cyan))
+            .value_style(Style::default().fg(Color::Black).bg(Color::Cyan))
+            .data(BarGroup::default().bars(&bars));
+
+        f.render_widget(chart, rect);
+    }
 }
 
 impl Component for LatencyComponent {
     fn update(&mut self, action: Action) -> Result<Option<Action>> {
         match action {
-            Action::LatencyPacketTotal(p) => self.packets_total = Some(p),
-            Action::LatencyPacketsSent(p) => self.packets_sent = p,
-            Action::LatencyPacketsReceived(p, min, avg, max) => {
+            Action::LatencyTarget(_, target) => self.target = Some(target),
+            Action::LatencyPacketTotal(_, p) => self.packets_total = Some(p),
+            Action::LatencyPacketsSent(_, p) => self.packets_sent = p,
+            Action::LatencyPacketsSkipped(_, p) => self.packets_skipped = p,
+            Action::LatencyPacketsReceived(_, p, min, avg, max) => {
                 self.packets_received = p;
                 self.min_latency = min;
                 self.avg_latency = avg;
@@ -34,19 +302,52 @@ impl Component for LatencyComponent {
 
                 self.packet_loss = 1.0 - (self.packets_received as f32 / self.packets_sent as f32);
             }
+            Action::LatencyEvent(_, at, event) => self.events.push((at, event)),
+            Action::LatencyPercentiles(_, percentiles) => self.percentiles = Some(percentiles),
+            Action::LatencySendRate(_, rate) => self.send_rate = Some(rate),
+            Action::LatencyTraffic(_, sent, received) => {
+                self.bytes_sent = sent;
+                self.bytes_received = received;
+            }
+            Action::LatencySample(_, at, latency) => self.push_sample(at, latency),
+            Action::LatencyLost(_, sent) => self.lost.push_back(sent.as_secs_f64()),
             _ => {}
         }
         Ok(None)
     }
 
     fn draw(&mut self, f: &mut Frame<'_>, rect: Rect) -> Result<()> {
-        let block = Block::new().title("Latency").borders(Borders::ALL);
+        let title = match self.target {
+            Some(ref target) => format!("Latency — {}", target),
+            None => "Latency".to_string(),
+        };
+        let down = self
+            .events
+            .iter()
+            .rev()
+            .find_map(|(_, e)| match e {
+                Event::Down { .. } => Some(true),
+                Event::Up(_) => Some(false),
+                _ => None,
+            })
+            .unwrap_or(false);
+        let title = match down {
+            true => Line::from(vec![title.into(), " DOWN ".white().on_red().bold()]),
+            false => Line::from(title),
+        };
+        let block = Block::new().title(title).borders(Borders::ALL);
 
-        let min_text = Line::from(format!("Min latency: {:#?}", self.min_latency).green());
-        let avg_text = Line::from(format!("Avg latency: {:#?}", self.avg_latency).blue());
-        let max_text = Line::from(format!("Max latency: {:#?}", self.max_latency).red());
+        let min_text =
+            Line::from(format!("Min latency: {}", self.format.duration(self.min_latency)).green());
+        let avg_text =
+            Line::from(format!("Avg latency: {}", self.format.duration(self.avg_latency)).blue());
+        let max_text =
+            Line::from(format!("Max latency: {}", self.format.duration(self.max_latency)).red());
 
-        let packet_loss_text = format!("Packet loss: {:.2}%", self.packet_loss * 100.0);
+        let packet_loss_text = format!(
+            "Packet loss: {}",
+            self.format.percent(self.packet_loss as f64)
+        );
         let packet_loss_text = match (self.packet_loss * 100.0f32).round() as u32 {
             0..=1 => packet_loss_text.green(),
             2..=10 => packet_loss_text.yellow(),
@@ -54,10 +355,73 @@ impl Component for LatencyComponent {
         };
         let packet_loss_text = Line::from(packet_loss_text);
 
-        let statistics =
-            Paragraph::new(vec![min_text, avg_text, max_text, packet_loss_text]).block(block);
+        let mut lines = vec![min_text, avg_text, max_text];
+        if let Some(p) = self.percentiles {
+            lines.push(Line::from(format!(
+                "Percentiles: p50 {}  p90 {}  p99 {}  p99.9 {}",
+                self.format.duration(p.p50),
+                self.format.duration(p.p90),
+                self.format.duration(p.p99),
+                self.format.duration(p.p999)
+            )));
+        }
+        lines.push(packet_loss_text);
+        if let Some(rate) = self.send_rate {
+            if let (Some(pps), Some(bps)) = (rate.packets_per_second(), rate.bits_per_second()) {
+                let text = format!(
+                    "Interval: {:?} ({:.1} pkt/s), achieved {:.1} pkt/s, {} offered",
+                    rate.interval,
+                    rate.expected_packets_per_second(),
+                    pps,
+                    format_bitrate(bps)
+                );
+                lines.push(Line::from(if rate.on_schedule() {
+                    text.into()
+                } else {
+                    text.yellow()
+                }));
+            }
+        }
+
+        let route_changes = self
+            .events
+            .iter()
+            .filter(|(_, e)| matches!(e, Event::Route(_)))
+            .count();
+        if let Some((at, event)) = self.events.last() {
+            let text = format!("{:.1?}: {}", at, event);
+            lines.push(Line::from(if route_changes > 1 {
+                format!("{} ({} route changes)", text, route_changes - 1).yellow()
+            } else {
+                text.dim()
+            }));
+        }
+
+        let chart_area = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![
+                Constraint::Length(lines.len() as u16),
+                Constraint::Min(0),
+                Constraint::Length(if self.packets_total.is_some() { 2 } else { 0 }),
+            ])
+            .split(block.inner(rect))[1];
+
+        let statistics = Paragraph::new(lines).block(block);
 
         f.render_widget(statistics, rect);
+        if self.show_histogram {
+            let areas = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints(vec![
+                    Constraint::Percentage(100 - HISTOGRAM_WIDTH_PERCENT),
+                    Constraint::Percentage(HISTOGRAM_WIDTH_PERCENT),
+                ])
+                .split(chart_area);
+            self.draw_chart(f, areas[0]);
+            self.draw_histogram(f, areas[1]);
+        } else {
+            self.draw_chart(f, chart_area);
+        }
 
         // Packet counter
         let layout = Layout::default()
@@ -71,11 +435,19 @@ impl Component for LatencyComponent {
 
         let rect = layout[0];
 
-        let s = if let Some(total) = self.packets_total {
+        let mut s = if let Some(total) = self.packets_total {
             format!("Packets sent: {}/{}", self.packets_sent, total)
         } else {
             format!("Packets sent: {}", self.packets_sent)
         };
+        if self.packets_skipped > 0 {
+            s += &format!(" ({} skipped)", self.packets_skipped);
+        }
+        s += &format!(
+            " · {} ↑ {} ↓",
+            format_bytes(self.bytes_sent),
+            format_bytes(self.bytes_received)
+        );
 
         let block = Block::default().title(Title::from(s.dim()).alignment(Alignment::Right));
         f.render_widget(block, rect);
@@ -87,7 +459,7 @@ impl Component for LatencyComponent {
             let gauge = LineGauge::default()
                 .block(gauge_block)
                 .gauge_style(Style::default().fg(Color::Red))
-                .ratio(self.packets_sent as f64 / total as f64);
+                .ratio((self.packets_sent + self.packets_skipped) as f64 / total as f64);
 
             f.render_widget(gauge, layout[2]);
         }
@@ -95,3 +467,39 @@ impl Component for LatencyComponent {
         Ok(())
     }
 }
+
+/// Reduces the points to what a chart `step` seconds per dot can show. Each column
+/// keeps its lowest and highest raw sample in the order they happened, so spikes
+/// stay visible, and the average of the trend line.
+fn downsample(raw: &[Point], trend: &[Point], start: f64, step: f64) -> (Vec<Point>, Vec<Point>) {
+    let mut envelope = Vec::new();
+    let mut average = Vec::new();
+
+    let mut i = 0;
+    while i < raw.len() {
+        let column = ((raw[i].0 - start) / step) as usize;
+        let end = i + raw[i..].partition_point(|&(t, _)| ((t - start) / step) as usize == column);
+
+        let samples = &raw[i..end];
+        let lowest = samples
+            .iter()
+            .fold(samples[0], |a, &b| if b.1 < a.1 { b } else { a });
+        let highest = samples
+            .iter()
+            .fold(samples[0], |a, &b| if b.1 > a.1 { b } else { a });
+        match lowest.0 <= highest.0 {
+            true => envelope.extend([lowest, highest]),
+            false => envelope.extend([highest, lowest]),
+        }
+
+        let count = (end - i) as f64;
+        let (x, y) = trend[i..end]
+            .iter()
+            .fold((0.0, 0.0), |(x, y), &(t, v)| (x + t, y + v));
+        average.push((x / count, y / count));
+
+        i = end;
+    }
+
+    (envelope, average)
+}
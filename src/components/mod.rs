@@ -1,5 +1,10 @@
+pub(crate) mod bandwidth;
 pub(crate) mod client_view;
+pub(crate) mod collector_view;
+pub(crate) mod history_view;
 pub(crate) mod latency;
+pub(crate) mod run_view;
+pub(crate) mod server_view;
 
 use color_eyre::eyre::Result;
 use crossterm::event::{KeyEvent, MouseEvent};
@@ -0,0 +1,193 @@
+use std::time::Duration;
+
+use color_eyre::eyre::Result;
+use ratatui::{
+    prelude::*,
+    symbols::Marker,
+    widgets::{Axis, Block, Borders, Chart, Dataset, GraphType, Paragraph, Row, Table},
+};
+
+use super::{Component, Frame};
+use crate::{
+    units::DisplayFormat,
+    view::{figure_rows, Change, RecordedRun},
+};
+
+/// Chart coordinates, seconds since the start and milliseconds.
+type Point = (f64, f64);
+
+/// Replays an exported latency run, with a second run drawn dimmed behind it
+/// and the differences to it in the stats table.
+pub struct RunView {
+    run: RecordedRun,
+    compared: Option<RecordedRun>,
+    format: DisplayFormat,
+}
+
+impl RunView {
+    pub fn new(run: RecordedRun, compared: Option<RecordedRun>, format: DisplayFormat) -> Self {
+        Self {
+            run,
+            compared,
+            format,
+        }
+    }
+
+    fn draw_chart(&self, f: &mut Frame<'_>, rect: Rect) {
+        if rect.height < 3 {
+            return;
+        }
+
+        let end = self
+            .compared
+            .as_ref()
+            .map_or(0.0, RecordedRun::span)
+            .max(self.run.span());
+        let ceiling = [Some(&self.run), self.compared.as_ref()]
+            .into_iter()
+            .flatten()
+            .flat_map(|run| run.latencies.iter().map(|&(_, y)| y))
+            .fold(0.0, f64::max)
+            * 1.1;
+
+        // Braille draws two dots per cell, more points than that only cost time
+        let step = end.max(1.0) / (rect.width as usize * 2).max(1) as f64;
+        let latencies = peaks(&self.run.latencies, step);
+        let compared = self
+            .compared
+            .as_ref()
+            .map(|run| peaks(&run.latencies, step));
+
+        let mut lost: Vec<Point> = self.run.lost_at.iter().map(|&t| (t, ceiling)).collect();
+        lost.dedup_by_key(|&mut (t, _)| (t / step) as usize);
+
+        let mut datasets = Vec::new();
+        if let (Some(run), Some(points)) = (&self.compared, &compared) {
+            datasets.push(
+                Dataset::default()
+                    .name(run.name.as_str())
+                    .marker(Marker::Braille)
+                    .graph_type(GraphType::Line)
+                    .style(Style::default().fg(Color::DarkGray))
+                    .data(points),
+            );
+        }
+        datasets.push(
+            Dataset::default()
+                .name(self.run.name.as_str())
+                .marker(Marker::Braille)
+                .graph_type(GraphType::Line)
+                .style(Style::default().fg(Color::Cyan))
+                .data(&latencies),
+        );
+        if !lost.is_empty() {
+            datasets.push(
+                Dataset::default()
+                    .name("lost")
+                    .marker(Marker::Dot)
+                    .graph_type(GraphType::Scatter)
+                    .style(Style::default().fg(Color::Red))
+                    .data(&lost),
+            );
+        }
+
+        let chart = Chart::new(datasets)
+            .x_axis(
+                Axis::default()
+                    .bounds([0.0, end.max(1.0)])
+                    .labels(vec!["0s".dim(), format!("{:.0}s", end).dim()]),
+            )
+            .y_axis(
+                Axis::default()
+                    .bounds([0.0, ceiling.max(0.001)])
+                    .labels(vec![
+                        "0".dim(),
+                        self.format
+                            .duration(Duration::from_secs_f64(ceiling / 1000.0))
+                            .dim(),
+                    ]),
+            );
+
+        f.render_widget(chart, rect);
+    }
+
+    fn draw_table(&self, f: &mut Frame<'_>, rect: Rect) {
+        let rows: Vec<Row> = figure_rows(&self.run, self.compared.as_ref(), &self.format)
+            .into_iter()
+            .map(|row| {
+                let mut cells = vec![Span::from(row.figure), Span::from(row.value)];
+                if let Some(compared) = row.compared {
+                    cells.push(Span::from(compared).dim());
+                }
+                if let Some((delta, change)) = row.delta {
+                    cells.push(match change {
+                        Change::Better => delta.green(),
+                        Change::Worse => delta.red(),
+                        Change::Same => delta.into(),
+                    });
+                }
+                Row::new(cells)
+            })
+            .collect();
+
+        let (header, widths) = match self.compared {
+            Some(_) => (
+                vec!["", "This run", "Compared", "Delta"],
+                vec![
+                    Constraint::Length(10),
+                    Constraint::Length(14),
+                    Constraint::Length(14),
+                    Constraint::Min(20),
+                ],
+            ),
+            None => (
+                vec!["", "This run"],
+                vec![Constraint::Length(10), Constraint::Min(14)],
+            ),
+        };
+        let table = Table::new(rows)
+            .header(Row::new(header).bold().bottom_margin(1))
+            .widths(&widths);
+        f.render_widget(table, rect);
+    }
+}
+
+impl Component for RunView {
+    fn draw(&mut self, f: &mut Frame<'_>, rect: Rect) -> Result<()> {
+        let layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![Constraint::Min(0), Constraint::Length(1)])
+            .split(rect);
+        f.render_widget(Paragraph::new("q quit".dim()), layout[1]);
+
+        let title = match self.compared {
+            Some(ref compared) => format!("{} vs. {}", self.run.name, compared.name),
+            None => self.run.name.clone(),
+        };
+        let block = Block::new().title(title).borders(Borders::ALL);
+        let inner = block.inner(layout[0]);
+        f.render_widget(block, layout[0]);
+
+        let layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![Constraint::Min(0), Constraint::Length(13)])
+            .split(inner);
+        self.draw_chart(f, layout[0]);
+        self.draw_table(f, layout[1]);
+
+        Ok(())
+    }
+}
+
+/// Keeps the highest latency of every `step` seconds, so spikes stay visible when
+/// there are more probes than the chart can draw.
+fn peaks(points: &[Point], step: f64) -> Vec<Point> {
+    let mut peaks: Vec<Point> = Vec::new();
+    for &(t, y) in points {
+        match peaks.last_mut() {
+            Some(last) if (last.0 / step) as usize == (t / step) as usize => last.1 = last.1.max(y),
+            _ => peaks.push((t, y)),
+        }
+    }
+    peaks
+}
@@ -0,0 +1,179 @@
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+
+use color_eyre::eyre::Result;
+use ratatui::{
+    prelude::*,
+    widgets::{Block, Borders, Paragraph, Row, Table},
+};
+
+use super::{Component, Frame};
+use crate::{
+    action::Action,
+    network::{
+        bandwidth::{format_bitrate, format_bytes},
+        echo::ClientActivity,
+    },
+};
+
+/// Clients heard from within this window count as connected.
+const ACTIVE_WINDOW: Duration = Duration::from_secs(10);
+
+/// A client with its rates over the last update.
+struct ClientRow {
+    activity: ClientActivity,
+    packets_per_second: f64,
+    bits_per_second: f64,
+}
+
+pub struct ServerView {
+    port: u16,
+    pairing_code: Option<String>,
+    uptime: Duration,
+    clients: Vec<ClientRow>,
+}
+
+impl ServerView {
+    pub fn new(port: u16, pairing_code: Option<String>) -> Self {
+        Self {
+            port,
+            pairing_code,
+            uptime: Duration::ZERO,
+            clients: Vec::new(),
+        }
+    }
+
+    fn update_clients(&mut self, uptime: Duration, mut clients: Vec<ClientActivity>) {
+        let previous: HashMap<SocketAddr, ClientActivity> = self
+            .clients
+            .iter()
+            .map(|row| (row.activity.address, row.activity))
+            .collect();
+        let seconds = (uptime - self.uptime).as_secs_f64();
+
+        clients.sort_by(|a, b| {
+            b.last_seen
+                .cmp(&a.last_seen)
+                .then(a.address.cmp(&b.address))
+        });
+        self.clients = clients
+            .into_iter()
+            .map(|activity| {
+                // Clients that showed up since the last update have no rate yet
+                let (packets, bytes) = previous
+                    .get(&activity.address)
+                    .map_or((activity.packets, activity.bytes), |p| (p.packets, p.bytes));
+                let rate = |now: u64, before: u64| match seconds > 0.0 {
+                    true => now.saturating_sub(before) as f64 / seconds,
+                    false => 0.0,
+                };
+                ClientRow {
+                    packets_per_second: rate(activity.packets, packets),
+                    bits_per_second: rate(activity.bytes, bytes) * 8.0,
+                    activity,
+                }
+            })
+            .collect();
+        self.uptime = uptime;
+    }
+
+    fn is_active(&self, row: &ClientRow) -> bool {
+        self.uptime - row.activity.last_seen < ACTIVE_WINDOW
+    }
+}
+
+impl Component for ServerView {
+    fn update(&mut self, action: Action) -> Result<Option<Action>> {
+        if let Action::ServerActivity(uptime, clients) = action {
+            self.update_clients(uptime, clients);
+        }
+        Ok(None)
+    }
+
+    fn draw(&mut self, f: &mut Frame<'_>, rect: Rect) -> Result<()> {
+        let layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![Constraint::Min(0), Constraint::Length(1)])
+            .split(rect);
+        f.render_widget(Paragraph::new("q quit".dim()), layout[1]);
+
+        let block = Block::new().title("Server").borders(Borders::ALL);
+        let inner = block.inner(layout[0]);
+        f.render_widget(block, layout[0]);
+
+        let layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![Constraint::Length(3), Constraint::Min(0)])
+            .split(inner);
+
+        let active = self
+            .clients
+            .iter()
+            .filter(|row| self.is_active(row))
+            .count();
+        let packets: u64 = self.clients.iter().map(|row| row.activity.packets).sum();
+        let bytes: u64 = self.clients.iter().map(|row| row.activity.bytes).sum();
+        let bits_per_second = self
+            .clients
+            .iter()
+            .fold(0.0, |sum, row| sum + row.bits_per_second);
+
+        let mut lines = vec![
+            Line::from(format!(
+                "Listening on port {}, up for {}",
+                self.port,
+                humantime::format_duration(Duration::from_secs(self.uptime.as_secs()))
+            )),
+            Line::from(
+                format!(
+                    "{} connected client(s), {}, echoed {} packets, {}",
+                    active,
+                    format_bitrate(bits_per_second),
+                    packets,
+                    format_bytes(bytes)
+                )
+                .blue(),
+            ),
+        ];
+        if let Some(ref code) = self.pairing_code {
+            lines.push(Line::from(format!("Pairing code: {}", code).yellow()));
+        }
+        f.render_widget(Paragraph::new(lines), layout[0]);
+
+        let rows: Vec<Row> = self
+            .clients
+            .iter()
+            .map(|row| {
+                let ago = self.uptime.saturating_sub(row.activity.last_seen);
+                let cells = vec![
+                    row.activity.address.to_string(),
+                    row.activity.packets.to_string(),
+                    format!("{:.1}", row.packets_per_second),
+                    format_bitrate(row.bits_per_second),
+                    format!("{}s ago", ago.as_secs()),
+                ];
+                match self.is_active(row) {
+                    true => Row::new(cells),
+                    false => Row::new(cells).dim(),
+                }
+            })
+            .collect();
+
+        let widths = [
+            Constraint::Min(24),
+            Constraint::Length(12),
+            Constraint::Length(10),
+            Constraint::Length(14),
+            Constraint::Length(12),
+        ];
+        let table = Table::new(rows)
+            .header(
+                Row::new(vec!["Client", "Echoed", "pkt/s", "Rate", "Last seen"])
+                    .bold()
+                    .bottom_margin(1),
+            )
+            .widths(&widths);
+        f.render_widget(table, layout[1]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,159 @@
+use std::{
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use clap::CommandFactory;
+use color_eyre::eyre::{bail, eyre, Result};
+use directories::ProjectDirs;
+use serde::Deserialize;
+use toml::{Table, Value};
+
+use crate::{alerts::AlertsConfig, cli::CliOptions};
+
+const FILE_NAME: &str = "config.toml";
+
+/// Settings of the config file, `config.toml` in the config directory unless
+/// another file is given.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Config {
+    pub alerts: Option<AlertsConfig>,
+    /// Options of `bwlat client` by their long names, e.g. `interval = "20ms"`
+    pub client: Option<Table>,
+    /// Options of `bwlat server`
+    pub server: Option<Table>,
+}
+
+impl Config {
+    /// Loads `path`, or the default file if there is one. Unlike the TUI
+    /// preferences a broken config is an error rather than ignored.
+    pub(crate) fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Self::default()),
+            },
+        };
+
+        let text = fs::read_to_string(&path)
+            .map_err(|e| eyre!("Cannot read config {}: {}", path.display(), e))?;
+        let config: Config =
+            toml::from_str(&text).map_err(|e| eyre!("Invalid config {}: {}", path.display(), e))?;
+        if let Some(ref alerts) = config.alerts {
+            alerts
+                .validate()
+                .map_err(|e| eyre!("Invalid config {}: {}", path.display(), e))?;
+        }
+
+        Ok(config)
+    }
+}
+
+/// Inserts the options of the `[client]` or `[server]` table right after the
+/// mode in the command line `args`, leaving out those the command line gives
+/// itself so they override the file, lists included. The config file is taken
+/// from `--config` or the default location.
+pub(crate) fn expand_args(mut args: Vec<OsString>) -> Result<Vec<OsString>> {
+    // The mode is the first argument that is not an option, none of the options
+    // before it take a value
+    let Some(position) = args
+        .iter()
+        .skip(1)
+        .position(|arg| !arg.to_string_lossy().starts_with('-'))
+        .map(|p| p + 1)
+    else {
+        return Ok(args);
+    };
+    let command = CliOptions::command();
+    let token = args[position].to_string_lossy().into_owned();
+    let mut modes = command
+        .get_subcommands()
+        .filter(|mode| mode.get_name().starts_with(&token));
+    let (Some(mode), None) = (modes.next(), modes.next()) else {
+        return Ok(args);
+    };
+    if mode.get_name() != "client" && mode.get_name() != "server" {
+        return Ok(args);
+    }
+
+    let mut path = None;
+    let mut given = Vec::new();
+    let mut rest = args[position + 1..].iter();
+    while let Some(arg) = rest.next() {
+        let arg = arg.to_string_lossy();
+        if arg == "--config" {
+            path = rest.next().map(PathBuf::from);
+        } else if let Some(value) = arg.strip_prefix("--config=") {
+            path = Some(PathBuf::from(value));
+        }
+
+        if let Some(long) = arg.strip_prefix("--") {
+            given.push(long.split('=').next().unwrap_or(long).to_string());
+        } else if let Some(shorts) = arg.strip_prefix('-') {
+            given.extend(
+                mode.get_arguments()
+                    .filter(|a| a.get_short().is_some_and(|c| shorts.contains(c)))
+                    .filter_map(|a| Some(a.get_long()?.to_string())),
+            );
+        }
+    }
+
+    let config = Config::load(path.as_deref())?;
+    let table = match mode.get_name() {
+        "client" => config.client,
+        _ => config.server,
+    };
+    let Some(table) = table else {
+        return Ok(args);
+    };
+
+    let options = arguments(mode, &table, &given)
+        .map_err(|e| eyre!("Invalid config [{}]: {}", mode.get_name(), e))?;
+    args.splice(position + 1..position + 1, options);
+    Ok(args)
+}
+
+/// Turns the entries of `table` into options of `mode`: `true` into a flag,
+/// `false` into nothing, arrays into one option per element and everything else
+/// into a value. Skips the options named in `given`.
+fn arguments(mode: &clap::Command, table: &Table, given: &[String]) -> Result<Vec<OsString>> {
+    let mut arguments = Vec::new();
+    for (key, value) in table {
+        let name = key.replace('_', "-");
+        if !mode
+            .get_arguments()
+            .any(|arg| arg.get_long() == Some(name.as_str()))
+        {
+            bail!("{} is not an option of bwlat {}", key, mode.get_name());
+        }
+        if given.contains(&name) {
+            continue;
+        }
+
+        let values = match value {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                Value::Boolean(true) => {
+                    arguments.push(format!("--{}", name).into());
+                    continue;
+                }
+                Value::Boolean(false) => continue,
+                Value::String(value) => value.clone(),
+                Value::Integer(_) | Value::Float(_) | Value::Datetime(_) => value.to_string(),
+                Value::Array(_) | Value::Table(_) => bail!("{} cannot be nested", key),
+            };
+            arguments.push(format!("--{}={}", name, value).into());
+        }
+    }
+    Ok(arguments)
+}
+
+fn default_path() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "bwlat").map(|dirs| dirs.config_dir().join(FILE_NAME))
+}
@@ -0,0 +1,347 @@
+use std::{
+    fs,
+    io::{self, IsTerminal},
+    net::{Ipv4Addr, SocketAddr, TcpListener, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tracing::{info, warn};
+
+/// Socket buffer limit below which fast latency or bandwidth tests drop packets
+/// in the kernel rather than on the link.
+const MIN_BUFFER_MAX: u64 = 4 * 1024 * 1024;
+
+/// Median oversleep above which short intervals are sent late.
+const MAX_TIMER_LATENESS: Duration = Duration::from_micros(500);
+
+/// Smallest terminal the TUI lays out without truncating its charts.
+const MIN_TERMINAL_SIZE: (u16, u16) = (80, 24);
+
+/// Outcome of one check, with a fix when something needs one.
+struct Check {
+    name: &'static str,
+    ok: bool,
+    detail: String,
+    fix: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            ok: true,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name,
+            ok: false,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    /// Passes, but the hint still applies, e.g. how to open a port.
+    fn hint(mut self, hint: impl Into<String>) -> Self {
+        self.fix = Some(hint.into());
+        self
+    }
+}
+
+/// Checks the parts of this host that skew measurements or keep modes from
+/// working and prints how to fix what it finds.
+pub(crate) struct Doctor {
+    ports: Vec<u16>,
+}
+
+impl Doctor {
+    pub(crate) fn new(ports: Vec<u16>) -> Self {
+        Self { ports }
+    }
+
+    pub(crate) fn run(&self) {
+        let checks = self.checks();
+        for check in checks.iter() {
+            match check.ok {
+                true => info!("ok    {:<16} {}", check.name, check.detail),
+                false => warn!("warn  {:<16} {}", check.name, check.detail),
+            }
+            if let Some(ref fix) = check.fix {
+                info!("      {:<16} {}", "", fix);
+            }
+        }
+
+        match checks.iter().filter(|c| !c.ok).count() {
+            0 => info!("No problems found"),
+            n => warn!("{} problem(s) found", n),
+        }
+    }
+
+    /// The findings as plain text, for bug reports.
+    pub(crate) fn report(&self) -> String {
+        let mut report = String::new();
+        for check in self.checks() {
+            let status = match check.ok {
+                true => "ok",
+                false => "warn",
+            };
+            report += &format!("{:<5} {:<16} {}\n", status, check.name, check.detail);
+            if let Some(fix) = check.fix {
+                report += &format!("      {:<16} {}\n", "", fix);
+            }
+        }
+        report
+    }
+
+    fn checks(&self) -> Vec<Check> {
+        let mut checks = vec![clock_source(), timer_resolution()];
+        checks.extend(socket_buffers());
+        checks.extend(self.ports.iter().map(|&port| firewall(port)));
+        checks.push(icmp_sockets());
+        checks.extend(packet_sockets());
+        checks.push(terminal());
+        checks
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn clock_source() -> Check {
+    const NAME: &str = "clock source";
+    let path = "/sys/devices/system/clocksource/clocksource0";
+    let Ok(current) = fs::read_to_string(format!("{}/current_clocksource", path)) else {
+        return Check::ok(NAME, "unknown, /sys is not available");
+    };
+    let current = current.trim();
+    let available =
+        fs::read_to_string(format!("{}/available_clocksource", path)).unwrap_or_default();
+
+    match current {
+        "tsc" | "kvm-clock" | "arch_sys_counter" => Check::ok(NAME, current),
+        _ if available.split_whitespace().any(|s| s == "tsc") => Check::warn(
+            NAME,
+            format!("{}, reading the time is slow and coarse", current),
+            format!(
+                "switch to tsc: echo tsc | sudo tee {}/current_clocksource",
+                path
+            ),
+        ),
+        _ => Check::warn(
+            NAME,
+            format!("{}, reading the time is slow and coarse", current),
+            "the kernel marked the TSC unstable, check dmesg for \"clocksource\"; on a VM \
+             enable the paravirtual clock",
+        ),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn clock_source() -> Check {
+    let resolution = (0..1000)
+        .map(|_| {
+            let start = Instant::now();
+            let mut now = Instant::now();
+            while now == start {
+                now = Instant::now();
+            }
+            now - start
+        })
+        .min()
+        .unwrap_or_default();
+    Check::ok("clock source", format!("ticks every {:?}", resolution))
+}
+
+fn timer_resolution() -> Check {
+    const NAME: &str = "timer";
+    const SAMPLES: usize = 50;
+    let sleep = Duration::from_millis(1);
+
+    // How late the OS wakes a sleeping thread, on top of the millisecond the
+    // async timers round to. Timer slack, power saving and VMs add to it
+    let mut late: Vec<Duration> = (0..SAMPLES)
+        .map(|_| {
+            let start = Instant::now();
+            std::thread::sleep(sleep);
+            start.elapsed().saturating_sub(sleep)
+        })
+        .collect();
+    late.sort();
+    let late = late[SAMPLES / 2];
+
+    let detail = format!("{:?} sleeps wake {:?} late", sleep, late);
+    match late > MAX_TIMER_LATENESS {
+        false => Check::ok(NAME, detail),
+        true => Check::warn(
+            NAME,
+            detail,
+            "probes at short intervals go out late and jittery: disable CPU power saving \
+             (e.g. cpupower frequency-set -g performance) or use a longer --interval",
+        ),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn socket_buffers() -> Vec<Check> {
+    [
+        ("rmem_max", "receive buffers"),
+        ("wmem_max", "send buffers"),
+    ]
+    .into_iter()
+    .map(|(key, name)| {
+        let value = fs::read_to_string(format!("/proc/sys/net/core/{}", key))
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok());
+        match value {
+            None => Check::ok(name, format!("net.core.{} is not readable", key)),
+            Some(value) if value >= MIN_BUFFER_MAX => {
+                Check::ok(name, format!("net.core.{} is {} bytes", key, value))
+            }
+            Some(value) => Check::warn(
+                name,
+                format!(
+                    "net.core.{} is {} bytes, fast tests may drop in the kernel",
+                    key, value
+                ),
+                format!("sudo sysctl -w net.core.{}={}", key, MIN_BUFFER_MAX * 2),
+            ),
+        }
+    })
+    .collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn socket_buffers() -> Vec<Check> {
+    Vec::new()
+}
+
+/// Whether `port` is free to serve on, with the commands that open it in the
+/// firewalls installed here.
+fn firewall(port: u16) -> Check {
+    const NAME: &str = "port";
+    let address = SocketAddr::from((Ipv4Addr::UNSPECIFIED, port));
+    let udp = UdpSocket::bind(address).is_ok();
+    let tcp = TcpListener::bind(address).is_ok();
+    if !udp || !tcp {
+        return Check::warn(
+            NAME,
+            format!("{} is already in use", port),
+            "stop the other process or pick another --port; a running bwlat server is fine \
+             for clients",
+        );
+    }
+
+    let hint = [
+        (
+            "ufw",
+            format!("sudo ufw allow {0}/udp && sudo ufw allow {0}/tcp", port),
+        ),
+        (
+            "firewall-cmd",
+            format!(
+                "sudo firewall-cmd --add-port={0}/udp --add-port={0}/tcp",
+                port
+            ),
+        ),
+        (
+            "nft",
+            format!(
+                "sudo nft add rule inet filter input udp dport {0} accept; \
+                 sudo nft add rule inet filter input tcp dport {0} accept",
+                port
+            ),
+        ),
+    ]
+    .into_iter()
+    .find(|(tool, _)| in_path(tool))
+    .map(|(_, hint)| hint);
+
+    let check = Check::ok(NAME, format!("{} is free", port));
+    match (hint, std::env::consts::OS) {
+        (Some(hint), _) => check.hint(format!("servers need it open: {}", hint)),
+        (None, "macos") => check
+            .hint("allow incoming connections for bwlat in System Settings > Network > Firewall"),
+        (None, _) => check.hint(format!(
+            "servers need UDP and TCP {} open in any firewall on the path",
+            port
+        )),
+    }
+}
+
+fn icmp_sockets() -> Check {
+    const NAME: &str = "icmp";
+    let raw = Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4));
+    let ping = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::ICMPV4));
+    match (raw, ping) {
+        (Ok(_), _) => Check::ok(NAME, "raw sockets available"),
+        (Err(_), Ok(_)) => Check::ok(NAME, "unprivileged ping sockets available"),
+        (Err(e), Err(_)) => Check::warn(
+            NAME,
+            format!("--protocol icmp cannot open a socket: {}", e),
+            "sudo setcap cap_net_raw+ep $(command -v bwlat), or allow ping sockets with \
+             sudo sysctl -w net.ipv4.ping_group_range=\"0 2147483647\"",
+        ),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn packet_sockets() -> Option<Check> {
+    const NAME: &str = "monitor";
+    let check = match Socket::new(Domain::PACKET, Type::RAW, None) {
+        Ok(_) => Check::ok(NAME, "packet sockets available"),
+        Err(e) => Check::warn(
+            NAME,
+            format!("the monitor mode cannot capture: {}", e),
+            "run it as root or sudo setcap cap_net_raw+ep $(command -v bwlat)",
+        ),
+    };
+    Some(check)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn packet_sockets() -> Option<Check> {
+    None
+}
+
+fn terminal() -> Check {
+    const NAME: &str = "terminal";
+    if !io::stdout().is_terminal() {
+        return Check::ok(NAME, "not a terminal, clients need --no-tui");
+    }
+
+    let mut problems = Vec::new();
+    let mut fixes = Vec::new();
+    let (width, height) = crossterm::terminal::size().unwrap_or(MIN_TERMINAL_SIZE);
+    if width < MIN_TERMINAL_SIZE.0 || height < MIN_TERMINAL_SIZE.1 {
+        problems.push(format!("{}x{} is small for the TUI", width, height));
+        fixes.push(format!(
+            "enlarge the window to at least {}x{}",
+            MIN_TERMINAL_SIZE.0, MIN_TERMINAL_SIZE.1
+        ));
+    }
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .find_map(|key| std::env::var(key).ok().filter(|v| !v.is_empty()))
+        .unwrap_or_default()
+        .to_lowercase();
+    if !locale.contains("utf-8") && !locale.contains("utf8") {
+        problems.push("no UTF-8 locale, charts may render as garbage".to_string());
+        fixes.push("export LANG=C.UTF-8".to_string());
+    }
+    if std::env::var("TERM").is_ok_and(|term| term == "dumb") {
+        problems.push("TERM=dumb cannot draw the TUI".to_string());
+        fixes.push("use a terminal emulator or --no-tui".to_string());
+    }
+
+    match problems.is_empty() {
+        true => Check::ok(NAME, format!("{}x{}", width, height)),
+        false => Check::warn(NAME, problems.join(", "), fixes.join(", ")),
+    }
+}
+
+fn in_path(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .is_some_and(|paths| std::env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+}
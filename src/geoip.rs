@@ -0,0 +1,92 @@
+use std::{fmt, net::IpAddr, path::Path};
+
+use color_eyre::eyre::{Result, WrapErr};
+use maxminddb::Reader;
+use serde::{Deserialize, Serialize};
+
+/// Country and network of an address, looked up in local MaxMind databases.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub(crate) struct Location {
+    pub asn: Option<u32>,
+    pub organization: Option<String>,
+    /// ISO 3166-1 country code
+    pub country: Option<String>,
+}
+
+impl Location {
+    fn is_empty(&self) -> bool {
+        self.asn.is_none() && self.organization.is_none() && self.country.is_none()
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(asn) = self.asn {
+            parts.push(format!("AS{}", asn));
+        }
+        if let Some(ref organization) = self.organization {
+            parts.push(organization.clone());
+        }
+        if let Some(ref country) = self.country {
+            parts.push(country.clone());
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// Fields of the GeoLite2/GeoIP2 ASN, Country and City databases we care about,
+/// so any of them can be passed.
+#[derive(Deserialize)]
+struct Record<'a> {
+    autonomous_system_number: Option<u32>,
+    #[serde(borrow)]
+    autonomous_system_organization: Option<&'a str>,
+    country: Option<Country<'a>>,
+}
+
+#[derive(Deserialize)]
+struct Country<'a> {
+    #[serde(borrow)]
+    iso_code: Option<&'a str>,
+}
+
+pub(crate) struct GeoIp {
+    readers: Vec<Reader<Vec<u8>>>,
+}
+
+impl GeoIp {
+    pub(crate) fn open(paths: &[impl AsRef<Path>]) -> Result<Self> {
+        let readers = paths
+            .iter()
+            .map(|path| {
+                Reader::open_readfile(path)
+                    .wrap_err_with(|| format!("Opening {}", path.as_ref().display()))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self { readers })
+    }
+
+    /// Merges what every database knows about `address`, the first one to
+    /// answer a field wins.
+    pub(crate) fn lookup(&self, address: IpAddr) -> Option<Location> {
+        let mut location = Location::default();
+
+        for reader in self.readers.iter() {
+            let Ok(record) = reader.lookup::<Record>(address) else {
+                continue;
+            };
+
+            location.asn = location.asn.or(record.autonomous_system_number);
+            location.organization = location
+                .organization
+                .or(record.autonomous_system_organization.map(str::to_string));
+            location.country = location
+                .country
+                .or(record.country.and_then(|c| c.iso_code).map(str::to_string));
+        }
+
+        (!location.is_empty()).then_some(location)
+    }
+}
@@ -0,0 +1,42 @@
+use color_eyre::eyre::{bail, eyre, Result};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+/// Posts `body` to `http://host{path}` and checks that the server accepted it.
+/// `headers` are extra header lines, each ending in CRLF.
+pub(crate) async fn post(
+    host: &str,
+    path: &str,
+    content_type: &str,
+    headers: &str,
+    body: &str,
+) -> Result<()> {
+    let mut stream = TcpStream::connect(host).await?;
+
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n{}",
+        path,
+        host,
+        content_type,
+        body.len(),
+        headers,
+        body
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    let response = String::from_utf8_lossy(&response);
+    let status = response
+        .split_whitespace()
+        .nth(1)
+        .ok_or_else(|| eyre!("No HTTP response"))?;
+    if !status.starts_with('2') {
+        let reason = response.lines().next().unwrap_or_default();
+        bail!("{}", reason);
+    }
+
+    Ok(())
+}
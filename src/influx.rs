@@ -0,0 +1,296 @@
+use std::{
+    fmt::Write as _,
+    net::SocketAddr,
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use color_eyre::eyre::{eyre, Result};
+use tokio::{fs::File, io::AsyncWriteExt, net::UdpSocket, sync::Mutex, time};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::{
+    http,
+    metadata::Identity,
+    network::{
+        latency::{PacketStatus, State},
+        listen,
+    },
+};
+
+/// Largest datagram sent to a UDP listener, lines are never split.
+const MAX_DATAGRAM: usize = 1400;
+
+/// How often a stopping sink checks whether the engines finished draining.
+const DRAIN_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Environment variable with the API token of InfluxDB 2.
+const TOKEN_VARIABLE: &str = "INFLUX_TOKEN";
+
+/// Where the line protocol goes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum InfluxDestination {
+    File(PathBuf),
+    /// `host:port` of a UDP listener
+    Udp(String),
+    /// Write endpoint, e.g. `/write?db=bwlat` or `/api/v2/write?org=o&bucket=b`
+    Http {
+        host: String,
+        path: String,
+    },
+}
+
+impl FromStr for InfluxDestination {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(address) = s.strip_prefix("udp://") {
+            return Ok(InfluxDestination::Udp(address.to_string()));
+        }
+        if let Some(url) = s.strip_prefix("http://") {
+            return match url.split_once('/') {
+                Some((host, path)) if !host.is_empty() => Ok(InfluxDestination::Http {
+                    host: host.to_string(),
+                    path: format!("/{}", path),
+                }),
+                _ => Err("expected a write endpoint like http://host:8086/write?db=bwlat".into()),
+            };
+        }
+        if s.starts_with("https://") {
+            return Err("HTTPS is not supported, write to a local Telegraf or relay".into());
+        }
+        Ok(InfluxDestination::File(s.into()))
+    }
+}
+
+/// Streams the probes of all targets in InfluxDB line protocol: a `bwlat_probe`
+/// point per probe once its outcome is final, and a `bwlat_interval` summary
+/// of the probes written in each interval.
+pub(crate) struct InfluxSink {
+    destination: InfluxDestination,
+    targets: Vec<(String, Arc<Mutex<State>>)>,
+    interval: Duration,
+    identity: Identity,
+    quit: CancellationToken,
+}
+
+enum Output {
+    File(File),
+    Udp(UdpSocket),
+    Http { host: String, path: String },
+}
+
+impl InfluxSink {
+    pub(crate) fn new(
+        destination: InfluxDestination,
+        targets: Vec<(String, Arc<Mutex<State>>)>,
+        interval: Duration,
+        quit: CancellationToken,
+    ) -> Self {
+        Self {
+            destination,
+            targets,
+            interval,
+            identity: Identity::default(),
+            quit,
+        }
+    }
+
+    /// Tags every point with the probe name and labels.
+    pub(crate) fn with_identity(mut self, identity: Identity) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    /// Writes until the run is cancelled and every engine drained its echoes.
+    pub(crate) async fn run(self) -> Result<()> {
+        let mut output = match self.destination {
+            InfluxDestination::File(ref path) => Output::File(File::create(path).await?),
+            InfluxDestination::Udp(ref address) => {
+                let target = tokio::net::lookup_host(address.as_str())
+                    .await?
+                    .next()
+                    .ok_or_else(|| eyre!("{} did not resolve to any address", address))?;
+                let socket =
+                    UdpSocket::bind(SocketAddr::new(listen::unspecified(target.ip()), 0)).await?;
+                socket.connect(target).await?;
+                Output::Udp(socket)
+            }
+            InfluxDestination::Http { ref host, ref path } => Output::Http {
+                host: host.clone(),
+                path: path.clone(),
+            },
+        };
+        info!("Streaming line protocol to {:?}", self.destination);
+
+        let mut written = vec![0; self.targets.len()];
+        let mut ticker = time::interval_at(time::Instant::now() + self.interval, self.interval);
+        let mut stopping = false;
+
+        loop {
+            if !stopping {
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    _ = self.quit.cancelled() => stopping = true,
+                }
+            } else {
+                time::sleep(DRAIN_CHECK_INTERVAL).await;
+            }
+
+            let mut lines = String::new();
+            let mut drained = true;
+            for ((target, state), written) in self.targets.iter().zip(written.iter_mut()) {
+                let state = state.lock().await;
+                let settled = state.settled();
+                drained &= state.stopped_at.is_some() && settled == state.packets.len();
+                let mut tags = format!("target={}", escape_tag(target));
+                for (key, value) in self.identity.tags() {
+                    let _ = write!(tags, ",{}={}", escape_tag(key), escape_tag(value));
+                }
+                write_points(&mut lines, &tags, &state, *written..settled);
+                *written = settled;
+            }
+
+            if !lines.is_empty() {
+                output.write(&lines).await?;
+            }
+            if stopping && drained {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Output {
+    async fn write(&mut self, lines: &str) -> Result<()> {
+        match self {
+            Output::File(file) => {
+                file.write_all(lines.as_bytes()).await?;
+                file.flush().await?;
+            }
+            // Losing a datagram now and then is the nature of the UDP listener
+            Output::Udp(socket) => {
+                let mut datagram = String::new();
+                for line in lines.split_inclusive('\n') {
+                    if !datagram.is_empty() && datagram.len() + line.len() > MAX_DATAGRAM {
+                        if let Err(e) = socket.send(datagram.as_bytes()).await {
+                            warn!("Could not send line protocol: {}", e);
+                        }
+                        datagram.clear();
+                    }
+                    datagram.push_str(line);
+                }
+                if let Err(e) = socket.send(datagram.as_bytes()).await {
+                    warn!("Could not send line protocol: {}", e);
+                }
+            }
+            // A database that is down should not end the run
+            Output::Http { host, path } => {
+                let authorization = match std::env::var(TOKEN_VARIABLE) {
+                    Ok(token) => format!("Authorization: Token {}\r\n", token),
+                    Err(_) => String::new(),
+                };
+                let content_type = "text/plain; charset=utf-8";
+                if let Err(e) = http::post(host, path, content_type, &authorization, lines).await {
+                    warn!("Could not write line protocol to {}: {}", host, e);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Appends the points of `packets` and their interval summary, tagged with the
+/// escaped `tags`.
+fn write_points(lines: &mut String, tags: &str, state: &State, packets: std::ops::Range<usize>) {
+    let Some(started_at) = state.started_at else {
+        return;
+    };
+
+    let mut sent = 0;
+    let mut latencies = Vec::new();
+    for (n, packet) in state.packets.range(packets) {
+        let (at, latency) = match *packet {
+            PacketStatus::Skipped(_) | PacketStatus::Invalid(_) | PacketStatus::Warmup(_) => {
+                continue
+            }
+            PacketStatus::Sent(at) => (at, None),
+            PacketStatus::Received { start, latency, .. } => (start, Some(latency)),
+        };
+        sent += 1;
+
+        let timestamp = nanos(started_at + at);
+        let _ = match latency {
+            Some(latency) => {
+                latencies.push(latency);
+                writeln!(
+                    lines,
+                    "bwlat_probe,{} seq={}i,status=\"received\",rtt_ms={} {}",
+                    tags,
+                    n,
+                    millis(latency),
+                    timestamp
+                )
+            }
+            None => writeln!(
+                lines,
+                "bwlat_probe,{} seq={}i,status=\"lost\" {}",
+                tags, n, timestamp
+            ),
+        };
+    }
+
+    if sent == 0 {
+        return;
+    }
+    let received = latencies.len();
+    let _ = write!(
+        lines,
+        "bwlat_interval,{} sent={}i,received={}i,lost={}i,loss={}",
+        tags,
+        sent,
+        received,
+        sent - received,
+        (sent - received) as f64 / sent as f64
+    );
+    if let (Some(min), Some(max)) = (latencies.iter().min(), latencies.iter().max()) {
+        let average = latencies.iter().sum::<Duration>() / received as u32;
+        let _ = write!(
+            lines,
+            ",rtt_min_ms={},rtt_avg_ms={},rtt_max_ms={}",
+            millis(*min),
+            millis(average),
+            millis(*max)
+        );
+    }
+    let _ = writeln!(lines, " {}", nanos(SystemTime::now()));
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_nanos() as f64 / 1e6
+}
+
+fn nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+/// Escapes a tag value, which may not contain unescaped commas, spaces or equals
+/// signs.
+fn escape_tag(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, ',' | ' ' | '=' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -1,47 +1,165 @@
 mod action;
+mod alerts;
+mod anonymize;
+mod app;
+mod bench;
+mod bugreport;
+mod chart;
 mod cli;
 mod client;
+mod collector;
 mod components;
+mod config;
+mod doctor;
+mod geoip;
+mod http;
+mod influx;
+mod metadata;
+mod metrics;
 mod network;
+mod pairing;
+mod preferences;
+mod profile;
+mod rating;
+mod recommend;
+mod replay;
+mod results;
+mod scenario;
+mod scheduling;
 mod server;
+mod signing;
+mod streams;
+mod test_plan;
 mod tui;
+mod units;
+mod version;
+mod view;
+mod web;
 
-use clap::Parser;
+use std::{io::IsTerminal, net::SocketAddr};
+
+use clap::{error::ErrorKind, CommandFactory, Parser};
 use clap_verbosity_flag::{InfoLevel, Verbosity};
-use cli::{CliOptions, ClientOptions, ServerOptions};
+use cli::{
+    BenchOptions, CliOptions, ClientOptions, CollectorOptions, MonitorOptions, PmtuOptions,
+    RateOptions, ScenarioOptions, ServerOptions, TestPlanOptions, VerifyOptions, ViewOptions,
+};
 use client::Client;
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{bail, eyre, Result};
+use config::Config;
+use metadata::Identity;
 use server::Server;
-use tracing::error;
+use tracing::{error, info};
 use tracing_log::AsTrace;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
+
+use crate::{
+    geoip::GeoIp,
+    network::{
+        buffers::SocketBuffers,
+        latency::{BurstCapture, Protocol, UDP_IPV4_OVERHEAD, UDP_IPV6_OVERHEAD},
+        packets::DEFAULT_RETENTION,
+        protocol::HEADER_LEN,
+    },
+    replay::SendSchedule,
+    tui::Tui,
+    units::DisplayFormat,
+};
 
-use crate::tui::Tui;
+const DEFAULT_BANDWIDTH_DURATION: std::time::Duration = std::time::Duration::from_secs(10);
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let cli_options = CliOptions::parse();
+    let cli_options = CliOptions::parse_from(config::expand_args(std::env::args_os().collect())?);
+
+    let profile_self = matches!(
+        cli_options.mode,
+        Some(cli::Modes::Client(ref options)) if options.profile_self
+    );
+    let accessible = matches!(
+        cli_options.mode,
+        Some(cli::Modes::Client(ref options)) if options.accessible
+    );
+    // Kept for bug reports
+    let log_file = match cli_options.mode {
+        Some(cli::Modes::Client(_)) => bugreport::log_file("client"),
+        Some(cli::Modes::Server(_)) => bugreport::log_file("server"),
+        _ => None,
+    };
+    initialize_logging(&cli_options.verbose, profile_self, accessible, log_file)?;
+    initialize_panic_handler(accessible)?;
 
-    initialize_logging(&cli_options.verbose)?;
-    initialize_panic_handler()?;
+    if cli_options.version_json {
+        let info = version::VersionInfo::detect();
+        println!("{}", serde_json::to_string_pretty(&info)?);
+        return Ok(());
+    }
+
+    let Some(mode) = cli_options.mode else {
+        CliOptions::command()
+            .error(ErrorKind::MissingSubcommand, "A mode is required")
+            .exit()
+    };
 
-    match cli_options.mode {
+    match mode {
         cli::Modes::Server(options) => run_server(options).await?,
-        cli::Modes::Client(options) => run_client(options).await?,
+        cli::Modes::Client(options) => run_client(*options).await?,
+        cli::Modes::Verify(options) => run_verify(options)?,
+        cli::Modes::Bench(options) => run_bench(options).await?,
+        cli::Modes::TestPlan(options) => run_test_plan(options).await?,
+        cli::Modes::Monitor(options) => run_monitor(options).await?,
+        cli::Modes::Scenario(options) => run_scenario(options).await?,
+        cli::Modes::Rate(options) => run_rate(options)?,
+        cli::Modes::View(options) => run_view(options).await?,
+        cli::Modes::Pmtu(options) => run_pmtu(options).await?,
+        cli::Modes::Doctor(options) => doctor::Doctor::new(options.port).run(),
+        cli::Modes::Bugreport(options) => bugreport::create(&options.output, options.port)?,
+        cli::Modes::Collector(options) => run_collector(options).await?,
     };
 
     Ok(())
 }
 
-fn initialize_logging(verbosity: &Verbosity<InfoLevel>) -> Result<()> {
-    tracing_subscriber::FmtSubscriber::builder()
-        .with_max_level(verbosity.log_level_filter().as_trace())
+/// Accessible logging leaves out colors, timestamps and module paths, so screen
+/// readers only read the message and its level.
+fn initialize_logging(
+    verbosity: &Verbosity<InfoLevel>,
+    profile_self: bool,
+    accessible: bool,
+    log_file: Option<std::fs::File>,
+) -> Result<()> {
+    let level = verbosity.log_level_filter().as_trace();
+    let fmt = (!accessible).then(|| tracing_subscriber::fmt::layer().with_filter(level));
+    let plain = accessible.then(|| {
+        tracing_subscriber::fmt::layer()
+            .with_ansi(false)
+            .without_time()
+            .with_target(false)
+            .with_filter(level)
+    });
+    let file = log_file.map(|file| {
+        tracing_subscriber::fmt::layer()
+            .with_ansi(false)
+            .with_writer(std::sync::Mutex::new(file))
+            .with_filter(level)
+    });
+
+    tracing_subscriber::registry()
+        .with(fmt)
+        .with(plain)
+        .with(file)
+        .with(profile_self.then(profile::Profiler::layer))
         .init();
 
     Ok(())
 }
 
-pub fn initialize_panic_handler() -> Result<()> {
-    let (panic_hook, eyre_hook) = color_eyre::config::HookBuilder::default()
+pub fn initialize_panic_handler(accessible: bool) -> Result<()> {
+    let mut hooks = color_eyre::config::HookBuilder::default();
+    if accessible {
+        hooks = hooks.theme(color_eyre::config::Theme::new());
+    }
+    let (panic_hook, eyre_hook) = hooks
         .panic_section(format!(
             "This is a bug. Consider reporting it at {}",
             env!("CARGO_PKG_REPOSITORY")
@@ -78,26 +196,615 @@ pub fn initialize_panic_handler() -> Result<()> {
     Ok(())
 }
 
-async fn run_client(options: ClientOptions) -> Result<()> {
+async fn run_client(mut options: ClientOptions) -> Result<()> {
+    scheduling::apply(options.realtime, options.cpu.map(usize::from));
+    if let Some(profile) = options.profile {
+        options.interval = profile.interval().into();
+        options.packet_size = profile.packet_size();
+    }
+
+    let mut targets = options.resolve_targets().await?;
+    if targets.len() > 1 {
+        info!("Probing {} addresses of {}", targets.len(), options.address);
+    }
+    if let Some(underlay) = options.underlay {
+        if targets.contains(&underlay) {
+            bail!(
+                "--underlay {} is the target itself, give the tunnel address as the target",
+                underlay
+            );
+        }
+        targets.push(underlay);
+    }
+
+    let port = options.server_port()?;
+    if options.protocol == Protocol::Icmp
+        && (options.wait_for_server.is_some() || options.pair.is_some() || options.clock_drift)
+    {
+        bail!(
+            "--wait-for-server, --pair and --clock-drift talk to the bwlat server, which ICMP probes do not use"
+        );
+    }
+    if options.bandwidth && !matches!(options.protocol, Protocol::Udp | Protocol::Quic) {
+        bail!("--bandwidth runs over TCP, or UDP with --rate, --protocol only selects quic");
+    }
+    if options.protocol == Protocol::Quic && options.rate.is_some() {
+        bail!("--rate sends UDP datagrams, QUIC paces itself");
+    }
+    match (options.protocol, options.quic_port) {
+        (Protocol::Quic, None) => {
+            bail!("--protocol quic needs --quic-port, the port the server was given for QUIC")
+        }
+        (Protocol::Quic, Some(_)) | (_, None) => {}
+        (_, Some(_)) => bail!("--quic-port applies to --protocol quic"),
+    }
+    if options.proxy.is_some() && options.protocol != Protocol::WebSocket {
+        bail!("--proxy applies to --protocol websocket, other probes do not go through it");
+    }
+    if options.protocol == Protocol::Icmp && options.bind.is_some() {
+        bail!("--bind is not supported with --protocol icmp");
+    }
+    if !options.source_addresses.is_empty()
+        && !matches!(options.protocol, Protocol::Udp | Protocol::UdpLite)
+    {
+        bail!("--source-addresses needs --protocol udp or udplite");
+    }
+    if (options.bidirectional || options.reverse)
+        && (options.protocol != Protocol::Udp || options.streams > 1)
+    {
+        bail!("--bidirectional and --reverse need --protocol udp and a single stream");
+    }
+    let exports = options.csv.is_some()
+        || options.json.is_some()
+        || options.hgrm.is_some()
+        || options.chart_out.is_some();
+    let live =
+        options.web.is_some() || options.metrics_listen.is_some() || options.influx.is_some();
+    if options.reverse && (exports || live) {
+        bail!("--reverse only summarizes what the server measured, exports and live outputs are not supported");
+    }
+    let socket_options = options.dscp.is_some()
+        || options.tos.is_some()
+        || options.ttl.is_some()
+        || options.flow_label.is_some();
+    if socket_options && (options.protocol == Protocol::Icmp || options.reverse) {
+        bail!("--dscp, --tos, --ttl and --flow-label apply to the UDP or TCP probes of this client, which --protocol icmp and --reverse do not send");
+    }
+    if options.flow_label.is_some() && !targets.iter().any(|t| t.is_ipv6()) {
+        bail!("--flow-label needs an IPv6 server");
+    }
+    if options.packet_size < HEADER_LEN {
+        bail!(
+            "--packet-size must be at least {} bytes, the probe header",
+            HEADER_LEN
+        );
+    }
+    if options.checksum_coverage.is_some() && options.protocol != Protocol::UdpLite {
+        bail!("--checksum-coverage needs --protocol udplite");
+    }
+    if options.timestamping.is_some()
+        && (!matches!(options.protocol, Protocol::Udp | Protocol::UdpLite)
+            || options.bidirectional
+            || options.reverse)
+    {
+        bail!("--timestamping applies to the UDP probes of this client, use --protocol udp or udplite without --bidirectional and --reverse");
+    }
+    if options.busy_poll.is_some() && !matches!(options.protocol, Protocol::Udp | Protocol::UdpLite)
+    {
+        bail!("--busy-poll spins on the UDP probe sockets, use --protocol udp or udplite");
+    }
+    if options.one_way && options.protocol != Protocol::Udp {
+        bail!("--one-way needs the server to timestamp UDP echoes, use --protocol udp");
+    }
+    if (options.burst_threshold.is_some() || options.burst_loss.is_some())
+        && *options.burst_interval >= *options.interval
+    {
+        bail!(
+            "--burst-interval {} has to be shorter than the probe interval of {}",
+            options.burst_interval,
+            options.interval
+        );
+    }
+    if let (Protocol::Icmp, Some(&target)) = (options.protocol, targets.first()) {
+        // Fail on missing privileges before the TUI takes over the terminal
+        network::icmp::IcmpSocket::open(target)?;
+    }
+
+    let replay = options
+        .replay_schedule
+        .as_deref()
+        .map(SendSchedule::load)
+        .transpose()?;
+    let (interval, count) = match replay {
+        Some(ref schedule) => {
+            info!(
+                "Replaying {} probes from {}",
+                schedule.len(),
+                schedule.path.display()
+            );
+            (schedule.interval, schedule.len())
+        }
+        None => options.schedule(targets.len() as u32)?,
+    };
+    if options.auto_interval {
+        info!(
+            "Auto-selected interval: {} ({} packets)",
+            humantime::format_duration(interval),
+            count
+        );
+    }
+
     let mut client = Client::new(
         options.address,
-        options.port,
+        targets,
+        port,
         options.client_port,
         options.packet_size,
-        options.count,
+        count,
     );
 
-    client.set_interval(options.interval.into());
+    client.set_interval(interval);
+    if let Some(schedule) = replay {
+        client.set_send_schedule(schedule);
+    }
+    if options.underlay.is_some() {
+        client.enable_tunnel_comparison();
+    }
+    let exports_probes = options.csv.is_some() || options.json.is_some();
+    match options.retain {
+        Some(retention) => client.set_retention(retention),
+        None if count == 0 && !exports_probes => client.set_retention(DEFAULT_RETENTION),
+        None => {}
+    }
+    if let Some(max) = options.max_memory {
+        client.set_max_memory(max);
+    }
+    if let Some(address) = options.bind {
+        client.set_bind_address(address);
+    }
+    if !options.source_addresses.is_empty() {
+        client.set_source_addresses(options.source_addresses.clone());
+    }
+    client.set_socket_buffers(SocketBuffers {
+        receive: options.rcvbuf.map(|size| size as usize),
+        send: options.sndbuf.map(|size| size as usize),
+    });
+
+    if let Some(link_type) = options.link_type {
+        client.set_link_type(link_type);
+    }
+    if let Some(k) = options.adaptive_loss_timeout {
+        client.set_adaptive_loss_timeout(k);
+    }
+    if options.bandwidth {
+        let default_duration = options
+            .link_type
+            .map_or(DEFAULT_BANDWIDTH_DURATION, |l| l.bandwidth_duration());
+        client.enable_bandwidth(options.duration.map_or(default_duration, Into::into));
+    }
+
+    if let Some(rate) = options.rate {
+        client.set_bandwidth_rate(rate);
+    }
+    if let Some(steps) = options.ramp {
+        client.set_bandwidth_ramp(steps);
+    }
+    if let Some(size) = options.batch {
+        client.set_bandwidth_batch(size as usize);
+    }
+    if let Some(congestion) = options.congestion {
+        client.set_congestion(congestion);
+    }
+    client.set_catch_up(options.catch_up);
+    client.set_payload(options.payload);
+    client.set_protocol(options.protocol);
+    if let Some(port) = options.quic_port {
+        client.set_quic_port(port);
+    }
+    if let Some(proxy) = options.proxy {
+        client.set_proxy(proxy);
+    }
+    #[cfg(feature = "udp-no-checksum")]
+    if options.no_udp_checksum {
+        if options.protocol != Protocol::Udp {
+            bail!("--no-udp-checksum applies to --protocol udp");
+        }
+        tracing::warn!(
+            "UDP checksums are disabled: probes damaged on the path are delivered instead \
+             of dropped and the results do not reflect a real network. Use in a lab only"
+        );
+        client.disable_udp_checksum();
+    }
+    client.set_ewma_alpha(options.ewma_alpha);
+    client.set_histogram_buckets(options.histogram_buckets.into());
+    client.set_display_format(match options.accessible {
+        true => DisplayFormat::from_env(options.units).with_unit_names(),
+        false => DisplayFormat::from_env(options.units),
+    });
+    client.set_suspend_threshold(options.suspend_threshold.into());
+    if let Some(policy) = options.on_metered {
+        client.set_metered_policy(policy);
+    }
+    if !options.quiet_hours.is_empty() {
+        client.set_quiet_hours(options.quiet_hours, options.quiet_policy);
+    }
+    if let Some(profile) = options.profile {
+        client.set_stream_profile(profile, options.jitter_buffer.into());
+    }
+    if let Some(dscp) = options.dscp {
+        client.set_tos(dscp << 2);
+    }
+    if let Some(tos) = options.tos {
+        client.set_tos(tos);
+    }
+    if let Some(ttl) = options.ttl {
+        client.set_ttl(ttl);
+    }
+    if let Some(label) = options.flow_label {
+        client.set_flow_label(label, options.vary_flow_label);
+    }
+    if let Some(coverage) = options.checksum_coverage {
+        client.set_checksum_coverage(coverage);
+    }
+    if let Some(tag) = options.probe_tag {
+        client.set_probe_tag(tag);
+    }
+    if let Some(timestamping) = options.timestamping {
+        client.set_timestamping(timestamping);
+    }
+    if let Some(micros) = options.busy_poll {
+        client.set_busy_poll(micros);
+    }
+
+    if let Some(max) = options.max_bytes {
+        client.set_max_bytes(max);
+    }
+
+    if let Some(code) = options.pair {
+        client.set_pairing_code(code);
+    }
+    if let Some(patience) = options.wait_for_server {
+        client.set_wait_for_server(patience.into());
+    }
+
+    if options.burst_threshold.is_some() || options.burst_loss.is_some() {
+        client.enable_burst_capture(BurstCapture {
+            latency_threshold: options.burst_threshold.map(Into::into),
+            loss_threshold: options.burst_loss,
+            interval: options.burst_interval.into(),
+            duration: options.burst_duration.into(),
+        });
+    }
 
     if let Some(csv_path) = options.csv {
         client.enable_output_csv(csv_path);
     }
+    if let Some(path) = options.json {
+        client.enable_output_json(path);
+    }
+    if let Some(path) = options.hgrm {
+        client.enable_output_hgrm(path);
+    }
+    if let Some(path) = options.chart_out {
+        client.enable_output_chart(path);
+    }
+
+    if let Some(seed) = options.seed {
+        client.set_seed(seed);
+    }
+    if options.randomize_source_port {
+        client.set_source_ports(options.port_pool);
+    }
+    client.set_streams(options.streams as usize);
+    if options.bidirectional {
+        client.enable_bidirectional();
+    }
+    if options.reverse {
+        client.enable_reverse();
+    }
+
+    if let Some(warmup) = options.warmup {
+        client.set_warmup(warmup);
+    }
+    if let Some(after) = options.down_after {
+        let after: std::time::Duration = after.into();
+        if after.is_zero() {
+            bail!("--down-after has to be longer than zero");
+        }
+        client.set_down_detection(after, options.on_down);
+    }
+    if let Some(condition) = options.stop_when {
+        client.set_stop_condition(condition);
+    }
+    client.set_phase_names(options.phases);
+    if let Some(path) = options.phase_marker {
+        client.enable_phase_marker(path);
+    }
+    if options.clock_drift {
+        client.enable_clock_tracking();
+    }
+    if options.one_way {
+        client.enable_one_way_delay(options.trust_clocks);
+    }
+    if options.track_route {
+        client.set_tunnel_change(options.on_tunnel_change);
+        client.enable_route_tracking();
+    }
+
+    client.set_identity(Identity {
+        probe: options.probe_name,
+        labels: options.label,
+    });
+    if let Some(web) = options.web {
+        client.enable_web_dashboard(web);
+    }
+    if let Some(listen) = options.metrics_listen {
+        client.enable_metrics(listen);
+    }
+    if let Some(destination) = options.influx {
+        client.enable_influx(destination, options.influx_interval.into());
+    }
+    if let Some(collector) = options.collector {
+        client.enable_collector(collector, options.collector_interval.into());
+    }
+    if let Some(alerts) = Config::load(options.config.as_deref())?.alerts {
+        if !alerts.sinks.is_empty() {
+            client.enable_alerts(alerts);
+        }
+    }
+
+    if !options.geoip_db.is_empty() {
+        client.enable_geoip(GeoIp::open(&options.geoip_db)?);
+    }
+
+    if options.anonymize {
+        client.enable_anonymize();
+    }
+
+    if let Some(key_path) = options.sign {
+        client.enable_signing(signing::load_signing_key(&key_path)?);
+    }
+    if options.no_tui {
+        client.enable_headless();
+    }
+    if options.accessible {
+        client.enable_accessible();
+    }
+    if let Some(interval) = options.repeat_every {
+        if count == 0 && !options.bandwidth {
+            bail!("--repeat-every needs tests that end, give a --count or --duration");
+        }
+        let interval: std::time::Duration = interval.into();
+        if interval.is_zero() {
+            bail!("--repeat-every has to be longer than zero");
+        }
+        client.set_repeat_every(interval);
+    }
+    if options.profile_self {
+        client.enable_self_profile();
+    }
 
     client.run().await
 }
 
 async fn run_server(options: ServerOptions) -> Result<()> {
-    let server = Server::new(options.port);
+    scheduling::apply(options.realtime, options.cpu.map(usize::from));
+    let mut server = Server::new(options.port);
+    if let Some(address) = options.bind {
+        server.set_bind_address(address);
+    }
+
+    let stats_interval: std::time::Duration = options.stats_interval.into();
+    if !stats_interval.is_zero() {
+        server.enable_stats(stats_interval);
+    }
+    if let Some(path) = options.stats_csv {
+        server.enable_stats_csv(path);
+    }
+    if options.pair {
+        server.enable_pairing();
+    }
+    if options.require_auth {
+        server.require_auth();
+    }
+    if options.allow_amplification {
+        server.allow_amplification();
+    }
+    if options.udplite {
+        server.enable_udplite();
+    }
+    #[cfg(feature = "udp-no-checksum")]
+    if options.no_udp_checksum {
+        server.accept_missing_udp_checksums();
+    }
+    if let Some(port) = options.quic_port {
+        if port == options.port {
+            bail!(
+                "--quic-port needs a port of its own, the echo listens on UDP port {}",
+                port
+            );
+        }
+        server.enable_quic(port);
+    }
+    server.set_batch(options.batch as usize);
+    server.set_socket_buffers(SocketBuffers {
+        receive: options.rcvbuf.map(|size| size as usize),
+        send: options.sndbuf.map(|size| size as usize),
+    });
+    if let Some(listen) = options.metrics_listen {
+        server.enable_metrics(listen);
+    }
+    if !options.no_tui && std::io::stdout().is_terminal() {
+        server.enable_tui();
+    }
 
     server.run().await
 }
+
+async fn run_bench(options: BenchOptions) -> Result<()> {
+    let bench = bench::Bench::new(
+        options.sizes,
+        options.intervals.into_iter().map(Into::into).collect(),
+        options.duration.into(),
+    )
+    .with_display_format(DisplayFormat::from_env(options.units));
+
+    bench.run().await
+}
+
+async fn run_monitor(options: MonitorOptions) -> Result<()> {
+    let mut monitor = network::passive::PassiveMonitor::new(options.filter)
+        .with_sequence(options.sequence)
+        .with_report_interval(options.stats_interval.into())
+        .with_display_format(DisplayFormat::from_env(options.units));
+    if let Some(interface) = options.interface {
+        monitor = monitor.with_interface(interface);
+    }
+    if let Some(duration) = options.duration {
+        monitor = monitor.with_duration(duration.into());
+    }
+
+    let cancel = tokio_util::sync::CancellationToken::new();
+    let interrupt = cancel.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            interrupt.cancel();
+        }
+    });
+
+    monitor.run(cancel).await
+}
+
+async fn run_test_plan(options: TestPlanOptions) -> Result<()> {
+    let plan = test_plan::TestPlan::load(&options.plan)?;
+
+    plan.run(options.junit.as_deref()).await
+}
+
+async fn run_scenario(options: ScenarioOptions) -> Result<()> {
+    let scenario = scenario::Scenario::load(&options.scenario)?;
+
+    scenario.run(&DisplayFormat::from_env(options.units)).await
+}
+
+fn run_rate(options: RateOptions) -> Result<()> {
+    let format = DisplayFormat::from_env(options.units);
+    let (verdicts, overall) = rating::rate(&options.results, options.link_type, &format)?;
+
+    for verdict in verdicts.iter() {
+        info!("{}", verdict);
+    }
+    match overall {
+        Some(grade) => info!("Overall: {}", grade.name()),
+        None => info!("Pass --link-type to rate the results against a specific link"),
+    }
+
+    Ok(())
+}
+
+/// Shows an exported run in the TUI, or prints its figures when there is no
+/// terminal.
+async fn run_view(options: ViewOptions) -> Result<()> {
+    let format = DisplayFormat::from_env(options.units);
+    let run = view::RecordedRun::load(&options.results)?;
+    let compared = options
+        .compare
+        .as_deref()
+        .map(view::RecordedRun::load)
+        .transpose()?;
+
+    if !std::io::stdout().is_terminal() {
+        for row in view::figure_rows(&run, compared.as_ref(), &format) {
+            let mut line = format!("{}: {}", row.figure, row.value);
+            if let (Some(compared), Some((delta, _))) = (row.compared, row.delta) {
+                line.push_str(&format!(", compared {}, delta {}", compared, delta));
+            }
+            info!("{}", line);
+        }
+        return Ok(());
+    }
+
+    let (action_tx, action_rx) = tokio::sync::mpsc::unbounded_channel();
+    let view = components::run_view::RunView::new(run, compared, format);
+    app::App::new(1.0, 4.0)?
+        .with_component(Box::new(view))
+        .run(action_tx, action_rx, |_| false)
+        .await
+}
+
+async fn run_collector(options: CollectorOptions) -> Result<()> {
+    let quit = tokio_util::sync::CancellationToken::new();
+    let _quit = quit.clone().drop_guard();
+    let collector = collector::Collector::new(options.listen, quit.clone());
+
+    if options.no_tui || !std::io::stdout().is_terminal() {
+        tokio::select! {
+            result = collector.run() => result?,
+            _ = tokio::signal::ctrl_c() => {}
+        }
+        return Ok(());
+    }
+
+    let (action_tx, action_rx) = tokio::sync::mpsc::unbounded_channel();
+    let collector = collector.with_notify(action_tx.clone());
+    let view = components::collector_view::CollectorView::new(
+        options.listen,
+        DisplayFormat::from_env(options.units),
+    );
+    app::App::new(1.0, 10.0)?
+        .with_component(Box::new(view))
+        .with_task(tokio::spawn(collector.run()))
+        .run(action_tx, action_rx, |_| false)
+        .await
+}
+
+async fn run_pmtu(options: PmtuOptions) -> Result<()> {
+    let server = tokio::net::lookup_host((options.address.as_str(), options.port))
+        .await?
+        .next()
+        .ok_or_else(|| eyre!("{} did not resolve to any address", options.address))?;
+
+    let result = network::mtu::discover(server.ip(), server.port(), options.max).await?;
+    let overhead = match server {
+        SocketAddr::V4(_) => UDP_IPV4_OVERHEAD,
+        SocketAddr::V6(_) => UDP_IPV6_OVERHEAD,
+    };
+    info!(
+        "Path MTU to {}: {} bytes, found with {} probes",
+        server, result.mtu, result.probes
+    );
+    if result.mtu == options.max && options.max < u16::MAX {
+        info!("The path carries the largest MTU tried, pass a larger --max to search further");
+    }
+    info!(
+        "Probes up to --packet-size {} fit without fragmentation",
+        result.mtu as u64 - overhead
+    );
+
+    Ok(())
+}
+
+fn run_verify(options: VerifyOptions) -> Result<()> {
+    let signature_path = options
+        .signature
+        .unwrap_or_else(|| signing::signature_path(&options.report));
+    let trusted_key = options
+        .key
+        .as_deref()
+        .map(signing::load_verifying_key)
+        .transpose()?;
+
+    let signature = signing::verify_report(&options.report, &signature_path, trusted_key.as_ref())?;
+
+    info!(
+        "Valid signature for {} (sha256:{})",
+        options.report.display(),
+        signing::to_hex(&signature.digest)
+    );
+    info!(
+        "Signed with public key {}",
+        signing::to_hex(signature.public_key.as_bytes())
+    );
+
+    Ok(())
+}
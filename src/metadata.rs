@@ -0,0 +1,155 @@
+use std::{
+    io::{self, Write},
+    time::SystemTime,
+};
+
+use serde::{Serialize, Serializer};
+
+/// Context about how a run was produced, stored alongside its results.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct RunMetadata {
+    pub software: String,
+    pub hostname: Option<String>,
+    pub os: String,
+    pub started: String,
+    pub finished: String,
+    /// Name the probe was given, to tell apart the runs of many probes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub probe: Option<String>,
+    #[serde(
+        serialize_with = "serialize_parameters",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub labels: Vec<(String, String)>,
+    /// Effective parameters of the run, in a stable order
+    #[serde(serialize_with = "serialize_parameters")]
+    pub parameters: Vec<(String, String)>,
+    /// Rates and other figures measured during the run, as opposed to configured
+    #[serde(
+        serialize_with = "serialize_parameters",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub measured: Vec<(String, String)>,
+}
+
+impl RunMetadata {
+    pub(crate) fn new(
+        hostname: Option<String>,
+        started: SystemTime,
+        finished: SystemTime,
+        parameters: Vec<(String, String)>,
+    ) -> Self {
+        Self {
+            software: format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            hostname,
+            os: format!("{} {}", std::env::consts::OS, std::env::consts::ARCH),
+            started: humantime::format_rfc3339_millis(started).to_string(),
+            finished: humantime::format_rfc3339_millis(finished).to_string(),
+            probe: None,
+            labels: Vec::new(),
+            parameters,
+            measured: Vec::new(),
+        }
+    }
+
+    pub(crate) fn with_identity(mut self, identity: &Identity) -> Self {
+        self.probe = identity.probe.clone();
+        self.labels = identity.labels.clone();
+        self
+    }
+
+    /// Writes the metadata as `# key: value` lines, which CSV readers can skip as comments.
+    pub(crate) fn write_comments(&self, w: &mut impl Write) -> io::Result<()> {
+        writeln!(w, "# software: {}", self.software)?;
+        if let Some(ref hostname) = self.hostname {
+            writeln!(w, "# hostname: {}", hostname)?;
+        }
+        writeln!(w, "# os: {}", self.os)?;
+        writeln!(w, "# started: {}", self.started)?;
+        writeln!(w, "# finished: {}", self.finished)?;
+        if let Some(ref probe) = self.probe {
+            writeln!(w, "# probe: {}", probe)?;
+        }
+        for (key, value) in &self.labels {
+            writeln!(w, "# label.{}: {}", key, value)?;
+        }
+        for (key, value) in self.parameters.iter().chain(self.measured.iter()) {
+            writeln!(w, "# {}: {}", key, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Name and labels of a probe, attached to everything it outputs so results of
+/// many probes can be grouped and filtered wherever they are collected.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Identity {
+    pub probe: Option<String>,
+    pub labels: Vec<(String, String)>,
+}
+
+impl Identity {
+    /// The probe name as `probe` label followed by the labels.
+    pub(crate) fn tags(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.probe
+            .iter()
+            .map(|probe| ("probe", probe.as_str()))
+            .chain(self.labels.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+    }
+}
+
+/// Names of labels bwlat sets itself.
+const RESERVED_LABELS: [&str; 4] = ["probe", "target", "quantile", "le"];
+
+/// Parses `key=value`, keys have to be valid Prometheus label names.
+pub(crate) fn parse_label(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| "expected KEY=VALUE".to_string())?;
+    check_label_name(key)?;
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Checks that `key` is a valid Prometheus label name bwlat does not set itself.
+pub(crate) fn check_label_name(key: &str) -> Result<(), String> {
+    let valid = key
+        .chars()
+        .enumerate()
+        .all(|(i, c)| c == '_' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit()));
+    if key.is_empty() || !valid || key.starts_with("__") {
+        return Err(format!(
+            "{} is not a valid label name, use letters, digits and underscores",
+            key
+        ));
+    }
+    if RESERVED_LABELS.contains(&key) {
+        return Err(format!("{} is set by bwlat itself", key));
+    }
+    Ok(())
+}
+
+/// Parameters as an object rather than a list of pairs, keeping their order.
+fn serialize_parameters<S: Serializer>(
+    parameters: &[(String, String)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(parameters.iter().map(|(k, v)| (k, v)))
+}
+
+/// Name of the machine running the probe.
+#[cfg(unix)]
+pub(crate) fn hostname() -> Option<String> {
+    let mut buf = [0 as libc::c_char; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr(), buf.len()) } != 0 {
+        return None;
+    }
+
+    let name = unsafe { std::ffi::CStr::from_ptr(buf.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn hostname() -> Option<String> {
+    None
+}
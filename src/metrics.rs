@@ -0,0 +1,325 @@
+use std::{
+    fmt::Write as _,
+    net::SocketAddr,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+
+use color_eyre::eyre::Result;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::Mutex,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info};
+
+use crate::{
+    metadata::Identity,
+    network::{echo::EchoCounters, latency::State},
+};
+
+/// Upper bounds of the round-trip histogram buckets in seconds.
+const LATENCY_BUCKETS: [f64; 14] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+/// What the metrics describe.
+pub(crate) enum Source {
+    /// Latency engines of a client by target address
+    Client(Vec<(String, Arc<Mutex<State>>)>),
+    Server(Arc<EchoCounters>),
+}
+
+/// Serves `/metrics` in the Prometheus text format.
+pub(crate) struct MetricsExporter {
+    listen: SocketAddr,
+    source: Arc<Source>,
+    identity: Arc<Identity>,
+    quit: CancellationToken,
+}
+
+impl MetricsExporter {
+    pub(crate) fn new(listen: SocketAddr, source: Source, quit: CancellationToken) -> Self {
+        Self {
+            listen,
+            source: Arc::new(source),
+            identity: Arc::default(),
+            quit,
+        }
+    }
+
+    /// Adds the probe name and labels to every sample.
+    pub(crate) fn with_identity(mut self, identity: Identity) -> Self {
+        self.identity = Arc::new(identity);
+        self
+    }
+
+    pub(crate) async fn run(self) -> Result<()> {
+        let listener = TcpListener::bind(self.listen).await?;
+        info!(
+            "Metrics listening on http://{}/metrics",
+            listener.local_addr()?
+        );
+
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let (stream, peer) = accepted?;
+                    let source = self.source.clone();
+                    let identity = self.identity.clone();
+
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_connection(stream, source, identity).await {
+                            debug!("Metrics connection from {} failed: {:?}", peer, e);
+                        }
+                    });
+                }
+                _ = self.quit.cancelled() => break,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    source: Arc<Source>,
+    identity: Arc<Identity>,
+) -> Result<()> {
+    let mut buf = [0; 1024];
+    let n = stream.read(&mut buf).await?;
+    let request = String::from_utf8_lossy(&buf[..n]);
+
+    let path = request
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .unwrap_or("/");
+
+    let (status, content_type, body) = match path {
+        "/metrics" => (
+            "200 OK",
+            "text/plain; version=0.0.4",
+            match *source {
+                Source::Client(ref targets) => client_metrics(targets, &identity).await,
+                Source::Server(ref counters) => server_metrics(counters),
+            },
+        ),
+        _ => ("404 Not Found", "text/plain", "Not found".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+
+    Ok(())
+}
+
+async fn client_metrics(targets: &[(String, Arc<Mutex<State>>)], identity: &Identity) -> String {
+    let mut sent = Vec::new();
+    let mut received = Vec::new();
+    let mut lost = Vec::new();
+    let mut bytes_sent = Vec::new();
+    let mut bytes_received = Vec::new();
+    let mut min = Vec::new();
+    let mut average = Vec::new();
+    let mut max = Vec::new();
+    let mut quantiles = Vec::new();
+    let mut histogram = String::new();
+
+    let mut common = String::new();
+    for (key, value) in identity.tags() {
+        let _ = write!(common, "{}=\"{}\",", key, escape(value));
+    }
+
+    for (target, state) in targets {
+        let state = state.lock().await;
+        let label = format!("{}target=\"{}\"", common, escape(target));
+
+        sent.push((label.clone(), state.sent_packets() as f64));
+        received.push((label.clone(), state.received_packets as f64));
+        lost.push((label.clone(), state.packet_loss as f64));
+        bytes_sent.push((label.clone(), state.traffic_sent.wire as f64));
+        bytes_received.push((label.clone(), state.traffic_received.wire as f64));
+        if state.received_packets > 0 {
+            min.push((label.clone(), state.min_latency.as_secs_f64()));
+            average.push((label.clone(), state.average_latency.as_secs_f64()));
+            max.push((label.clone(), state.max_latency.as_secs_f64()));
+        }
+        if let Some(p) = state.percentiles() {
+            for (quantile, latency) in [
+                ("0.5", p.p50),
+                ("0.9", p.p90),
+                ("0.99", p.p99),
+                ("0.999", p.p999),
+            ] {
+                quantiles.push((
+                    format!("{},quantile=\"{}\"", label, quantile),
+                    latency.as_secs_f64(),
+                ));
+            }
+        }
+
+        // The histogram holds nanoseconds
+        for bound in LATENCY_BUCKETS {
+            let nanos = Duration::from_secs_f64(bound).as_nanos() as u64;
+            let count = state.histogram.count_between(0, nanos);
+            let _ = writeln!(
+                histogram,
+                "bwlat_latency_seconds_bucket{{{},le=\"{}\"}} {}",
+                label, bound, count
+            );
+        }
+        let count = state.histogram.len();
+        let sum = state.histogram.mean() * count as f64 / 1e9;
+        let _ = writeln!(
+            histogram,
+            "bwlat_latency_seconds_bucket{{{},le=\"+Inf\"}} {}",
+            label, count
+        );
+        let _ = writeln!(histogram, "bwlat_latency_seconds_sum{{{}}} {}", label, sum);
+        let _ = writeln!(
+            histogram,
+            "bwlat_latency_seconds_count{{{}}} {}",
+            label, count
+        );
+    }
+
+    let mut body = String::new();
+    metric(
+        &mut body,
+        "bwlat_probes_sent_total",
+        "counter",
+        "Probes sent",
+        &sent,
+    );
+    metric(
+        &mut body,
+        "bwlat_probes_received_total",
+        "counter",
+        "Probes answered",
+        &received,
+    );
+    metric(
+        &mut body,
+        "bwlat_probes_lost",
+        "gauge",
+        "Probes without an answer so far, including the ones in flight",
+        &lost,
+    );
+    metric(
+        &mut body,
+        "bwlat_sent_bytes_total",
+        "counter",
+        "Bytes of probes put on the wire, headers included",
+        &bytes_sent,
+    );
+    metric(
+        &mut body,
+        "bwlat_received_bytes_total",
+        "counter",
+        "Bytes of echoes received on the wire, headers included",
+        &bytes_received,
+    );
+    metric(
+        &mut body,
+        "bwlat_latency_min_seconds",
+        "gauge",
+        "Fastest round trip",
+        &min,
+    );
+    metric(
+        &mut body,
+        "bwlat_latency_average_seconds",
+        "gauge",
+        "Mean round trip",
+        &average,
+    );
+    metric(
+        &mut body,
+        "bwlat_latency_max_seconds",
+        "gauge",
+        "Slowest round trip",
+        &max,
+    );
+    metric(
+        &mut body,
+        "bwlat_latency_quantile_seconds",
+        "gauge",
+        "Round-trip percentiles of the whole run",
+        &quantiles,
+    );
+
+    let _ = writeln!(
+        body,
+        "# HELP bwlat_latency_seconds Round trips of the probes"
+    );
+    let _ = writeln!(body, "# TYPE bwlat_latency_seconds histogram");
+    body.push_str(&histogram);
+    body
+}
+
+fn server_metrics(counters: &EchoCounters) -> String {
+    let mut body = String::new();
+    metric(
+        &mut body,
+        "bwlat_server_echoed_packets_total",
+        "counter",
+        "Probes echoed",
+        &[(
+            String::new(),
+            counters.packets.load(Ordering::Relaxed) as f64,
+        )],
+    );
+    metric(
+        &mut body,
+        "bwlat_server_echoed_bytes_total",
+        "counter",
+        "Payload bytes of the echoed probes",
+        &[(String::new(), counters.bytes.load(Ordering::Relaxed) as f64)],
+    );
+    metric(
+        &mut body,
+        "bwlat_server_clients",
+        "gauge",
+        "Client addresses heard from recently",
+        &[(
+            String::new(),
+            counters.clients.load(Ordering::Relaxed) as f64,
+        )],
+    );
+    body
+}
+
+/// Writes one metric family, `values` pairs the labels of a sample with its value.
+pub(crate) fn metric(
+    body: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    values: &[(String, f64)],
+) {
+    let _ = writeln!(body, "# HELP {} {}", name, help);
+    let _ = writeln!(body, "# TYPE {} {}", name, kind);
+    for (labels, value) in values {
+        let _ = match labels.is_empty() {
+            true => writeln!(body, "{} {}", name, value),
+            false => writeln!(body, "{}{{{}}} {}", name, labels, value),
+        };
+    }
+}
+
+/// Escapes a label value.
+pub(crate) fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,1144 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use color_eyre::eyre::{bail, Result};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpSocket, TcpStream, UdpSocket},
+    sync::mpsc::UnboundedSender,
+    time::{self, Instant, MissedTickBehavior},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use super::{
+    echo::{echo_stream, TCP_ECHO_MAGIC},
+    latency::Payload,
+    listen, mmsg, quic,
+    tcp::{self, TcpInfo},
+    websocket::{self, UPGRADE_METHOD},
+};
+use crate::{
+    action::Action,
+    pairing::{Authenticator, AUTH_MAGIC, PAIR_MAGIC},
+};
+
+const WRITE_SIZE: usize = 128 * 1024;
+
+/// Two-sided critical value for p < 0.05 when comparing payloads. A test yields
+/// few report intervals, a stricter level would hardly ever be reached.
+const SIGNIFICANCE_CRITICAL_VALUE: f64 = 1.96;
+
+/// Smallest relative throughput difference between payloads worth reporting.
+const MIN_PAYLOAD_EFFECT: f64 = 0.05;
+
+/// Bulk TCP transfer measuring the goodput of a single connection.
+pub(crate) struct TcpBandwidth {
+    server_address: IpAddr,
+    server_port: u16,
+
+    duration: Duration,
+    report_interval: Duration,
+    congestion: Option<String>,
+    max_bytes: Option<u64>,
+    bind_address: Option<IpAddr>,
+    payload: Payload,
+    seed: u64,
+
+    notify: UnboundedSender<Action>,
+    quit: CancellationToken,
+}
+
+impl TcpBandwidth {
+    pub(crate) fn new(
+        address: IpAddr,
+        port: u16,
+        duration: Duration,
+        notify: UnboundedSender<Action>,
+        quit: CancellationToken,
+    ) -> Self {
+        Self {
+            server_address: address,
+            server_port: port,
+
+            duration,
+            report_interval: Duration::from_secs(1),
+            congestion: None,
+            max_bytes: None,
+            bind_address: None,
+            payload: Payload::default(),
+            seed: 0,
+
+            notify,
+            quit,
+        }
+    }
+
+    /// Congestion control algorithm to request from the kernel, e.g. `bbr`.
+    pub(crate) fn with_congestion(mut self, algorithm: String) -> Self {
+        self.congestion = Some(algorithm);
+        self
+    }
+
+    /// Stop once this many bytes have been written.
+    pub(crate) fn with_max_bytes(mut self, bytes: u64) -> Self {
+        self.max_bytes = Some(bytes);
+        self
+    }
+
+    /// Connect from this address instead of the one the routing table picks.
+    pub(crate) fn with_bind_address(mut self, address: IpAddr) -> Self {
+        self.bind_address = Some(address);
+        self
+    }
+
+    /// Fill the writes with `payload`, random contents are generated from `seed`.
+    /// [`Payload::Alternate`] switches between both every report interval.
+    pub(crate) fn with_payload(mut self, payload: Payload, seed: u64) -> Self {
+        self.payload = payload;
+        self.seed = seed;
+        self
+    }
+
+    pub(crate) async fn run(&self) -> Result<BandwidthState> {
+        let socket = match self.server_address {
+            IpAddr::V4(_) => TcpSocket::new_v4()?,
+            IpAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+
+        if let Some(address) = self.bind_address {
+            socket.bind(SocketAddr::new(address, 0))?;
+        }
+
+        // Set before connecting so the algorithm is in effect from the first segment
+        if let Some(ref algorithm) = self.congestion {
+            tcp::set_congestion_control(&socket, algorithm)?;
+        }
+
+        let mut stream = socket
+            .connect(SocketAddr::new(self.server_address, self.server_port))
+            .await?;
+        stream.set_nodelay(true)?;
+
+        let mut state = BandwidthState {
+            congestion: self.congestion.clone(),
+            ..Default::default()
+        };
+
+        let buffers = payload_buffers(WRITE_SIZE, self.seed);
+        let start = Instant::now();
+        let deadline = time::sleep(self.duration);
+        tokio::pin!(deadline);
+
+        let mut report = time::interval_at(start + self.report_interval, self.report_interval);
+        let mut previous = Sample::new(start);
+
+        loop {
+            let buf = &buffers[payload_index(self.payload, &state)];
+            tokio::select! {
+                written = stream.write(&buf[..next_write(self.max_bytes, &state)]) => {
+                    state.total_bytes += written? as u64;
+                    if self.max_bytes.is_some_and(|max| state.total_bytes >= max) {
+                        debug!("Byte budget of {} reached", format_bytes(state.total_bytes));
+                        break;
+                    }
+                }
+                now = report.tick() => {
+                    let sample = self.sample(&stream, &state, &mut previous, now, start);
+                    self.notify.send(Action::BandwidthSample(sample.clone()))?;
+                    state.samples.push(sample);
+                }
+                _ = &mut deadline => break,
+                _ = self.quit.cancelled() => break,
+            }
+        }
+
+        state.elapsed = start.elapsed();
+        if let Ok(info) = tcp::tcp_info(&stream) {
+            state.retransmits = info.total_retransmits;
+        }
+
+        stream.shutdown().await?;
+
+        Ok(state)
+    }
+
+    fn sample(
+        &self,
+        stream: &TcpStream,
+        state: &BandwidthState,
+        previous: &mut Sample,
+        now: Instant,
+        start: Instant,
+    ) -> BandwidthSample {
+        let info = tcp::tcp_info(stream).ok();
+
+        // Prefer what the peer acknowledged over what was handed to the kernel,
+        // as long as both ends of the interval come from the same counter
+        let acked = match info {
+            Some(TcpInfo { bytes_acked, .. }) if bytes_acked > 0 => Some(bytes_acked),
+            _ => None,
+        };
+        let bytes = match (acked, previous.acked) {
+            (Some(acked), Some(before)) => acked.saturating_sub(before),
+            _ => state.total_bytes.saturating_sub(previous.written),
+        };
+
+        let elapsed = (now - previous.at).as_secs_f64();
+        let retransmits = info.map_or(0, |i| i.total_retransmits);
+
+        let sample = BandwidthSample {
+            at: now - start,
+            bytes,
+            bits_per_second: (bytes as f64 * 8.0 / elapsed) as u64,
+            retransmits: retransmits.saturating_sub(previous.retransmits),
+            lost: None,
+            offered: None,
+            cwnd: info.map_or(0, |i| i.cwnd),
+            rtt: info.map_or(Duration::ZERO, |i| i.rtt),
+            rttvar: info.map_or(Duration::ZERO, |i| i.rttvar),
+            delivery_rate: info.map_or(0, |i| i.delivery_rate * 8),
+        };
+
+        *previous = Sample {
+            at: now,
+            written: state.total_bytes,
+            acked,
+            retransmits,
+        };
+
+        sample
+    }
+}
+
+/// Bulk transfer over a unidirectional QUIC stream, congestion controlled by
+/// QUIC in user space rather than by the kernel.
+pub(crate) struct QuicBandwidth {
+    server_address: IpAddr,
+    server_port: u16,
+
+    duration: Duration,
+    report_interval: Duration,
+    max_bytes: Option<u64>,
+    bind_address: Option<IpAddr>,
+    payload: Payload,
+    seed: u64,
+
+    notify: UnboundedSender<Action>,
+    quit: CancellationToken,
+}
+
+impl QuicBandwidth {
+    pub(crate) fn new(
+        address: IpAddr,
+        port: u16,
+        duration: Duration,
+        notify: UnboundedSender<Action>,
+        quit: CancellationToken,
+    ) -> Self {
+        Self {
+            server_address: address,
+            server_port: port,
+
+            duration,
+            report_interval: Duration::from_secs(1),
+            max_bytes: None,
+            bind_address: None,
+            payload: Payload::default(),
+            seed: 0,
+
+            notify,
+            quit,
+        }
+    }
+
+    /// Stop once this many bytes have been written.
+    pub(crate) fn with_max_bytes(mut self, bytes: u64) -> Self {
+        self.max_bytes = Some(bytes);
+        self
+    }
+
+    /// Connect from this address instead of the one the routing table picks.
+    pub(crate) fn with_bind_address(mut self, address: IpAddr) -> Self {
+        self.bind_address = Some(address);
+        self
+    }
+
+    /// Fill the writes with `payload`, random contents are generated from `seed`.
+    /// [`Payload::Alternate`] switches between both every report interval.
+    pub(crate) fn with_payload(mut self, payload: Payload, seed: u64) -> Self {
+        self.payload = payload;
+        self.seed = seed;
+        self
+    }
+
+    pub(crate) async fn run(&self) -> Result<BandwidthState> {
+        let bind = self
+            .bind_address
+            .unwrap_or_else(|| listen::unspecified(self.server_address));
+        let endpoint = quic::client(std::net::UdpSocket::bind(SocketAddr::new(bind, 0))?)?;
+        let server = SocketAddr::new(self.server_address, self.server_port);
+        let (connection, handshake) = quic::connect(&endpoint, server).await?;
+        info!("QUIC handshake took {:.1?}", handshake);
+        let mut stream = connection.open_uni().await?;
+
+        let mut state = BandwidthState {
+            // The default of quinn
+            congestion: Some("cubic (QUIC)".to_string()),
+            ..Default::default()
+        };
+
+        let buffers = payload_buffers(WRITE_SIZE, self.seed);
+        let start = Instant::now();
+        let deadline = time::sleep(self.duration);
+        tokio::pin!(deadline);
+
+        let mut report = time::interval_at(start + self.report_interval, self.report_interval);
+        let mut previous = Sample::new(start);
+
+        loop {
+            let buf = &buffers[payload_index(self.payload, &state)];
+            tokio::select! {
+                written = stream.write(&buf[..next_write(self.max_bytes, &state)]) => {
+                    state.total_bytes += written? as u64;
+                    if self.max_bytes.is_some_and(|max| state.total_bytes >= max) {
+                        debug!("Byte budget of {} reached", format_bytes(state.total_bytes));
+                        break;
+                    }
+                }
+                now = report.tick() => {
+                    let sample = quic_sample(&connection, &state, &mut previous, now, start);
+                    self.notify.send(Action::BandwidthSample(sample.clone()))?;
+                    state.samples.push(sample);
+                }
+                _ = &mut deadline => break,
+                _ = self.quit.cancelled() => break,
+            }
+        }
+
+        state.elapsed = start.elapsed();
+        state.retransmits = connection.stats().path.lost_packets as u32;
+        quic::finish(&endpoint, &connection, stream).await;
+
+        Ok(state)
+    }
+}
+
+/// Like [`TcpBandwidth::sample`], from the statistics quinn keeps for the path.
+/// Lost packets take the place of retransmissions.
+fn quic_sample(
+    connection: &quinn::Connection,
+    state: &BandwidthState,
+    previous: &mut Sample,
+    now: Instant,
+    start: Instant,
+) -> BandwidthSample {
+    let path = connection.stats().path;
+    let elapsed = (now - previous.at).as_secs_f64();
+    let bytes = state.total_bytes.saturating_sub(previous.written);
+    let lost = path.lost_packets as u32;
+
+    let sample = BandwidthSample {
+        at: now - start,
+        bytes,
+        bits_per_second: (bytes as f64 * 8.0 / elapsed) as u64,
+        retransmits: lost - previous.retransmits,
+        lost: None,
+        offered: None,
+        cwnd: (path.cwnd / path.current_mtu.max(1) as u64) as u32,
+        rtt: path.rtt,
+        rttvar: Duration::ZERO,
+        delivery_rate: 0,
+    };
+
+    *previous = Sample {
+        at: now,
+        written: state.total_bytes,
+        acked: None,
+        retransmits: lost,
+    };
+
+    sample
+}
+
+/// Size of the next write, smaller than a full chunk to land exactly on the budget.
+fn next_write(max_bytes: Option<u64>, state: &BandwidthState) -> usize {
+    match max_bytes {
+        Some(max) => (max.saturating_sub(state.total_bytes) as usize).clamp(1, WRITE_SIZE),
+        None => WRITE_SIZE,
+    }
+}
+
+/// Compressible and incompressible contents of `size` bytes.
+fn payload_buffers(size: usize, seed: u64) -> [Vec<u8>; 2] {
+    let mut random = vec![0; size];
+    StdRng::seed_from_u64(seed).fill(&mut random[..]);
+    [vec![0; size], random]
+}
+
+/// Which of the [`payload_buffers`] to write during the current report interval.
+fn payload_index(payload: Payload, state: &BandwidthState) -> usize {
+    usize::from(!payload.is_compressible(state.samples.len()))
+}
+
+struct Sample {
+    at: Instant,
+    /// Bytes handed to the kernel or the QUIC stream
+    written: u64,
+    /// Bytes the peer acknowledged, if the kernel reports them
+    acked: Option<u64>,
+    retransmits: u32,
+}
+
+impl Sample {
+    fn new(at: Instant) -> Self {
+        Self {
+            at,
+            written: 0,
+            acked: Some(0),
+            retransmits: 0,
+        }
+    }
+}
+
+/// Throughput and congestion state over one report interval.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BandwidthSample {
+    /// End of the interval, relative to the start of the test.
+    pub at: Duration,
+    pub bytes: u64,
+    pub bits_per_second: u64,
+    pub retransmits: u32,
+    /// Datagrams lost during the interval, UDP only.
+    pub lost: Option<u64>,
+    /// Bits per second the sender put on the wire, UDP only.
+    pub offered: Option<u64>,
+    /// Congestion window in segments at the end of the interval.
+    pub cwnd: u32,
+    /// Smoothed RTT and its variation as seen by the kernel, for UDP the round trip
+    /// of the report request.
+    pub rtt: Duration,
+    pub rttvar: Duration,
+    /// Kernel delivery rate estimate in bits per second.
+    pub delivery_rate: u64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct BandwidthState {
+    pub samples: Vec<BandwidthSample>,
+    pub total_bytes: u64,
+    pub retransmits: u32,
+    pub elapsed: Duration,
+    pub congestion: Option<String>,
+
+    /// Rate the UDP sender aimed for in bits per second, unset for TCP
+    pub target_rate: Option<u64>,
+    pub datagrams_sent: u64,
+    /// What the server received, UDP only
+    pub received: Option<UdpCounters>,
+}
+
+impl BandwidthState {
+    pub(crate) fn bits_per_second(&self) -> f64 {
+        self.total_bytes as f64 * 8.0 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// Splits the report intervals of a [`Payload::Alternate`] run into those with
+    /// compressible and incompressible payloads. Each pair of adjacent intervals
+    /// holds one of both. The first pair is left out, it includes the slow start.
+    pub(crate) fn payload_comparison(&self) -> BandwidthComparison {
+        let mut groups: [Vec<f64>; 2] = [Vec::new(), Vec::new()];
+        for (i, sample) in self.samples.iter().enumerate().skip(2) {
+            let group = usize::from(!Payload::Alternate.is_compressible(i));
+            groups[group].push(sample.bits_per_second as f64);
+        }
+        let mean = |group: &[f64]| group.iter().sum::<f64>() / group.len().max(1) as f64;
+
+        let mut differences: Vec<f64> = self
+            .samples
+            .chunks_exact(2)
+            .enumerate()
+            .skip(1)
+            .filter_map(|(i, pair)| {
+                let (a, b) = (
+                    pair[0].bits_per_second as f64,
+                    pair[1].bits_per_second as f64,
+                );
+                let (compressible, incompressible) = match Payload::Alternate.is_compressible(2 * i)
+                {
+                    true => (a, b),
+                    false => (b, a),
+                };
+                (compressible > 0.0).then(|| incompressible / compressible - 1.0)
+            })
+            .collect();
+        differences.sort_by(f64::total_cmp);
+
+        BandwidthComparison {
+            compressible: mean(&groups[0]),
+            incompressible: mean(&groups[1]),
+            pairs: differences.len() as u32,
+            incompressible_lower: differences.iter().filter(|&&d| d < 0.0).count() as u32,
+            incompressible_higher: differences.iter().filter(|&&d| d > 0.0).count() as u32,
+            median_difference: differences
+                .get(differences.len() / 2)
+                .copied()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Throughput with compressible versus incompressible payloads.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BandwidthComparison {
+    /// Mean throughput of the intervals in bits per second
+    pub compressible: f64,
+    pub incompressible: f64,
+    /// Adjacent interval pairs compared
+    pub pairs: u32,
+    pub incompressible_lower: u32,
+    pub incompressible_higher: u32,
+    /// Median of the relative throughput difference of incompressible to
+    /// compressible intervals over the pairs, e.g. -0.2 for 20% less
+    pub median_difference: f64,
+}
+
+impl BandwidthComparison {
+    /// Whether one payload is consistently faster within the pairs (sign test) and
+    /// by a relevant amount.
+    pub(crate) fn differs(&self) -> bool {
+        let n = (self.incompressible_lower + self.incompressible_higher) as f64;
+        if n == 0.0 {
+            return false;
+        }
+
+        let z = (self.incompressible_lower as f64 - n / 2.0) / (n / 4.0).sqrt();
+        z.abs() > SIGNIFICANCE_CRITICAL_VALUE && self.median_difference.abs() >= MIN_PAYLOAD_EFFECT
+    }
+}
+
+/// Server side of [`TcpBandwidth`], reads and discards everything it receives.
+pub(crate) struct TcpSink {
+    port: u16,
+    bind: Option<IpAddr>,
+    auth: Option<Arc<Authenticator>>,
+}
+
+impl TcpSink {
+    pub(crate) fn new(port: u16) -> Self {
+        Self {
+            port,
+            bind: None,
+            auth: None,
+        }
+    }
+
+    /// Listen on this address only instead of all IPv6 and IPv4 addresses.
+    pub(crate) fn with_bind_address(mut self, address: IpAddr) -> Self {
+        self.bind = Some(address);
+        self
+    }
+
+    /// Also serve pairing and authentication, and only accept bandwidth tests
+    /// from clients the authenticator admits.
+    pub(crate) fn with_authenticator(mut self, auth: Arc<Authenticator>) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    pub(crate) async fn run(&self) -> Result<()> {
+        let listener = listen::tcp(self.bind, self.port)?;
+
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let peer = listen::canonical(peer);
+            let auth = self.auth.clone();
+
+            tokio::spawn(async move {
+                let mut magic = [0; 10];
+                let peeked = stream.peek(&mut magic).await.unwrap_or_default();
+                let magic = &magic[..peeked];
+
+                if let Some(auth) = auth {
+                    if magic == PAIR_MAGIC || magic == AUTH_MAGIC {
+                        if let Err(e) = auth.handle(stream, peer).await {
+                            warn!("Pairing or authentication of {} failed: {}", peer, e);
+                        }
+                        return;
+                    }
+                    if !auth.admit(peer.ip()) {
+                        debug!("Refusing connection from unauthenticated {}", peer);
+                        return;
+                    }
+                }
+
+                if magic == TCP_ECHO_MAGIC {
+                    if let Err(e) = echo_stream(stream, peer).await {
+                        warn!("TCP latency test from {} failed: {:?}", peer, e);
+                    }
+                    return;
+                }
+                if magic.starts_with(UPGRADE_METHOD) {
+                    if let Err(e) = websocket::echo(stream, peer).await {
+                        warn!("WebSocket latency test from {} failed: {:?}", peer, e);
+                    }
+                    return;
+                }
+
+                debug!("Bandwidth test from {}", peer);
+                if let Err(e) = drain(stream, peer).await {
+                    warn!("Bandwidth test from {} failed: {:?}", peer, e);
+                }
+            });
+        }
+    }
+}
+
+async fn drain(mut stream: TcpStream, peer: SocketAddr) -> Result<()> {
+    let mut buf = vec![0; WRITE_SIZE];
+    let mut total = 0u64;
+    let start = Instant::now();
+
+    loop {
+        match stream.read(&mut buf).await? {
+            0 => break,
+            n => total += n as u64,
+        }
+    }
+
+    let elapsed = start.elapsed();
+    info!(
+        "Received {} bytes from {} in {:.2?} ({})",
+        total,
+        peer,
+        elapsed,
+        format_bitrate(total as f64 * 8.0 / elapsed.as_secs_f64().max(f64::EPSILON))
+    );
+
+    Ok(())
+}
+
+/// Opens a UDP bandwidth session, followed by a nonce of the client. The reply
+/// adds the session token the server picked, which only the real sender sees.
+const UDP_OPEN: &[u8; 8] = b"bwlat-uo";
+/// Marks datagrams of a UDP bandwidth test, followed by the session token and
+/// sequence number. Never a valid latency probe, whose first bytes are a packet
+/// counter.
+const UDP_DATA: &[u8; 8] = b"bwlat-ud";
+/// Report request and reply, followed by the session token and request id.
+const UDP_REPORT: &[u8; 8] = b"bwlat-ur";
+/// Report request, the reply adds the counters after it.
+const UDP_REPORT_LEN: usize = 20;
+/// Report reply. Requests to open a session and for reports are padded to this
+/// size, so the server never answers with more than it received.
+const UDP_REPLY_LEN: usize = UDP_REPORT_LEN + 32;
+
+/// Payload of the datagrams sent by [`UdpBandwidth`].
+const UDP_DATAGRAM_SIZE: usize = 1400;
+
+/// How often the sender tops up the datagrams due according to the rate.
+const PACING_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Upper bound of datagrams sent per pacing tick, so reports still get through
+/// when the rate cannot be reached.
+const MAX_DATAGRAMS_PER_TICK: u64 = 4096;
+
+/// Attempts and timeout for opening the session and for the final report after
+/// the sender stopped.
+const FINAL_REPORT_ATTEMPTS: u32 = 3;
+const FINAL_REPORT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Sessions without traffic for this long are dropped by the server.
+const UDP_SESSION_TIMEOUT: Duration = Duration::from_secs(60);
+/// Open sessions the server keeps at most, beyond this many requests to open
+/// one go unanswered.
+const MAX_UDP_SESSIONS: usize = 1024;
+
+/// Sends UDP at a fixed rate and asks the server how much of it arrived.
+pub(crate) struct UdpBandwidth {
+    server_address: IpAddr,
+    server_port: u16,
+
+    duration: Duration,
+    /// Target rate in bits per second
+    rate: u64,
+    ramp_steps: Option<u32>,
+    min_step: Duration,
+    report_interval: Duration,
+    max_bytes: Option<u64>,
+    bind_address: Option<IpAddr>,
+    payload: Payload,
+    seed: u64,
+    /// Datagrams handed to the kernel per system call
+    batch: usize,
+
+    notify: UnboundedSender<Action>,
+    quit: CancellationToken,
+}
+
+impl UdpBandwidth {
+    pub(crate) fn new(
+        address: IpAddr,
+        port: u16,
+        duration: Duration,
+        rate: u64,
+        notify: UnboundedSender<Action>,
+        quit: CancellationToken,
+    ) -> Self {
+        Self {
+            server_address: address,
+            server_port: port,
+
+            duration,
+            rate,
+            ramp_steps: None,
+            min_step: Duration::ZERO,
+            report_interval: Duration::from_secs(1),
+            max_bytes: None,
+            bind_address: None,
+            payload: Payload::default(),
+            seed: 0,
+            batch: mmsg::DEFAULT_BATCH,
+
+            notify,
+            quit,
+        }
+    }
+
+    /// Stop once this many bytes have been sent.
+    pub(crate) fn with_max_bytes(mut self, bytes: u64) -> Self {
+        self.max_bytes = Some(bytes);
+        self
+    }
+
+    /// Send from this address instead of the one the routing table picks.
+    pub(crate) fn with_bind_address(mut self, address: IpAddr) -> Self {
+        self.bind_address = Some(address);
+        self
+    }
+
+    /// Fill the writes with `payload`, random contents are generated from `seed`.
+    /// [`Payload::Alternate`] switches between both every report interval.
+    pub(crate) fn with_payload(mut self, payload: Payload, seed: u64) -> Self {
+        self.payload = payload;
+        self.seed = seed;
+        self
+    }
+
+    /// Send up to `size` datagrams per system call, one sends each on its own.
+    pub(crate) fn with_batch(mut self, size: usize) -> Self {
+        self.batch = size.clamp(1, mmsg::MAX_BATCH);
+        self
+    }
+
+    /// Raise the rate in equal steps over the duration, reaching the full rate in
+    /// the last one.
+    pub(crate) fn with_ramp(mut self, steps: u32) -> Self {
+        self.ramp_steps = Some(steps.max(1));
+        self
+    }
+
+    /// Shortest step of the ramp, fewer steps are taken when the duration does not
+    /// fit them all.
+    pub(crate) fn with_min_step(mut self, step: Duration) -> Self {
+        self.min_step = step;
+        self
+    }
+
+    /// Steps of the ramp that fit the duration, each at least the minimum step long.
+    fn ramp_steps(&self) -> Option<u32> {
+        let steps = self.ramp_steps?;
+        if self.min_step.is_zero() {
+            return Some(steps);
+        }
+        let fitting = (self.duration.as_nanos() / self.min_step.as_nanos()).max(1);
+        Some(steps.min(fitting.min(u32::MAX as u128) as u32))
+    }
+
+    /// Rate to send at `elapsed` into the test, in bits per second.
+    fn rate_at(&self, elapsed: Duration) -> f64 {
+        match self.ramp_steps() {
+            Some(steps) => {
+                let progress = elapsed.as_secs_f64() / self.duration.as_secs_f64();
+                let step = (progress * steps as f64).floor().min(steps as f64 - 1.0);
+                self.rate as f64 * (step + 1.0) / steps as f64
+            }
+            None => self.rate as f64,
+        }
+    }
+
+    pub(crate) async fn run(&self) -> Result<BandwidthState> {
+        let bind = self
+            .bind_address
+            .unwrap_or_else(|| listen::unspecified(self.server_address));
+        let socket = UdpSocket::bind(SocketAddr::new(bind, 0)).await?;
+        socket
+            .connect(SocketAddr::new(self.server_address, self.server_port))
+            .await?;
+
+        let Some(session) = self.open(&socket).await else {
+            bail!("The server did not open a UDP bandwidth session");
+        };
+        let mut batches = payload_buffers(UDP_DATAGRAM_SIZE, self.seed).map(|mut datagram| {
+            datagram[..8].copy_from_slice(UDP_DATA);
+            datagram[8..16].copy_from_slice(&session.to_be_bytes());
+            vec![datagram; self.batch]
+        });
+
+        if let (Some(requested), Some(steps)) = (self.ramp_steps, self.ramp_steps()) {
+            if steps < requested {
+                info!(
+                    "Ramping in {} steps instead of {}, each step needs at least {:.1?} on this link",
+                    steps, requested, self.min_step
+                );
+            }
+        }
+
+        let mut state = BandwidthState {
+            target_rate: Some(self.rate),
+            ..Default::default()
+        };
+        let mut sent_bytes = 0u64;
+
+        let start = Instant::now();
+        let deadline = time::sleep(self.duration);
+        tokio::pin!(deadline);
+
+        let mut pacing = time::interval(PACING_INTERVAL);
+        pacing.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let mut report = time::interval_at(start + self.report_interval, self.report_interval);
+
+        let mut request = 0u32;
+        let mut pending: Option<(u32, Instant)> = None;
+        let mut previous = (start, UdpCounters::default(), 0);
+        let mut buf = [0; 64];
+
+        // Datagrams due but not sent yet
+        let mut credit = 0.0;
+        let mut last_tick = start;
+
+        loop {
+            tokio::select! {
+                now = pacing.tick() => {
+                    credit += (now - last_tick).as_secs_f64() * self.rate_at(now - start)
+                        / (8 * UDP_DATAGRAM_SIZE) as f64;
+                    credit = credit.min(MAX_DATAGRAMS_PER_TICK as f64);
+                    last_tick = now;
+
+                    let mut due = state.datagrams_sent + credit as u64;
+                    if let Some(max) = self.max_bytes {
+                        due = due.min(state.datagrams_sent + max.saturating_sub(sent_bytes) / UDP_DATAGRAM_SIZE as u64);
+                    }
+                    let batch = &mut batches[payload_index(self.payload, &state)];
+                    while state.datagrams_sent < due {
+                        let count = (due - state.datagrams_sent).min(batch.len() as u64) as usize;
+                        for (i, datagram) in batch[..count].iter_mut().enumerate() {
+                            let sequence = state.datagrams_sent + i as u64;
+                            datagram[16..24].copy_from_slice(&sequence.to_be_bytes());
+                        }
+                        let sent = match mmsg::send(&socket, &batch[..count]).await {
+                            Ok(sent) => sent,
+                            // Send queue full, the rate is beyond what this host can push
+                            Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => 0,
+                            Err(e) => return Err(e.into()),
+                        };
+                        sent_bytes += (sent * UDP_DATAGRAM_SIZE) as u64;
+                        state.datagrams_sent += sent as u64;
+                        credit -= sent as f64;
+                        if sent < count {
+                            break;
+                        }
+                    }
+
+                    if self.max_bytes.is_some_and(|max| sent_bytes + UDP_DATAGRAM_SIZE as u64 > max) {
+                        debug!("Byte budget of {} reached", format_bytes(sent_bytes));
+                        break;
+                    }
+                }
+                now = report.tick() => {
+                    request += 1;
+                    socket.send(&report_request(session, request)).await?;
+                    pending = Some((request, now));
+                }
+                received = socket.recv(&mut buf) => {
+                    let now = Instant::now();
+                    let Some((id, counters)) = parse_report(&buf[..received?], session) else {
+                        continue;
+                    };
+                    let rtt = match pending {
+                        Some((pending_id, sent)) if pending_id == id => now - sent,
+                        _ => continue,
+                    };
+                    pending = None;
+
+                    let current = (now, counters, state.datagrams_sent);
+                    let sample = udp_sample(&previous, &current, rtt, start);
+                    self.notify.send(Action::BandwidthSample(sample.clone()))?;
+                    state.samples.push(sample);
+                    previous = current;
+                }
+                _ = &mut deadline => break,
+                _ = self.quit.cancelled() => break,
+            }
+        }
+
+        state.elapsed = start.elapsed();
+
+        let counters = match self.final_report(&socket, session, request + 1).await {
+            Some((counters, rtt)) => {
+                // Covers the tail after the last report tick
+                let current = (Instant::now(), counters, state.datagrams_sent);
+                if counters.packets > previous.1.packets {
+                    state
+                        .samples
+                        .push(udp_sample(&previous, &current, rtt, start));
+                }
+                counters
+            }
+            None => {
+                warn!("No final report from the server, results are from the last interval");
+                previous.1
+            }
+        };
+        state.total_bytes = counters.bytes;
+        state.received = Some(counters);
+
+        Ok(state)
+    }
+
+    /// Asks the server for the token of a new session.
+    async fn open(&self, socket: &UdpSocket) -> Option<u64> {
+        let nonce: u32 = rand::random();
+        let mut request = [0; UDP_REPLY_LEN];
+        request[..8].copy_from_slice(UDP_OPEN);
+        request[8..12].copy_from_slice(&nonce.to_be_bytes());
+
+        let mut buf = [0; 64];
+        for _ in 0..FINAL_REPORT_ATTEMPTS {
+            socket.send(&request).await.ok()?;
+
+            let deadline = Instant::now() + FINAL_REPORT_TIMEOUT;
+            while let Ok(Ok(n)) = time::timeout_at(deadline, socket.recv(&mut buf)).await {
+                if n >= 20 && buf[..12] == request[..12] {
+                    return Some(u64::from_be_bytes(buf[12..20].try_into().unwrap()));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Asks for the counters once everything sent had time to arrive.
+    async fn final_report(
+        &self,
+        socket: &UdpSocket,
+        session: u64,
+        request: u32,
+    ) -> Option<(UdpCounters, Duration)> {
+        let mut buf = [0; 64];
+        for _ in 0..FINAL_REPORT_ATTEMPTS {
+            let sent = Instant::now();
+            socket.send(&report_request(session, request)).await.ok()?;
+
+            let deadline = Instant::now() + FINAL_REPORT_TIMEOUT;
+            while let Ok(Ok(n)) = time::timeout_at(deadline, socket.recv(&mut buf)).await {
+                match parse_report(&buf[..n], session) {
+                    Some((id, counters)) if id == request => {
+                        return Some((counters, sent.elapsed()))
+                    }
+                    _ => continue,
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// Builds the sample between two reports, each the time it arrived, the server's
+/// counters and the number of datagrams sent until then.
+fn udp_sample(
+    previous: &(Instant, UdpCounters, u64),
+    current: &(Instant, UdpCounters, u64),
+    rtt: Duration,
+    start: Instant,
+) -> BandwidthSample {
+    let (at, before, sent_before) = previous;
+    let (now, counters, sent) = current;
+    let elapsed = (*now - *at).as_secs_f64();
+    // The counters start over if the server dropped the session in between
+    let bytes = counters.bytes.saturating_sub(before.bytes);
+    let offered = sent.saturating_sub(*sent_before) * UDP_DATAGRAM_SIZE as u64;
+
+    BandwidthSample {
+        at: *now - start,
+        bytes,
+        bits_per_second: (bytes as f64 * 8.0 / elapsed) as u64,
+        retransmits: 0,
+        lost: Some(counters.lost().saturating_sub(before.lost())),
+        offered: Some((offered as f64 * 8.0 / elapsed) as u64),
+        cwnd: 0,
+        rtt,
+        rttvar: Duration::ZERO,
+        delivery_rate: 0,
+    }
+}
+
+fn report_request(session: u64, request: u32) -> [u8; UDP_REPLY_LEN] {
+    let mut message = [0; UDP_REPLY_LEN];
+    message[..8].copy_from_slice(UDP_REPORT);
+    message[8..16].copy_from_slice(&session.to_be_bytes());
+    message[16..20].copy_from_slice(&request.to_be_bytes());
+    message
+}
+
+fn parse_report(message: &[u8], session: u64) -> Option<(u32, UdpCounters)> {
+    if message.len() < UDP_REPLY_LEN
+        || &message[..8] != UDP_REPORT
+        || message[8..16] != session.to_be_bytes()
+    {
+        return None;
+    }
+
+    let u64_at = |i: usize| u64::from_be_bytes(message[i..i + 8].try_into().unwrap());
+    let request = u32::from_be_bytes(message[16..20].try_into().unwrap());
+    Some((
+        request,
+        UdpCounters {
+            packets: u64_at(20),
+            bytes: u64_at(28),
+            expected: u64_at(36),
+            reordered: u64_at(44),
+        },
+    ))
+}
+
+/// What the server received of one UDP bandwidth session so far.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct UdpCounters {
+    pub packets: u64,
+    pub bytes: u64,
+    /// One past the highest sequence number seen
+    pub expected: u64,
+    /// Datagrams that arrived after a later one
+    pub reordered: u64,
+}
+
+impl UdpCounters {
+    pub(crate) fn lost(&self) -> u64 {
+        self.expected.saturating_sub(self.packets)
+    }
+}
+
+/// Server side of [`UdpBandwidth`], shares the socket of the echo server. Hands
+/// out session tokens, counts the datagrams of each session and answers report
+/// requests. Sessions are known by their token rather than the source address,
+/// so spoofed datagrams cannot touch a running test.
+#[derive(Default)]
+pub(crate) struct UdpSink {
+    sessions: HashMap<u64, (UdpCounters, Instant)>,
+}
+
+impl UdpSink {
+    /// Whether `datagram` belongs to a UDP bandwidth test rather than a probe.
+    pub(crate) fn accepts(datagram: &[u8]) -> bool {
+        datagram.len() >= 12
+            && [UDP_OPEN, UDP_DATA, UDP_REPORT].contains(&datagram[..8].try_into().unwrap())
+    }
+
+    /// Counts a data datagram or returns the reply to a request to open a
+    /// session or for a report.
+    pub(crate) fn handle(&mut self, datagram: &[u8], src: SocketAddr) -> Option<Vec<u8>> {
+        let now = Instant::now();
+
+        if &datagram[..8] == UDP_OPEN {
+            if datagram.len() < UDP_REPLY_LEN {
+                return None;
+            }
+            if self.sessions.len() >= MAX_UDP_SESSIONS {
+                self.sessions
+                    .retain(|_, (_, seen)| now - *seen < UDP_SESSION_TIMEOUT);
+                if self.sessions.len() >= MAX_UDP_SESSIONS {
+                    warn!("Too many UDP bandwidth tests, ignoring {}", src);
+                    return None;
+                }
+            }
+
+            let session: u64 = rand::random();
+            self.sessions.insert(session, (UdpCounters::default(), now));
+            info!("UDP bandwidth test from {}", src);
+
+            let mut reply = datagram[..12].to_vec();
+            reply.extend_from_slice(&session.to_be_bytes());
+            return Some(reply);
+        }
+
+        if datagram.len() < 16 {
+            return None;
+        }
+        let session = u64::from_be_bytes(datagram[8..16].try_into().unwrap());
+
+        if &datagram[..8] == UDP_REPORT {
+            if datagram.len() < UDP_REPLY_LEN {
+                return None;
+            }
+            let counters = self
+                .sessions
+                .get(&session)
+                .map_or_else(UdpCounters::default, |(counters, _)| *counters);
+
+            let mut reply = Vec::with_capacity(UDP_REPLY_LEN);
+            reply.extend_from_slice(&datagram[..UDP_REPORT_LEN]);
+            for value in [
+                counters.packets,
+                counters.bytes,
+                counters.expected,
+                counters.reordered,
+            ] {
+                reply.extend_from_slice(&value.to_be_bytes());
+            }
+            return Some(reply);
+        }
+
+        if datagram.len() < 24 {
+            return None;
+        }
+        let sequence = u64::from_be_bytes(datagram[16..24].try_into().unwrap());
+        // Tokens the server did not hand out are ignored
+        let (counters, seen) = self.sessions.get_mut(&session)?;
+
+        counters.packets += 1;
+        counters.bytes += datagram.len() as u64;
+        if sequence < counters.expected {
+            counters.reordered += 1;
+        } else {
+            counters.expected = sequence + 1;
+        }
+        *seen = now;
+
+        None
+    }
+}
+
+pub(crate) fn format_bytes(bytes: u64) -> String {
+    match bytes as f64 {
+        b if b >= 1e9 => format!("{:.2} GB", b / 1e9),
+        b if b >= 1e6 => format!("{:.2} MB", b / 1e6),
+        b if b >= 1e3 => format!("{:.2} kB", b / 1e3),
+        b => format!("{:.0} B", b),
+    }
+}
+
+pub(crate) fn format_bitrate(bits_per_second: f64) -> String {
+    match bits_per_second {
+        b if b >= 1e9 => format!("{:.2} Gbit/s", b / 1e9),
+        b if b >= 1e6 => format!("{:.2} Mbit/s", b / 1e6),
+        b if b >= 1e3 => format!("{:.2} kbit/s", b / 1e3),
+        b => format!("{:.0} bit/s", b),
+    }
+}
@@ -0,0 +1,423 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use color_eyre::eyre::{bail, Result};
+use tokio::{
+    net::UdpSocket,
+    sync::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        Notify,
+    },
+    time::{self, Instant},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use super::{
+    latency::{Latency, Percentiles, State},
+    listen,
+};
+use crate::action::Action;
+
+/// First bytes of a datagram asking the server to probe the sending socket
+/// back, repeated as a keepalive while the client runs.
+const BIDIR_MAGIC: &[u8; 11] = b"bwlat-bidir";
+/// Magic, probe count, interval in nanoseconds, probe size and the server port
+/// the client opened its NAT to, zero until it knows it.
+const REQUEST_LEN: usize = BIDIR_MAGIC.len() + 4 + 8 + 2 + 2;
+
+/// First bytes of the server's reports on the probes it sends the client.
+const REPORT_MAGIC: &[u8; 12] = b"bwlat-report";
+/// Magic, probe port, sent and received probes, min, average and max latency,
+/// four percentiles in nanoseconds and whether the server is done.
+const REPORT_LEN: usize = REPORT_MAGIC.len() + 2 + 4 + 4 + 7 * 8 + 1;
+
+/// How often the client repeats its request.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
+/// The server stops probing a client that stopped asking or echoing for this long.
+const KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often the server reports to the client.
+const REPORT_INTERVAL: Duration = Duration::from_millis(250);
+/// The final report is sent this often in case one is lost.
+const FINAL_REPORTS: usize = 3;
+
+/// Limits on what a client can ask for, the server sends probes on request.
+const MIN_INTERVAL: Duration = Duration::from_millis(1);
+const MAX_PROBE_SIZE: u16 = 1472;
+
+/// Largest UDP payload, probes of any size are echoed whole.
+const MAX_DATAGRAM: usize = 65535;
+
+/// Probes a client asks the server to send it during a bidirectional test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BidirRequest {
+    pub count: u32,
+    pub interval: Duration,
+    pub packet_size: u16,
+    /// Port the server's probes come from, once the client knows it
+    pub port: u16,
+}
+
+impl BidirRequest {
+    fn write(&self) -> Vec<u8> {
+        let mut request = Vec::with_capacity(REQUEST_LEN);
+        request.extend_from_slice(BIDIR_MAGIC);
+        request.extend_from_slice(&self.count.to_be_bytes());
+        request.extend_from_slice(&(self.interval.as_nanos() as u64).to_be_bytes());
+        request.extend_from_slice(&self.packet_size.to_be_bytes());
+        request.extend_from_slice(&self.port.to_be_bytes());
+        request
+    }
+
+    pub(crate) fn read(buf: &[u8]) -> Option<Self> {
+        let fields = buf.strip_prefix(BIDIR_MAGIC)?;
+        if buf.len() != REQUEST_LEN {
+            return None;
+        }
+        Some(Self {
+            count: u32::from_be_bytes(fields[..4].try_into().unwrap()),
+            interval: Duration::from_nanos(u64::from_be_bytes(fields[4..12].try_into().unwrap())),
+            packet_size: u16::from_be_bytes(fields[12..14].try_into().unwrap()),
+            port: u16::from_be_bytes(fields[14..16].try_into().unwrap()),
+        })
+    }
+}
+
+/// Progress of the probes the server sends the client, as the server sees it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ReverseReport {
+    pub port: u16,
+    pub sent: u32,
+    pub received: u32,
+    pub min_latency: Duration,
+    pub average_latency: Duration,
+    pub max_latency: Duration,
+    pub percentiles: Option<Percentiles>,
+    pub finished: bool,
+}
+
+impl ReverseReport {
+    fn of(state: &State, port: u16, finished: bool) -> Self {
+        Self {
+            port,
+            sent: state.sent_packets(),
+            received: state.received_packets,
+            min_latency: state.min_latency,
+            average_latency: state.average_latency,
+            max_latency: state.max_latency,
+            percentiles: state.percentiles(),
+            finished,
+        }
+    }
+
+    pub(crate) fn loss(&self) -> f64 {
+        1.0 - self.received as f64 / self.sent.max(1) as f64
+    }
+
+    fn write(&self) -> Vec<u8> {
+        let p = self.percentiles;
+        let mut report = Vec::with_capacity(REPORT_LEN);
+        report.extend_from_slice(REPORT_MAGIC);
+        report.extend_from_slice(&self.port.to_be_bytes());
+        report.extend_from_slice(&self.sent.to_be_bytes());
+        report.extend_from_slice(&self.received.to_be_bytes());
+        for latency in [
+            self.min_latency,
+            self.average_latency,
+            self.max_latency,
+            // Zero marks missing percentiles, latencies are never zero
+            p.map_or(Duration::ZERO, |p| p.p50),
+            p.map_or(Duration::ZERO, |p| p.p90),
+            p.map_or(Duration::ZERO, |p| p.p99),
+            p.map_or(Duration::ZERO, |p| p.p999),
+        ] {
+            report.extend_from_slice(&(latency.as_nanos() as u64).to_be_bytes());
+        }
+        report.push(self.finished as u8);
+        report
+    }
+
+    fn read(buf: &[u8]) -> Option<Self> {
+        let fields = buf.strip_prefix(REPORT_MAGIC)?;
+        if buf.len() != REPORT_LEN {
+            return None;
+        }
+        let latency = |i: usize| {
+            let at = 10 + i * 8;
+            Duration::from_nanos(u64::from_be_bytes(fields[at..at + 8].try_into().unwrap()))
+        };
+        let percentiles = Percentiles {
+            p50: latency(3),
+            p90: latency(4),
+            p99: latency(5),
+            p999: latency(6),
+        };
+        Some(Self {
+            port: u16::from_be_bytes(fields[..2].try_into().unwrap()),
+            sent: u32::from_be_bytes(fields[2..6].try_into().unwrap()),
+            received: u32::from_be_bytes(fields[6..10].try_into().unwrap()),
+            min_latency: latency(0),
+            average_latency: latency(1),
+            max_latency: latency(2),
+            percentiles: (!percentiles.p50.is_zero()).then_some(percentiles),
+            finished: fields[REPORT_LEN - REPORT_MAGIC.len() - 1] != 0,
+        })
+    }
+}
+
+/// Client half of a bidirectional test: asks the server to probe a socket of
+/// its own, echoes the probes and turns the server's reports into actions.
+pub(crate) struct Bidirectional {
+    server: SocketAddr,
+    request: BidirRequest,
+    bind_address: Option<IpAddr>,
+    target: usize,
+    notify: UnboundedSender<Action>,
+    quit: CancellationToken,
+}
+
+impl Bidirectional {
+    pub(crate) fn new(
+        server: SocketAddr,
+        count: u32,
+        interval: Duration,
+        packet_size: u16,
+        notify: UnboundedSender<Action>,
+        quit: CancellationToken,
+    ) -> Self {
+        Self {
+            server,
+            request: BidirRequest {
+                count,
+                interval,
+                packet_size,
+                port: 0,
+            },
+            bind_address: None,
+            target: 0,
+            notify,
+            quit,
+        }
+    }
+
+    /// Index the actions of the server's probes are tagged with.
+    pub(crate) fn with_target(mut self, target: usize) -> Self {
+        self.target = target;
+        self
+    }
+
+    pub(crate) fn with_bind_address(mut self, address: IpAddr) -> Self {
+        self.bind_address = Some(address);
+        self
+    }
+
+    /// Echoes until the server reports it is done or the run ends, returns the
+    /// last report.
+    pub(crate) async fn run(mut self) -> Result<Option<ReverseReport>> {
+        let bind = self
+            .bind_address
+            .unwrap_or_else(|| listen::unspecified(self.server.ip()));
+        let socket = UdpSocket::bind(SocketAddr::new(bind, 0)).await?;
+        let mut buf = vec![0; MAX_DATAGRAM];
+        let mut keepalive = time::interval(KEEPALIVE_INTERVAL);
+        let start = Instant::now();
+        let mut last: Option<ReverseReport> = None;
+
+        loop {
+            tokio::select! {
+                _ = self.quit.cancelled() => break,
+                _ = keepalive.tick() => {
+                    if last.is_none() && start.elapsed() > KEEPALIVE_TIMEOUT {
+                        bail!("The server did not probe back, it may be too old for bidirectional tests");
+                    }
+                    socket.send_to(&self.request.write(), self.server).await?;
+                }
+                received = socket.recv_from(&mut buf) => {
+                    let (size, src) = received?;
+                    if listen::canonical(src).ip() != self.server.ip() {
+                        continue;
+                    }
+
+                    let Some(report) = ReverseReport::read(&buf[..size]) else {
+                        // A probe of the server
+                        socket.send_to(&buf[..size], src).await?;
+                        continue;
+                    };
+
+                    if self.request.port == 0 {
+                        // Opens NATs and firewalls to the probes, too short to
+                        // count as an echo
+                        socket.send_to(&[0], SocketAddr::new(src.ip(), report.port)).await?;
+                        self.request.port = report.port;
+                        socket.send_to(&self.request.write(), self.server).await?;
+                        if self.request.count > 0 {
+                            self.notify
+                                .send(Action::LatencyPacketTotal(self.target, self.request.count))?;
+                        }
+                    }
+                    self.report(&report)?;
+                    last = Some(report);
+                    if report.finished {
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(last)
+    }
+
+    fn report(&self, report: &ReverseReport) -> Result<()> {
+        self.notify
+            .send(Action::LatencyPacketsSent(self.target, report.sent))?;
+        self.notify.send(Action::LatencyPacketsReceived(
+            self.target,
+            report.received,
+            report.min_latency,
+            report.average_latency,
+            report.max_latency,
+        ))?;
+        if let Some(percentiles) = report.percentiles {
+            self.notify
+                .send(Action::LatencyPercentiles(self.target, percentiles))?;
+        }
+        Ok(())
+    }
+}
+
+/// Server half of bidirectional tests, one probing engine per client socket.
+#[derive(Default)]
+pub(crate) struct ReverseProbes {
+    bind: Option<IpAddr>,
+    clients: HashMap<SocketAddr, UnboundedSender<BidirRequest>>,
+}
+
+impl ReverseProbes {
+    pub(crate) fn new(bind: Option<IpAddr>) -> Self {
+        Self {
+            bind,
+            clients: HashMap::new(),
+        }
+    }
+
+    /// Starts probing `src` back, or passes a repeated request on to the
+    /// running test.
+    pub(crate) fn handle(
+        &mut self,
+        socket: &Arc<UdpSocket>,
+        request: BidirRequest,
+        src: SocketAddr,
+    ) {
+        self.clients.retain(|_, requests| !requests.is_closed());
+        if let Some(requests) = self.clients.get(&src) {
+            let _ = requests.send(request);
+            return;
+        }
+
+        let (requests, rx) = mpsc::unbounded_channel();
+        self.clients.insert(src, requests);
+        let socket = socket.clone();
+        let bind = self.bind;
+        tokio::spawn(async move {
+            if let Err(e) = probe_client(socket, src, request, bind, rx).await {
+                warn!("Bidirectional test with {} failed: {:?}", src, e);
+            }
+        });
+    }
+}
+
+/// Probes a client's echo socket and reports the results from `socket` until
+/// the probes are done or the client goes silent.
+async fn probe_client(
+    socket: Arc<UdpSocket>,
+    client: SocketAddr,
+    request: BidirRequest,
+    bind: Option<IpAddr>,
+    mut requests: UnboundedReceiver<BidirRequest>,
+) -> Result<()> {
+    let peer = listen::canonical(client);
+    let bind = bind
+        .filter(|b| b.is_ipv4() == peer.is_ipv4())
+        .unwrap_or_else(|| listen::unspecified(peer.ip()));
+    // The client opens its NAT to this port before the first probe is sent
+    let port = std::net::UdpSocket::bind(SocketAddr::new(bind, 0))?
+        .local_addr()?
+        .port();
+
+    let (notify, mut actions) = mpsc::unbounded_channel();
+    let cancel = CancellationToken::new();
+    let _cancel = cancel.clone().drop_guard();
+    let mut latency = Latency::new_with_count(
+        peer.ip(),
+        peer.port(),
+        request.count,
+        notify,
+        cancel.clone(),
+    )
+    .with_interval(request.interval.max(MIN_INTERVAL))
+    .with_packet_size(request.packet_size.clamp(8, MAX_PROBE_SIZE))
+    .with_client_port(port)
+    .with_bind_address(bind);
+    let state = latency.state();
+
+    let start = Arc::new(Notify::new());
+    let mut engine = tokio::spawn({
+        let start = start.clone();
+        async move {
+            start.notified().await;
+            latency.run().await
+        }
+    });
+
+    info!("Bidirectional test with {}", peer);
+    let mut started = false;
+    let mut heard = Instant::now();
+    let mut echoed = (Instant::now(), 0);
+    let mut report = time::interval(REPORT_INTERVAL);
+    loop {
+        tokio::select! {
+            result = &mut engine => {
+                result??;
+                break;
+            }
+            Some(request) = requests.recv() => {
+                heard = Instant::now();
+                if !started && request.port == port {
+                    started = true;
+                    echoed.0 = Instant::now();
+                    start.notify_one();
+                }
+            }
+            // The engine's progress is read from its state instead
+            Some(_) = actions.recv() => {}
+            _ = report.tick() => {
+                let state = state.lock().await;
+                if state.received_packets > echoed.1 {
+                    echoed = (Instant::now(), state.received_packets);
+                }
+                if heard.elapsed() > KEEPALIVE_TIMEOUT || (started && echoed.0.elapsed() > KEEPALIVE_TIMEOUT) {
+                    debug!("{} went silent, ending the bidirectional test", peer);
+                    if !started {
+                        return Ok(());
+                    }
+                    cancel.cancel();
+                }
+                socket
+                    .send_to(&ReverseReport::of(&state, port, false).write(), client)
+                    .await?;
+            }
+        }
+    }
+
+    let report = ReverseReport::of(&*state.lock().await, port, true).write();
+    for _ in 0..FINAL_REPORTS {
+        socket.send_to(&report, client).await?;
+    }
+    debug!("Bidirectional test with {} done", peer);
+
+    Ok(())
+}
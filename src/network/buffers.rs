@@ -0,0 +1,61 @@
+use std::io;
+
+use socket2::SockRef;
+use tracing::{info, warn};
+
+use super::bandwidth::format_bytes;
+
+/// Sizes asked for the kernel buffers of a socket, the OS defaults where unset.
+/// Small default buffers drop datagrams in the kernel during high-rate tests.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SocketBuffers {
+    pub receive: Option<usize>,
+    pub send: Option<usize>,
+}
+
+impl SocketBuffers {
+    pub(crate) fn is_default(&self) -> bool {
+        self.receive.is_none() && self.send.is_none()
+    }
+
+    pub(crate) fn apply(&self, socket: &SockRef<'_>) -> io::Result<()> {
+        if let Some(size) = self.receive {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let Some(size) = self.send {
+            socket.set_send_buffer_size(size)?;
+        }
+        Ok(())
+    }
+
+    /// Logs the sizes `socket` got. Linux doubles each request for its
+    /// bookkeeping and caps it at `net.core.rmem_max` and `wmem_max`, which a
+    /// warning points out when the cap was hit.
+    pub(crate) fn report(&self, socket: &SockRef<'_>, name: &str) -> io::Result<()> {
+        let receive = socket.recv_buffer_size()?;
+        let send = socket.send_buffer_size()?;
+        info!(
+            "{} socket buffers: receive {}, send {}",
+            name,
+            format_bytes(receive as u64),
+            format_bytes(send as u64)
+        );
+
+        for (asked, got, key, option) in [
+            (self.receive, receive, "rmem_max", "--rcvbuf"),
+            (self.send, send, "wmem_max", "--sndbuf"),
+        ] {
+            if let Some(asked) = asked.filter(|&asked| got < asked) {
+                warn!(
+                    "{} {} was capped at {}, raise it with sudo sysctl -w net.core.{}={}",
+                    option,
+                    format_bytes(asked as u64),
+                    format_bytes(got as u64),
+                    key,
+                    asked
+                );
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,51 @@
+use std::io;
+
+use tokio::net::UdpSocket;
+
+/// Not exported by libc for Linux, the value of all architectures but SPARC.
+#[cfg(target_os = "linux")]
+const SO_BUSY_POLL: libc::c_int = 46;
+
+/// Has the kernel poll the device queue for up to `micros` when `socket` is
+/// read with nothing waiting, instead of leaving it to the interrupt. Raising it
+/// above `net.core.busy_read` needs `CAP_NET_ADMIN`.
+#[cfg(target_os = "linux")]
+pub(crate) fn enable(socket: &UdpSocket, micros: u32) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let micros = micros as libc::c_int;
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            SO_BUSY_POLL,
+            &micros as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn enable(_socket: &UdpSocket, _micros: u32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "SO_BUSY_POLL needs Linux",
+    ))
+}
+
+/// Calls the non-blocking `recv` until a datagram is there instead of waiting
+/// for the runtime to be woken up, which takes tens of microseconds. Keeps a
+/// core busy, but yields between attempts so the other tasks keep running.
+pub(crate) async fn spin<T>(mut recv: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    loop {
+        match recv() {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => tokio::task::yield_now().await,
+            received => return received,
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use std::{io, net::IpAddr};
+
+#[cfg(target_os = "linux")]
+const SO_NO_CHECK: libc::c_int = 11;
+#[cfg(target_os = "linux")]
+const UDP_NO_CHECK6_TX: libc::c_int = 101;
+#[cfg(target_os = "linux")]
+const UDP_NO_CHECK6_RX: libc::c_int = 102;
+
+/// Sends the datagrams of `socket` with a zero UDP checksum, so payloads damaged
+/// on the path reach the receiver instead of being dropped by its stack. IPv6
+/// requires checksums, receivers other than Linux with `UDP_NO_CHECK6_RX` drop
+/// such datagrams (RFC 6935), so over IPv6 the socket also accepts them.
+#[cfg(target_os = "linux")]
+pub(crate) fn disable(socket: socket2::SockRef<'_>, local: IpAddr) -> io::Result<()> {
+    match local {
+        IpAddr::V4(_) => set(&socket, libc::SOL_SOCKET, SO_NO_CHECK),
+        IpAddr::V6(_) => {
+            set(&socket, libc::SOL_UDP, UDP_NO_CHECK6_TX)?;
+            set(&socket, libc::SOL_UDP, UDP_NO_CHECK6_RX)
+        }
+    }
+}
+
+/// Accepts IPv6 datagrams with a zero UDP checksum on `socket`, which the stack
+/// drops otherwise. IPv4 receivers always take them.
+#[cfg(target_os = "linux")]
+pub(crate) fn accept_missing(socket: socket2::SockRef<'_>) -> io::Result<()> {
+    set(&socket, libc::SOL_UDP, UDP_NO_CHECK6_RX)
+}
+
+#[cfg(target_os = "linux")]
+fn set(socket: &socket2::SockRef<'_>, level: libc::c_int, name: libc::c_int) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let value: libc::c_int = 1;
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn accept_missing(_socket: socket2::SockRef<'_>) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "UDP checksums can only be disabled on Linux",
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn disable(_socket: socket2::SockRef<'_>, _local: IpAddr) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "UDP checksums can only be disabled on Linux",
+    ))
+}
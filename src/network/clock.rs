@@ -0,0 +1,260 @@
+use std::{
+    io,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::{net::UdpSocket, time};
+
+/// First bytes of a datagram asking the server for its clock.
+pub(crate) const CLOCK_MAGIC: &[u8; 10] = b"bwlat-time";
+
+/// Request is the magic and a token, the reply appends the server's clock.
+const REQUEST_LEN: usize = CLOCK_MAGIC.len() + 8;
+const REPLY_LEN: usize = REQUEST_LEN + 8;
+
+/// How long to wait for the server's clock before giving up on a sample.
+const SAMPLE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Samples that took longer than this multiple of the fastest round trip, and
+/// more than the slack on top of it, are too imprecise to estimate drift from.
+const MAX_RTT_FACTOR: u32 = 2;
+const RTT_SLACK: Duration = Duration::from_micros(250);
+
+/// Exchanges of the offset estimation before a run, and the pause between them.
+const OFFSET_SAMPLES: usize = 8;
+const OFFSET_SAMPLE_GAP: Duration = Duration::from_millis(20);
+
+/// Standard errors the drift has to stand out by to count as real rather than
+/// noise of the offset readings.
+const SIGNIFICANCE: f64 = 3.0;
+/// With fewer samples the standard error itself is too uncertain to judge by.
+const MIN_SIGNIFICANT_SAMPLES: usize = 10;
+
+/// Share of the smallest one-way delay the offset may move over a run before
+/// one-way delays measured against a single offset become meaningless.
+const MAX_DRIFT_SHARE: f64 = 0.1;
+
+/// One reading of the server's clock against the client's.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ClockSample {
+    /// Time since the start of the run
+    pub at: Duration,
+    /// Server clock minus client clock in nanoseconds, assuming a symmetric path
+    pub offset: i64,
+    pub rtt: Duration,
+    /// Drift estimated from this and all earlier samples, in parts per million
+    pub drift: Option<f64>,
+}
+
+/// How far the client's clock ran away from the server's over a run.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ClockDrift {
+    /// Parts per million, positive when the server's clock runs faster
+    pub ppm: f64,
+    /// Standard error of `ppm`
+    pub error: f64,
+    /// Samples the estimate is based on
+    pub samples: usize,
+    /// Change of the offset between the first and last sample
+    pub accumulated: Duration,
+}
+
+impl ClockDrift {
+    /// Whether the drift is clearly measurable and large compared to one-way
+    /// delays on a path with this smallest round trip.
+    pub(crate) fn invalidates_one_way_delay(&self, min_rtt: Duration) -> bool {
+        self.samples >= MIN_SIGNIFICANT_SAMPLES
+            && self.ppm.abs() > self.error * SIGNIFICANCE
+            && self.accumulated.as_secs_f64() > min_rtt.as_secs_f64() / 2.0 * MAX_DRIFT_SHARE
+    }
+}
+
+/// The server's answer to a clock request, `None` for other datagrams.
+pub(crate) fn reply(request: &[u8]) -> Option<Vec<u8>> {
+    if request.len() != REQUEST_LEN || !request.starts_with(CLOCK_MAGIC) {
+        return None;
+    }
+
+    let mut reply = Vec::with_capacity(REPLY_LEN);
+    reply.extend_from_slice(request);
+    reply.extend_from_slice(&now().to_be_bytes());
+    Some(reply)
+}
+
+/// Asks the server on the connected `socket` for its clock, `None` when no
+/// answer arrived in time.
+pub(crate) async fn sample(socket: &UdpSocket, at: Duration) -> io::Result<Option<ClockSample>> {
+    let token: u64 = rand::random();
+    let mut request = Vec::with_capacity(REQUEST_LEN);
+    request.extend_from_slice(CLOCK_MAGIC);
+    request.extend_from_slice(&token.to_be_bytes());
+
+    let sent = now();
+    socket.send(&request).await?;
+
+    let mut buf = [0; REPLY_LEN + 1];
+    let deadline = time::Instant::now() + SAMPLE_TIMEOUT;
+    loop {
+        let size = match time::timeout_at(deadline, socket.recv(&mut buf)).await {
+            Ok(size) => size?,
+            Err(_) => return Ok(None),
+        };
+        let received = now();
+
+        // Servers without clock support echo the request back unchanged
+        if size != REPLY_LEN || buf[..REQUEST_LEN] != request[..] {
+            continue;
+        }
+
+        let server = i64::from_be_bytes(buf[REQUEST_LEN..REPLY_LEN].try_into().unwrap());
+        return Ok(Some(ClockSample {
+            at,
+            offset: server - (sent + received) / 2,
+            rtt: Duration::from_nanos(received.saturating_sub(sent) as u64),
+            drift: None,
+        }));
+    }
+}
+
+/// Least-squares slope of the offset over the run, leaving out samples that
+/// were slowed down by queueing. Needs at least three precise samples.
+pub(crate) fn drift(samples: &[ClockSample]) -> Option<ClockDrift> {
+    let fastest = samples.iter().map(|s| s.rtt).min()?;
+    let limit = (fastest * MAX_RTT_FACTOR).max(fastest + RTT_SLACK);
+    let precise: Vec<_> = samples.iter().filter(|s| s.rtt <= limit).collect();
+    if precise.len() < 3 {
+        return None;
+    }
+
+    let n = precise.len() as f64;
+    let mean_at = precise.iter().map(|s| s.at.as_secs_f64()).sum::<f64>() / n;
+    let mean_offset = precise.iter().map(|s| s.offset as f64).sum::<f64>() / n;
+    let (covariance, variance) = precise.iter().fold((0.0, 0.0), |(c, v), s| {
+        let dx = s.at.as_secs_f64() - mean_at;
+        (c + dx * (s.offset as f64 - mean_offset), v + dx * dx)
+    });
+    if variance == 0.0 {
+        return None;
+    }
+
+    // Nanoseconds per second are parts per billion
+    let slope = covariance / variance;
+    let residuals = precise
+        .iter()
+        .map(|s| {
+            let fitted = mean_offset + slope * (s.at.as_secs_f64() - mean_at);
+            (s.offset as f64 - fitted).powi(2)
+        })
+        .sum::<f64>();
+    let error = (residuals / (n - 2.0) / variance).sqrt();
+
+    let span = precise.last()?.at.saturating_sub(precise.first()?.at);
+    Some(ClockDrift {
+        ppm: slope / 1000.0,
+        error: error / 1000.0,
+        samples: precise.len(),
+        accumulated: Duration::from_nanos((slope.abs() * span.as_secs_f64()) as u64),
+    })
+}
+
+/// Offset of the server's clock estimated before a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ClockOffset {
+    /// Server clock minus client clock in nanoseconds
+    pub offset: i64,
+    /// Round trip of the exchange the offset is taken from, the estimate is off
+    /// by at most half of it
+    pub rtt: Duration,
+}
+
+/// Estimates the offset NTP-style from a few clock readings on the connected
+/// `socket`, keeping the one with the fastest round trip as it suffered the
+/// least from queueing. `None` when the server does not answer clock requests.
+///
+/// Like NTP this assumes both directions take equally long. A path that is
+/// slower one way shifts half the difference into the offset, which then hides
+/// that asymmetry in the one-way delays.
+pub(crate) async fn estimate_offset(socket: &UdpSocket) -> io::Result<Option<ClockOffset>> {
+    let mut best: Option<ClockSample> = None;
+    for i in 0..OFFSET_SAMPLES {
+        if i > 0 {
+            time::sleep(OFFSET_SAMPLE_GAP).await;
+        }
+        // A server without clock support will not answer the others either
+        let Some(sample) = sample(socket, Duration::ZERO).await? else {
+            break;
+        };
+        if best.is_none_or(|b| sample.rtt < b.rtt) {
+            best = Some(sample);
+        }
+    }
+
+    Ok(best.map(|s| ClockOffset {
+        offset: s.offset,
+        rtt: s.rtt,
+    }))
+}
+
+/// Halves of a round trip in nanoseconds, split by the time the server received
+/// the probe. They are only as accurate as the two clocks agree: an offset between
+/// them moves delay from one direction to the other and can make either negative.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct OneWayDelay {
+    pub upstream: i64,
+    pub downstream: i64,
+}
+
+/// Fastest, average and slowest delay of one direction in nanoseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DelayRange {
+    pub min: i64,
+    pub average: i64,
+    pub max: i64,
+}
+
+/// Running [`DelayRange`] of a direction.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DelayTotals {
+    min: i64,
+    max: i64,
+    sum: i128,
+    count: i128,
+}
+
+impl Default for DelayTotals {
+    fn default() -> Self {
+        Self {
+            min: i64::MAX,
+            max: i64::MIN,
+            sum: 0,
+            count: 0,
+        }
+    }
+}
+
+impl DelayTotals {
+    pub(crate) fn add(&mut self, delay: i64) {
+        self.min = self.min.min(delay);
+        self.max = self.max.max(delay);
+        self.sum += delay as i128;
+        self.count += 1;
+    }
+
+    pub(crate) fn range(&self) -> Option<DelayRange> {
+        (self.count > 0).then(|| DelayRange {
+            min: self.min,
+            average: (self.sum / self.count) as i64,
+            max: self.max,
+        })
+    }
+}
+
+/// Wall clock in nanoseconds since the Unix epoch.
+pub(crate) fn now() -> i64 {
+    nanos_since_epoch(SystemTime::now())
+}
+
+pub(crate) fn nanos_since_epoch(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as i64)
+}
@@ -0,0 +1,147 @@
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+
+use color_eyre::eyre::{bail, Result};
+use tokio::{
+    net::UdpSocket,
+    time::{self, Instant, MissedTickBehavior},
+};
+use tokio_util::sync::CancellationToken;
+
+/// Queries without an answer after this long count as lost.
+const DNS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Header of a DNS message: ID, flags and four section counts.
+const HEADER_LEN: usize = 12;
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const RCODE_NO_ERROR: u16 = 0;
+const RCODE_NAME_ERROR: u16 = 3;
+const TYPE_A: u16 = 1;
+const CLASS_IN: u16 = 1;
+
+/// Resolves a name at a fixed interval and times the answers, like a client
+/// looking up hosts while other traffic runs.
+pub(crate) struct DnsProbe {
+    server: SocketAddr,
+    name: String,
+    interval: Duration,
+    count: u32,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct DnsResults {
+    pub sent: u32,
+    pub answered: u32,
+    /// Answers that were errors other than a missing name, e.g. SERVFAIL
+    pub failed: u32,
+    /// When each query was sent relative to the start, and how long the answer
+    /// took if there was one
+    pub samples: Vec<(Duration, Option<Duration>)>,
+}
+
+impl DnsProbe {
+    pub(crate) fn new(server: SocketAddr, name: String, interval: Duration, count: u32) -> Self {
+        Self {
+            server,
+            name,
+            interval,
+            count,
+        }
+    }
+
+    pub(crate) async fn run(&self, quit: CancellationToken) -> Result<DnsResults> {
+        if self
+            .name
+            .trim_end_matches('.')
+            .split('.')
+            .any(|label| label.is_empty() || label.len() > 63)
+        {
+            bail!("Invalid DNS name {}", self.name);
+        }
+
+        let bind: SocketAddr = match self.server {
+            SocketAddr::V4(_) => "0.0.0.0:0".parse()?,
+            SocketAddr::V6(_) => "[::]:0".parse()?,
+        };
+        let socket = UdpSocket::bind(bind).await?;
+        socket.connect(self.server).await?;
+
+        let mut results = DnsResults::default();
+        let mut buf = [0; 512];
+        let start = Instant::now();
+        let mut interval = time::interval(self.interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        // Queries waiting for an answer by ID, with their index in the samples
+        let mut pending: HashMap<u16, usize> = HashMap::new();
+        let mut sending = true;
+        let mut last_sent = start;
+
+        loop {
+            tokio::select! {
+                _ = interval.tick(), if sending => {
+                    let id: u16 = rand::random();
+                    last_sent = Instant::now();
+                    socket.send(&query(id, &self.name)).await?;
+
+                    pending.insert(id, results.samples.len());
+                    results.samples.push((last_sent - start, None));
+                    results.sent += 1;
+                    sending = self.count == 0 || results.sent < self.count;
+                }
+                received = socket.recv(&mut buf) => {
+                    let received_at = Instant::now() - start;
+                    let Some((id, rcode)) = response(&buf[..received?]) else {
+                        continue;
+                    };
+                    let Some(i) = pending.remove(&id) else { continue };
+                    let answer = received_at - results.samples[i].0;
+                    if answer > DNS_TIMEOUT {
+                        continue;
+                    }
+
+                    results.samples[i].1 = Some(answer);
+                    results.answered += 1;
+                    if rcode != RCODE_NO_ERROR && rcode != RCODE_NAME_ERROR {
+                        results.failed += 1;
+                    }
+                }
+                _ = time::sleep_until(last_sent + DNS_TIMEOUT), if !sending => break,
+                _ = quit.cancelled(), if sending => sending = false,
+            }
+
+            if !sending && pending.is_empty() {
+                break;
+            }
+        }
+
+        Ok(results)
+    }
+}
+
+/// Query for the A records of `name`.
+fn query(id: u16, name: &str) -> Vec<u8> {
+    let mut message = Vec::with_capacity(HEADER_LEN + name.len() + 6);
+    message.extend_from_slice(&id.to_be_bytes());
+    message.extend_from_slice(&FLAG_RECURSION_DESIRED.to_be_bytes());
+    message.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+
+    for label in name.trim_end_matches('.').split('.') {
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.push(0);
+    message.extend_from_slice(&TYPE_A.to_be_bytes());
+    message.extend_from_slice(&CLASS_IN.to_be_bytes());
+    message
+}
+
+/// ID and response code of `message` if it is a response.
+fn response(message: &[u8]) -> Option<(u16, u16)> {
+    if message.len() < HEADER_LEN {
+        return None;
+    }
+    let id = u16::from_be_bytes([message[0], message[1]]);
+    let flags = u16::from_be_bytes([message[2], message[3]]);
+    (flags & FLAG_RESPONSE != 0).then_some((id, flags & 0x000f))
+}
@@ -1,35 +1,620 @@
-use std::{collections::HashMap, net::SocketAddr};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{File, OpenOptions},
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
 
 use color_eyre::eyre::Result;
-use tokio::net::UdpSocket;
-use tracing::debug;
+use csv::Writer;
+use tokio::{
+    io::{self, AsyncReadExt},
+    net::{TcpStream, UdpSocket},
+    sync::mpsc::UnboundedSender,
+    time::{self, Instant},
+};
+use tracing::{debug, info, warn};
+
+use super::{
+    bandwidth::{format_bitrate, format_bytes, UdpSink},
+    bidir::{BidirRequest, ReverseProbes},
+    buffers::SocketBuffers,
+    clock,
+    handshake::HANDSHAKE_PAYLOAD,
+    listen,
+    mmsg::{self, RecvBatch},
+    protocol::{self, DecodeError, Header, FLAG_REPLY, FLAG_TAG, FLAG_TIMESTAMP, HEADER_LEN},
+};
+use crate::{action::Action, pairing::Authenticator};
+
+#[cfg(feature = "udp-no-checksum")]
+use super::checksum;
+#[cfg(feature = "udp-no-checksum")]
+use color_eyre::eyre::eyre;
+
+/// Weight of the newest interval in the smoothed rates.
+const STATS_EWMA_ALPHA: f64 = 0.3;
+
+/// First bytes of a connection to the TCP port that wants its probes echoed.
+pub(crate) const TCP_ECHO_MAGIC: &[u8; 10] = b"bwlat-echo";
+
+/// Header and the reply size and datagram count of a probe that asks for a
+/// reply that differs from it.
+pub(crate) const REPLY_REQUEST_LEN: usize = HEADER_LEN + 3;
+
+/// Largest UDP payload, probes of any size are echoed whole.
+const MAX_DATAGRAM: usize = 65535;
+
+/// Largest reply datagram, fits a 1500 byte MTU over IPv4.
+const MAX_REPLY_SIZE: usize = 1472;
+const MAX_REPLY_COUNT: usize = 16;
+/// Reply bytes per second an address that did not authenticate gets beyond the
+/// size of its probes with amplified replies allowed, enough for the game profile.
+const AMPLIFIED_RATE: usize = 256 * 1024;
+/// Addresses whose amplified reply rate is tracked, beyond this many the
+/// finished windows are dropped.
+const MAX_AMPLIFIED: usize = 1024;
+
+/// How often the server TUI gets a snapshot of the clients.
+const ACTIVITY_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+/// Senders of foreign datagrams remembered to warn only once about each, beyond
+/// this many a scan of the port goes unlogged.
+const MAX_FOREIGN: usize = 1024;
+
+/// Clients silent for this long are forgotten.
+const CLIENT_EXPIRY: Duration = Duration::from_secs(300);
+
+/// Reply of the server to a probe: `count` datagrams of `size` bytes each, all
+/// starting with the probe's header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ReplyShape {
+    pub size: usize,
+    pub count: usize,
+}
+
+impl ReplyShape {
+    /// Asks for this reply in `probe`, which needs [`REPLY_REQUEST_LEN`] bytes
+    /// and its header written.
+    pub(crate) fn write(&self, probe: &mut [u8]) {
+        protocol::set_flag(probe, FLAG_REPLY);
+        let request = &mut probe[HEADER_LEN..REPLY_REQUEST_LEN];
+        request[..2].copy_from_slice(&(self.size as u16).to_be_bytes());
+        request[2] = self.count as u8;
+    }
+
+    /// The reply `probe` asks for, limited to what the server is willing to send.
+    fn read(header: &Header, probe: &[u8]) -> Option<Self> {
+        if !header.has(FLAG_REPLY) {
+            return None;
+        }
+
+        let request = probe.get(HEADER_LEN..REPLY_REQUEST_LEN)?;
+        let size = u16::from_be_bytes([request[0], request[1]]);
+        Some(Self {
+            size: (size as usize).clamp(HEADER_LEN, MAX_REPLY_SIZE),
+            count: (request[2] as usize).clamp(1, MAX_REPLY_COUNT),
+        })
+    }
+
+    /// One datagram of the reply to `probe`, carrying its header, the receive
+    /// time if it was asked for and its tag if there is room.
+    fn datagram(&self, header: &Header, probe: &[u8], received_at: i64) -> Vec<u8> {
+        let mut reply = vec![0; self.size];
+        reply[..HEADER_LEN].copy_from_slice(&probe[..HEADER_LEN]);
+        protocol::clear_flag(&mut reply, FLAG_REPLY | FLAG_TAG);
+        if header.has(FLAG_TIMESTAMP) {
+            protocol::write_received(&mut reply, received_at);
+        }
+        if let Some(tag) = protocol::read_tag(header, probe) {
+            if self.size >= HEADER_LEN + protocol::tag_len(tag.len()) {
+                protocol::write_tag(&mut reply, tag);
+            }
+        }
+        reply
+    }
+
+    /// The reply that is no larger than `probe_len` and a single datagram, so a
+    /// spoofed request cannot turn the server into an amplifier.
+    fn unamplified(&self, probe_len: usize) -> Self {
+        Self {
+            size: self.size.min(probe_len),
+            count: 1,
+        }
+    }
+
+    /// Bytes this reply sends beyond a plain echo of `probe_len` bytes.
+    fn excess(&self, probe_len: usize) -> usize {
+        (self.size * self.count).saturating_sub(probe_len)
+    }
+}
+
+/// Amplified reply bytes sent to one address in the current second.
+#[derive(Debug, Clone, Copy)]
+struct AmplifiedWindow {
+    start: Instant,
+    bytes: usize,
+}
+
+/// Probes echoed to one client address so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ClientActivity {
+    pub address: SocketAddr,
+    pub packets: u64,
+    pub bytes: u64,
+    /// Time since the server started
+    pub last_seen: Duration,
+}
+
+/// Running totals of the echo server for the metrics exporter.
+#[derive(Debug, Default)]
+pub(crate) struct EchoCounters {
+    pub packets: AtomicU64,
+    pub bytes: AtomicU64,
+    /// Client addresses heard from within the client expiry
+    pub clients: AtomicUsize,
+}
 
 pub(crate) struct Echo {
     port: u16,
-    packets: HashMap<SocketAddr, u32>,
+    bind: Option<IpAddr>,
+    clients: HashMap<SocketAddr, ClientActivity>,
+    /// Addresses already warned about for sending datagrams that are not probes
+    /// of this version, and why
+    foreign: HashSet<(IpAddr, DecodeError)>,
+    notify: Option<UnboundedSender<Action>>,
+    counters: Option<Arc<EchoCounters>>,
+    bandwidth: UdpSink,
+    auth: Option<Arc<Authenticator>>,
+    /// Whether addresses that did not authenticate may ask for replies larger
+    /// than their probes, up to [`AMPLIFIED_RATE`]
+    amplification: bool,
+    amplified: HashMap<IpAddr, AmplifiedWindow>,
+    udplite: bool,
+    #[cfg(feature = "udp-no-checksum")]
+    missing_checksums: bool,
+    /// Datagrams read per system call
+    batch: usize,
+    buffers: SocketBuffers,
+
+    stats_interval: Option<Duration>,
+    stats_csv: Option<PathBuf>,
+}
+
+/// Traffic echoed during one statistics interval.
+#[derive(Debug, Default)]
+struct Interval {
+    packets: u64,
+    bytes: u64,
+    clients: HashSet<IpAddr>,
+}
+
+/// Exponentially weighted averages of the per-interval rates.
+#[derive(Debug, Default)]
+struct Smoothed {
+    packets_per_second: Option<f64>,
+    bytes_per_second: Option<f64>,
+}
+
+impl Smoothed {
+    fn update(value: &mut Option<f64>, sample: f64) -> f64 {
+        let smoothed = match *value {
+            Some(previous) => STATS_EWMA_ALPHA * sample + (1.0 - STATS_EWMA_ALPHA) * previous,
+            None => sample,
+        };
+        *value = Some(smoothed);
+        smoothed
+    }
 }
 
 impl Echo {
     pub(crate) fn new(port: u16) -> Self {
         Self {
             port,
-            packets: HashMap::new(),
+            bind: None,
+            clients: HashMap::new(),
+            foreign: HashSet::new(),
+            notify: None,
+            counters: None,
+            bandwidth: UdpSink::default(),
+            auth: None,
+            amplification: false,
+            amplified: HashMap::new(),
+            udplite: false,
+            #[cfg(feature = "udp-no-checksum")]
+            missing_checksums: false,
+            batch: mmsg::DEFAULT_BATCH,
+            buffers: SocketBuffers::default(),
+            stats_interval: None,
+            stats_csv: None,
         }
     }
 
+    /// Listen on this address only instead of all IPv6 and IPv4 addresses.
+    pub(crate) fn with_bind_address(mut self, address: IpAddr) -> Self {
+        self.bind = Some(address);
+        self
+    }
+
+    /// Also echo UDP-Lite probes on the same port.
+    pub(crate) fn with_udplite(mut self) -> Self {
+        self.udplite = true;
+        self
+    }
+
+    /// Accept IPv6 probes sent without UDP checksums.
+    #[cfg(feature = "udp-no-checksum")]
+    pub(crate) fn with_missing_checksums(mut self) -> Self {
+        self.missing_checksums = true;
+        self
+    }
+
+    /// Read up to `size` datagrams per system call, one reads each on its own.
+    pub(crate) fn with_batch(mut self, size: usize) -> Self {
+        self.batch = size.clamp(1, mmsg::MAX_BATCH);
+        self
+    }
+
+    /// Size the kernel buffers of the UDP sockets instead of keeping the OS
+    /// defaults, which drop datagrams at high rates.
+    pub(crate) fn with_socket_buffers(mut self, buffers: SocketBuffers) -> Self {
+        self.buffers = buffers;
+        self
+    }
+
+    /// Log echo and byte rates plus the number of distinct clients every `interval`.
+    pub(crate) fn with_stats(mut self, interval: Duration) -> Self {
+        self.stats_interval = Some(interval);
+        self
+    }
+
+    /// Only serve clients the authenticator admits, handshakes are always answered.
+    pub(crate) fn with_authenticator(mut self, auth: Arc<Authenticator>) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// Send replies larger than the probe, or several of them, to clients that
+    /// did not authenticate, rate limited per address. Authenticated clients
+    /// always get the reply they ask for.
+    pub(crate) fn with_amplification(mut self) -> Self {
+        self.amplification = true;
+        self
+    }
+
+    /// Send the server TUI a snapshot of the clients every second, the TUI then
+    /// takes the place of the statistics log.
+    pub(crate) fn with_notify(mut self, notify: UnboundedSender<Action>) -> Self {
+        self.notify = Some(notify);
+        self
+    }
+
+    /// Keep `counters` up to date with the echoed traffic.
+    pub(crate) fn with_counters(mut self, counters: Arc<EchoCounters>) -> Self {
+        self.counters = Some(counters);
+        self
+    }
+
+    /// Also append the per-interval statistics to a CSV file.
+    pub(crate) fn with_stats_csv(mut self, path: PathBuf) -> Self {
+        self.stats_csv = Some(path);
+        self
+    }
+
     pub(crate) async fn run(&mut self) -> Result<()> {
-        let socket = UdpSocket::bind(format!("0.0.0.0:{}", self.port)).await?;
-        let mut buf = [0; 1500];
+        let udp = Arc::new(listen::udp(self.bind, self.port)?);
+        let udplite = match self.udplite {
+            true => Some(Arc::new(listen::udplite(self.bind, self.port)?)),
+            false => None,
+        };
+        #[cfg(feature = "udp-no-checksum")]
+        if self.missing_checksums && udp.local_addr()?.is_ipv6() {
+            checksum::accept_missing(socket2::SockRef::from(udp.as_ref()))
+                .map_err(|e| eyre!("Could not accept missing UDP checksums: {}", e))?;
+        }
+        if !self.buffers.is_default() {
+            for socket in std::iter::once(&udp).chain(udplite.as_ref()) {
+                self.buffers
+                    .apply(&socket2::SockRef::from(socket.as_ref()))?;
+            }
+            self.buffers
+                .report(&socket2::SockRef::from(udp.as_ref()), "Echo")?;
+        }
+        let mut reverse = ReverseProbes::new(self.bind);
+        let mut batch = RecvBatch::new(self.batch, MAX_DATAGRAM);
+        let start = Instant::now();
+        let mut activity = time::interval(ACTIVITY_UPDATE_INTERVAL);
+
+        // The ticker is only polled with statistics enabled
+        let period = self.stats_interval.unwrap_or(Duration::from_secs(3600));
+        let mut ticker = time::interval_at(time::Instant::now() + period, period);
+        let mut current = Interval::default();
+        let mut smoothed = Smoothed::default();
+
+        let mut csv = match self.stats_csv {
+            Some(ref path) => {
+                let file = OpenOptions::new().append(true).create(true).open(path)?;
+                // A file of an earlier run already has the header
+                let empty = file.metadata()?.len() == 0;
+                let mut wtr = Writer::from_writer(file);
+                if empty {
+                    wtr.write_record([
+                        "time",
+                        "packets",
+                        "bytes",
+                        "clients",
+                        "packets_per_second",
+                        "bytes_per_second",
+                        "ewma_packets_per_second",
+                        "ewma_bytes_per_second",
+                    ])?;
+                }
+                wtr.flush()?;
+                Some(wtr)
+            }
+            None => None,
+        };
 
         loop {
-            let (size, src) = socket.recv_from(&mut buf).await?;
-            socket.send_to(&buf[..size], src).await?;
+            tokio::select! {
+                ready = readable(&udp, udplite.as_ref()) => {
+                    let socket = ready?;
+                    match batch.try_recv(socket) {
+                        Ok(_) => {}
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                        Err(e) => return Err(e.into()),
+                    }
+                    for (datagram, src) in batch.datagrams() {
+                        self.handle(socket, datagram, src, &mut reverse, &mut current, start)
+                            .await?;
+                    }
+                }
+                _ = ticker.tick(), if self.stats_interval.is_some() => {
+                    let interval = std::mem::take(&mut current);
+                    self.report(&interval, period, &mut smoothed, csv.as_mut())?;
+                }
+                _ = activity.tick(), if self.notify.is_some() || self.counters.is_some() => {
+                    let now = start.elapsed();
+                    self.clients
+                        .retain(|_, client| now - client.last_seen < CLIENT_EXPIRY);
+                    if let Some(ref counters) = self.counters {
+                        counters.clients.store(self.clients.len(), Ordering::Relaxed);
+                    }
 
-            debug!("Received {} bytes from {}", size, src);
-            self.packets
-                .entry(src)
-                .and_modify(|count| *count += 1)
-                .or_insert(1);
+                    let clients = self.clients.values().copied().collect();
+                    if let Some(ref notify) = self.notify {
+                        notify.send(Action::ServerActivity(now, clients))?;
+                    }
+                }
+            }
         }
     }
+
+    /// Answers one datagram received on `socket`, probes are echoed, bandwidth
+    /// datagrams counted and requests of the other tests answered.
+    async fn handle(
+        &mut self,
+        socket: &Arc<UdpSocket>,
+        datagram: &mut [u8],
+        src: SocketAddr,
+        reverse: &mut ReverseProbes,
+        current: &mut Interval,
+        start: Instant,
+    ) -> Result<()> {
+        // Replies go to `src`, everything else knows the client as `peer`
+        let peer = listen::canonical(src);
+        let received_at = clock::now();
+
+        if let Some(ref auth) = self.auth {
+            if *datagram != *HANDSHAKE_PAYLOAD && !auth.admit(peer.ip()) {
+                debug!("Ignoring unauthenticated client {}", peer);
+                return Ok(());
+            }
+        }
+
+        if *datagram == *HANDSHAKE_PAYLOAD {
+            socket.send_to(datagram, src).await?;
+            return Ok(());
+        }
+
+        if let Some(reply) = clock::reply(datagram) {
+            socket.send_to(&reply, src).await?;
+            return Ok(());
+        }
+
+        if let Some(request) = BidirRequest::read(datagram) {
+            reverse.handle(socket, request, src);
+            return Ok(());
+        }
+
+        if UdpSink::accepts(datagram) {
+            if let Some(reply) = self.bandwidth.handle(datagram, src) {
+                socket.send_to(&reply, src).await?;
+            }
+            return Ok(());
+        }
+
+        let header = match Header::decode(datagram) {
+            Ok(header) => header,
+            Err(DecodeError::Foreign) => {
+                if self.first_foreign(peer.ip(), DecodeError::Foreign) {
+                    warn!("Ignoring datagrams from {} that are not bwlat probes, is it an older client?", peer.ip());
+                }
+                return Ok(());
+            }
+            Err(e @ DecodeError::Version(_)) => {
+                if self.first_foreign(peer.ip(), e) {
+                    warn!("Ignoring probes of {}: {}", peer.ip(), e);
+                }
+                socket.send_to(&protocol::version_answer(), src).await?;
+                return Ok(());
+            }
+        };
+
+        match ReplyShape::read(&header, datagram) {
+            Some(shape) => {
+                let shape = self.limit_reply(shape, peer.ip(), datagram.len());
+                let reply = shape.datagram(&header, datagram, received_at);
+                for _ in 0..shape.count {
+                    socket.send_to(&reply, src).await?;
+                }
+            }
+            None => {
+                if header.has(FLAG_TIMESTAMP) {
+                    protocol::write_received(datagram, received_at);
+                }
+                socket.send_to(datagram, src).await?;
+            }
+        }
+
+        debug!("Received {} bytes from {}", datagram.len(), peer);
+        let client = self.clients.entry(peer).or_insert(ClientActivity {
+            address: peer,
+            packets: 0,
+            bytes: 0,
+            last_seen: Duration::ZERO,
+        });
+        client.packets += 1;
+        client.bytes += datagram.len() as u64;
+        client.last_seen = start.elapsed();
+        if let Some(ref counters) = self.counters {
+            counters.packets.fetch_add(1, Ordering::Relaxed);
+            counters
+                .bytes
+                .fetch_add(datagram.len() as u64, Ordering::Relaxed);
+            counters
+                .clients
+                .store(self.clients.len(), Ordering::Relaxed);
+        }
+
+        current.packets += 1;
+        current.bytes += datagram.len() as u64;
+        current.clients.insert(peer.ip());
+
+        Ok(())
+    }
+
+    /// The reply to a probe of `probe_len` bytes from `address` as far as the
+    /// server sends it: what was asked for if the client authenticated, or if
+    /// amplification is allowed and the address is within its rate, otherwise
+    /// a single datagram no larger than the probe.
+    fn limit_reply(&mut self, shape: ReplyShape, address: IpAddr, probe_len: usize) -> ReplyShape {
+        if self
+            .auth
+            .as_ref()
+            .is_some_and(|auth| auth.authenticated(address))
+        {
+            return shape;
+        }
+        if !self.amplification {
+            return shape.unamplified(probe_len);
+        }
+
+        let now = Instant::now();
+        if self.amplified.len() >= MAX_AMPLIFIED {
+            self.amplified
+                .retain(|_, window| now - window.start < Duration::from_secs(1));
+        }
+        if self.amplified.len() >= MAX_AMPLIFIED && !self.amplified.contains_key(&address) {
+            return shape.unamplified(probe_len);
+        }
+
+        let window = self.amplified.entry(address).or_insert(AmplifiedWindow {
+            start: now,
+            bytes: 0,
+        });
+        if now - window.start >= Duration::from_secs(1) {
+            *window = AmplifiedWindow {
+                start: now,
+                bytes: 0,
+            };
+        }
+        let excess = shape.excess(probe_len);
+        if window.bytes + excess > AMPLIFIED_RATE {
+            return shape.unamplified(probe_len);
+        }
+        window.bytes += excess;
+        shape
+    }
+
+    /// Whether `address` sent its first datagram this server does not understand
+    /// for this reason.
+    fn first_foreign(&mut self, address: IpAddr, error: DecodeError) -> bool {
+        self.foreign.len() < MAX_FOREIGN && self.foreign.insert((address, error))
+    }
+
+    fn report(
+        &self,
+        interval: &Interval,
+        period: Duration,
+        smoothed: &mut Smoothed,
+        csv: Option<&mut Writer<File>>,
+    ) -> Result<()> {
+        let seconds = period.as_secs_f64();
+        let packets_per_second = interval.packets as f64 / seconds;
+        let bytes_per_second = interval.bytes as f64 / seconds;
+
+        let ewma_packets = Smoothed::update(&mut smoothed.packets_per_second, packets_per_second);
+        let ewma_bytes = Smoothed::update(&mut smoothed.bytes_per_second, bytes_per_second);
+
+        if self.notify.is_none() {
+            info!(
+                "Echoed {:.1} pkt/s (avg {:.1}), {} (avg {}), {} client(s), {}",
+                packets_per_second,
+                ewma_packets,
+                format_bitrate(bytes_per_second * 8.0),
+                format_bitrate(ewma_bytes * 8.0),
+                interval.clients.len(),
+                format_bytes(interval.bytes)
+            );
+        }
+
+        if let Some(wtr) = csv {
+            wtr.write_record([
+                &humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+                &interval.packets.to_string(),
+                &interval.bytes.to_string(),
+                &interval.clients.len().to_string(),
+                &format!("{:.3}", packets_per_second),
+                &format!("{:.3}", bytes_per_second),
+                &format!("{:.3}", ewma_packets),
+                &format!("{:.3}", ewma_bytes),
+            ])?;
+            wtr.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Waits until the UDP or the UDP-Lite socket has a datagram and returns it.
+async fn readable<'a>(
+    udp: &'a Arc<UdpSocket>,
+    udplite: Option<&'a Arc<UdpSocket>>,
+) -> io::Result<&'a Arc<UdpSocket>> {
+    match udplite {
+        Some(udplite) => tokio::select! {
+            ready = udp.readable() => ready.map(|_| udp),
+            ready = udplite.readable() => ready.map(|_| udplite),
+        },
+        None => udp.readable().await.map(|_| udp),
+    }
+}
+
+/// Echoes the probes of a TCP latency test back on the same connection.
+pub(crate) async fn echo_stream(mut stream: TcpStream, peer: SocketAddr) -> Result<()> {
+    let mut magic = [0; TCP_ECHO_MAGIC.len()];
+    stream.read_exact(&mut magic).await?;
+    stream.set_nodelay(true)?;
+
+    debug!("TCP latency test from {}", peer);
+    let (mut reader, mut writer) = stream.split();
+    let echoed = io::copy(&mut reader, &mut writer).await?;
+    debug!("Echoed {} bytes to {}", echoed, peer);
+
+    Ok(())
 }
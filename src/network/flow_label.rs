@@ -0,0 +1,105 @@
+use std::{io, net::Ipv6Addr};
+
+/// Largest IPv6 flow label, it is 20 bits wide.
+pub(crate) const MAX_FLOW_LABEL: u32 = 0xf_ffff;
+
+/// `struct in6_flowlabel_req` of `linux/in6.h`.
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct FlowLabelRequest {
+    destination: libc::in6_addr,
+    /// Network byte order
+    label: u32,
+    action: u8,
+    share: u8,
+    flags: u16,
+    expires: u16,
+    linger: u16,
+    pad: u32,
+}
+
+#[cfg(target_os = "linux")]
+const IPV6_FL_A_GET: u8 = 0;
+#[cfg(target_os = "linux")]
+const IPV6_FL_S_PROCESS: u8 = 2;
+#[cfg(target_os = "linux")]
+const IPV6_FL_F_CREATE: u16 = 1;
+
+/// Leases `label` towards `destination` for `socket` and has the kernel take
+/// the flow label of its packets from the destination address, see
+/// [`flow_info`]. Sockets of this process may share a label.
+#[cfg(target_os = "linux")]
+pub(crate) fn enable(
+    socket: &socket2::Socket,
+    destination: Ipv6Addr,
+    label: u32,
+) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let request = FlowLabelRequest {
+        destination: libc::in6_addr {
+            s6_addr: destination.octets(),
+        },
+        label: label.to_be(),
+        action: IPV6_FL_A_GET,
+        share: IPV6_FL_S_PROCESS,
+        flags: IPV6_FL_F_CREATE,
+        expires: 0,
+        linger: 0,
+        pad: 0,
+    };
+    setsockopt(
+        socket.as_raw_fd(),
+        libc::IPV6_FLOWLABEL_MGR,
+        &request as *const FlowLabelRequest as *const libc::c_void,
+        std::mem::size_of::<FlowLabelRequest>(),
+    )?;
+
+    let send: libc::c_int = 1;
+    setsockopt(
+        socket.as_raw_fd(),
+        libc::IPV6_FLOWINFO_SEND,
+        &send as *const libc::c_int as *const libc::c_void,
+        std::mem::size_of::<libc::c_int>(),
+    )
+}
+
+#[cfg(target_os = "linux")]
+fn setsockopt(
+    fd: std::os::fd::RawFd,
+    option: libc::c_int,
+    value: *const libc::c_void,
+    len: usize,
+) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::IPPROTO_IPV6,
+            option,
+            value,
+            len as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn enable(
+    _socket: &socket2::Socket,
+    _destination: Ipv6Addr,
+    _label: u32,
+) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "flow labels can only be set on Linux",
+    ))
+}
+
+/// `sin6_flowinfo` of a destination that carries `label`, which the socket
+/// address holds in network byte order.
+pub(crate) fn flow_info(label: u32) -> u32 {
+    (label & MAX_FLOW_LABEL).to_be()
+}
@@ -0,0 +1,162 @@
+use std::{fmt, time::Duration};
+
+use super::latency::{PacketStatus, State};
+
+/// Rise above the median ping that players notice as a lag spike.
+const SPIKE_MARGIN: Duration = Duration::from_millis(50);
+
+/// Worst ping, jitter, loss and spike rate for each verdict, best first.
+const LIMITS: [(Verdict, Limits); 3] = [
+    (
+        Verdict::Great,
+        Limits {
+            ping: Duration::from_millis(30),
+            jitter: Duration::from_millis(5),
+            loss: 0.001,
+            spikes_per_minute: 0.5,
+        },
+    ),
+    (
+        Verdict::Good,
+        Limits {
+            ping: Duration::from_millis(60),
+            jitter: Duration::from_millis(10),
+            loss: 0.01,
+            spikes_per_minute: 1.0,
+        },
+    ),
+    (
+        Verdict::Playable,
+        Limits {
+            ping: Duration::from_millis(100),
+            jitter: Duration::from_millis(20),
+            loss: 0.03,
+            spikes_per_minute: 3.0,
+        },
+    ),
+];
+
+struct Limits {
+    ping: Duration,
+    jitter: Duration,
+    loss: f64,
+    spikes_per_minute: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Verdict {
+    Great,
+    Good,
+    Playable,
+    Poor,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verdict::Great => write!(f, "great"),
+            Verdict::Good => write!(f, "good"),
+            Verdict::Playable => write!(f, "playable"),
+            Verdict::Poor => write!(f, "poor"),
+        }
+    }
+}
+
+/// The metrics players look at, for the game profile.
+#[derive(Debug, Clone)]
+pub(crate) struct GameQuality {
+    /// Median round trip
+    pub ping: Duration,
+    /// Mean difference between the round trips of consecutive answered probes
+    pub jitter: Duration,
+    pub loss: f64,
+    /// Runs of probes slower than the median ping by the spike margin
+    pub spikes: u32,
+    pub spikes_per_minute: f64,
+    pub verdict: Verdict,
+    /// Metrics that kept the verdict from being better
+    pub limited_by: Vec<&'static str>,
+}
+
+impl GameQuality {
+    /// `None` until a probe was answered.
+    pub(crate) fn estimate(state: &State) -> Option<Self> {
+        let ping = state.percentile(50.0)?;
+
+        let mut sent = 0;
+        let mut lost = 0;
+        let mut spikes = 0;
+        let mut in_spike = false;
+        let mut differences = Duration::ZERO;
+        let mut pairs = 0;
+        let mut previous: Option<Duration> = None;
+        for packet in state.packets.iter() {
+            match *packet {
+                PacketStatus::Skipped(_) | PacketStatus::Invalid(_) | PacketStatus::Warmup(_) => {
+                    continue
+                }
+                PacketStatus::Sent(_) => {
+                    sent += 1;
+                    lost += 1;
+                }
+                PacketStatus::Received { latency, .. } => {
+                    sent += 1;
+                    if let Some(previous) = previous {
+                        differences += latency.abs_diff(previous);
+                        pairs += 1;
+                    }
+                    previous = Some(latency);
+
+                    let spike = latency > ping + SPIKE_MARGIN;
+                    if spike && !in_spike {
+                        spikes += 1;
+                    }
+                    in_spike = spike;
+                }
+            }
+        }
+
+        let span = match (state.packets.first(), state.packets.last()) {
+            (Some(first), Some(last)) => last.sent_at().saturating_sub(first.sent_at()),
+            _ => Duration::ZERO,
+        };
+        let minutes = span.as_secs_f64() / 60.0;
+
+        let mut quality = GameQuality {
+            ping,
+            jitter: differences / pairs.max(1),
+            loss: lost as f64 / sent.max(1) as f64,
+            spikes,
+            spikes_per_minute: if minutes > 0.0 {
+                spikes as f64 / minutes
+            } else {
+                0.0
+            },
+            verdict: Verdict::Poor,
+            limited_by: Vec::new(),
+        };
+
+        for (verdict, limits) in LIMITS.iter() {
+            let exceeded = quality.exceeded(limits);
+            if exceeded.is_empty() {
+                quality.verdict = *verdict;
+                break;
+            }
+            quality.limited_by = exceeded;
+        }
+
+        Some(quality)
+    }
+
+    fn exceeded(&self, limits: &Limits) -> Vec<&'static str> {
+        [
+            (self.ping > limits.ping, "ping"),
+            (self.jitter > limits.jitter, "jitter"),
+            (self.loss > limits.loss, "loss"),
+            (self.spikes_per_minute > limits.spikes_per_minute, "spikes"),
+        ]
+        .into_iter()
+        .filter_map(|(exceeded, metric)| exceeded.then_some(metric))
+        .collect()
+    }
+}
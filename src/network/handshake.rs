@@ -0,0 +1,110 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use color_eyre::eyre::{bail, eyre, Result};
+use tokio::{
+    net::{TcpStream, UdpSocket},
+    time::{self, Instant},
+};
+use tracing::info;
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// How long a single attempt waits for the echo or the connection.
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Marks handshake datagrams, the echo server returns them unchanged.
+pub(crate) const HANDSHAKE_PAYLOAD: &[u8] = b"bwlat-handshake";
+
+/// Outcome of waiting for the server to become reachable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Handshake {
+    pub attempts: u32,
+    /// Time from the first attempt until the server answered
+    pub delay: Duration,
+}
+
+/// Checks that the server answers, retrying with exponential backoff for up to
+/// `patience`. Probes that boot before their network is up would otherwise start
+/// measuring 100% loss or fail outright.
+pub(crate) async fn wait_for_server(
+    address: IpAddr,
+    port: u16,
+    tcp: bool,
+    patience: Duration,
+) -> Result<Handshake> {
+    let start = Instant::now();
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempts = 0;
+
+    loop {
+        attempts += 1;
+        let result = if tcp {
+            tcp_attempt(address, port).await
+        } else {
+            udp_attempt(address, port).await
+        };
+
+        let error = match result {
+            Ok(()) => {
+                return Ok(Handshake {
+                    attempts,
+                    delay: start.elapsed(),
+                })
+            }
+            Err(e) => e,
+        };
+
+        if start.elapsed() + backoff > patience {
+            bail!(
+                "Server {} did not answer after {} attempt(s) in {:.1?}: {}",
+                SocketAddr::new(address, port),
+                attempts,
+                start.elapsed(),
+                error
+            );
+        }
+
+        info!(
+            "Server not reachable yet ({}), retrying in {:.1?}",
+            error, backoff
+        );
+        time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+async fn udp_attempt(address: IpAddr, port: u16) -> Result<()> {
+    let bind: IpAddr = match address {
+        IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(bind, 0)).await?;
+    socket.connect(SocketAddr::new(address, port)).await?;
+    socket.send(HANDSHAKE_PAYLOAD).await?;
+
+    let mut buf = [0; 64];
+    let deadline = Instant::now() + ATTEMPT_TIMEOUT;
+    loop {
+        let n = time::timeout_at(deadline, socket.recv(&mut buf))
+            .await
+            .map_err(|_| eyre!("no echo within {:?}", ATTEMPT_TIMEOUT))??;
+        if &buf[..n] == HANDSHAKE_PAYLOAD {
+            return Ok(());
+        }
+    }
+}
+
+async fn tcp_attempt(address: IpAddr, port: u16) -> Result<()> {
+    time::timeout(
+        ATTEMPT_TIMEOUT,
+        TcpStream::connect(SocketAddr::new(address, port)),
+    )
+    .await
+    .map_err(|_| eyre!("no connection within {:?}", ATTEMPT_TIMEOUT))??;
+
+    Ok(())
+}
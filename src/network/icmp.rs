@@ -0,0 +1,128 @@
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+};
+
+use color_eyre::eyre::{eyre, Result};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+
+const ECHO_REQUEST_V4: u8 = 8;
+const ECHO_REPLY_V4: u8 = 0;
+const ECHO_REQUEST_V6: u8 = 128;
+const ECHO_REPLY_V6: u8 = 129;
+
+/// Type, code, checksum, identifier and sequence number.
+pub(crate) const ICMP_HEADER: usize = 8;
+
+/// Socket sending ICMP echo requests, for hosts that do not run the bwlat server.
+pub(crate) struct IcmpSocket {
+    socket: UdpSocket,
+    address: SocketAddr,
+    /// Raw sockets see every echo reply of the host, ping sockets only their own
+    raw: bool,
+    identifier: u16,
+}
+
+impl IcmpSocket {
+    /// Opens a raw socket, or an unprivileged ping socket where the system allows
+    /// them, and fails with a hint on how to get permission otherwise.
+    pub(crate) fn open(address: IpAddr) -> Result<Self> {
+        let (domain, protocol) = match address {
+            IpAddr::V4(_) => (Domain::IPV4, Protocol::ICMPV4),
+            IpAddr::V6(_) => (Domain::IPV6, Protocol::ICMPV6),
+        };
+
+        let (socket, raw) = match Socket::new(domain, Type::RAW, Some(protocol)) {
+            Ok(socket) => (socket, true),
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                match Socket::new(domain, Type::DGRAM, Some(protocol)) {
+                    Ok(socket) => (socket, false),
+                    Err(_) => {
+                        return Err(eyre!(
+                            "ICMP mode needs raw sockets: run as root, grant the capability with \
+                             `setcap cap_net_raw+ep` or allow ping sockets through the \
+                             net.ipv4.ping_group_range sysctl"
+                        ))
+                    }
+                }
+            }
+            Err(e) => return Err(e.into()),
+        };
+        socket.set_nonblocking(true)?;
+
+        Ok(Self {
+            socket: UdpSocket::from_std(socket.into())?,
+            address: SocketAddr::new(address, 0),
+            raw,
+            identifier: rand::random(),
+        })
+    }
+
+    /// Sends an echo request carrying `payload`, returns the bytes sent.
+    pub(crate) async fn send(&self, sequence: u16, payload: &[u8]) -> io::Result<usize> {
+        let request = match self.address {
+            SocketAddr::V4(_) => ECHO_REQUEST_V4,
+            SocketAddr::V6(_) => ECHO_REQUEST_V6,
+        };
+
+        let mut packet = Vec::with_capacity(ICMP_HEADER + payload.len());
+        packet.extend_from_slice(&[request, 0, 0, 0]);
+        packet.extend_from_slice(&self.identifier.to_be_bytes());
+        packet.extend_from_slice(&sequence.to_be_bytes());
+        packet.extend_from_slice(payload);
+
+        // The kernel fills in the ICMPv6 checksum, it covers a pseudo header
+        if let SocketAddr::V4(_) = self.address {
+            let checksum = checksum(&packet);
+            packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+        }
+
+        self.socket.send_to(&packet, self.address).await
+    }
+
+    /// Waits for the next echo reply from the target and returns its payload,
+    /// `None` for other ICMP traffic the socket saw.
+    pub(crate) async fn recv<'a>(&self, buf: &'a mut [u8]) -> io::Result<Option<&'a [u8]>> {
+        let (size, from) = self.socket.recv_from(buf).await?;
+        if from.ip() != self.address.ip() {
+            return Ok(None);
+        }
+
+        // Raw IPv4 sockets include the IP header
+        let offset = match self.address {
+            SocketAddr::V4(_) if self.raw && size > 0 => (buf[0] & 0x0f) as usize * 4,
+            _ => 0,
+        };
+        let Some(packet) = buf[..size].get(offset..) else {
+            return Ok(None);
+        };
+        if packet.len() < ICMP_HEADER {
+            return Ok(None);
+        }
+
+        let reply = match self.address {
+            SocketAddr::V4(_) => ECHO_REPLY_V4,
+            SocketAddr::V6(_) => ECHO_REPLY_V6,
+        };
+        // Ping sockets get their identifier rewritten and filtered by the kernel
+        let identifier = u16::from_be_bytes([packet[4], packet[5]]);
+        if packet[0] != reply || (self.raw && identifier != self.identifier) {
+            return Ok(None);
+        }
+
+        Ok(Some(&packet[ICMP_HEADER..]))
+    }
+}
+
+/// Internet checksum of RFC 1071.
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)]) as u32)
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
@@ -0,0 +1,273 @@
+use std::{fmt, io, net::IpAddr};
+
+use tokio::net::UdpSocket;
+
+use super::timestamping::KernelTimestamp;
+
+/// ICMP and ICMPv6 types and codes the probes can run into.
+const ICMP_DEST_UNREACHABLE: u8 = 3;
+const ICMP_TIME_EXCEEDED: u8 = 11;
+const ICMP_NET_UNREACHABLE: u8 = 0;
+const ICMP_HOST_UNREACHABLE: u8 = 1;
+const ICMP_PORT_UNREACHABLE: u8 = 3;
+const ICMP_FRAG_NEEDED: u8 = 4;
+const ICMP_NET_PROHIBITED: u8 = 9;
+const ICMP_HOST_PROHIBITED: u8 = 10;
+const ICMP_ADMIN_PROHIBITED: u8 = 13;
+
+const ICMPV6_DEST_UNREACHABLE: u8 = 1;
+const ICMPV6_PACKET_TOO_BIG: u8 = 2;
+const ICMPV6_TIME_EXCEEDED: u8 = 3;
+const ICMPV6_NO_ROUTE: u8 = 0;
+const ICMPV6_ADMIN_PROHIBITED: u8 = 1;
+const ICMPV6_ADDR_UNREACHABLE: u8 = 3;
+const ICMPV6_PORT_UNREACHABLE: u8 = 4;
+const ICMPV6_POLICY_FAILED: u8 = 5;
+const ICMPV6_REJECT_ROUTE: u8 = 6;
+
+/// Why a probe was rejected on its way to the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) enum IcmpErrorKind {
+    /// Nothing listens on the server port, or a firewall rejects it
+    PortUnreachable,
+    HostUnreachable,
+    NetworkUnreachable,
+    /// A firewall rejected the probe
+    Prohibited,
+    /// The probe ran out of hops, a routing loop or a too low TTL
+    TtlExceeded,
+    /// The probe exceeds this MTU of a link and may not be fragmented
+    FragmentationNeeded(u32),
+    Other {
+        icmp_type: u8,
+        code: u8,
+    },
+}
+
+impl fmt::Display for IcmpErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IcmpErrorKind::PortUnreachable => write!(f, "port unreachable"),
+            IcmpErrorKind::HostUnreachable => write!(f, "host unreachable"),
+            IcmpErrorKind::NetworkUnreachable => write!(f, "network unreachable"),
+            IcmpErrorKind::Prohibited => write!(f, "administratively prohibited"),
+            IcmpErrorKind::TtlExceeded => write!(f, "TTL exceeded"),
+            IcmpErrorKind::FragmentationNeeded(mtu) => {
+                write!(f, "fragmentation needed, MTU {}", mtu)
+            }
+            IcmpErrorKind::Other { icmp_type, code } => {
+                write!(f, "ICMP type {} code {}", icmp_type, code)
+            }
+        }
+    }
+}
+
+impl IcmpErrorKind {
+    fn classify_v4(icmp_type: u8, code: u8, info: u32) -> Self {
+        match (icmp_type, code) {
+            (ICMP_DEST_UNREACHABLE, ICMP_PORT_UNREACHABLE) => IcmpErrorKind::PortUnreachable,
+            (ICMP_DEST_UNREACHABLE, ICMP_HOST_UNREACHABLE) => IcmpErrorKind::HostUnreachable,
+            (ICMP_DEST_UNREACHABLE, ICMP_NET_UNREACHABLE) => IcmpErrorKind::NetworkUnreachable,
+            (ICMP_DEST_UNREACHABLE, ICMP_FRAG_NEEDED) => IcmpErrorKind::FragmentationNeeded(info),
+            (
+                ICMP_DEST_UNREACHABLE,
+                ICMP_NET_PROHIBITED | ICMP_HOST_PROHIBITED | ICMP_ADMIN_PROHIBITED,
+            ) => IcmpErrorKind::Prohibited,
+            (ICMP_TIME_EXCEEDED, _) => IcmpErrorKind::TtlExceeded,
+            (icmp_type, code) => IcmpErrorKind::Other { icmp_type, code },
+        }
+    }
+
+    fn classify_v6(icmp_type: u8, code: u8, info: u32) -> Self {
+        match (icmp_type, code) {
+            (ICMPV6_DEST_UNREACHABLE, ICMPV6_PORT_UNREACHABLE) => IcmpErrorKind::PortUnreachable,
+            (ICMPV6_DEST_UNREACHABLE, ICMPV6_ADDR_UNREACHABLE) => IcmpErrorKind::HostUnreachable,
+            (ICMPV6_DEST_UNREACHABLE, ICMPV6_NO_ROUTE) => IcmpErrorKind::NetworkUnreachable,
+            (
+                ICMPV6_DEST_UNREACHABLE,
+                ICMPV6_ADMIN_PROHIBITED | ICMPV6_POLICY_FAILED | ICMPV6_REJECT_ROUTE,
+            ) => IcmpErrorKind::Prohibited,
+            (ICMPV6_PACKET_TOO_BIG, _) => IcmpErrorKind::FragmentationNeeded(info),
+            (ICMPV6_TIME_EXCEEDED, _) => IcmpErrorKind::TtlExceeded,
+            (icmp_type, code) => IcmpErrorKind::Other { icmp_type, code },
+        }
+    }
+}
+
+/// An ICMP error the kernel matched to a probe, and the router or host that sent
+/// it. Errors raised by the local stack have no sender.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) struct IcmpError {
+    pub kind: IcmpErrorKind,
+    pub from: Option<IpAddr>,
+}
+
+impl fmt::Display for IcmpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.from {
+            Some(from) => write!(f, "{} from {}", self.kind, from),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+/// Queues the ICMP errors caused by datagrams of `socket` so they can be read
+/// with [`drain`]. Pending errors also fail the next send or receive on the
+/// socket once enabled.
+#[cfg(target_os = "linux")]
+pub(crate) fn enable(socket: &UdpSocket, address: IpAddr) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let (level, option) = match address {
+        IpAddr::V4(_) => (libc::IPPROTO_IP, libc::IP_RECVERR),
+        IpAddr::V6(_) => (libc::IPPROTO_IPV6, libc::IPV6_RECVERR),
+    };
+    let value: libc::c_int = 1;
+
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            option,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn enable(_socket: &UdpSocket, _address: IpAddr) -> io::Result<()> {
+    Ok(())
+}
+
+/// What [`drain`] read from the error queue of a socket.
+#[derive(Debug, Default)]
+pub(crate) struct ErrorQueue {
+    /// Errors with the start of the datagram that caused each. Routers quote at
+    /// least the first 8 bytes of the payload
+    pub errors: Vec<(Vec<u8>, IcmpError)>,
+    /// Transmit timestamps with the start of the datagram they belong to, from
+    /// its link layer header on, with kernel timestamping only
+    pub sent: Vec<(Vec<u8>, KernelTimestamp)>,
+}
+
+/// Reads all queued errors and transmit timestamps of `socket` without waiting.
+#[cfg(target_os = "linux")]
+pub(crate) fn drain(socket: &UdpSocket) -> io::Result<ErrorQueue> {
+    use std::os::fd::AsRawFd;
+
+    use super::timestamping;
+
+    let mut queue = ErrorQueue::default();
+    loop {
+        // Room for the probe header behind the link, network and UDP headers
+        let mut data = [0u8; 256];
+        let mut control = [0u8; 256];
+        let mut iov = libc::iovec {
+            iov_base: data.as_mut_ptr() as *mut libc::c_void,
+            iov_len: data.len(),
+        };
+        let mut message: libc::msghdr = unsafe { std::mem::zeroed() };
+        message.msg_iov = &mut iov;
+        message.msg_iovlen = 1;
+        message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        message.msg_controllen = control.len() as _;
+
+        let size = unsafe {
+            libc::recvmsg(
+                socket.as_raw_fd(),
+                &mut message,
+                libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT,
+            )
+        };
+        if size < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::WouldBlock {
+                return Ok(queue);
+            }
+            return Err(e);
+        }
+
+        let mut timestamp = None;
+        let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&message) };
+        while !cmsg.is_null() {
+            // The timestamp comes first, the extended error then tells it is one
+            if let Some(sent) = unsafe { timestamping::read_sent(cmsg) } {
+                timestamp = Some(sent);
+            }
+            let header = unsafe { &*cmsg };
+            let v4 = header.cmsg_level == libc::SOL_IP && header.cmsg_type == libc::IP_RECVERR;
+            let v6 = header.cmsg_level == libc::SOL_IPV6 && header.cmsg_type == libc::IPV6_RECVERR;
+            if v4 || v6 {
+                let extended = unsafe { libc::CMSG_DATA(cmsg) as *const libc::sock_extended_err };
+                let error = unsafe { std::ptr::read_unaligned(extended) };
+                let offender = unsafe { offender(extended) };
+
+                let kind = match error.ee_origin {
+                    libc::SO_EE_ORIGIN_TIMESTAMPING => {
+                        if let Some(sent) = timestamp {
+                            queue.sent.push((data[..size as usize].to_vec(), sent));
+                        }
+                        cmsg = unsafe { libc::CMSG_NXTHDR(&message, cmsg) };
+                        continue;
+                    }
+                    libc::SO_EE_ORIGIN_ICMP => {
+                        IcmpErrorKind::classify_v4(error.ee_type, error.ee_code, error.ee_info)
+                    }
+                    libc::SO_EE_ORIGIN_ICMP6 => {
+                        IcmpErrorKind::classify_v6(error.ee_type, error.ee_code, error.ee_info)
+                    }
+                    // Raised by this host, e.g. a probe larger than the known path MTU
+                    _ if error.ee_errno == libc::EMSGSIZE as u32 => {
+                        IcmpErrorKind::FragmentationNeeded(error.ee_info)
+                    }
+                    _ => {
+                        cmsg = unsafe { libc::CMSG_NXTHDR(&message, cmsg) };
+                        continue;
+                    }
+                };
+                queue.errors.push((
+                    data[..size as usize].to_vec(),
+                    IcmpError {
+                        kind,
+                        from: offender,
+                    },
+                ));
+            }
+            cmsg = unsafe { libc::CMSG_NXTHDR(&message, cmsg) };
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn drain(_socket: &UdpSocket) -> io::Result<ErrorQueue> {
+    Ok(ErrorQueue::default())
+}
+
+/// Address of the host that sent the error, which follows the extended error.
+///
+/// # Safety
+///
+/// `extended` has to point into a control message of type `IP_RECVERR` or
+/// `IPV6_RECVERR`.
+#[cfg(target_os = "linux")]
+unsafe fn offender(extended: *const libc::sock_extended_err) -> Option<IpAddr> {
+    let address = libc::SO_EE_OFFENDER(extended);
+    match (*address).sa_family as libc::c_int {
+        libc::AF_INET => {
+            let address = std::ptr::read_unaligned(address as *const libc::sockaddr_in);
+            Some(std::net::Ipv4Addr::from(u32::from_be(address.sin_addr.s_addr)).into())
+        }
+        libc::AF_INET6 => {
+            let address = std::ptr::read_unaligned(address as *const libc::sockaddr_in6);
+            Some(std::net::Ipv6Addr::from(address.sin6_addr.s6_addr).into())
+        }
+        // Errors raised locally have no offender
+        _ => None,
+    }
+}
@@ -1,22 +1,279 @@
 use std::{
-    net::{IpAddr, SocketAddr},
-    sync::Arc,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fmt,
+    net::{IpAddr, SocketAddr, SocketAddrV6},
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     time::Duration,
 };
 
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{bail, eyre, Result};
+use futures::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
+use hdrhistogram::Histogram;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use tokio::{
-    net::UdpSocket,
-    sync::{mpsc::UnboundedSender, Mutex},
-    time::{self, Instant},
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpSocket, TcpStream, UdpSocket,
+    },
+    sync::{broadcast, mpsc::UnboundedSender, Mutex, Notify},
+    time::{self, Instant, Interval, MissedTickBehavior},
 };
+use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
 use tokio_util::sync::CancellationToken;
+use tracing::{debug, trace_span, warn, Instrument};
 
+use super::{
+    bandwidth::format_bytes,
+    buffers::SocketBuffers,
+    busy_poll,
+    clock::{self, ClockDrift, ClockOffset, ClockSample, DelayRange, DelayTotals, OneWayDelay},
+    echo::{ReplyShape, REPLY_REQUEST_LEN, TCP_ECHO_MAGIC},
+    flow_label,
+    icmp::{IcmpSocket, ICMP_HEADER},
+    icmp_error::{self, IcmpError},
+    listen,
+    metered::{self, MeteredPolicy},
+    packets::Packets,
+    protocol::{self, DecodeError, Header, FLAG_TIMESTAMP, HEADER_LEN},
+    quic::{self, QUIC_OVERHEAD},
+    quiet::{self, QuietPolicy, QuietWindow},
+    route::{self, Route, RouteWatch},
+    tcp,
+    timestamping::{self, KernelTimestamp, Timestamping},
+    udplite,
+    websocket::{self, WEBSOCKET_OVERHEAD},
+};
 use crate::action::Action;
 
+#[cfg(feature = "udp-no-checksum")]
+use super::checksum;
+
+/// Tokio only applies the missed tick behavior once a tick is this late, smaller
+/// delays are always caught up immediately.
+const CATCH_UP_THRESHOLD: Duration = Duration::from_millis(5);
+
+const SUSPEND_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// UDP + IP header bytes added to every probe on the wire.
+pub(crate) const UDP_IPV4_OVERHEAD: u64 = 28;
+pub(crate) const UDP_IPV6_OVERHEAD: u64 = 48;
+
+/// TCP + IP header bytes without options, the ACKs are not counted.
+const TCP_IPV4_OVERHEAD: u64 = 40;
+const TCP_IPV6_OVERHEAD: u64 = 60;
+
+/// How often an infinite run that lost the network tries to reopen its sockets.
+const REBIND_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Two-sided critical value for p < 0.001, used when comparing payload groups.
+const SIGNIFICANCE_CRITICAL_VALUE: f64 = 3.29;
+
+/// Smallest relative latency difference between payload groups worth reporting,
+/// with many probes even negligible differences become significant.
+const MIN_PAYLOAD_EFFECT: f64 = 0.02;
+
+/// Fixed header of every RTP packet, without CSRCs or extensions.
+const RTP_HEADER: usize = 12;
+/// Expedited Forwarding, the usual marking of voice media.
+const DSCP_EF: u8 = 46;
+
+/// Server updates per second of the game profile.
+const GAME_TICK_RATE: u32 = 64;
+/// Player input sent upstream every tick.
+const GAME_INPUT_SIZE: usize = 60;
+/// World snapshot sent downstream every tick, split over several datagrams.
+const GAME_SNAPSHOT: ReplyShape = ReplyShape {
+    size: 400,
+    count: 2,
+};
+
+const DRAIN_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often TCP_INFO of the probe connection is sampled with `--protocol tcp`.
+const TCP_INFO_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the ICMP errors queued on the UDP sockets are read.
+const ICMP_ERROR_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Share of the configured packet rate the sender has to reach to count as
+/// keeping up.
+const ON_SCHEDULE: f64 = 0.95;
+
+/// Longest latency the histogram tracks precisely, slower echoes are clamped.
+const HISTOGRAM_MAX: Duration = Duration::from_secs(3600);
+/// Significant decimal digits of the histogram.
+const HISTOGRAM_PRECISION: u8 = 3;
+
+/// How often the TUI gets updated percentiles.
+const PERCENTILE_UPDATE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How often the percentile of a [`StopCondition`] is estimated.
+const CONVERGENCE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Longest time between two checks whether the target went down.
+const DOWN_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the memory the state takes is estimated and reported.
+const MEMORY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Probes kept once the memory cap is reached, enough for the chart and the
+/// echoes still in flight.
+const MEMORY_CAP_RETENTION: usize = 1000;
+
+/// Coefficient of variation of the per-source average latency above which
+/// sources count as different.
+const SOURCE_LATENCY_VARIATION: f64 = 0.25;
+
+/// Difference in loss rate between the best and worst source above which
+/// sources count as different.
+const SOURCE_LOSS_SPREAD: f64 = 0.02;
+
+/// First probes of a run that prime ARP/ND entries, NAT state and route caches.
+/// They are sent on top of `--count` and left out of the statistics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Warmup {
+    Probes(u32),
+    Duration(Duration),
+}
+
+impl Warmup {
+    /// Whether probe `n`, sent at `sent` since the start, is part of it.
+    fn covers(&self, n: usize, sent: Duration) -> bool {
+        match *self {
+            Warmup::Probes(probes) => n < probes as usize,
+            Warmup::Duration(duration) => sent < duration,
+        }
+    }
+}
+
+impl fmt::Display for Warmup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warmup::Probes(probes) => write!(f, "{}", probes),
+            Warmup::Duration(duration) => write!(f, "{:?}", duration),
+        }
+    }
+}
+
+/// Ends a run once a latency percentile has settled: all estimates during the
+/// last `window` are within `tolerance` of the current one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct StopCondition {
+    /// Percentile in (0, 100), e.g. 99 for p99
+    pub percentile: f64,
+    /// Relative spread, e.g. 0.02 for 2%
+    pub tolerance: f64,
+    pub window: Duration,
+}
+
+impl StopCondition {
+    /// Received probes needed before the percentile says anything about the
+    /// tail, p99 needs at least a hundred.
+    fn min_samples(&self) -> usize {
+        (100.0 / (100.0 - self.percentile)).ceil() as usize
+    }
+}
+
+impl fmt::Display for StopCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "p{} stable within {}% over {}",
+            self.percentile,
+            self.tolerance * 100.0,
+            humantime::format_duration(self.window)
+        )
+    }
+}
+
+/// Switches to a faster probing rate for a while when latency or loss crosses a
+/// threshold, to capture incidents in detail without always probing fast.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BurstCapture {
+    pub latency_threshold: Option<Duration>,
+    /// Consecutive unanswered probes that trigger a capture
+    pub loss_threshold: Option<u32>,
+    pub interval: Duration,
+    pub duration: Duration,
+}
+
+/// How long echoes are waited for before a probe counts as lost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct LossTimeout {
+    /// How long a probe may stay unanswered before it counts towards the loss
+    /// trigger of a [`BurstCapture`] and is shown as lost on the chart
+    pub grace: Duration,
+    /// How long receivers wait for the echoes still in flight once sending
+    /// stopped, twice the slowest echo so far within these bounds
+    pub min_drain: Duration,
+    pub max_drain: Duration,
+    /// Derive each probe's timeout from the round trips so far instead, as
+    /// SRTT + k·RTTVAR with this k like the TCP retransmission timeout. The
+    /// grace still applies until the first echo
+    pub adaptive: Option<u32>,
+}
+
+impl Default for LossTimeout {
+    fn default() -> Self {
+        Self {
+            grace: Duration::from_secs(1),
+            min_drain: Duration::from_millis(500),
+            max_drain: Duration::from_secs(5),
+            adaptive: None,
+        }
+    }
+}
+
+impl LossTimeout {
+    fn drain(&self, max_latency: Duration) -> Duration {
+        (max_latency * 2).clamp(self.min_drain, self.max_drain)
+    }
+}
+
+/// Bounds of an adaptive loss timeout. The lower one leaves room for scheduling
+/// delays on links with sub-millisecond round trips.
+const MIN_ADAPTIVE_TIMEOUT: Duration = Duration::from_millis(50);
+const MAX_ADAPTIVE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Smoothed round trip and its variation, estimated like TCP does (RFC 6298).
+#[derive(Debug, Clone, Copy)]
+struct RttEstimate {
+    srtt: Duration,
+    rttvar: Duration,
+}
+
+impl RttEstimate {
+    fn new(rtt: Duration) -> Self {
+        Self {
+            srtt: rtt,
+            rttvar: rtt / 2,
+        }
+    }
+
+    /// Folds in a round trip with alpha = 1/8 and beta = 1/4.
+    fn update(&mut self, rtt: Duration) {
+        let deviation = self.srtt.abs_diff(rtt);
+        self.rttvar = (self.rttvar * 3 + deviation) / 4;
+        self.srtt = (self.srtt * 7 + rtt) / 8;
+    }
+
+    fn timeout(&self, k: u32) -> Duration {
+        (self.srtt + self.rttvar * k).clamp(MIN_ADAPTIVE_TIMEOUT, MAX_ADAPTIVE_TIMEOUT)
+    }
+}
+
 pub(crate) struct Latency {
     state: Arc<Mutex<State>>,
 
+    target: usize,
     count: u32,
 
     packet_size: u16,
@@ -26,6 +283,58 @@ pub(crate) struct Latency {
     server_port: u16,
 
     client_port: u16,
+    bind_address: Option<IpAddr>,
+    /// Local addresses the probes take turns leaving from
+    source_addresses: Vec<IpAddr>,
+    buffers: SocketBuffers,
+    /// Microseconds of `SO_BUSY_POLL`, the echoes are then also spun for
+    busy_poll: Option<u32>,
+
+    route_check_interval: Option<Duration>,
+    clock_check_interval: Option<Duration>,
+    tunnel_change: TunnelChange,
+    stop_condition: Option<StopCondition>,
+    warmup: Option<Warmup>,
+    /// Silence after which the target counts as down
+    down_after: Option<Duration>,
+    on_down: DownPolicy,
+    first_phase: Option<String>,
+    phase_marks: Option<broadcast::Receiver<String>>,
+    source_ports: usize,
+    catch_up: CatchUp,
+    suspend_threshold: Duration,
+    metered: Option<MeteredPolicy>,
+    /// Set while a metered connection holds the probes back
+    paused: AtomicBool,
+    quiet_hours: Vec<QuietWindow>,
+    quiet_policy: QuietPolicy,
+    /// Set during quiet hours with [`QuietPolicy::Pause`]
+    quiet: AtomicBool,
+    burst_capture: Option<BurstCapture>,
+    burst_trigger: Notify,
+    /// Send times replayed instead of following the interval
+    send_schedule: Option<Arc<[Duration]>>,
+    loss_timeout: LossTimeout,
+    max_bytes: Option<u64>,
+    payload: Payload,
+    seed: u64,
+    protocol: Protocol,
+    /// HTTP proxy the WebSocket connection is tunneled through
+    proxy: Option<String>,
+    #[cfg(feature = "udp-no-checksum")]
+    no_checksum: bool,
+    checksum_coverage: Option<u16>,
+    retention: Option<usize>,
+    max_memory: Option<usize>,
+    /// Type of service byte, DSCP in the upper six bits and ECN in the lower two
+    tos: Option<u8>,
+    ttl: Option<u8>,
+    flow_label: Option<u32>,
+    reply: Option<ReplyShape>,
+    one_way_delay: bool,
+    estimate_offset: bool,
+    tag: Option<String>,
+    timestamping: Option<Timestamping>,
 
     start: Instant,
 
@@ -43,6 +352,7 @@ impl Latency {
     ) -> Self {
         Self {
             state: Arc::new(Mutex::new(State::new(count))),
+            target: 0,
             count,
 
             packet_interval: Duration::from_millis(100),
@@ -52,6 +362,50 @@ impl Latency {
             server_port: port,
 
             client_port: 0,
+            bind_address: None,
+            source_addresses: Vec::new(),
+            buffers: SocketBuffers::default(),
+            busy_poll: None,
+
+            route_check_interval: None,
+            clock_check_interval: None,
+            tunnel_change: TunnelChange::default(),
+            stop_condition: None,
+            warmup: None,
+            down_after: None,
+            on_down: DownPolicy::default(),
+            first_phase: None,
+            phase_marks: None,
+            source_ports: 1,
+            catch_up: CatchUp::default(),
+            suspend_threshold: Duration::from_secs(2),
+            metered: None,
+            paused: AtomicBool::new(false),
+            quiet_hours: Vec::new(),
+            quiet_policy: QuietPolicy::default(),
+            quiet: AtomicBool::new(false),
+            burst_capture: None,
+            burst_trigger: Notify::new(),
+            send_schedule: None,
+            loss_timeout: LossTimeout::default(),
+            max_bytes: None,
+            payload: Payload::default(),
+            seed: rand::random(),
+            protocol: Protocol::default(),
+            proxy: None,
+            #[cfg(feature = "udp-no-checksum")]
+            no_checksum: false,
+            checksum_coverage: None,
+            retention: None,
+            max_memory: None,
+            tos: None,
+            ttl: None,
+            flow_label: None,
+            reply: None,
+            one_way_delay: false,
+            estimate_offset: false,
+            tag: None,
+            timestamping: None,
 
             start: Instant::now(),
 
@@ -60,6 +414,12 @@ impl Latency {
         }
     }
 
+    /// Index used to tag the actions of this engine when probing several targets.
+    pub(crate) fn with_target(mut self, target: usize) -> Self {
+        self.target = target;
+        self
+    }
+
     pub(crate) fn with_interval(mut self, interval: Duration) -> Self {
         self.packet_interval = interval;
         self
@@ -75,156 +435,3100 @@ impl Latency {
         self
     }
 
-    pub(crate) async fn run(&mut self) -> Result<Arc<Mutex<State>>> {
-        let socket = UdpSocket::bind(format!("0.0.0.0:{}", self.client_port)).await?;
+    /// Source address of the probes, the wildcard address of the target's family
+    /// by default.
+    pub(crate) fn with_bind_address(mut self, address: IpAddr) -> Self {
+        self.bind_address = Some(address);
+        self
+    }
 
-        if self.count > 0 {
-            self.notify.send(Action::LatencyPacketTotal(self.count))?;
-        }
+    /// Send the probes round-robin from each of these local addresses, which
+    /// have to be of the target's family. Only for UDP and UDP-Lite.
+    pub(crate) fn with_source_addresses(mut self, addresses: Vec<IpAddr>) -> Self {
+        self.source_addresses = addresses;
+        self
+    }
 
-        self.start = Instant::now();
-        tokio::try_join!(
-            self.send_packets(&socket, self.state.clone()),
-            self.receive_packets(&socket, self.state.clone())
-        )?;
+    /// Size the kernel buffers of the UDP sockets instead of keeping the OS
+    /// defaults.
+    pub(crate) fn with_socket_buffers(mut self, buffers: SocketBuffers) -> Self {
+        self.buffers = buffers;
+        self
+    }
 
-        Ok(self.state.clone())
+    /// Spin on the UDP sockets for echoes instead of waiting to be woken up,
+    /// with the kernel busy polling the device for up to `micros`. Trades a busy
+    /// core for less wakeup latency in sub-100µs measurements.
+    pub(crate) fn with_busy_poll(mut self, micros: u32) -> Self {
+        self.busy_poll = Some(micros);
+        self
     }
 
-    pub(crate) async fn send_packets(
-        &self,
-        socket: &UdpSocket,
-        state: Arc<Mutex<State>>,
-    ) -> Result<()> {
-        let addr = SocketAddr::new(self.server_address, self.server_port);
-        let mut buf = vec![0; self.packet_size as usize];
+    pub(crate) fn with_catch_up(mut self, catch_up: CatchUp) -> Self {
+        self.catch_up = catch_up;
+        self
+    }
 
-        let mut interval = time::interval(self.packet_interval);
+    /// Minimum clock divergence treated as a system suspend, zero disables detection.
+    pub(crate) fn with_suspend_threshold(mut self, threshold: Duration) -> Self {
+        self.suspend_threshold = threshold;
+        self
+    }
 
-        // FIXME: Allow graceful exit when running infinitely (count = 0)
-        loop {
-            // Run loop at specified interval
-            interval.tick().await;
+    /// Watches whether NetworkManager considers the connection metered, and
+    /// records or pauses for it.
+    pub(crate) fn with_metered_policy(mut self, policy: MeteredPolicy) -> Self {
+        self.metered = Some(policy);
+        self
+    }
 
-            // Add counter in packet
-            let mut counter = state.lock().await.packets.len();
-            let counter_bytes = counter.to_ne_bytes();
-            buf[..counter_bytes.len()].copy_from_slice(&counter_bytes);
+    /// Daily windows of local time, e.g. a backup window, during which the
+    /// probes pause with [`QuietPolicy::Pause`]. Their beginning and end are
+    /// recorded as events, so the gap is not mistaken for missing data.
+    pub(crate) fn with_quiet_hours(
+        mut self,
+        windows: Vec<QuietWindow>,
+        policy: QuietPolicy,
+    ) -> Self {
+        self.quiet_hours = windows;
+        self.quiet_policy = policy;
+        self
+    }
 
-            let start = Instant::now() - self.start;
-            socket.send_to(&buf, addr).await?;
-            state.lock().await.packets.push(PacketStatus::Sent(start));
-            state.lock().await.packet_loss += 1;
+    /// Marks the target down once no reply arrived for `after` while probes
+    /// were sent, and up again with the next reply. Both are recorded as
+    /// events, [`DownPolicy::Stop`] also ends the run.
+    pub(crate) fn with_down_detection(mut self, after: Duration, policy: DownPolicy) -> Self {
+        self.down_after = Some(after);
+        self.on_down = policy;
+        self
+    }
 
-            counter += 1;
-            self.notify
-                .send(Action::LatencyPacketsSent(counter as u32))?;
+    /// Spread probes over a pool of sockets, each with its own source port.
+    pub(crate) fn with_source_ports(mut self, ports: usize) -> Self {
+        self.source_ports = ports.max(1);
+        self
+    }
 
-            if self.quit.is_cancelled() || (self.count > 0 && counter >= self.count as usize) {
-                state.lock().await.should_stop = true;
-                break;
-            }
-        }
+    /// Stop once the traffic in both directions, including headers, would exceed
+    /// this many bytes.
+    pub(crate) fn with_max_bytes(mut self, bytes: u64) -> Self {
+        self.max_bytes = Some(bytes);
+        self
+    }
 
-        Ok(())
+    pub(crate) fn with_payload(mut self, payload: Payload) -> Self {
+        self.payload = payload;
+        self
     }
 
-    pub(crate) async fn receive_packets(
-        &self,
-        socket: &UdpSocket,
-        state: Arc<Mutex<State>>,
-    ) -> Result<()> {
-        let mut buf = [0; 1500];
+    /// Seed of the random payloads. Each probe's payload depends only on the seed
+    /// and its sequence number, so a run can be repeated byte for byte.
+    pub(crate) fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
 
-        loop {
-            tokio::select! {
-                _ = socket.recv_from(&mut buf) => {
-                    let stop = Instant::now() - self.start;
+    pub(crate) fn with_protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
 
-                    let n = u64::from_ne_bytes(buf[..std::mem::size_of::<u64>()].try_into().unwrap());
-                    let mut state = state.lock().await;
+    /// Reach the server through this HTTP proxy, given as `host:port`, with
+    /// [`Protocol::WebSocket`].
+    pub(crate) fn with_proxy(mut self, proxy: String) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
 
-                    let start = match state.packets[n as usize] {
-                        PacketStatus::Sent(start) => start,
-                        _ => panic!("Packet was not sent"),
-                    };
+    /// Send the UDP probes with a zero checksum, so ones damaged on the way
+    /// reach the server instead of being dropped.
+    #[cfg(feature = "udp-no-checksum")]
+    pub(crate) fn with_udp_checksum_disabled(mut self) -> Self {
+        self.no_checksum = true;
+        self
+    }
 
-                    let latency = stop - start;
+    /// Bytes of the [`Protocol::UdpLite`] probes covered by the checksum, header
+    /// included. The whole probe by default.
+    pub(crate) fn with_checksum_coverage(mut self, coverage: u16) -> Self {
+        self.checksum_coverage = Some(coverage);
+        self
+    }
 
-                    state.packets[n as usize] = PacketStatus::Received {
-                        start,
-                        stop,
-                        latency,
-                    };
+    /// Marks the UDP probes with this differentiated services code point.
+    pub(crate) fn with_dscp(mut self, dscp: u8) -> Self {
+        self.tos = Some(dscp << 2);
+        self
+    }
 
-                    update_statistics(&mut state, latency);
-                    self.notify.send(Action::LatencyPacketsReceived(state.received_packets, state.min_latency, state.average_latency, state.max_latency))?;
-                }
-                // TODO: Make this smarter by exiting if all recent packets have been received
-                _ = tokio::time::sleep(Duration::from_millis(500)), if state.lock().await.should_stop => {
-                    break;
-                }
-            }
-        }
+    /// Sets the whole type of service byte, or traffic class over IPv6, of the
+    /// UDP and TCP probes.
+    pub(crate) fn with_tos(mut self, tos: u8) -> Self {
+        self.tos = Some(tos);
+        self
+    }
 
-        Ok(())
+    /// Sends the UDP and TCP probes with this TTL, or hop limit over IPv6. Routers
+    /// further away answer with ICMP errors, which are recorded per probe.
+    pub(crate) fn with_ttl(mut self, ttl: u8) -> Self {
+        self.ttl = Some(ttl);
+        self
     }
-}
 
-pub(crate) enum PacketStatus {
-    Sent(Duration),
-    Received {
-        start: Duration,
-        stop: Duration,
-        latency: Duration,
-    },
-}
+    /// Sends the UDP and TCP probes to an IPv6 server with this flow label, so
+    /// they take a predictable path through flow-label based ECMP.
+    pub(crate) fn with_flow_label(mut self, label: u32) -> Self {
+        self.flow_label = Some(label);
+        self
+    }
 
-pub(crate) struct State {
-    pub packets: Vec<PacketStatus>,
+    /// Asks the server to answer every UDP probe with this reply instead of an
+    /// echo. Latency is measured to the first datagram of the reply.
+    pub(crate) fn with_reply(mut self, reply: ReplyShape) -> Self {
+        self.reply = Some(reply);
+        self
+    }
 
-    pub received_packets: u32,
-    pub packet_loss: u32,
+    /// Asks the server to stamp its receive time into every UDP echo, splitting
+    /// the round trip into upstream and downstream delay. Both are off by the
+    /// offset between the clocks, which is estimated before the run and corrected
+    /// with `estimate_offset`. Otherwise the clocks have to be in sync already.
+    pub(crate) fn with_one_way_delay(mut self, estimate_offset: bool) -> Self {
+        self.one_way_delay = true;
+        self.estimate_offset = estimate_offset;
+        self
+    }
 
-    pub min_latency: Duration,
-    pub max_latency: Duration,
-    pub average_latency: Duration,
+    /// Attaches `tag` to every probe. The server echoes it back untouched and it
+    /// is recorded with the probe, so exports can be joined on it.
+    pub(crate) fn with_tag(mut self, tag: String) -> Self {
+        self.tag = Some(tag);
+        self
+    }
 
-    pub should_stop: bool,
-}
+    /// Takes send and receive times of UDP probes in the kernel or network card.
+    pub(crate) fn with_timestamping(mut self, timestamping: Timestamping) -> Self {
+        self.timestamping = Some(timestamping);
+        self
+    }
 
-impl State {
-    fn new(count: u32) -> Self {
-        Self {
-            packets: Vec::with_capacity(count as usize),
-            received_packets: 0,
-            packet_loss: 0,
-            min_latency: Duration::from_secs(0),
-            max_latency: Duration::from_secs(0),
-            average_latency: Duration::from_secs(0),
-            should_stop: false,
-        }
+    /// Waits longer, or shorter, for echoes before declaring probes lost.
+    pub(crate) fn with_loss_timeout(mut self, timeout: LossTimeout) -> Self {
+        self.loss_timeout = timeout;
+        self
     }
-}
 
-fn update_statistics(state: &mut State, latency: Duration) {
-    let n = state.received_packets as f64;
+    pub(crate) fn with_burst_capture(mut self, burst: BurstCapture) -> Self {
+        self.burst_capture = Some(burst);
+        self
+    }
 
-    // Cumulative average: avg = avg * (n / (n + 1)) + new_value * (1 / (n + 1))
-    state.average_latency = state.average_latency.mul_f64(n / (n + 1.0)) + latency.div_f64(n + 1.0);
+    /// Send probe `n` at `offsets[n]` after the start instead of following the
+    /// interval, the count should not exceed the offsets.
+    pub(crate) fn with_send_schedule(mut self, offsets: Arc<[Duration]>) -> Self {
+        self.send_schedule = Some(offsets);
+        self
+    }
 
-    if state.received_packets == 0 {
-        state.min_latency = latency;
-        state.max_latency = latency;
-    } else {
-        if latency < state.min_latency {
-            state.min_latency = latency;
+    /// Periodically look up the route to the target and record changes as events.
+    pub(crate) fn with_route_tracking(mut self, interval: Duration) -> Self {
+        self.route_check_interval = Some(interval);
+        self
+    }
+
+    /// What to do when route tracking sees the route move onto or off a tunnel.
+    pub(crate) fn with_tunnel_change(mut self, policy: TunnelChange) -> Self {
+        self.tunnel_change = policy;
+        self
+    }
+
+    /// Periodically read the server's clock to estimate how the clocks drift apart.
+    pub(crate) fn with_clock_tracking(mut self, interval: Duration) -> Self {
+        self.clock_check_interval = Some(interval);
+        self
+    }
+
+    /// Sends the first probes of the run as a warm-up, recorded as such and
+    /// kept out of the statistics.
+    pub(crate) fn with_warmup(mut self, warmup: Warmup) -> Self {
+        self.warmup = Some(warmup);
+        self
+    }
+
+    pub(crate) fn with_stop_condition(mut self, condition: StopCondition) -> Self {
+        self.stop_condition = Some(condition);
+        self
+    }
+
+    /// Starts in the phase `first` and begins a new phase for every name received
+    /// on `marks`.
+    pub(crate) fn with_phases(mut self, first: String, marks: broadcast::Receiver<String>) -> Self {
+        self.first_phase = Some(first);
+        self.phase_marks = Some(marks);
+        self
+    }
+
+    /// Keeps only the last `retention` probes in memory. The totals still cover
+    /// the whole run, analyses that need every probe see only the retained ones.
+    pub(crate) fn with_retention(mut self, retention: usize) -> Self {
+        self.retention = Some(retention);
+        self
+    }
+
+    /// Keeps only the most recent probes once the state takes more than `bytes`,
+    /// the totals still cover the whole run.
+    pub(crate) fn with_max_memory(mut self, bytes: usize) -> Self {
+        self.max_memory = Some(bytes);
+        self
+    }
+
+    pub(crate) fn state(&self) -> Arc<Mutex<State>> {
+        self.state.clone()
+    }
+
+    pub(crate) async fn run(&mut self) -> Result<Arc<Mutex<State>>> {
+        let bind_address = self
+            .bind_address
+            .or_else(|| self.source_addresses.first().copied())
+            .unwrap_or_else(|| listen::unspecified(self.server_address));
+
+        let transport = self.open_transport(bind_address).await?;
+
+        if self.count > 0 {
+            self.notify
+                .send(Action::LatencyPacketTotal(self.target, self.count))?;
         }
-        if latency > state.max_latency {
-            state.max_latency = latency;
+
+        {
+            let mut state = self.state.lock().await;
+            state.interval = self.packet_interval;
+            state.loss_timeout = self.loss_timeout;
+            if let Some(retention) = self.retention {
+                state.packets.set_retention(retention);
+            }
+        }
+
+        if let Some(ref name) = self.first_phase {
+            self.state.lock().await.phases = vec![Phase {
+                name: name.clone(),
+                first: 0,
+            }];
+        }
+        let phase_marks = self.phase_marks.take();
+
+        if self.one_way_delay && self.estimate_offset {
+            self.estimate_clock_offset(bind_address).await;
+        }
+
+        self.start = Instant::now();
+        {
+            let mut state = self.state.lock().await;
+            state.start = Some(self.start);
+            state.started_at = Some(std::time::SystemTime::now());
+        }
+        tokio::select! {
+            result = self.probe(transport, bind_address) => {
+                result?;
+            }
+            _ = async {
+                tokio::join!(
+                    self.track_route(self.state.clone()),
+                    self.track_clock(bind_address, self.state.clone()),
+                    self.detect_suspend(self.state.clone()),
+                    self.watch_metered(self.state.clone()),
+                    self.watch_quiet_hours(self.state.clone()),
+                    self.watch_down(self.state.clone()),
+                    self.track_phases(phase_marks, self.state.clone()),
+                    self.watch_convergence(self.state.clone())
+                )
+            } => {}
         }
+
+        Ok(self.state.clone())
+    }
+
+    /// Opens the sockets, or connection, the probes are sent over.
+    async fn open_transport(&self, bind_address: IpAddr) -> Result<Transport> {
+        Ok(match self.protocol {
+            Protocol::Udp | Protocol::UdpLite => {
+                let addresses = match self.source_addresses.is_empty() {
+                    true => vec![bind_address],
+                    false => self.source_addresses.clone(),
+                };
+                // The first socket of each address honours the configured client
+                // port, the rest of the pool gets ephemeral ports. Consecutive
+                // probes leave from different addresses.
+                let mut sockets = Vec::with_capacity(self.source_ports * addresses.len());
+                for i in 0..self.source_ports.max(1) {
+                    for &local in addresses.iter() {
+                        let port = if i == 0 { self.client_port } else { 0 };
+                        let address = SocketAddr::new(local, port);
+                        let socket = match self.protocol {
+                            Protocol::UdpLite => udplite::bind(address, self.checksum_coverage)?,
+                            _ => UdpSocket::bind(address)
+                                .await
+                                .map_err(|e| eyre!("Could not bind to {}: {}", address, e))?,
+                        };
+                        if let Err(e) = icmp_error::enable(&socket, local) {
+                            debug!("ICMP errors are not captured: {}", e);
+                        }
+                        if let Some(timestamping) = self.timestamping {
+                            timestamping::enable(&socket, timestamping)
+                                .map_err(|e| eyre!("Could not enable kernel timestamps: {}", e))?;
+                        }
+                        self.configure(socket2::SockRef::from(&socket), local)?;
+                        #[cfg(feature = "udp-no-checksum")]
+                        if self.no_checksum && self.protocol == Protocol::Udp {
+                            checksum::disable(socket2::SockRef::from(&socket), local)
+                                .map_err(|e| eyre!("Could not disable UDP checksums: {}", e))?;
+                        }
+                        self.buffers.apply(&socket2::SockRef::from(&socket))?;
+                        if let Some(micros) = self.busy_poll {
+                            if let Err(e) = busy_poll::enable(&socket, micros) {
+                                // Spinning alone still saves the wakeup
+                                match sockets.is_empty() {
+                                    true => warn!("The kernel does not busy poll: {}", e),
+                                    false => debug!("The kernel does not busy poll: {}", e),
+                                }
+                            }
+                        }
+                        sockets.push(socket);
+                    }
+                }
+                if !self.buffers.is_default() {
+                    self.buffers
+                        .report(&socket2::SockRef::from(&sockets[0]), "Probe")?;
+                }
+
+                self.state.lock().await.sources = sockets
+                    .iter()
+                    .map(|s| s.local_addr())
+                    .collect::<Result<_, _>>()?;
+
+                Transport::Udp(sockets)
+            }
+            Protocol::Tcp => self.connect(bind_address).await?,
+            Protocol::Quic => self.connect_quic(bind_address).await?,
+            Protocol::WebSocket => self.connect_websocket(bind_address).await?,
+            Protocol::Icmp => Transport::Icmp(IcmpSocket::open(self.server_address)?),
+        })
     }
 
+    /// Sends and receives the probes. Infinite runs survive the network going
+    /// away, e.g. when the interface changes: they reopen the transport once it is
+    /// back and continue the sequence numbers and the session, with a
+    /// [`Event::Rebind`] marking the gap.
+    async fn probe(&self, mut transport: Transport, bind_address: IpAddr) -> Result<()> {
+        loop {
+            let result = match transport {
+                Transport::Udp(ref sockets) => tokio::try_join!(
+                    self.send_packets(&transport, self.state.clone()),
+                    futures::future::try_join_all(
+                        sockets
+                            .iter()
+                            .map(|s| self.receive_packets(s, self.state.clone()))
+                    )
+                )
+                .map(|_| ()),
+                Transport::Tcp {
+                    ref reader,
+                    ref writer,
+                } => tokio::try_join!(
+                    self.send_packets(&transport, self.state.clone()),
+                    self.receive_stream(reader, self.state.clone()),
+                    self.sample_tcp_info(writer, self.state.clone())
+                )
+                .map(|_| ()),
+                Transport::Quic { ref reader, .. } => tokio::try_join!(
+                    self.send_packets(&transport, self.state.clone()),
+                    self.receive_stream(reader, self.state.clone())
+                )
+                .map(|_| ()),
+                Transport::WebSocket { ref reader, .. } => tokio::try_join!(
+                    self.send_packets(&transport, self.state.clone()),
+                    self.receive_messages(reader, self.state.clone())
+                )
+                .map(|_| ()),
+                Transport::Icmp(ref socket) => tokio::try_join!(
+                    self.send_packets(&transport, self.state.clone()),
+                    self.receive_icmp(socket, self.state.clone())
+                )
+                .map(|_| ()),
+            };
+
+            let error = match result {
+                Err(e) if self.count == 0 && is_network_change(&e) => e,
+                result => return result,
+            };
+            // The old sockets have to go before their port can be bound again
+            drop(transport);
+
+            let down = Instant::now();
+            warn!("Network lost ({}), rebinding", error);
+            transport = loop {
+                tokio::select! {
+                    _ = time::sleep(REBIND_RETRY_INTERVAL) => {}
+                    _ = self.quit.cancelled() => {
+                        self.state.lock().await.stop(self.start.elapsed());
+                        return Ok(());
+                    }
+                }
+                match self.open_transport(bind_address).await {
+                    Ok(transport) => break transport,
+                    Err(e) if is_network_change(&e) => continue,
+                    Err(e) => return Err(e),
+                }
+            };
+
+            // A capture cut short by the outage cannot be resumed
+            self.state.lock().await.in_burst = false;
+            self.record_event(
+                &self.state,
+                Event::Rebind {
+                    downtime: down.elapsed(),
+                    reason: error.to_string(),
+                },
+            )
+            .await;
+        }
+    }
+
+    /// Applies the marking, TTL and flow label of the probes to a socket.
+    fn configure(&self, socket: socket2::SockRef<'_>, bind_address: IpAddr) -> std::io::Result<()> {
+        if let (Some(label), IpAddr::V6(server)) = (self.flow_label, self.server_address) {
+            flow_label::enable(&socket, server, label)?;
+        }
+        match bind_address {
+            IpAddr::V4(_) => {
+                if let Some(tos) = self.tos {
+                    socket.set_tos(tos as u32)?;
+                }
+                if let Some(ttl) = self.ttl {
+                    socket.set_ttl(ttl as u32)?;
+                }
+            }
+            IpAddr::V6(_) => {
+                if let Some(tos) = self.tos {
+                    socket.set_tclass_v6(tos as u32)?;
+                }
+                if let Some(ttl) = self.ttl {
+                    socket.set_unicast_hops_v6(ttl as u32)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Where the probes go, carrying the flow label over IPv6.
+    fn destination(&self) -> SocketAddr {
+        match (self.server_address, self.flow_label) {
+            (IpAddr::V6(address), Some(label)) => SocketAddr::V6(SocketAddrV6::new(
+                address,
+                self.server_port,
+                flow_label::flow_info(label),
+                0,
+            )),
+            (address, _) => SocketAddr::new(address, self.server_port),
+        }
+    }
+
+    /// Opens the connection of [`Protocol::Tcp`] and asks the server to echo it.
+    async fn connect(&self, bind_address: IpAddr) -> Result<Transport> {
+        let mut stream = self.open_stream(bind_address, self.destination()).await?;
+        stream.write_all(TCP_ECHO_MAGIC).await?;
+
+        self.state.lock().await.sources = vec![stream.local_addr()?];
+
+        let (reader, writer) = stream.into_split();
+        Ok(Transport::Tcp {
+            reader: Mutex::new(reader),
+            writer: Mutex::new(writer),
+        })
+    }
+
+    /// Opens the connection of [`Protocol::WebSocket`], through the proxy if
+    /// there is one, and upgrades it.
+    async fn connect_websocket(&self, bind_address: IpAddr) -> Result<Transport> {
+        let stream = match self.proxy {
+            Some(ref proxy) => {
+                let address = tokio::net::lookup_host(proxy.as_str())
+                    .await
+                    .map_err(|e| eyre!("Cannot resolve the proxy {}: {}", proxy, e))?
+                    .next()
+                    .ok_or_else(|| eyre!("The proxy {} has no address", proxy))?;
+                // Without a configured address the socket takes the family of the proxy
+                let local = self
+                    .bind_address
+                    .unwrap_or_else(|| listen::unspecified(address.ip()));
+                let mut stream = self.open_stream(local, address).await?;
+                websocket::tunnel(&mut stream, self.destination()).await?;
+                stream
+            }
+            None => self.open_stream(bind_address, self.destination()).await?,
+        };
+        self.state.lock().await.sources = vec![stream.local_addr()?];
+
+        let (writer, reader) = websocket::connect(stream, self.destination())
+            .await?
+            .split();
+        Ok(Transport::WebSocket {
+            reader: Mutex::new(reader),
+            writer: Mutex::new(writer),
+        })
+    }
+
+    /// TCP connection to `peer` from a socket with the marking of the probes.
+    async fn open_stream(&self, bind_address: IpAddr, peer: SocketAddr) -> Result<TcpStream> {
+        let socket = match bind_address {
+            IpAddr::V4(_) => TcpSocket::new_v4()?,
+            IpAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        if self.client_port != 0 || self.bind_address.is_some() {
+            socket.bind(SocketAddr::new(bind_address, self.client_port))?;
+        }
+        self.configure(socket2::SockRef::from(&socket), bind_address)?;
+
+        let stream = socket.connect(peer).await?;
+        stream.set_nodelay(true)?;
+        Ok(stream)
+    }
+
+    /// Connects to the QUIC listener of the server and opens the stream the
+    /// probes are echoed on, recording how long the handshake took.
+    async fn connect_quic(&self, bind_address: IpAddr) -> Result<Transport> {
+        let socket = std::net::UdpSocket::bind(SocketAddr::new(bind_address, self.client_port))?;
+        self.configure(socket2::SockRef::from(&socket), bind_address)?;
+        let endpoint = quic::client(socket)?;
+
+        let (connection, handshake) = quic::connect(&endpoint, self.destination()).await?;
+        debug!("QUIC handshake took {:.1?}", handshake);
+        let (writer, reader) = connection.open_bi().await?;
+        {
+            let mut state = self.state.lock().await;
+            state.sources = vec![endpoint.local_addr()?];
+            state.handshake = Some(handshake);
+        }
+
+        Ok(Transport::Quic {
+            _endpoint: endpoint,
+            reader: Mutex::new(reader),
+            writer: Mutex::new(writer),
+        })
+    }
+
+    /// Sends the probes. Over UDP they cycle round-robin through the socket pool so
+    /// that packet `n` always leaves from `sockets[n % sockets.len()]`.
+    async fn send_packets(&self, transport: &Transport, state: Arc<Mutex<State>>) -> Result<()> {
+        let addr = self.destination();
+        let mut buf = vec![0; self.probe_size()];
+
+        let mut period = self.packet_interval;
+        let mut interval = time::interval(period);
+        interval.set_missed_tick_behavior(self.catch_up.into());
+        let mut behind = false;
+
+        // End of the running high-resolution capture and its first packet
+        let mut burst: Option<(Instant, usize)> = None;
+        let mut memory_checked = Instant::now();
+        // Probes of the warm-up, which come on top of the count
+        let mut warmup = 0;
+
+        loop {
+            // Run loop at specified interval
+            let slot = tokio::select! {
+                slot = self.next_slot(&mut interval, &state) => slot,
+                // Infinite runs only end here, without waiting for the next slot
+                _ = self.quit.cancelled() => {
+                    let mut state = state.lock().await;
+                    if let Some((_, first)) = burst {
+                        let last = state.packets.len();
+                        state.bursts.push(first..last);
+                    }
+                    state.stop(self.start.elapsed());
+                    break;
+                }
+                _ = self.burst_trigger.notified(), if burst.is_none() => {
+                    let Some(capture) = self.burst_capture else { continue };
+
+                    let first = {
+                        let mut state = state.lock().await;
+                        state.in_burst = true;
+                        state.packets.len()
+                    };
+                    burst = Some((Instant::now() + capture.duration, first));
+                    period = capture.interval;
+                    interval = time::interval(period);
+                    interval.set_missed_tick_behavior(self.catch_up.into());
+                    continue;
+                }
+            };
+
+            if let Some((until, first)) = burst {
+                if slot >= until {
+                    burst = None;
+                    let packets = {
+                        let mut state = state.lock().await;
+                        state.in_burst = false;
+                        let last = state.packets.len();
+                        state.bursts.push(first..last);
+                        last - first
+                    };
+                    self.record_event(&state, Event::BurstEnded(packets as u32))
+                        .await;
+
+                    period = self.packet_interval;
+                    interval = time::interval_at(slot + period, period);
+                    interval.set_missed_tick_behavior(self.catch_up.into());
+                    continue;
+                }
+            }
+
+            if self.paused.load(Ordering::Relaxed) || self.quiet.load(Ordering::Relaxed) {
+                let (skipped, sequence) = {
+                    let mut state = state.lock().await;
+                    state.push_packet(PacketStatus::Skipped(slot - self.start));
+                    state.skipped_packets += 1;
+                    (state.skipped_packets, state.packets.len())
+                };
+                self.notify
+                    .send(Action::LatencyPacketsSkipped(self.target, skipped))?;
+                self.report_lost(&state).await?;
+
+                if self.count > 0 && sequence >= self.count as usize {
+                    state.lock().await.stop(self.start.elapsed());
+                    break;
+                }
+                continue;
+            }
+
+            let late = Instant::now() - slot;
+            let is_behind = late >= period.max(CATCH_UP_THRESHOLD);
+            if is_behind && !behind {
+                self.record_event(&state, Event::BehindSchedule(late)).await;
+            }
+            behind = is_behind;
+
+            let mut counter = state.lock().await.packets.len();
+            self.fill_payload(counter, &mut buf[HEADER_LEN..]);
+            Header {
+                flags: match self.one_way_delay {
+                    true => protocol::FLAG_TIMESTAMP,
+                    false => 0,
+                },
+                sequence: counter as u64,
+                sent: clock::now(),
+                received: 0,
+            }
+            .encode(&mut buf);
+            if let Some(reply) = self.reply {
+                reply.write(&mut buf);
+            }
+            if let Some(ref tag) = self.tag {
+                protocol::write_tag(&mut buf, tag.as_bytes());
+            }
+
+            if let Some(max) = self.max_bytes {
+                // Leave room for the echo of this probe as well
+                let reply = match self.reply {
+                    Some(reply) => {
+                        (reply.size as u64 + self.header_overhead()) * reply.count as u64
+                    }
+                    None => buf.len() as u64 + self.header_overhead(),
+                };
+                let round_trip = buf.len() as u64 + self.header_overhead() + reply;
+                let used = {
+                    let state = state.lock().await;
+                    state.traffic_sent.wire + state.traffic_received.wire
+                };
+                if used + round_trip > max {
+                    self.record_event(&state, Event::ByteBudgetReached(used))
+                        .await;
+                    state.lock().await.stop(self.start.elapsed());
+                    break;
+                }
+            }
+
+            let start = Instant::now() - self.start;
+            let sent = async {
+                Ok::<_, std::io::Error>(match transport {
+                    Transport::Udp(sockets) => {
+                        let socket = &sockets[counter % sockets.len()];
+                        match socket.send_to(&buf, addr).await {
+                            // A queued ICMP error of an earlier probe fails the next
+                            // send once, the receiver picks it up from the queue
+                            Err(_) => socket.send_to(&buf, addr).await?,
+                            sent => sent?,
+                        }
+                    }
+                    Transport::Tcp { writer, .. } => {
+                        writer.lock().await.write_all(&buf).await?;
+                        buf.len()
+                    }
+                    Transport::Quic { writer, .. } => {
+                        writer.lock().await.write_all(&buf).await?;
+                        buf.len()
+                    }
+                    Transport::WebSocket { writer, .. } => {
+                        writer
+                            .lock()
+                            .await
+                            .send(Message::Binary(buf.clone()))
+                            .await
+                            .map_err(websocket::io_error)?;
+                        buf.len()
+                    }
+                    // The ICMP header takes the place of the UDP header in the overhead
+                    Transport::Icmp(socket) => {
+                        socket.send(counter as u16, &buf).await? - ICMP_HEADER
+                    }
+                })
+            }
+            .instrument(trace_span!("send"))
+            .await?;
+            {
+                // Both under one lock, the echo may already be racing back
+                let mut state = state.lock().instrument(trace_span!("lock_wait")).await;
+                let n = state.packets.len();
+                if self.warmup.is_some_and(|w| w.covers(n, start)) {
+                    state.push_packet(PacketStatus::Warmup(start));
+                    state.warmup_packets += 1;
+                    warmup += 1;
+                } else {
+                    state.push_packet(PacketStatus::Sent(start));
+                    state.record_loss_timeout();
+                    state.packet_loss += 1;
+                }
+                state.traffic_sent.add(sent as u64, self.header_overhead());
+            }
+            if memory_checked.elapsed() >= MEMORY_CHECK_INTERVAL {
+                memory_checked = Instant::now();
+                self.check_memory(&state).await?;
+            }
+
+            counter += 1;
+
+            if is_behind && self.catch_up == CatchUp::Skip {
+                self.record_skipped_slots(&state, slot, late, period)
+                    .await?;
+                counter = state.lock().await.packets.len();
+            }
+
+            if burst.is_none() {
+                self.check_loss_trigger(&state).await;
+            }
+            self.report_lost(&state).await?;
+
+            {
+                let state = state.lock().await;
+                self.notify.send(Action::LatencyPacketsSent(
+                    self.target,
+                    state.sent_packets(),
+                ))?;
+                self.notify.send(Action::LatencyTraffic(
+                    self.target,
+                    state.traffic_sent.wire,
+                    state.traffic_received.wire,
+                ))?;
+                self.notify
+                    .send(Action::LatencySendRate(self.target, state.send_rate()))?;
+            }
+
+            if self.quit.is_cancelled()
+                || (self.count > 0 && counter >= self.count as usize + warmup)
+            {
+                let mut state = state.lock().await;
+                if let Some((_, first)) = burst {
+                    let last = state.packets.len();
+                    state.bursts.push(first..last);
+                }
+                state.stop(self.start.elapsed());
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// With [`CatchUp::Skip`] the ticker jumps over the slots that passed while the
+    /// packet for `slot` was overdue. Those slots still consume a sequence number so
+    /// exports show where the gap was, but they are not counted as sent or lost.
+    async fn record_skipped_slots(
+        &self,
+        state: &Arc<Mutex<State>>,
+        slot: Instant,
+        late: Duration,
+        period: Duration,
+    ) -> Result<()> {
+        let missed = (late.as_nanos() / period.as_nanos()) as usize;
+        if missed == 0 {
+            return Ok(());
+        }
+
+        let (marked, skipped) = {
+            let mut state = state.lock().await;
+            let remaining = match self.count {
+                0 => usize::MAX,
+                count => (count as usize).saturating_sub(state.packets.len()),
+            };
+
+            // Slots past --count are never sent anyway, only the rest are recorded
+            let marked = missed.min(remaining);
+            let slot = slot - self.start;
+            for i in 1..=marked {
+                state.push_packet(PacketStatus::Skipped(slot + period * i as u32));
+            }
+            state.skipped_packets += marked as u32;
+            (marked, state.skipped_packets)
+        };
+        if marked == 0 {
+            return Ok(());
+        }
+
+        self.record_event(state, Event::SkippedSlots(marked as u32))
+            .await;
+        self.notify
+            .send(Action::LatencyPacketsSkipped(self.target, skipped))?;
+
+        Ok(())
+    }
+
+    async fn receive_packets(&self, socket: &UdpSocket, state: Arc<Mutex<State>>) -> Result<()> {
+        let mut buf = vec![0; self.max_echo_size()];
+        let mut icmp_errors = time::interval(ICMP_ERROR_CHECK_INTERVAL);
+
+        loop {
+            tokio::select! {
+                received = self.recv(socket, &mut buf) => {
+                    let stop = Instant::now() - self.start;
+                    let (size, truncated, kernel_received) = match received {
+                        Ok(received) => received,
+                        // Queued ICMP errors fail the receive when no echo is waiting
+                        Err(_) if self.record_icmp_errors(socket, &state).await? > 0 => continue,
+                        Err(e) => return Err(e.into()),
+                    };
+                    if truncated {
+                        let first = {
+                            let mut state = state.lock().await;
+                            state.truncated_packets += 1;
+                            state.truncated_packets == 1
+                        };
+                        if first {
+                            self.record_event(&state, Event::Truncated(buf.len())).await;
+                        }
+                    }
+
+                    if kernel_received.is_some() {
+                        // Picks up the probe's transmit timestamp, queued long before
+                        self.record_icmp_errors(socket, &state).await?;
+                    }
+                    self.record_echo(&buf[..size], stop, kernel_received, &state).await?;
+                }
+                // Sockets of a pool may never see another packet, so the ticker also
+                // re-checks the stop flag. A fresh sleep would never fire between ticks
+                _ = icmp_errors.tick() => {
+                    self.record_icmp_errors(socket, &state).await?;
+                    if state.lock().await.drained(self.start.elapsed()) {
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Receives an echo, with the kernel's receive time when it stamps them.
+    async fn recv(
+        &self,
+        socket: &UdpSocket,
+        buf: &mut [u8],
+    ) -> std::io::Result<(usize, bool, Option<KernelTimestamp>)> {
+        if self.busy_poll.is_some() {
+            return busy_poll::spin(|| match self.timestamping {
+                Some(_) => timestamping::try_recv(socket, buf),
+                None => try_recv_with_truncation(socket, buf)
+                    .map(|(size, truncated)| (size, truncated, None)),
+            })
+            .await;
+        }
+        match self.timestamping {
+            Some(_) => timestamping::recv(socket, buf).await,
+            None => recv_with_truncation(socket, buf)
+                .await
+                .map(|(size, truncated)| (size, truncated, None)),
+        }
+    }
+
+    /// Reads the ICMP errors queued on `socket` and attributes them to the probes
+    /// that caused them. The first error of each kind and sender becomes an event.
+    /// Transmit timestamps queued with them are kept for the echoes. Returns the
+    /// number of errors read.
+    async fn record_icmp_errors(
+        &self,
+        socket: &UdpSocket,
+        state: &Arc<Mutex<State>>,
+    ) -> Result<usize> {
+        let (errors, sent) = match icmp_error::drain(socket) {
+            Ok(queue) => (queue.errors, queue.sent),
+            Err(e) => {
+                debug!("Could not read ICMP errors: {}", e);
+                return Ok(0);
+            }
+        };
+
+        if !sent.is_empty() {
+            let mut state = state.lock().await;
+            for (looped, timestamp) in sent {
+                let Some(n) = timestamping::sent_probe(&looped) else {
+                    continue;
+                };
+                if matches!(state.packets.get(n as usize), Some(PacketStatus::Sent(_))) {
+                    state.kernel_sent.insert(n as usize, timestamp);
+                }
+            }
+        }
+
+        for (probe, error) in errors.iter() {
+            let Ok(header) = Header::decode(probe) else {
+                debug!("ICMP error without the probe that caused it: {}", error);
+                continue;
+            };
+            let n = header.sequence as usize;
+
+            let first = {
+                let mut state = state.lock().await;
+                if !matches!(state.packets.get(n), Some(PacketStatus::Sent(_))) {
+                    continue;
+                }
+                let first = !state.icmp_errors.values().any(|e| e == error)
+                    && !state.retired.icmp_errors.contains_key(error);
+                state.icmp_errors.insert(n, *error);
+                first
+            };
+            if first {
+                self.record_event(state, Event::IcmpError(n, *error)).await;
+            }
+        }
+
+        Ok(errors.len())
+    }
+
+    /// Samples TCP_INFO of the connection of [`Protocol::Tcp`], the kernel's
+    /// view of the round trip to compare with the one the probes measure.
+    async fn sample_tcp_info(
+        &self,
+        writer: &Mutex<OwnedWriteHalf>,
+        state: Arc<Mutex<State>>,
+    ) -> Result<()> {
+        let mut sampled = Duration::ZERO;
+
+        loop {
+            tokio::time::sleep(DRAIN_CHECK_INTERVAL).await;
+            let now = self.start.elapsed();
+            if state.lock().await.drained(now) {
+                break;
+            }
+            if now - sampled < TCP_INFO_INTERVAL {
+                continue;
+            }
+            sampled = now;
+
+            let info = match tcp::tcp_info(writer.lock().await.as_ref()) {
+                Ok(info) => info,
+                Err(e) => {
+                    debug!("No TCP_INFO of the probe connection: {}", e);
+                    return Ok(());
+                }
+            };
+            state.lock().await.tcp_info.push(TcpInfoSample {
+                at: now,
+                rtt: info.rtt,
+                rttvar: info.rttvar,
+                retransmits: info.total_retransmits,
+                delivery_rate: info.delivery_rate * 8,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Reads the echoes of [`Protocol::Tcp`] and [`Protocol::Quic`], which come
+    /// back in order and with the size they were sent with.
+    async fn receive_stream(
+        &self,
+        reader: &Mutex<impl AsyncRead + Unpin>,
+        state: Arc<Mutex<State>>,
+    ) -> Result<()> {
+        let mut reader = reader.lock().await;
+        let mut buf = vec![0; self.probe_size()];
+        let mut filled = 0;
+
+        loop {
+            tokio::select! {
+                // Unlike read_exact, read can be cancelled without losing data
+                received = reader.read(&mut buf[filled..]) => {
+                    match received? {
+                        0 => break,
+                        n => filled += n,
+                    }
+                    if filled < buf.len() {
+                        continue;
+                    }
+                    filled = 0;
+
+                    let stop = Instant::now() - self.start;
+                    self.record_echo(&buf, stop, None, &state).await?;
+                }
+                _ = tokio::time::sleep(DRAIN_CHECK_INTERVAL) => {
+                    if state.lock().await.drained(self.start.elapsed()) {
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the echoes of [`Protocol::WebSocket`], one binary message each.
+    async fn receive_messages(
+        &self,
+        reader: &Mutex<SplitStream<WebSocketStream<TcpStream>>>,
+        state: Arc<Mutex<State>>,
+    ) -> Result<()> {
+        let mut reader = reader.lock().await;
+
+        loop {
+            tokio::select! {
+                message = reader.next() => {
+                    let stop = Instant::now() - self.start;
+                    match message.transpose().map_err(websocket::io_error)? {
+                        Some(Message::Binary(payload)) => {
+                            self.record_echo(&payload, stop, None, &state).await?;
+                        }
+                        Some(Message::Close(_)) | None => break,
+                        Some(_) => {}
+                    }
+                }
+                _ = tokio::time::sleep(DRAIN_CHECK_INTERVAL) => {
+                    if state.lock().await.drained(self.start.elapsed()) {
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn receive_icmp(&self, socket: &IcmpSocket, state: Arc<Mutex<State>>) -> Result<()> {
+        // Raw IPv4 sockets also receive the IP header, with options up to 60 bytes
+        let mut buf = vec![0; self.max_echo_size() + ICMP_HEADER + 60];
+
+        loop {
+            tokio::select! {
+                received = socket.recv(&mut buf) => {
+                    let stop = Instant::now() - self.start;
+                    if let Some(payload) = received? {
+                        self.record_echo(payload, stop, None, &state).await?;
+                    }
+                }
+                _ = tokio::time::sleep(DRAIN_CHECK_INTERVAL) => {
+                    if state.lock().await.drained(self.start.elapsed()) {
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Matches an echo to its probe by the counter in its first bytes.
+    #[tracing::instrument(name = "receive", level = "trace", skip_all)]
+    async fn record_echo(
+        &self,
+        echo: &[u8],
+        stop: Duration,
+        kernel_received: Option<KernelTimestamp>,
+        state: &Arc<Mutex<State>>,
+    ) -> Result<()> {
+        let header = match Header::decode(echo) {
+            Ok(header) => header,
+            // Traffic of something else that found the socket
+            Err(DecodeError::Foreign) => return Ok(()),
+            Err(e) => bail!("Server {}: {}", self.destination(), e),
+        };
+        let n = header.sequence;
+        let mut state = state.lock().instrument(trace_span!("lock_wait")).await;
+        state
+            .traffic_received
+            .add(echo.len() as u64, self.header_overhead());
+
+        let start = match state.packets.get(n as usize) {
+            Some(&PacketStatus::Sent(start)) => start,
+            // Later datagrams of a reply only count as traffic
+            Some(PacketStatus::Received { .. }) if self.reply.is_some() => return Ok(()),
+            // Probes outstanding during a suspend were already written off
+            Some(PacketStatus::Invalid(_)) => return Ok(()),
+            // Warm-up echoes only prime the path
+            Some(PacketStatus::Warmup(_)) => return Ok(()),
+            // Replies to another ping of this host can end up on a raw ICMP socket
+            None => return Ok(()),
+            // The network duplicated the echo, or it arrived late for a slot
+            // that was skipped
+            Some(PacketStatus::Received { .. } | PacketStatus::Skipped(_)) => {
+                state.duplicate_packets += 1;
+                return Ok(());
+            }
+        };
+
+        // Kernel timestamps leave out the time until the scheduler ran the client
+        let kernel_sent = state.kernel_sent.remove(&(n as usize));
+        let kernel_latency = kernel_received
+            .zip(kernel_sent)
+            .and_then(|(received, sent)| received.since(&sent));
+        let latency = match kernel_latency {
+            Some(latency) => {
+                state.kernel_timestamped += 1;
+                latency
+            }
+            None => stop - start,
+        };
+        let stop = start + latency;
+        let corrupted = self.is_corrupted(&header, echo);
+
+        state.packets[n as usize] = PacketStatus::Received {
+            start,
+            stop,
+            latency,
+        };
+        let received = Some(header.received).filter(|&at| header.has(FLAG_TIMESTAMP) && at != 0);
+        if let (Some(received), Some(started_at)) = (received, state.started_at) {
+            // The server's receive time on the client's clock
+            let received = received - state.clock_offset.map_or(0, |o| o.offset);
+            let epoch = clock::nanos_since_epoch(started_at);
+            state.one_way_delays.insert(
+                n as usize,
+                OneWayDelay {
+                    upstream: received - (epoch + start.as_nanos() as i64),
+                    downstream: epoch + stop.as_nanos() as i64 - received,
+                },
+            );
+        }
+
+        if let Some(tag) = protocol::read_tag(&header, echo) {
+            state.tags.insert(n as usize, tag.to_vec());
+        }
+        match state.rtt {
+            Some(ref mut rtt) => rtt.update(latency),
+            None => state.rtt = Some(RttEstimate::new(latency)),
+        }
+        if corrupted {
+            state.corrupted.insert(n as usize);
+            state.corrupted_packets += 1;
+            if state.corrupted_packets == 1 {
+                // Under the lock already, unlike `record_event`
+                let at = Instant::now() - self.start;
+                let event = Event::Corrupted(n as usize);
+                state.events.push((at, event.clone()));
+                let _ = self
+                    .notify
+                    .send(Action::LatencyEvent(self.target, at, event));
+            }
+        }
+
+        update_statistics(&mut state, latency);
+        self.check_latency_trigger(&mut state, latency);
+        self.notify
+            .send(Action::LatencySample(self.target, stop, latency))?;
+        self.notify.send(Action::LatencyPacketsReceived(
+            self.target,
+            state.received_packets,
+            state.min_latency,
+            state.average_latency,
+            state.max_latency,
+        ))?;
+
+        if stop.saturating_sub(state.percentiles_updated) >= PERCENTILE_UPDATE_INTERVAL {
+            state.percentiles_updated = stop;
+            if let Some(percentiles) = state.percentiles() {
+                self.notify
+                    .send(Action::LatencyPercentiles(self.target, percentiles))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the server's clock a few times before the run so one-way delays can
+    /// be corrected for the offset between the clocks.
+    async fn estimate_clock_offset(&self, bind_address: IpAddr) {
+        let offset = async {
+            let socket = UdpSocket::bind(SocketAddr::new(bind_address, 0)).await?;
+            socket
+                .connect(SocketAddr::new(self.server_address, self.server_port))
+                .await?;
+            clock::estimate_offset(&socket).await
+        };
+        match offset.await {
+            Ok(Some(offset)) => self.state.lock().await.clock_offset = Some(offset),
+            Ok(None) => {
+                warn!("The server did not answer clock requests, one-way delays are uncorrected")
+            }
+            Err(e) => warn!(
+                "Clock offset estimation failed, one-way delays are uncorrected: {}",
+                e
+            ),
+        }
+    }
+
+    /// Records the initial route and every change to it. Never completes, so it
+    /// is dropped together with the run.
+    async fn track_route(&self, state: Arc<Mutex<State>>) {
+        let Some(period) = self.route_check_interval else {
+            return std::future::pending().await;
+        };
+
+        let mut current: Option<Route> = None;
+        let mut interval = time::interval(period);
+        let mut watch = match RouteWatch::new() {
+            Ok(watch) => Some(watch),
+            Err(e) => {
+                debug!("Polling the route only: {}", e);
+                None
+            }
+        };
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                changed = async { watch.as_ref().unwrap().changed().await }, if watch.is_some() => {
+                    if let Err(e) = changed {
+                        debug!("Polling the route only: {}", e);
+                        watch = None;
+                    }
+                }
+            }
+
+            let route = match route::lookup(self.server_address) {
+                Ok(route) => route,
+                Err(e) => {
+                    warn!("Route tracking disabled: {}", e);
+                    return std::future::pending().await;
+                }
+            };
+
+            if current.as_ref() == Some(&route) {
+                continue;
+            }
+            let previous = current.replace(route.clone());
+            self.record_event(&state, Event::Route(route.clone())).await;
+
+            if previous.is_some_and(|p| p.tunnel != route.tunnel) {
+                self.record_event(&state, Event::TunnelChanged(route.tunnel))
+                    .await;
+                match self.tunnel_change {
+                    TunnelChange::Annotate => {}
+                    TunnelChange::Stop => {
+                        state.lock().await.stop(self.start.elapsed());
+                        self.quit.cancel();
+                    }
+                    TunnelChange::Segment => {
+                        let name = if route.tunnel { "tunnel" } else { "no tunnel" };
+                        state.lock().await.start_phase(name.to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Samples the server's clock on a socket of its own. Never completes, so it
+    /// is dropped together with the run.
+    async fn track_clock(&self, bind_address: IpAddr, state: Arc<Mutex<State>>) {
+        let Some(period) = self.clock_check_interval else {
+            return std::future::pending().await;
+        };
+
+        let socket = async {
+            let socket = UdpSocket::bind(SocketAddr::new(bind_address, 0)).await?;
+            socket
+                .connect(SocketAddr::new(self.server_address, self.server_port))
+                .await?;
+            Ok::<_, std::io::Error>(socket)
+        };
+        let socket = match socket.await {
+            Ok(socket) => socket,
+            Err(e) => {
+                warn!("Clock tracking disabled: {}", e);
+                return std::future::pending().await;
+            }
+        };
+
+        let mut interval = time::interval(period);
+        loop {
+            interval.tick().await;
+
+            match clock::sample(&socket, self.start.elapsed()).await {
+                Ok(Some(mut sample)) => {
+                    let mut state = state.lock().await;
+                    state.clock_samples.push(sample);
+                    sample.drift = state.clock_drift().map(|d| d.ppm);
+                    *state.clock_samples.last_mut().unwrap() = sample;
+                }
+                Ok(None) => debug!("No answer to a clock request"),
+                Err(e) => debug!("Clock request failed: {}", e),
+            }
+        }
+    }
+
+    /// Starts the phases marked from the TUI or the phase marker file. Never
+    /// completes, so it is dropped together with the run.
+    async fn track_phases(
+        &self,
+        marks: Option<broadcast::Receiver<String>>,
+        state: Arc<Mutex<State>>,
+    ) {
+        let Some(mut marks) = marks else {
+            return std::future::pending().await;
+        };
+
+        loop {
+            let name = match marks.recv().await {
+                Ok(name) => name,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return std::future::pending().await,
+            };
+            state.lock().await.start_phase(name.clone());
+            self.record_event(&state, Event::Phase(name)).await;
+        }
+    }
+
+    /// Stops the run once the [`StopCondition`] is met. Never completes, so it is
+    /// dropped together with the run.
+    async fn watch_convergence(&self, state: Arc<Mutex<State>>) {
+        let Some(condition) = self.stop_condition else {
+            return std::future::pending().await;
+        };
+
+        let mut estimates: VecDeque<(Instant, Duration)> = VecDeque::new();
+        let mut interval = time::interval(CONVERGENCE_CHECK_INTERVAL);
+
+        loop {
+            let now = interval.tick().await;
+
+            let estimate = {
+                let state = state.lock().await;
+                if (state.received_packets as usize) < condition.min_samples() {
+                    continue;
+                }
+                state.percentile(condition.percentile)
+            };
+            let Some(estimate) = estimate else { continue };
+
+            estimates.push_back((now, estimate));
+            while estimates
+                .front()
+                .is_some_and(|&(at, _)| now - at > condition.window)
+            {
+                estimates.pop_front();
+            }
+
+            // The estimates have to cover the whole window
+            let covered = estimates
+                .front()
+                .is_some_and(|&(at, _)| now - at + CONVERGENCE_CHECK_INTERVAL > condition.window);
+            let (Some(min), Some(max)) = (
+                estimates.iter().map(|&(_, e)| e).min(),
+                estimates.iter().map(|&(_, e)| e).max(),
+            ) else {
+                continue;
+            };
+            let spread = (max - min).as_secs_f64() / estimate.as_secs_f64().max(f64::EPSILON);
+
+            if covered && spread <= condition.tolerance {
+                self.record_event(&state, Event::Converged(estimate)).await;
+                state.lock().await.stop(self.start.elapsed());
+                self.quit.cancel();
+                let _ = self.notify.send(Action::LatencyConverged(self.target));
+                return std::future::pending().await;
+            }
+        }
+    }
+
+    /// Compares the monotonic clock, which stops while the system is suspended,
+    /// with a clock that keeps running. When they diverge the system slept, and
+    /// probes that were outstanding across the suspend are marked invalid instead
+    /// of being counted as lost.
+    async fn detect_suspend(&self, state: Arc<Mutex<State>>) {
+        if self.suspend_threshold.is_zero() {
+            return std::future::pending().await;
+        }
+
+        let mut interval = time::interval(SUSPEND_CHECK_INTERVAL);
+        let mut previous = (Instant::now(), wall_clock());
+
+        loop {
+            interval.tick().await;
+
+            let now = (Instant::now(), wall_clock());
+            let monotonic = now.0 - previous.0;
+            let wall = now.1.saturating_sub(previous.1);
+
+            if wall > monotonic + self.suspend_threshold {
+                let suspended = wall - monotonic;
+                // The monotonic clock stood still while suspended, so the suspend
+                // lies between the previous check and this one
+                let window = previous.0 - self.start..now.0 - self.start;
+
+                let invalidated = {
+                    let mut state = state.lock().await;
+                    state.invalidate(window)
+                };
+
+                self.record_event(
+                    &state,
+                    Event::Suspended {
+                        duration: suspended,
+                        invalidated,
+                    },
+                )
+                .await;
+            }
+
+            previous = now;
+        }
+    }
+
+    /// Polls whether the connection is metered. Never completes, so it is dropped
+    /// together with the run.
+    async fn watch_metered(&self, state: Arc<Mutex<State>>) {
+        let Some(policy) = self.metered else {
+            return std::future::pending().await;
+        };
+
+        let mut interval = time::interval(metered::CHECK_INTERVAL);
+        let mut current = false;
+        let mut known = false;
+        loop {
+            interval.tick().await;
+
+            let Some(metered) = metered::check().await else {
+                if !known {
+                    warn!("Metered connections are not detected, NetworkManager cannot be asked");
+                    return std::future::pending().await;
+                }
+                continue;
+            };
+            known = true;
+            if metered == current {
+                continue;
+            }
+            current = metered;
+
+            let paused = metered && policy == MeteredPolicy::Pause;
+            self.paused.store(paused, Ordering::Relaxed);
+            self.record_event(&state, Event::Metered { metered, paused })
+                .await;
+        }
+    }
+
+    /// The slot of the next probe, from the replayed schedule by its sequence
+    /// so a rebind resumes where it left off, or else from the interval.
+    async fn next_slot(&self, interval: &mut Interval, state: &Mutex<State>) -> Instant {
+        let Some(ref schedule) = self.send_schedule else {
+            return interval.tick().await;
+        };
+        let sequence = state.lock().await.packets.len();
+        let Some(&offset) = schedule.get(sequence) else {
+            return std::future::pending().await;
+        };
+        let slot = self.start + offset;
+        time::sleep_until(slot).await;
+        slot
+    }
+
+    /// Follows the quiet hours as local time passes through them. Never
+    /// completes, so it is dropped together with the run.
+    async fn watch_quiet_hours(&self, state: Arc<Mutex<State>>) {
+        if self.quiet_hours.is_empty() {
+            return std::future::pending().await;
+        }
+
+        let mut interval = time::interval(quiet::CHECK_INTERVAL);
+        let mut current = None;
+        loop {
+            interval.tick().await;
+
+            let active = quiet::active(&self.quiet_hours);
+            if active == current {
+                continue;
+            }
+            let paused = self.quiet_policy == QuietPolicy::Pause;
+            if let Some(window) = current {
+                self.quiet.store(false, Ordering::Relaxed);
+                self.record_event(
+                    &state,
+                    Event::QuietHours {
+                        window,
+                        active: false,
+                        paused,
+                    },
+                )
+                .await;
+            }
+            if let Some(window) = active {
+                self.quiet.store(paused, Ordering::Relaxed);
+                self.record_event(
+                    &state,
+                    Event::QuietHours {
+                        window,
+                        active: true,
+                        paused,
+                    },
+                )
+                .await;
+            }
+            current = active;
+        }
+    }
+
+    /// Tells a target that stopped answering from one that loses probes: the
+    /// target is down once the oldest probe sent since the last reply is
+    /// `down_after` old. Pauses send nothing, so they do not count. Never
+    /// completes, so it is dropped together with the run.
+    async fn watch_down(&self, state: Arc<Mutex<State>>) {
+        let Some(after) = self.down_after else {
+            return std::future::pending().await;
+        };
+
+        let mut interval = time::interval((after / 4).min(DOWN_CHECK_INTERVAL));
+        let mut received = 0;
+        let mut sent = 0;
+        // When the first probe after the last reply was noticed
+        let mut unanswered: Option<Instant> = None;
+        let mut down: Option<Instant> = None;
+        loop {
+            let now = interval.tick().await;
+
+            let (now_received, now_sent) = {
+                let state = state.lock().await;
+                (
+                    state.received_packets,
+                    state.packets.len() - state.skipped_packets as usize,
+                )
+            };
+            if now_received != received {
+                received = now_received;
+                sent = now_sent;
+                unanswered = None;
+                if let Some(since) = down.take() {
+                    state.lock().await.down_since = None;
+                    self.record_event(&state, Event::Up(now - since)).await;
+                }
+                continue;
+            }
+            if now_sent == sent || down.is_some() {
+                continue;
+            }
+            let first = *unanswered.get_or_insert(now);
+            if now - first < after {
+                continue;
+            }
+
+            let stop = self.on_down == DownPolicy::Stop;
+            down = Some(first);
+            state.lock().await.down_since = Some(first - self.start);
+            self.record_event(
+                &state,
+                Event::Down {
+                    silence: now - first,
+                    stop,
+                },
+            )
+            .await;
+            if stop {
+                state.lock().await.stop(self.start.elapsed());
+                self.quit.cancel();
+                return std::future::pending().await;
+            }
+        }
+    }
+
+    /// Writes the payload of probe `n`, which only depends on the payload kind,
+    /// the seed and `n` so echoes can be checked against it.
+    fn fill_payload(&self, n: usize, payload: &mut [u8]) {
+        if self.payload.is_compressible(n) {
+            payload.fill(0);
+        } else {
+            StdRng::seed_from_u64(self.seed.wrapping_add(n as u64)).fill(payload);
+        }
+    }
+
+    /// Whether the payload of a plain echo differs from what was sent. Replies
+    /// and truncated echoes are not checked, and neither are the parts of the
+    /// probe the server writes to.
+    fn is_corrupted(&self, header: &Header, echo: &[u8]) -> bool {
+        if self.reply.is_some() || echo.len() != self.probe_size() {
+            return false;
+        }
+        let end = match protocol::read_tag(header, echo) {
+            Some(tag) => echo.len() - protocol::tag_len(tag.len()),
+            None => echo.len(),
+        };
+        let mut expected = vec![0; echo.len() - HEADER_LEN];
+        self.fill_payload(header.sequence as usize, &mut expected);
+        echo[HEADER_LEN..end] != expected[..end - HEADER_LEN]
+    }
+
+    /// Size of the probes, grown from the configured one when the requests and
+    /// the tag they carry do not fit.
+    fn probe_size(&self) -> usize {
+        let mut requests = match self.reply {
+            Some(_) => REPLY_REQUEST_LEN,
+            None => HEADER_LEN,
+        };
+        if let Some(ref tag) = self.tag {
+            requests += protocol::tag_len(tag.len());
+        }
+        (self.packet_size as usize).max(requests)
+    }
+
+    /// Largest echo the server was asked for: the probe itself, or the reply of
+    /// `--profile game` when that is larger.
+    fn max_echo_size(&self) -> usize {
+        let size = self.probe_size();
+        self.reply.map_or(size, |reply| size.max(reply.size))
+    }
+
+    fn header_overhead(&self) -> u64 {
+        match (self.protocol, self.server_address) {
+            (Protocol::Udp | Protocol::UdpLite | Protocol::Icmp, IpAddr::V4(_)) => {
+                UDP_IPV4_OVERHEAD
+            }
+            (Protocol::Udp | Protocol::UdpLite | Protocol::Icmp, IpAddr::V6(_)) => {
+                UDP_IPV6_OVERHEAD
+            }
+            (Protocol::Tcp, IpAddr::V4(_)) => TCP_IPV4_OVERHEAD,
+            (Protocol::Tcp, IpAddr::V6(_)) => TCP_IPV6_OVERHEAD,
+            (Protocol::Quic, IpAddr::V4(_)) => UDP_IPV4_OVERHEAD + QUIC_OVERHEAD,
+            (Protocol::Quic, IpAddr::V6(_)) => UDP_IPV6_OVERHEAD + QUIC_OVERHEAD,
+            (Protocol::WebSocket, IpAddr::V4(_)) => TCP_IPV4_OVERHEAD + WEBSOCKET_OVERHEAD,
+            (Protocol::WebSocket, IpAddr::V6(_)) => TCP_IPV6_OVERHEAD + WEBSOCKET_OVERHEAD,
+        }
+    }
+
+    fn check_latency_trigger(&self, state: &mut State, latency: Duration) {
+        let Some(threshold) = self.burst_capture.and_then(|b| b.latency_threshold) else {
+            return;
+        };
+
+        if latency > threshold && !state.in_burst {
+            state.in_burst = true;
+            self.trigger_burst(state, Event::BurstStarted(BurstReason::Latency(latency)));
+        }
+    }
+
+    async fn check_loss_trigger(&self, state: &Arc<Mutex<State>>) {
+        let Some(threshold) = self.burst_capture.and_then(|b| b.loss_threshold) else {
+            return;
+        };
+
+        let mut state = state.lock().await;
+        let lost = state.unanswered_since(Instant::now() - self.start);
+        if lost >= threshold && !state.in_burst {
+            state.in_burst = true;
+            self.trigger_burst(&mut state, Event::BurstStarted(BurstReason::Loss(lost)));
+        }
+    }
+
+    /// Tells the TUI about the probes that passed the loss grace unanswered.
+    async fn report_lost(&self, state: &Arc<Mutex<State>>) -> Result<()> {
+        let now = Instant::now() - self.start;
+        for sent in state.lock().await.newly_unanswered(now) {
+            self.notify.send(Action::LatencyLost(self.target, sent))?;
+        }
+        Ok(())
+    }
+
+    /// Tells the TUI how much memory the state takes, and switches to keeping
+    /// only the most recent probes once it takes more than allowed.
+    async fn check_memory(&self, state: &Arc<Mutex<State>>) -> Result<()> {
+        let mut state = state.lock().await;
+        let capped = self
+            .max_memory
+            .and_then(|cap| state.enforce_memory_cap(cap));
+        if let Some(usage) = capped {
+            // Under the lock already, unlike `record_event`
+            let at = Instant::now() - self.start;
+            let event = Event::MemoryCapped {
+                usage,
+                retained: MEMORY_CAP_RETENTION,
+            };
+            state.events.push((at, event.clone()));
+            let _ = self
+                .notify
+                .send(Action::LatencyEvent(self.target, at, event));
+        }
+        self.notify
+            .send(Action::LatencyMemory(self.target, state.memory_usage()))?;
+        Ok(())
+    }
+
+    /// Wakes the sender to start a capture. Takes the already locked state since
+    /// both triggers hold the lock while deciding.
+    fn trigger_burst(&self, state: &mut State, event: Event) {
+        let at = Instant::now() - self.start;
+        state.events.push((at, event.clone()));
+        let _ = self
+            .notify
+            .send(Action::LatencyEvent(self.target, at, event));
+
+        self.burst_trigger.notify_one();
+    }
+
+    async fn record_event(&self, state: &Arc<Mutex<State>>, event: Event) {
+        let at = Instant::now() - self.start;
+        state.lock().await.events.push((at, event.clone()));
+        let _ = self
+            .notify
+            .send(Action::LatencyEvent(self.target, at, event));
+    }
+}
+
+/// Something that happened during a run which explains or qualifies the measurements
+/// around it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Event {
+    Route(Route),
+    /// The sender woke up this much later than its scheduled slot.
+    BehindSchedule(Duration),
+    SkippedSlots(u32),
+    /// The system was suspended, outstanding probes were invalidated.
+    Suspended {
+        duration: Duration,
+        invalidated: u32,
+    },
+    /// A high-resolution capture started.
+    BurstStarted(BurstReason),
+    /// The capture ended after sending this many probes.
+    BurstEnded(u32),
+    /// The run stopped after using this many bytes of the `--max-bytes` budget.
+    ByteBudgetReached(u64),
+    /// The route moved onto (`true`) or off a VPN or other tunnel.
+    TunnelChanged(bool),
+    /// A phase with this name started.
+    Phase(String),
+    /// The connection became metered, or unmetered again, and the probes were
+    /// paused or resumed accordingly.
+    Metered {
+        metered: bool,
+        paused: bool,
+    },
+    /// Quiet hours began or ended, and the probes were paused or resumed if
+    /// the policy pauses them.
+    QuietHours {
+        window: QuietWindow,
+        active: bool,
+        paused: bool,
+    },
+    /// The `--stop-when` percentile settled at this latency.
+    Converged(Duration),
+    /// No reply arrived for `silence` while probes were sent, the run stops if
+    /// `stop` is set.
+    Down {
+        silence: Duration,
+        stop: bool,
+    },
+    /// Replies are back after the target was down this long.
+    Up(Duration),
+    /// The sockets were reopened after the network was gone for `downtime`,
+    /// probes continue the same sequence.
+    Rebind {
+        downtime: Duration,
+        reason: String,
+    },
+    /// The first probe rejected with this ICMP error, later ones with the same
+    /// error only count towards the summary.
+    IcmpError(usize, IcmpError),
+    /// The first echo larger than the receive buffer of this many bytes, later
+    /// ones only count towards the summary.
+    Truncated(usize),
+    /// The first probe whose echo came back with a different payload, later
+    /// ones only count towards the summary.
+    Corrupted(usize),
+    /// The state took `usage` bytes, more than `--max-memory`, from now on only
+    /// the last `retained` probes are kept.
+    MemoryCapped {
+        usage: usize,
+        retained: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BurstReason {
+    Latency(Duration),
+    /// Number of consecutive unanswered probes
+    Loss(u32),
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Route(route) => write!(f, "route {}", route),
+            Event::BehindSchedule(late) => write!(f, "sender fell behind schedule by {:.1?}", late),
+            Event::SkippedSlots(n) => write!(f, "skipped {} send slot(s)", n),
+            Event::Suspended {
+                duration,
+                invalidated,
+            } => write!(
+                f,
+                "system suspended for {:.1?}, {} probe(s) invalidated",
+                duration, invalidated
+            ),
+            Event::BurstStarted(BurstReason::Latency(latency)) => write!(
+                f,
+                "high-resolution capture triggered by {:.1?} latency",
+                latency
+            ),
+            Event::BurstStarted(BurstReason::Loss(lost)) => write!(
+                f,
+                "high-resolution capture triggered by {} lost probe(s)",
+                lost
+            ),
+            Event::ByteBudgetReached(bytes) => {
+                write!(f, "byte budget reached after {} bytes", bytes)
+            }
+            Event::TunnelChanged(tunnel) => write!(
+                f,
+                "route {} a tunnel, results before and after are not comparable",
+                if *tunnel { "moved onto" } else { "left" }
+            ),
+            Event::Phase(name) => write!(f, "phase \"{}\" started", name),
+            Event::Metered {
+                metered: true,
+                paused,
+            } => write!(
+                f,
+                "connection is metered{}",
+                if *paused { ", probes paused" } else { "" }
+            ),
+            Event::Metered { metered: false, .. } => {
+                write!(f, "connection is no longer metered, probing normally")
+            }
+            Event::QuietHours {
+                window,
+                active: true,
+                paused,
+            } => write!(
+                f,
+                "quiet hours {} began{}",
+                window,
+                if *paused { ", probes paused" } else { "" }
+            ),
+            Event::QuietHours {
+                window,
+                active: false,
+                paused,
+            } => write!(
+                f,
+                "quiet hours {} ended{}",
+                window,
+                if *paused { ", probing normally" } else { "" }
+            ),
+            Event::Converged(latency) => {
+                write!(f, "percentile settled at {:.1?}, stopping", latency)
+            }
+            Event::Down { silence, stop } => write!(
+                f,
+                "target down, no reply for {:.1?}{}",
+                silence,
+                if *stop { ", stopping" } else { "" }
+            ),
+            Event::Up(downtime) => write!(f, "target up again after {:.1?} down", downtime),
+            Event::Rebind { downtime, reason } => write!(
+                f,
+                "rebound after the network was gone for {:.1?} ({}), sequence continues",
+                downtime, reason
+            ),
+            Event::IcmpError(probe, error) => write!(f, "probe {} rejected: {}", probe, error),
+            Event::Truncated(buffer) => write!(
+                f,
+                "echo larger than the {} byte receive buffer was truncated",
+                buffer
+            ),
+            Event::Corrupted(probe) => {
+                write!(f, "echo of probe {} came back corrupted", probe)
+            }
+            Event::MemoryCapped { usage, retained } => write!(
+                f,
+                "memory cap reached at {}, keeping only the last {} probes",
+                format_bytes(*usage as u64),
+                retained
+            ),
+            Event::BurstEnded(packets) => {
+                write!(
+                    f,
+                    "high-resolution capture ended after {} probe(s)",
+                    packets
+                )
+            }
+        }
+    }
+}
+
+/// What to do with send slots that were missed because the sender fell behind,
+/// e.g. after a system suspend or under heavy load.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub(crate) enum CatchUp {
+    /// Send the missed packets back-to-back
+    Burst,
+    /// Drop the missed slots and record them as skipped
+    #[default]
+    Skip,
+    /// Shift the remaining schedule back
+    Delay,
+}
+
+impl From<CatchUp> for MissedTickBehavior {
+    fn from(catch_up: CatchUp) -> Self {
+        match catch_up {
+            CatchUp::Burst => MissedTickBehavior::Burst,
+            CatchUp::Skip => MissedTickBehavior::Skip,
+            CatchUp::Delay => MissedTickBehavior::Delay,
+        }
+    }
+}
+
+/// Transport the latency probes are sent over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub(crate) enum Protocol {
+    #[default]
+    Udp,
+    /// A single connection, retransmissions show up as latency instead of loss
+    Tcp,
+    /// Echo requests to any host, no bwlat server needed
+    Icmp,
+    /// UDP-Lite, the checksum can cover only part of the probes so damaged ones
+    /// arrive instead of being dropped (experimental, Linux, server --udplite)
+    #[value(name = "udplite")]
+    UdpLite,
+    /// A stream of a QUIC connection, as HTTP/3 traffic sees the path, with the
+    /// handshake time reported (server --quic-port)
+    Quic,
+    /// Messages of a WebSocket on the server's TCP port, for networks that only
+    /// let HTTP through, optionally via an HTTP proxy (--proxy)
+    #[value(name = "websocket")]
+    WebSocket,
+}
+
+/// Where probes leave from and echoes arrive on.
+enum Transport {
+    Udp(Vec<UdpSocket>),
+    Tcp {
+        reader: Mutex<OwnedReadHalf>,
+        writer: Mutex<OwnedWriteHalf>,
+    },
+    Icmp(IcmpSocket),
+    Quic {
+        /// Drives the connection, which ends with it
+        _endpoint: quinn::Endpoint,
+        reader: Mutex<quinn::RecvStream>,
+        writer: Mutex<quinn::SendStream>,
+    },
+    WebSocket {
+        reader: Mutex<SplitStream<WebSocketStream<TcpStream>>>,
+        writer: Mutex<SplitSink<WebSocketStream<TcpStream>, Message>>,
+    },
+}
+
+/// What to do when the route to the target moves onto or off a VPN or tunnel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub(crate) enum TunnelChange {
+    /// Record the change as an event
+    #[default]
+    Annotate,
+    /// Stop probing
+    Stop,
+    /// Report the results before and after the change separately
+    Segment,
+}
+
+/// What to do when the target stops answering, see [`Latency::with_down_detection`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub(crate) enum DownPolicy {
+    /// Record it as an event and keep probing until the target is back
+    #[default]
+    Continue,
+    /// Stop probing
+    Stop,
+}
+
+/// Contents of the probes after the sequence number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub(crate) enum Payload {
+    /// All zeros, trivially compressible
+    #[default]
+    Zeros,
+    /// Random bytes that do not compress
+    Random,
+    /// Zeros and random bytes in alternating order within each pair of probes,
+    /// or of report intervals in bandwidth tests, to detect compression or
+    /// content-based shaping on the path
+    Alternate,
+}
+
+/// Traffic patterns of applications that `--profile` probes like.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum StreamProfile {
+    /// RTP with G.711 at 64 kbit/s in 20ms frames
+    #[value(name = "rtp-g711")]
+    RtpG711,
+    /// RTP with G.729A at 8 kbit/s in 20ms frames
+    #[value(name = "rtp-g729")]
+    RtpG729,
+    /// Game netcode at 64 Hz, small inputs upstream and bursts of snapshot
+    /// datagrams downstream
+    Game,
+}
+
+impl StreamProfile {
+    pub(crate) fn interval(&self) -> Duration {
+        match self {
+            StreamProfile::RtpG711 | StreamProfile::RtpG729 => Duration::from_millis(20),
+            StreamProfile::Game => Duration::from_secs(1) / GAME_TICK_RATE,
+        }
+    }
+
+    /// UDP payload of the upstream packets.
+    pub(crate) fn packet_size(&self) -> usize {
+        match self {
+            StreamProfile::RtpG711 => RTP_HEADER + 160,
+            StreamProfile::RtpG729 => RTP_HEADER + 20,
+            StreamProfile::Game => GAME_INPUT_SIZE,
+        }
+    }
+
+    /// Downstream traffic when it differs from the upstream.
+    pub(crate) fn reply(&self) -> Option<ReplyShape> {
+        match self {
+            StreamProfile::RtpG711 | StreamProfile::RtpG729 => None,
+            StreamProfile::Game => Some(GAME_SNAPSHOT),
+        }
+    }
+
+    /// Marking of the upstream packets.
+    pub(crate) fn dscp(&self) -> Option<u8> {
+        match self {
+            StreamProfile::RtpG711 | StreamProfile::RtpG729 => Some(DSCP_EF),
+            StreamProfile::Game => None,
+        }
+    }
+}
+
+impl Payload {
+    pub(crate) fn is_compressible(&self, packet: usize) -> bool {
+        match self {
+            Payload::Zeros => true,
+            Payload::Random => false,
+            // Swap the order every pair so neither payload always goes first
+            Payload::Alternate => matches!(packet % 4, 0 | 3),
+        }
+    }
+}
+
+/// Latency percentiles of the received probes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Percentiles {
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub p999: Duration,
+}
+
+/// How fast probes actually left compared to the configured interval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SendRate {
+    pub interval: Duration,
+    /// Probes sent and the time between the first and the last of them
+    pub packets: u32,
+    pub span: Duration,
+    pub wire_bytes: u64,
+}
+
+impl SendRate {
+    pub(crate) fn expected_packets_per_second(&self) -> f64 {
+        1.0 / self.interval.as_secs_f64()
+    }
+
+    pub(crate) fn packets_per_second(&self) -> Option<f64> {
+        if self.packets < 2 || self.span.is_zero() {
+            return None;
+        }
+        Some((self.packets - 1) as f64 / self.span.as_secs_f64())
+    }
+
+    /// Bitrate put on the wire, headers included.
+    pub(crate) fn bits_per_second(&self) -> Option<f64> {
+        Some(self.packets_per_second()? * self.wire_bytes as f64 * 8.0 / self.packets as f64)
+    }
+
+    /// Whether the sender kept up with the configured interval.
+    pub(crate) fn on_schedule(&self) -> bool {
+        self.packets_per_second()
+            .is_none_or(|pps| pps >= self.expected_packets_per_second() * ON_SCHEDULE)
+    }
+}
+
+pub(crate) enum PacketStatus {
+    /// Send slot that was dropped by the [`CatchUp::Skip`] policy.
+    Skipped(Duration),
+    Sent(Duration),
+    /// Probe that was outstanding while the system was suspended.
+    Invalid(Duration),
+    /// Probe of the warm-up, whether it was answered is not recorded.
+    Warmup(Duration),
+    Received {
+        start: Duration,
+        stop: Duration,
+        latency: Duration,
+    },
+}
+
+impl PacketStatus {
+    /// When the probe was, or for skipped slots would have been, sent.
+    pub(crate) fn sent_at(&self) -> Duration {
+        match *self {
+            PacketStatus::Skipped(s)
+            | PacketStatus::Sent(s)
+            | PacketStatus::Invalid(s)
+            | PacketStatus::Warmup(s) => s,
+            PacketStatus::Received { start, .. } => start,
+        }
+    }
+}
+
+/// TCP_INFO of the probe connection at one point of a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TcpInfoSample {
+    /// Relative to the start
+    pub at: Duration,
+    /// Smoothed round trip the kernel measured from the ACKs
+    pub rtt: Duration,
+    pub rttvar: Duration,
+    /// Retransmitted segments since the connection opened
+    pub retransmits: u32,
+    /// Bits per second
+    pub delivery_rate: u64,
+}
+
+/// The kernel's view of the probe connection over a whole run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct KernelRtt {
+    pub rtt: Duration,
+    pub rttvar: Duration,
+    pub retransmits: u32,
+    /// Bits per second
+    pub delivery_rate: u64,
+}
+
+/// Bytes that crossed the link in one direction.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Traffic {
+    pub payload: u64,
+    /// Payload plus estimated UDP/IP headers
+    pub wire: u64,
+}
+
+impl Traffic {
+    fn add(&mut self, payload: u64, overhead: u64) {
+        self.payload += payload;
+        self.wire += payload + overhead;
+    }
+}
+
+pub(crate) struct State {
+    pub packets: Packets,
+
+    pub received_packets: u32,
+    pub skipped_packets: u32,
+    pub invalid_packets: u32,
+    /// Probes of the warm-up, not counted as sent
+    pub warmup_packets: u32,
+    /// Echoes that did not fit the receive buffer, UDP only
+    pub truncated_packets: u32,
+    /// Echoes whose payload differs from the probe, they still count as received
+    pub corrupted_packets: u32,
+    /// Echoes of probes already answered or of skipped slots, not counted
+    pub duplicate_packets: u32,
+    pub packet_loss: u32,
+
+    pub min_latency: Duration,
+    pub max_latency: Duration,
+    pub average_latency: Duration,
+    /// Latencies of all received probes in nanoseconds
+    pub histogram: Histogram<u64>,
+    /// When the TUI last got percentiles, relative to the start
+    percentiles_updated: Duration,
+
+    pub events: Vec<(Duration, Event)>,
+    /// ICMP errors by the probe that caused them, UDP only
+    pub icmp_errors: BTreeMap<usize, IcmpError>,
+    /// Delays of each direction by probe, with one-way delay measurement only
+    pub one_way_delays: BTreeMap<usize, OneWayDelay>,
+    /// Tags echoed back with each probe, with probe tags only
+    pub tags: BTreeMap<usize, Vec<u8>>,
+    /// Probes whose echo came back with a different payload
+    pub corrupted: BTreeSet<usize>,
+    /// Loss timeout of each probe when sent, with an adaptive timeout only
+    pub loss_timeouts: BTreeMap<usize, Duration>,
+    /// Kernel transmit timestamps of the probes not answered yet
+    kernel_sent: BTreeMap<usize, KernelTimestamp>,
+    /// Received probes whose latency was taken from kernel timestamps
+    pub kernel_timestamped: u32,
+    rtt: Option<RttEstimate>,
+    /// Offset the one-way delays are corrected by, if it was estimated
+    pub clock_offset: Option<ClockOffset>,
+    /// How long the QUIC handshake took, of the last connection after a rebind
+    pub handshake: Option<Duration>,
+    /// TCP_INFO of the probe connection over time, with `--protocol tcp` only
+    pub tcp_info: Vec<TcpInfoSample>,
+    /// Since when the target is down, relative to the start, with down
+    /// detection only
+    pub down_since: Option<Duration>,
+
+    /// Configured time between probes
+    pub interval: Duration,
+    loss_timeout: LossTimeout,
+    pub traffic_sent: Traffic,
+    pub traffic_received: Traffic,
+
+    /// Local addresses of the socket pool, packet `n` was sent from
+    /// `sources[n % sources.len()]`.
+    pub sources: Vec<SocketAddr>,
+
+    /// Packet ranges sent during high-resolution captures
+    pub bursts: Vec<Range<usize>>,
+    /// Consecutive parts of the run that are reported separately, empty unless
+    /// phases were marked or the run was segmented by [`TunnelChange::Segment`]
+    pub phases: Vec<Phase>,
+    /// Readings of the server's clock, empty unless clock tracking is enabled
+    pub clock_samples: Vec<ClockSample>,
+    in_burst: bool,
+    /// Whether the memory cap was reached and older probes are no longer kept
+    pub memory_capped: bool,
+    /// Packets before this one were already checked by `newly_unanswered`
+    loss_reported: usize,
+    /// What the probes that are no longer retained add to the results
+    retired: Retired,
+
+    /// When sending ended, relative to the start
+    pub stopped_at: Option<Duration>,
+    /// When the engine started sending, the origin of all times in the state
+    start: Option<Instant>,
+    pub started_at: Option<std::time::SystemTime>,
+}
+
+impl State {
+    fn new(count: u32) -> Self {
+        Self {
+            packets: Packets::with_capacity(count as usize),
+            received_packets: 0,
+            skipped_packets: 0,
+            invalid_packets: 0,
+            warmup_packets: 0,
+            truncated_packets: 0,
+            corrupted_packets: 0,
+            duplicate_packets: 0,
+            packet_loss: 0,
+            min_latency: Duration::from_secs(0),
+            max_latency: Duration::from_secs(0),
+            average_latency: Duration::from_secs(0),
+            histogram: Histogram::new_with_bounds(
+                1,
+                HISTOGRAM_MAX.as_nanos() as u64,
+                HISTOGRAM_PRECISION,
+            )
+            .expect("valid histogram bounds"),
+            percentiles_updated: Duration::ZERO,
+            events: Vec::new(),
+            icmp_errors: BTreeMap::new(),
+            one_way_delays: BTreeMap::new(),
+            tags: BTreeMap::new(),
+            corrupted: BTreeSet::new(),
+            loss_timeouts: BTreeMap::new(),
+            kernel_sent: BTreeMap::new(),
+            kernel_timestamped: 0,
+            rtt: None,
+            clock_offset: None,
+            handshake: None,
+            tcp_info: Vec::new(),
+            down_since: None,
+            interval: Duration::ZERO,
+            loss_timeout: LossTimeout::default(),
+            traffic_sent: Traffic::default(),
+            traffic_received: Traffic::default(),
+            sources: Vec::new(),
+            bursts: Vec::new(),
+            phases: Vec::new(),
+            clock_samples: Vec::new(),
+            in_burst: false,
+            memory_capped: false,
+            loss_reported: 0,
+            retired: Retired::default(),
+            stopped_at: None,
+            start: None,
+            started_at: None,
+        }
+    }
+}
+
+/// Time since boot including time spent suspended.
+#[cfg(target_os = "linux")]
+fn wall_clock() -> Duration {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_BOOTTIME, &mut ts) };
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+/// Wall clock time, which keeps running while the system is suspended.
+#[cfg(not(target_os = "linux"))]
+fn wall_clock() -> Duration {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Phase {
+    pub name: String,
+    /// Index of the first packet sent in this phase
+    pub first: usize,
+}
+
+pub(crate) struct PhaseStatistics {
+    pub name: String,
+    pub packets: Range<usize>,
+    pub sent: u32,
+    pub received: u32,
+    pub min_latency: Duration,
+    pub average_latency: Duration,
+    pub max_latency: Duration,
+}
+
+impl PhaseStatistics {
+    pub(crate) fn loss(&self) -> f64 {
+        1.0 - self.received as f64 / self.sent.max(1) as f64
+    }
+}
+
+/// Running counts and latencies of a group of probes.
+#[derive(Debug, Default, Clone, Copy)]
+struct Tally {
+    sent: u32,
+    received: u32,
+    min_latency: Duration,
+    max_latency: Duration,
+    total_latency: Duration,
+}
+
+impl Tally {
+    fn add(&mut self, packet: &PacketStatus) {
+        match *packet {
+            PacketStatus::Skipped(_) | PacketStatus::Invalid(_) | PacketStatus::Warmup(_) => {}
+            PacketStatus::Sent(_) => self.sent += 1,
+            PacketStatus::Received { latency, .. } => {
+                if self.received == 0 || latency < self.min_latency {
+                    self.min_latency = latency;
+                }
+                self.max_latency = self.max_latency.max(latency);
+                self.sent += 1;
+                self.received += 1;
+                self.total_latency += latency;
+            }
+        }
+    }
+
+    fn average_latency(&self) -> Duration {
+        self.total_latency / self.received.max(1)
+    }
+}
+
+/// Probes that fell out of the retention, folded into what the results need
+/// from them.
+#[derive(Debug, Default)]
+struct Retired {
+    /// By index of the phase
+    phases: Vec<Tally>,
+    /// By index of the source in the socket pool
+    sources: Vec<Tally>,
+    icmp_errors: BTreeMap<IcmpError, u32>,
+    upstream: DelayTotals,
+    downstream: DelayTotals,
+    first_sent: Option<Duration>,
+}
+
+pub(crate) struct SourceStatistics {
+    pub source: SocketAddr,
+    pub sent: u32,
+    pub received: u32,
+    pub average_latency: Duration,
+}
+
+impl SourceStatistics {
+    pub(crate) fn loss(&self) -> f64 {
+        1.0 - self.received as f64 / self.sent.max(1) as f64
+    }
+}
+
+/// Whether the sources behave noticeably different, which hints at per-flow
+/// policing, NAT limits or source-based routing.
+pub(crate) fn sources_differ(sources: &[SourceStatistics]) -> bool {
+    let averages: Vec<f64> = sources
+        .iter()
+        .filter(|s| s.received > 0)
+        .map(|s| s.average_latency.as_secs_f64())
+        .collect();
+
+    let mean = averages.iter().sum::<f64>() / averages.len().max(1) as f64;
+    let variance =
+        averages.iter().map(|a| (a - mean).powi(2)).sum::<f64>() / averages.len().max(1) as f64;
+    let loss = sources.iter().map(SourceStatistics::loss);
+    let loss_spread = loss.clone().fold(0.0, f64::max) - loss.fold(1.0, f64::min);
+
+    (mean > 0.0 && variance.sqrt() / mean > SOURCE_LATENCY_VARIATION)
+        || loss_spread > SOURCE_LOSS_SPREAD
+}
+
+impl State {
+    /// Appends the next probe. One that falls out of the retention is folded
+    /// into the totals, it is long settled by then.
+    fn push_packet(&mut self, packet: PacketStatus) {
+        if let Some((n, packet)) = self.packets.push(packet) {
+            self.retire(n, packet);
+        }
+    }
+
+    /// Folds probe `n`, which is no longer retained, into the totals.
+    fn retire(&mut self, n: usize, packet: PacketStatus) {
+        let retired = &mut self.retired;
+        if !matches!(packet, PacketStatus::Skipped(_)) {
+            retired.first_sent.get_or_insert(packet.sent_at());
+        }
+        let phase = self.phases.iter().rposition(|p| p.first <= n).unwrap_or(0);
+        if retired.phases.len() <= phase {
+            retired.phases.resize(phase + 1, Tally::default());
+        }
+        retired.phases[phase].add(&packet);
+        if !self.sources.is_empty() {
+            retired.sources.resize(self.sources.len(), Tally::default());
+            retired.sources[n % self.sources.len()].add(&packet);
+        }
+
+        let error = self.icmp_errors.remove(&n);
+        if let (Some(error), PacketStatus::Sent(_)) = (error, packet) {
+            *retired.icmp_errors.entry(error).or_default() += 1;
+        }
+        if let Some(delay) = self.one_way_delays.remove(&n) {
+            retired.upstream.add(delay.upstream);
+            retired.downstream.add(delay.downstream);
+        }
+        self.tags.remove(&n);
+        self.corrupted.remove(&n);
+        self.loss_timeouts.remove(&n);
+        self.kernel_sent.remove(&n);
+    }
+
+    /// Estimated bytes the probe history and the per-probe data of the exports
+    /// take. Map entries count twice for the slack of their nodes.
+    pub(crate) fn memory_usage(&self) -> usize {
+        use std::mem::size_of;
+
+        fn map<K, V>(map: &BTreeMap<K, V>) -> usize {
+            map.len() * 2 * (size_of::<K>() + size_of::<V>())
+        }
+
+        let tags: usize = self.tags.values().map(Vec::capacity).sum();
+        self.packets.capacity() * size_of::<PacketStatus>()
+            + map(&self.icmp_errors)
+            + map(&self.one_way_delays)
+            + map(&self.tags)
+            + tags
+            + self.corrupted.len() * 2 * size_of::<usize>()
+            + map(&self.loss_timeouts)
+            + map(&self.kernel_sent)
+            + self.histogram.distinct_values() * size_of::<u64>()
+            + self.events.capacity() * size_of::<(Duration, Event)>()
+            + self.clock_samples.capacity() * size_of::<ClockSample>()
+    }
+
+    /// Switches to keeping only the last [`MEMORY_CAP_RETENTION`] probes once
+    /// the state takes more than `cap` bytes, folding the older ones into the
+    /// totals. Returns the memory it took when it switched.
+    fn enforce_memory_cap(&mut self, cap: usize) -> Option<usize> {
+        let usage = self.memory_usage();
+        if self.memory_capped || usage <= cap {
+            return None;
+        }
+
+        self.memory_capped = true;
+        self.packets.set_retention(MEMORY_CAP_RETENTION);
+        for (n, packet) in self.packets.drain_excess() {
+            self.retire(n, packet);
+        }
+        Some(usage)
+    }
+
+    /// Fixes the loss timeout of the probe just sent from the round trips so
+    /// far, with an adaptive timeout.
+    fn record_loss_timeout(&mut self) {
+        let (Some(k), Some(rtt)) = (self.loss_timeout.adaptive, self.rtt) else {
+            return;
+        };
+        let n = self.packets.len() - 1;
+        self.loss_timeouts.insert(n, rtt.timeout(k));
+    }
+
+    /// How long probe `n` is waited for before it counts as lost.
+    pub(crate) fn grace(&self, n: usize) -> Duration {
+        self.loss_timeouts
+            .get(&n)
+            .copied()
+            .unwrap_or(self.loss_timeout.grace)
+    }
+
+    /// How long probe `n` is waited for once sending stopped, the adaptive
+    /// timeout replaces the drain bounds too.
+    fn drain_timeout(&self, n: usize) -> Duration {
+        match self.loss_timeouts.get(&n) {
+            Some(&timeout) => timeout,
+            None => self.loss_timeout.drain(self.max_latency),
+        }
+    }
+
+    /// Tag echoed back with probe `n` as text.
+    pub(crate) fn tag(&self, n: usize) -> Option<String> {
+        self.tags
+            .get(&n)
+            .map(|tag| String::from_utf8_lossy(tag).into_owned())
+    }
+
+    /// Packets that went on the wire and count towards the results.
+    pub(crate) fn sent_packets(&self) -> u32 {
+        self.packets.len() as u32
+            - self.skipped_packets
+            - self.invalid_packets
+            - self.warmup_packets
+    }
+
+    /// ICMP error of a probe that stayed unanswered.
+    pub(crate) fn icmp_error(&self, n: usize) -> Option<&IcmpError> {
+        match self.packets.get(n) {
+            Some(PacketStatus::Sent(_)) => self.icmp_errors.get(&n),
+            _ => None,
+        }
+    }
+
+    /// Lost probes per ICMP error, most frequent first.
+    pub(crate) fn icmp_error_counts(&self) -> Vec<(IcmpError, u32)> {
+        let mut counts = self.retired.icmp_errors.clone();
+        for n in self.icmp_errors.keys() {
+            if let Some(error) = self.icmp_error(*n) {
+                *counts.entry(*error).or_default() += 1;
+            }
+        }
+
+        let mut counts: Vec<_> = counts.into_iter().collect();
+        counts.sort_by_key(|&(_, probes)| std::cmp::Reverse(probes));
+        counts
+    }
+
+    /// Number of consecutive packets unanswered past their grace at `now`,
+    /// counting back from the most recent one.
+    fn unanswered_since(&self, now: Duration) -> u32 {
+        let mut unanswered = 0;
+        for (n, packet) in self.packets.numbered().rev() {
+            match *packet {
+                PacketStatus::Sent(start) if start + self.grace(n) > now => {}
+                PacketStatus::Sent(_) => unanswered += 1,
+                PacketStatus::Skipped(_) => {}
+                PacketStatus::Invalid(_)
+                | PacketStatus::Warmup(_)
+                | PacketStatus::Received { .. } => break,
+            }
+        }
+        unanswered
+    }
+
+    /// Send times of the packets unanswered past their grace at `now` that were
+    /// not returned by an earlier call.
+    fn newly_unanswered(&mut self, now: Duration) -> Vec<Duration> {
+        let checked = self.loss_reported.max(self.packets.first_retained());
+        // Graces differ with an adaptive timeout, stop at the first one not over
+        let end = self
+            .packets
+            .range(checked..self.packets.len())
+            .find(|&(n, p)| p.sent_at() + self.grace(n) >= now)
+            .map_or(self.packets.len(), |(n, _)| n);
+        self.loss_reported = end;
+
+        self.packets
+            .range(checked..end)
+            .filter_map(|(_, packet)| match *packet {
+                PacketStatus::Sent(sent) => Some(sent),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Marks every unanswered packet sent within `window` as invalid and returns
+    /// how many were affected. Packets sent after the window are left alone.
+    fn invalidate(&mut self, window: Range<Duration>) -> u32 {
+        let since = window.start;
+        let mut invalidated = 0;
+        for packet in self.packets.iter_mut().rev() {
+            match *packet {
+                PacketStatus::Sent(start) if window.contains(&start) => {
+                    *packet = PacketStatus::Invalid(start);
+                    invalidated += 1;
+                }
+                PacketStatus::Sent(start)
+                | PacketStatus::Skipped(start)
+                | PacketStatus::Invalid(start)
+                | PacketStatus::Warmup(start)
+                | PacketStatus::Received { start, .. }
+                    if start < since =>
+                {
+                    break
+                }
+                _ => {}
+            }
+        }
+
+        self.invalid_packets += invalidated;
+        self.packet_loss -= invalidated;
+        invalidated
+    }
+
+    /// How often the target went down and for how long in total, including
+    /// an outage that lasted until the end.
+    pub(crate) fn outages(&self) -> (usize, Duration) {
+        let mut count = 0;
+        let mut total = Duration::ZERO;
+        for (_, event) in &self.events {
+            match *event {
+                Event::Down { .. } => count += 1,
+                Event::Up(downtime) => total += downtime,
+                _ => {}
+            }
+        }
+        if let Some(since) = self.down_since {
+            let end = self
+                .stopped_at
+                .or_else(|| self.start.map(|start| start.elapsed()))
+                .unwrap_or(since);
+            total += end.saturating_sub(since);
+        }
+        (count, total)
+    }
+
+    /// Starts a phase with the next probe. The probes before a first mark form a
+    /// phase of their own.
+    pub(crate) fn start_phase(&mut self, name: String) {
+        if self.phases.is_empty() {
+            self.phases.push(Phase {
+                name: "start".to_string(),
+                first: 0,
+            });
+        }
+        let first = self.packets.len();
+        match self.phases.last_mut() {
+            // Nothing was sent in the previous phase, the new one replaces it
+            Some(last) if last.first == first => last.name = name,
+            _ => self.phases.push(Phase { name, first }),
+        }
+    }
+
+    /// Latency below which `percentile` percent of the received probes fall,
+    /// within the precision of the histogram.
+    pub(crate) fn percentile(&self, percentile: f64) -> Option<Duration> {
+        if self.histogram.is_empty() {
+            return None;
+        }
+        Some(Duration::from_nanos(
+            self.histogram.value_at_percentile(percentile),
+        ))
+    }
+
+    pub(crate) fn percentiles(&self) -> Option<Percentiles> {
+        Some(Percentiles {
+            p50: self.percentile(50.0)?,
+            p90: self.percentile(90.0)?,
+            p99: self.percentile(99.0)?,
+            p999: self.percentile(99.9)?,
+        })
+    }
+
+    /// Name of the phase packet `n` was sent in.
+    pub(crate) fn phase_of(&self, n: usize) -> Option<&str> {
+        self.phases
+            .iter()
+            .rev()
+            .find(|p| p.first <= n)
+            .map(|p| p.name.as_str())
+    }
+
+    /// Probes actually sent and the span they were sent over, including the
+    /// retired ones, to compare with the configured interval.
+    pub(crate) fn send_rate(&self) -> SendRate {
+        let mut sent = self
+            .packets
+            .iter()
+            .filter(|p| !matches!(p, PacketStatus::Skipped(_)))
+            .map(PacketStatus::sent_at);
+        let first = self.retired.first_sent.or_else(|| sent.next());
+        let first = first.unwrap_or_default();
+        let last = sent.next_back().unwrap_or(first);
+
+        SendRate {
+            interval: self.interval,
+            packets: self.sent_packets() + self.invalid_packets + self.warmup_packets,
+            span: last.saturating_sub(first),
+            wire_bytes: self.traffic_sent.wire,
+        }
+    }
+
+    /// Ends sending, the receivers keep draining echoes still in flight.
+    pub(crate) fn stop(&mut self, at: Duration) {
+        self.stopped_at.get_or_insert(at);
+    }
+
+    /// Number of leading packets whose outcome is final: answered, skipped, or
+    /// unanswered for longer than echoes are waited for.
+    pub(crate) fn settled(&self) -> usize {
+        let Some(start) = self.start else {
+            return 0;
+        };
+        let now = start.elapsed();
+        if self.drained(now) {
+            return self.packets.len();
+        }
+
+        self.packets
+            .numbered()
+            .find(|&(n, p)| {
+                matches!(*p, PacketStatus::Sent(sent) if sent + self.drain_timeout(n) > now)
+            })
+            .map_or(self.packets.len(), |(n, _)| n)
+    }
+
+    /// Whether sending ended and every probe that could still be answered was,
+    /// or the drain timeout passed.
+    pub(crate) fn drained(&self, now: Duration) -> bool {
+        let Some(stopped) = self.stopped_at else {
+            return false;
+        };
+
+        // Older probes count as lost, only recent ones may still come back
+        !self
+            .packets
+            .numbered()
+            .rev()
+            .map(|(n, p)| (p, self.drain_timeout(n)))
+            .take_while(|&(p, timeout)| p.sent_at() + timeout > stopped)
+            .any(|(p, timeout)| matches!(p, PacketStatus::Sent(_)) && now < stopped + timeout)
+    }
+
+    /// Averages of the TCP_INFO samples, with `--protocol tcp` only.
+    pub(crate) fn kernel_rtt(&self) -> Option<KernelRtt> {
+        let last = self.tcp_info.last()?;
+        let samples = self.tcp_info.len() as u32;
+        Some(KernelRtt {
+            rtt: self.tcp_info.iter().map(|s| s.rtt).sum::<Duration>() / samples,
+            rttvar: self.tcp_info.iter().map(|s| s.rttvar).sum::<Duration>() / samples,
+            retransmits: last.retransmits,
+            delivery_rate: self.tcp_info.iter().map(|s| s.delivery_rate).sum::<u64>()
+                / samples as u64,
+        })
+    }
+
+    /// Upstream and downstream delays of all probes that measured them.
+    pub(crate) fn one_way_delay_ranges(&self) -> Option<(DelayRange, DelayRange)> {
+        let (mut upstream, mut downstream) = (self.retired.upstream, self.retired.downstream);
+        for delay in self.one_way_delays.values() {
+            upstream.add(delay.upstream);
+            downstream.add(delay.downstream);
+        }
+        Some((upstream.range()?, downstream.range()?))
+    }
+
+    /// Drift between the client's and the server's clock over the run so far.
+    pub(crate) fn clock_drift(&self) -> Option<ClockDrift> {
+        clock::drift(&self.clock_samples)
+    }
+
+    /// Results of every phase, empty unless the run was split into phases.
+    pub(crate) fn phase_statistics(&self) -> Vec<PhaseStatistics> {
+        let ends = self
+            .phases
+            .iter()
+            .skip(1)
+            .map(|p| p.first)
+            .chain(std::iter::once(self.packets.len()));
+
+        self.phases
+            .iter()
+            .zip(ends)
+            .enumerate()
+            .map(|(i, (phase, end))| {
+                let mut tally = self.retired.phases.get(i).copied().unwrap_or_default();
+                for (_, packet) in self.packets.range(phase.first..end) {
+                    tally.add(packet);
+                }
+                PhaseStatistics {
+                    name: phase.name.clone(),
+                    packets: phase.first..end,
+                    sent: tally.sent,
+                    received: tally.received,
+                    min_latency: tally.min_latency,
+                    average_latency: tally.average_latency(),
+                    max_latency: tally.max_latency,
+                }
+            })
+            .collect()
+    }
+
+    pub(crate) fn source_statistics(&self) -> Vec<SourceStatistics> {
+        if self.sources.is_empty() {
+            return Vec::new();
+        }
+
+        let sources = self.sources.len();
+        let mut tallies = self.retired.sources.clone();
+        tallies.resize(sources, Tally::default());
+        for (n, packet) in self.packets.numbered() {
+            tallies[n % sources].add(packet);
+        }
+
+        self.sources
+            .iter()
+            .zip(tallies)
+            .map(|(&source, tally)| SourceStatistics {
+                source,
+                sent: tally.sent,
+                received: tally.received,
+                average_latency: tally.average_latency(),
+            })
+            .collect()
+    }
+
+    /// Splits the probes of a [`Payload::Alternate`] run into compressible and
+    /// incompressible ones. Each pair of adjacent probes holds one of both, which
+    /// saw nearly the same network conditions.
+    pub(crate) fn payload_comparison(&self) -> PayloadComparison {
+        let mut groups = [PayloadGroup::default(), PayloadGroup::default()];
+        let mut latencies: [Vec<f64>; 2] = [Vec::new(), Vec::new()];
+
+        let mut differences = Vec::new();
+        // Pairs start at even numbers, the retained probes may not
+        let mut pairs = self
+            .packets
+            .numbered()
+            .skip(self.packets.first_retained() % 2);
+        while let (Some((i, a)), Some((_, b))) = (pairs.next(), pairs.next()) {
+            if let (
+                PacketStatus::Received { latency: a, .. },
+                PacketStatus::Received { latency: b, .. },
+            ) = (a, b)
+            {
+                let difference = b.as_secs_f64() - a.as_secs_f64();
+                match Payload::Alternate.is_compressible(i) {
+                    true => differences.push(difference),
+                    false => differences.push(-difference),
+                }
+            }
+        }
+        differences.sort_by(f64::total_cmp);
+
+        for (i, packet) in self.packets.numbered() {
+            let group = match Payload::Alternate.is_compressible(i) {
+                true => 0,
+                false => 1,
+            };
+            match *packet {
+                PacketStatus::Sent(_) => groups[group].sent += 1,
+                PacketStatus::Received { latency, .. } => {
+                    groups[group].sent += 1;
+                    latencies[group].push(latency.as_secs_f64());
+                }
+                PacketStatus::Skipped(_) | PacketStatus::Invalid(_) | PacketStatus::Warmup(_) => {}
+            }
+        }
+
+        for (group, latencies) in groups.iter_mut().zip(latencies) {
+            let n = latencies.len() as f64;
+            group.received = latencies.len() as u32;
+            if latencies.is_empty() {
+                continue;
+            }
+
+            let mean = latencies.iter().sum::<f64>() / n;
+            let variance =
+                latencies.iter().map(|l| (l - mean).powi(2)).sum::<f64>() / (n - 1.0).max(1.0);
+            group.mean = Duration::from_secs_f64(mean);
+            group.std_dev = Duration::from_secs_f64(variance.sqrt());
+        }
+
+        let [compressible, incompressible] = groups;
+        PayloadComparison {
+            compressible,
+            incompressible,
+            pairs: differences.len() as u32,
+            incompressible_slower: differences.iter().filter(|&&d| d > 0.0).count() as u32,
+            incompressible_faster: differences.iter().filter(|&&d| d < 0.0).count() as u32,
+            median_difference: differences
+                .get(differences.len() / 2)
+                .copied()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct PayloadGroup {
+    pub sent: u32,
+    pub received: u32,
+    pub mean: Duration,
+    pub std_dev: Duration,
+}
+
+impl PayloadGroup {
+    pub(crate) fn loss(&self) -> f64 {
+        (self.sent - self.received) as f64 / self.sent.max(1) as f64
+    }
+}
+
+/// Latency and loss of compressible versus incompressible probes.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PayloadComparison {
+    pub compressible: PayloadGroup,
+    pub incompressible: PayloadGroup,
+    /// Adjacent probe pairs where both were answered
+    pub pairs: u32,
+    pub incompressible_slower: u32,
+    pub incompressible_faster: u32,
+    /// Median of incompressible minus compressible latency over the pairs, in seconds
+    pub median_difference: f64,
+}
+
+impl PayloadComparison {
+    /// Whether one payload is consistently faster within the pairs (sign test) and
+    /// by a relevant amount. Latency is heavy-tailed, so means are not compared.
+    pub(crate) fn latency_differs(&self) -> bool {
+        let n = (self.incompressible_slower + self.incompressible_faster) as f64;
+        if n == 0.0 {
+            return false;
+        }
+
+        let z = (self.incompressible_slower as f64 - n / 2.0) / (n / 4.0).sqrt();
+        let baseline = self.compressible.mean.as_secs_f64().max(f64::EPSILON);
+        z.abs() > SIGNIFICANCE_CRITICAL_VALUE
+            && self.median_difference.abs() / baseline >= MIN_PAYLOAD_EFFECT
+    }
+
+    /// Whether the loss rates differ beyond chance (two-proportion z-test).
+    pub(crate) fn loss_differs(&self) -> bool {
+        let (a, b) = (&self.compressible, &self.incompressible);
+        if a.sent == 0 || b.sent == 0 {
+            return false;
+        }
+
+        let pooled = (a.sent - a.received + b.sent - b.received) as f64 / (a.sent + b.sent) as f64;
+        let standard_error =
+            (pooled * (1.0 - pooled) * (1.0 / a.sent as f64 + 1.0 / b.sent as f64)).sqrt();
+        if standard_error == 0.0 {
+            return false;
+        }
+
+        ((a.loss() - b.loss()) / standard_error).abs() > SIGNIFICANCE_CRITICAL_VALUE
+    }
+}
+
+/// Receives a datagram into `buf` and tells whether the kernel cut it short
+/// because it did not fit.
+async fn recv_with_truncation(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> std::io::Result<(usize, bool)> {
+    loop {
+        socket.readable().await?;
+        match socket.try_io(tokio::io::Interest::READABLE, || {
+            try_recv_with_truncation(socket, buf)
+        }) {
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+            received => return received,
+        }
+    }
+}
+
+/// Like [`recv_with_truncation`], without waiting for a datagram. The sockets
+/// are non-blocking, it fails with `WouldBlock` when none is there.
+fn try_recv_with_truncation(socket: &UdpSocket, buf: &mut [u8]) -> std::io::Result<(usize, bool)> {
+    // SAFETY: initialized bytes are valid `MaybeUninit` bytes, and the kernel
+    // only writes initialized bytes into them
+    let uninit = unsafe { &mut *(buf as *mut [u8] as *mut [std::mem::MaybeUninit<u8>]) };
+    socket2::SockRef::from(socket)
+        .recv_vectored(&mut [socket2::MaybeUninitSlice::new(uninit)])
+        .map(|(size, flags)| (size, flags.is_truncated()))
+}
+
+/// Whether `error` means the network went away under the sockets rather than
+/// something being wrong with the run.
+fn is_network_change(error: &color_eyre::eyre::Report) -> bool {
+    use std::io::ErrorKind;
+
+    error.downcast_ref::<std::io::Error>().is_some_and(|e| {
+        matches!(
+            e.kind(),
+            ErrorKind::NetworkUnreachable
+                | ErrorKind::NetworkDown
+                | ErrorKind::HostUnreachable
+                | ErrorKind::AddrNotAvailable
+                | ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::ConnectionRefused
+                | ErrorKind::BrokenPipe
+        )
+    })
+}
+
+fn update_statistics(state: &mut State, latency: Duration) {
+    let n = state.received_packets as f64;
+
+    // Cumulative average: avg = avg * (n / (n + 1)) + new_value * (1 / (n + 1))
+    state.average_latency = state.average_latency.mul_f64(n / (n + 1.0)) + latency.div_f64(n + 1.0);
+
+    if state.received_packets == 0 {
+        state.min_latency = latency;
+        state.max_latency = latency;
+    } else {
+        if latency < state.min_latency {
+            state.min_latency = latency;
+        }
+        if latency > state.max_latency {
+            state.max_latency = latency;
+        }
+    }
+
+    state
+        .histogram
+        .saturating_record(latency.as_nanos().clamp(1, u64::MAX as u128) as u64);
+
     state.received_packets += 1;
     state.packet_loss -= 1;
 }
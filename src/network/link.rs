@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use super::latency::LossTimeout;
+
+/// Kinds of access links whose normal behaviour the default timeouts misjudge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum LinkType {
+    /// Geostationary satellite, around 600ms round trips that stretch to seconds
+    /// under load
+    Satellite,
+    /// Cellular, short round trips with large spikes from deep radio buffers
+    Lte,
+    /// DOCSIS cable, request-grant scheduling adds a few milliseconds upstream
+    Cable,
+    /// Copper telephone lines, interleaving adds latency on long loops
+    Dsl,
+    /// Wired links with short and stable round trips
+    Fiber,
+}
+
+impl LinkType {
+    /// Round trip of an idle link of this type.
+    pub(crate) fn typical_rtt(&self) -> Duration {
+        match self {
+            LinkType::Satellite => Duration::from_millis(600),
+            LinkType::Lte => Duration::from_millis(60),
+            LinkType::Cable => Duration::from_millis(20),
+            LinkType::Dsl => Duration::from_millis(30),
+            LinkType::Fiber => Duration::from_millis(10),
+        }
+    }
+
+    /// How long echoes are waited for before probes count as lost.
+    pub(crate) fn loss_timeout(&self) -> LossTimeout {
+        match self {
+            LinkType::Satellite => LossTimeout {
+                grace: Duration::from_secs(4),
+                min_drain: Duration::from_secs(2),
+                max_drain: Duration::from_secs(15),
+                ..LossTimeout::default()
+            },
+            LinkType::Lte => LossTimeout {
+                grace: Duration::from_secs(2),
+                min_drain: Duration::from_secs(1),
+                max_drain: Duration::from_secs(8),
+                ..LossTimeout::default()
+            },
+            LinkType::Cable | LinkType::Dsl => LossTimeout::default(),
+            LinkType::Fiber => LossTimeout {
+                grace: Duration::from_millis(500),
+                min_drain: Duration::from_millis(250),
+                max_drain: Duration::from_secs(2),
+                ..LossTimeout::default()
+            },
+        }
+    }
+
+    /// Shortest step of a bandwidth ramp. The bottleneck's buffer holds about a
+    /// bandwidth-delay product, a step has to last several round trips to fill it
+    /// before the rate rises again.
+    pub(crate) fn ramp_step(&self) -> Duration {
+        self.typical_rtt() * 10
+    }
+
+    /// Human-readable name for verdicts.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            LinkType::Satellite => "satellite",
+            LinkType::Lte => "LTE",
+            LinkType::Cable => "DOCSIS cable",
+            LinkType::Dsl => "DSL",
+            LinkType::Fiber => "fiber",
+        }
+    }
+
+    /// Bandwidth test duration when none is given, long enough for TCP to leave
+    /// slow start on high-latency links.
+    pub(crate) fn bandwidth_duration(&self) -> Duration {
+        match self {
+            LinkType::Satellite => Duration::from_secs(30),
+            LinkType::Lte => Duration::from_secs(15),
+            LinkType::Cable | LinkType::Dsl | LinkType::Fiber => Duration::from_secs(10),
+        }
+    }
+}
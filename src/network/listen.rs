@@ -0,0 +1,95 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::{TcpListener, UdpSocket};
+use tracing::debug;
+
+use super::udplite;
+
+/// Opens the UDP socket of a server. Without an address it listens on all IPv6
+/// and IPv4 addresses, or on IPv4 only where the host has no IPv6.
+pub(crate) fn udp(address: Option<IpAddr>, port: u16) -> std::io::Result<UdpSocket> {
+    let socket = match address {
+        Some(address) => bind(SocketAddr::new(address, port), Type::DGRAM, Protocol::UDP)?,
+        None => bind_any(port, Type::DGRAM, Protocol::UDP)?,
+    };
+    UdpSocket::from_std(socket.into())
+}
+
+/// Opens the UDP-Lite socket of a server, on all addresses like [`udp`] by
+/// default. Its echoes only protect the header and the sequence number.
+pub(crate) fn udplite(address: Option<IpAddr>, port: u16) -> std::io::Result<UdpSocket> {
+    let protocol = Protocol::from(udplite::IPPROTO_UDPLITE);
+    let socket = match address {
+        Some(address) => bind(SocketAddr::new(address, port), Type::DGRAM, protocol)?,
+        None => bind_any(port, Type::DGRAM, protocol)?,
+    };
+    udplite::set_coverage(&socket, udplite::ECHO_COVERAGE)?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// Opens the TCP listener of a server, on all addresses like [`udp`] by default.
+pub(crate) fn tcp(address: Option<IpAddr>, port: u16) -> std::io::Result<TcpListener> {
+    let socket = match address {
+        Some(address) => bind(SocketAddr::new(address, port), Type::STREAM, Protocol::TCP)?,
+        None => bind_any(port, Type::STREAM, Protocol::TCP)?,
+    };
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// Opens the UDP socket of the QUIC listener of a server, on all addresses like
+/// [`udp`] by default. Quinn drives it, so it stays a std socket.
+pub(crate) fn quic(address: Option<IpAddr>, port: u16) -> std::io::Result<std::net::UdpSocket> {
+    let socket = match address {
+        Some(address) => bind(SocketAddr::new(address, port), Type::DGRAM, Protocol::UDP)?,
+        None => bind_any(port, Type::DGRAM, Protocol::UDP)?,
+    };
+    Ok(socket.into())
+}
+
+/// Wildcard address of the family of `target`, what a client binds to when no
+/// source address is given.
+pub(crate) fn unspecified(target: IpAddr) -> IpAddr {
+    match target {
+        IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    }
+}
+
+/// Peers reaching a dual-stack socket over IPv4 show up as IPv4-mapped IPv6
+/// addresses, this turns them back into plain IPv4.
+pub(crate) fn canonical(peer: SocketAddr) -> SocketAddr {
+    SocketAddr::new(peer.ip().to_canonical(), peer.port())
+}
+
+fn bind_any(port: u16, kind: Type, protocol: Protocol) -> std::io::Result<Socket> {
+    let dual_stack = (|| {
+        let socket = Socket::new(Domain::IPV6, kind, Some(protocol))?;
+        socket.set_only_v6(false)?;
+        socket.set_reuse_address(kind == Type::STREAM)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), port).into())?;
+        Ok::<_, std::io::Error>(socket)
+    })();
+
+    match dual_stack {
+        Ok(socket) => Ok(socket),
+        Err(e) => {
+            debug!("Listening on IPv4 only: {}", e);
+            bind(
+                SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port),
+                kind,
+                protocol,
+            )
+        }
+    }
+}
+
+fn bind(address: SocketAddr, kind: Type, protocol: Protocol) -> std::io::Result<Socket> {
+    let socket = Socket::new(Domain::for_address(address), kind, Some(protocol))?;
+    socket.set_reuse_address(kind == Type::STREAM)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+    Ok(socket)
+}
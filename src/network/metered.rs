@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use tokio::{process::Command, time};
+use tracing::{debug, info, warn};
+
+/// How often NetworkManager is asked whether the connection is metered.
+pub(crate) const CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+/// What to do while the connection is metered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum MeteredPolicy {
+    /// Record when the connection becomes metered and keep probing
+    Warn,
+    /// Stop sending until the connection is unmetered again, the slots in
+    /// between are skipped
+    Pause,
+}
+
+/// Whether the primary connection of NetworkManager is metered, including its
+/// guesses for e.g. phone hotspots. `None` when NetworkManager cannot be asked or
+/// does not know.
+#[cfg(target_os = "linux")]
+pub(crate) async fn check() -> Option<bool> {
+    let output = Command::new("busctl")
+        .args([
+            "--system",
+            "get-property",
+            "org.freedesktop.NetworkManager",
+            "/org/freedesktop/NetworkManager",
+            "org.freedesktop.NetworkManager",
+            "Metered",
+        ])
+        .output()
+        .await;
+    let output = match output {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            debug!(
+                "NetworkManager did not answer: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            return None;
+        }
+        Err(e) => {
+            debug!("Cannot run busctl: {}", e);
+            return None;
+        }
+    };
+
+    // NMMetered as "u <value>": unknown, yes, no, guess-yes, guess-no
+    match String::from_utf8_lossy(&output.stdout).trim() {
+        "u 1" | "u 3" => Some(true),
+        "u 2" | "u 4" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) async fn check() -> Option<bool> {
+    None
+}
+
+/// Holds a bandwidth test back while the connection is metered, or only warns
+/// about it with [`MeteredPolicy::Warn`].
+pub(crate) async fn hold_off(policy: MeteredPolicy) {
+    if check().await != Some(true) {
+        return;
+    }
+
+    match policy {
+        MeteredPolicy::Warn => warn!("The connection is metered, the test may be billed"),
+        MeteredPolicy::Pause => {
+            info!("The connection is metered, waiting for an unmetered one");
+            while check().await == Some(true) {
+                time::sleep(CHECK_INTERVAL).await;
+            }
+        }
+    }
+}
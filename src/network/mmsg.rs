@@ -0,0 +1,165 @@
+use std::{io, net::SocketAddr};
+
+use tokio::net::UdpSocket;
+
+/// Datagrams moved per system call unless configured otherwise.
+pub(crate) const DEFAULT_BATCH: usize = 32;
+
+/// Largest batch, the kernel caps `sendmmsg` and `recvmmsg` at 1024.
+pub(crate) const MAX_BATCH: usize = 1024;
+
+/// Sends `datagrams` to the peer `socket` is connected to, with a single
+/// `sendmmsg` on Linux. Returns how many were sent, fewer than given when the
+/// send buffer filled up.
+#[cfg(target_os = "linux")]
+pub(crate) async fn send(socket: &UdpSocket, datagrams: &[Vec<u8>]) -> io::Result<usize> {
+    use std::os::fd::AsRawFd;
+
+    if datagrams.is_empty() {
+        return Ok(0);
+    }
+
+    loop {
+        socket.writable().await?;
+        // Built within the attempt, the raw pointers must not live across awaits
+        let sent = socket.try_io(tokio::io::Interest::WRITABLE, || {
+            let mut iovs: Vec<libc::iovec> = datagrams
+                .iter()
+                .map(|d| libc::iovec {
+                    iov_base: d.as_ptr() as *mut libc::c_void,
+                    iov_len: d.len(),
+                })
+                .collect();
+            let mut messages: Vec<libc::mmsghdr> = iovs
+                .iter_mut()
+                .map(|iov| {
+                    let mut message: libc::mmsghdr = unsafe { std::mem::zeroed() };
+                    message.msg_hdr.msg_iov = iov;
+                    message.msg_hdr.msg_iovlen = 1;
+                    message
+                })
+                .collect();
+            let sent = unsafe {
+                libc::sendmmsg(
+                    socket.as_raw_fd(),
+                    messages.as_mut_ptr(),
+                    messages.len() as libc::c_uint,
+                    libc::MSG_DONTWAIT,
+                )
+            };
+            match sent {
+                n if n < 0 => Err(io::Error::last_os_error()),
+                n => Ok(n as usize),
+            }
+        });
+        match sent {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            sent => return sent,
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) async fn send(socket: &UdpSocket, datagrams: &[Vec<u8>]) -> io::Result<usize> {
+    for (sent, datagram) in datagrams.iter().enumerate() {
+        if let Err(e) = socket.send(datagram).await {
+            return match sent {
+                0 => Err(e),
+                sent => Ok(sent),
+            };
+        }
+    }
+    Ok(datagrams.len())
+}
+
+/// Buffers to receive several datagrams with one `recvmmsg` on Linux.
+pub(crate) struct RecvBatch {
+    bufs: Vec<Vec<u8>>,
+    /// Buffer, size and sender of the datagrams of the last receive
+    received: Vec<(usize, usize, SocketAddr)>,
+}
+
+impl RecvBatch {
+    /// Room for `count` datagrams of up to `size` bytes.
+    pub(crate) fn new(count: usize, size: usize) -> Self {
+        Self {
+            bufs: vec![vec![0; size]; count.clamp(1, MAX_BATCH)],
+            received: Vec::with_capacity(count),
+        }
+    }
+
+    /// Reads the datagrams waiting on `socket` without waiting, at most as many
+    /// as fit the batch. Fails with `WouldBlock` when none are.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn try_recv(&mut self, socket: &UdpSocket) -> io::Result<usize> {
+        use std::os::fd::AsRawFd;
+
+        self.received.clear();
+        let mut names: Vec<libc::sockaddr_storage> =
+            vec![unsafe { std::mem::zeroed() }; self.bufs.len()];
+        let mut iovs: Vec<libc::iovec> = self
+            .bufs
+            .iter_mut()
+            .map(|buf| libc::iovec {
+                iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+                iov_len: buf.len(),
+            })
+            .collect();
+        let mut messages: Vec<libc::mmsghdr> = iovs
+            .iter_mut()
+            .zip(names.iter_mut())
+            .map(|(iov, name)| {
+                let mut message: libc::mmsghdr = unsafe { std::mem::zeroed() };
+                message.msg_hdr.msg_name = name as *mut libc::sockaddr_storage as *mut libc::c_void;
+                message.msg_hdr.msg_namelen =
+                    std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+                message.msg_hdr.msg_iov = iov;
+                message.msg_hdr.msg_iovlen = 1;
+                message
+            })
+            .collect();
+
+        let count = socket.try_io(tokio::io::Interest::READABLE, || {
+            let count = unsafe {
+                libc::recvmmsg(
+                    socket.as_raw_fd(),
+                    messages.as_mut_ptr(),
+                    messages.len() as libc::c_uint,
+                    libc::MSG_DONTWAIT,
+                    std::ptr::null_mut(),
+                )
+            };
+            match count {
+                n if n < 0 => Err(io::Error::last_os_error()),
+                n => Ok(n as usize),
+            }
+        })?;
+
+        for (i, (message, name)) in messages.iter().zip(names.iter()).take(count).enumerate() {
+            let address = unsafe { socket2::SockAddr::new(*name, message.msg_hdr.msg_namelen) };
+            if let Some(src) = address.as_socket() {
+                self.received.push((i, message.msg_len as usize, src));
+            }
+        }
+        Ok(self.received.len())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn try_recv(&mut self, socket: &UdpSocket) -> io::Result<usize> {
+        self.received.clear();
+        let (size, src) = socket.try_recv_from(&mut self.bufs[0])?;
+        self.received.push((0, size, src));
+        Ok(1)
+    }
+
+    /// Datagrams of the last receive with their senders.
+    pub(crate) fn datagrams(&mut self) -> impl Iterator<Item = (&mut [u8], SocketAddr)> + '_ {
+        let received = &self.received;
+        let mut bufs = self.bufs.iter_mut().enumerate();
+        received.iter().filter_map(move |&(i, size, src)| {
+            // Buffers of datagrams without a sender are skipped
+            let (_, buf) = bufs.find(|(j, _)| *j == i)?;
+            Some((&mut buf[..size], src))
+        })
+    }
+}
@@ -1,3 +1,32 @@
 pub(crate) mod bandwidth;
+pub(crate) mod bidir;
+pub(crate) mod buffers;
+pub(crate) mod busy_poll;
+#[cfg(feature = "udp-no-checksum")]
+pub(crate) mod checksum;
+pub(crate) mod clock;
+pub(crate) mod dns;
 pub(crate) mod echo;
+pub(crate) mod flow_label;
+pub(crate) mod game;
+pub(crate) mod handshake;
+pub(crate) mod icmp;
+pub(crate) mod icmp_error;
 pub(crate) mod latency;
+pub(crate) mod link;
+pub(crate) mod listen;
+pub(crate) mod metered;
+pub(crate) mod mmsg;
+pub(crate) mod mtu;
+pub(crate) mod packets;
+pub(crate) mod passive;
+pub(crate) mod protocol;
+pub(crate) mod quic;
+pub(crate) mod quiet;
+pub(crate) mod route;
+pub(crate) mod rtp;
+pub(crate) mod shaping;
+pub(crate) mod tcp;
+pub(crate) mod timestamping;
+pub(crate) mod udplite;
+pub(crate) mod websocket;
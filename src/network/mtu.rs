@@ -0,0 +1,172 @@
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use color_eyre::eyre::{bail, Result};
+use tokio::{
+    net::UdpSocket,
+    time::{self, Instant},
+};
+use tracing::debug;
+
+use super::{
+    latency::{UDP_IPV4_OVERHEAD, UDP_IPV6_OVERHEAD},
+    protocol::Header,
+};
+
+/// Smallest MTU every IPv4 and IPv6 link has to support.
+pub(crate) const MIN_IPV4_MTU: u16 = 576;
+const MIN_IPV6_MTU: u16 = 1280;
+
+/// How long to wait for the echo of a single probe.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// A size counts as too large after this many unanswered probes.
+const PROBE_ATTEMPTS: u32 = 3;
+
+/// Result of a path MTU search.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PathMtu {
+    /// Largest IP packet that made it to the server and back
+    pub mtu: u16,
+    pub probes: u32,
+}
+
+/// Binary-searches the largest datagram the echo server returns intact with the
+/// don't fragment bit set, between the protocol minimum and `max_mtu`.
+pub(crate) async fn discover(address: IpAddr, port: u16, max_mtu: u16) -> Result<PathMtu> {
+    let (bind, overhead, min_mtu): (IpAddr, u16, u16) = match address {
+        IpAddr::V4(_) => (
+            Ipv4Addr::UNSPECIFIED.into(),
+            UDP_IPV4_OVERHEAD as u16,
+            MIN_IPV4_MTU,
+        ),
+        IpAddr::V6(_) => (
+            Ipv6Addr::UNSPECIFIED.into(),
+            UDP_IPV6_OVERHEAD as u16,
+            MIN_IPV6_MTU,
+        ),
+    };
+    if max_mtu < min_mtu {
+        bail!("The MTU to search up to must be at least {}", min_mtu);
+    }
+
+    let socket = UdpSocket::bind(SocketAddr::new(bind, 0)).await?;
+    socket.connect(SocketAddr::new(address, port)).await?;
+    set_dont_fragment(&socket, address)?;
+
+    let mut probes = 0;
+    let mut probe = |mtu: u16| {
+        probes += 1;
+        let payload = (mtu - overhead) as usize;
+        fits(&socket, payload, probes)
+    };
+
+    if !probe(min_mtu).await? {
+        bail!(
+            "No answer to {} byte packets, is the server reachable?",
+            min_mtu
+        );
+    }
+
+    // `good` always fits, `bad` never does. In u32, `bad` is one past a max of
+    // u16::MAX
+    let (mut good, mut bad) = (u32::from(min_mtu), u32::from(max_mtu) + 1);
+    while bad - good > 1 {
+        let mtu = good + (bad - good) / 2;
+        if probe(mtu as u16).await? {
+            good = mtu;
+        } else {
+            bad = mtu;
+        }
+        debug!("Path MTU between {} and {}", good, bad - 1);
+    }
+
+    Ok(PathMtu {
+        mtu: good as u16,
+        probes,
+    })
+}
+
+/// Whether a datagram with `size` bytes of payload is echoed back unchanged.
+async fn fits(socket: &UdpSocket, size: usize, id: u32) -> Result<bool> {
+    let mut payload = vec![0; size];
+    Header {
+        sequence: id as u64,
+        ..Default::default()
+    }
+    .encode(&mut payload);
+
+    let mut buf = vec![0; size + 1];
+    for _ in 0..PROBE_ATTEMPTS {
+        match socket.send(&payload).await {
+            Ok(_) => {}
+            // Larger than the MTU of the local interface or a cached path MTU
+            Err(e) if e.raw_os_error() == Some(libc::EMSGSIZE) => return Ok(false),
+            Err(e) => return Err(e.into()),
+        }
+
+        let deadline = Instant::now() + PROBE_TIMEOUT;
+        loop {
+            let received = match time::timeout_at(deadline, socket.recv(&mut buf)).await {
+                Ok(received) => received,
+                Err(_) => break,
+            };
+            match received {
+                Ok(n) if n == size && buf[..n] == payload[..] => return Ok(true),
+                // Echoes of earlier probes or truncated copies
+                Ok(_) => continue,
+                // ICMP fragmentation needed reported on the socket
+                Err(e) if e.raw_os_error() == Some(libc::EMSGSIZE) => return Ok(false),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    Ok(false)
+}
+
+/// Sets the don't fragment bit and ignores the kernel's cached path MTU so probes
+/// larger than a previous estimate still leave the host.
+#[cfg(target_os = "linux")]
+fn set_dont_fragment(socket: &UdpSocket, address: IpAddr) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let (level, option, value) = match address {
+        IpAddr::V4(_) => (
+            libc::IPPROTO_IP,
+            libc::IP_MTU_DISCOVER,
+            libc::IP_PMTUDISC_PROBE,
+        ),
+        IpAddr::V6(_) => (
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MTU_DISCOVER,
+            libc::IPV6_PMTUDISC_PROBE,
+        ),
+    };
+
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            option,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_dont_fragment(_socket: &UdpSocket, _address: IpAddr) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "setting the don't fragment bit is only supported on Linux",
+    ))
+}
@@ -0,0 +1,137 @@
+use std::{
+    collections::VecDeque,
+    ops::{Index, IndexMut, Range},
+};
+
+use super::latency::PacketStatus;
+
+/// Probes kept in memory by runs without an end that do not export every probe.
+pub(crate) const DEFAULT_RETENTION: usize = 100_000;
+
+/// Outcomes of the probes of a run by number. Every probe is kept unless a
+/// retention is set, then only the most recent ones are and older numbers are
+/// missing from [`Packets::get`] and the iterators.
+pub(crate) struct Packets {
+    retained: VecDeque<PacketStatus>,
+    /// Number of the oldest retained probe, which is how many were dropped
+    first: usize,
+    retention: Option<usize>,
+}
+
+impl Packets {
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self {
+            retained: VecDeque::with_capacity(capacity),
+            first: 0,
+            retention: None,
+        }
+    }
+
+    /// Keeps only the last `retention` probes. Probes already beyond it stay
+    /// until taken with [`Packets::drain_excess`].
+    pub(crate) fn set_retention(&mut self, retention: usize) {
+        self.retention = Some(retention.max(1));
+        self.retained.shrink_to(retention);
+    }
+
+    /// Drops the probes beyond the retention and returns them with their numbers.
+    pub(crate) fn drain_excess(&mut self) -> Vec<(usize, PacketStatus)> {
+        let mut dropped = Vec::new();
+        while let Some(packet) = self.pop_excess() {
+            dropped.push(packet);
+        }
+        if let Some(retention) = self.retention {
+            self.retained.shrink_to(retention);
+        }
+        dropped
+    }
+
+    /// Probes room is allocated for.
+    pub(crate) fn capacity(&self) -> usize {
+        self.retained.capacity()
+    }
+
+    /// Probes of the whole run, retained or not.
+    pub(crate) fn len(&self) -> usize {
+        self.first + self.retained.len()
+    }
+
+    /// Number of the oldest probe still in memory.
+    pub(crate) fn first_retained(&self) -> usize {
+        self.first
+    }
+
+    /// Appends the next probe and returns the one it pushed out with its number.
+    pub(crate) fn push(&mut self, packet: PacketStatus) -> Option<(usize, PacketStatus)> {
+        self.retained.push_back(packet);
+        self.pop_excess()
+    }
+
+    fn pop_excess(&mut self) -> Option<(usize, PacketStatus)> {
+        match self.retention {
+            Some(retention) if self.retained.len() > retention => {
+                let dropped = self.retained.pop_front()?;
+                self.first += 1;
+                Some((self.first - 1, dropped))
+            }
+            _ => None,
+        }
+    }
+
+    pub(crate) fn get(&self, n: usize) -> Option<&PacketStatus> {
+        self.retained.get(n.checked_sub(self.first)?)
+    }
+
+    pub(crate) fn first(&self) -> Option<&PacketStatus> {
+        self.retained.front()
+    }
+
+    pub(crate) fn last(&self) -> Option<&PacketStatus> {
+        self.retained.back()
+    }
+
+    /// Retained probes, oldest first.
+    pub(crate) fn iter(
+        &self,
+    ) -> impl DoubleEndedIterator<Item = &PacketStatus> + ExactSizeIterator + '_ {
+        self.retained.iter()
+    }
+
+    pub(crate) fn iter_mut(
+        &mut self,
+    ) -> impl DoubleEndedIterator<Item = &mut PacketStatus> + ExactSizeIterator + '_ {
+        self.retained.iter_mut()
+    }
+
+    /// Retained probes with their numbers.
+    pub(crate) fn numbered(
+        &self,
+    ) -> impl DoubleEndedIterator<Item = (usize, &PacketStatus)> + ExactSizeIterator + '_ {
+        self.range(0..self.len())
+    }
+
+    /// Retained probes numbered within `range`.
+    pub(crate) fn range(
+        &self,
+        range: Range<usize>,
+    ) -> impl DoubleEndedIterator<Item = (usize, &PacketStatus)> + ExactSizeIterator + '_ {
+        let start = range.start.clamp(self.first, self.len());
+        let end = range.end.clamp(start, self.len());
+        (start..end).zip(self.retained.range(start - self.first..end - self.first))
+    }
+}
+
+/// Panics for probes that are no longer retained, check with [`Packets::get`].
+impl Index<usize> for Packets {
+    type Output = PacketStatus;
+
+    fn index(&self, n: usize) -> &PacketStatus {
+        &self.retained[n - self.first]
+    }
+}
+
+impl IndexMut<usize> for Packets {
+    fn index_mut(&mut self, n: usize) -> &mut PacketStatus {
+        &mut self.retained[n - self.first]
+    }
+}
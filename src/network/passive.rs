@@ -0,0 +1,546 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+    time::Duration,
+};
+
+use color_eyre::eyre::{bail, eyre, Report, Result};
+use hdrhistogram::Histogram;
+use tokio::time::{self, Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use crate::units::DisplayFormat;
+
+/// RTP header bytes before the payload, without CSRCs or extensions.
+const RTP_HEADER: usize = 12;
+
+/// Which datagrams a passive monitor looks at, the UDP subset of tcpdump's
+/// filter syntax: `[src|dst] host ADDR` and `[src|dst] port PORT` terms joined
+/// by `and`, e.g. `udp and dst port 5004`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Filter {
+    host: Option<IpAddr>,
+    src_host: Option<IpAddr>,
+    dst_host: Option<IpAddr>,
+    port: Option<u16>,
+    src_port: Option<u16>,
+    dst_port: Option<u16>,
+}
+
+impl FromStr for Filter {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut filter = Filter::default();
+        let mut tokens = s.split_whitespace();
+
+        while let Some(token) = tokens.next() {
+            let direction = match token {
+                "udp" | "and" | "&&" => continue,
+                "src" | "dst" => {
+                    let direction = token;
+                    match tokens.next() {
+                        Some(kind) => (Some(direction), kind),
+                        None => bail!("`{}` needs `host` or `port` after it", direction),
+                    }
+                }
+                kind => (None, kind),
+            };
+
+            let value = tokens
+                .next()
+                .ok_or_else(|| eyre!("`{}` needs a value", direction.1))?;
+            match direction {
+                (direction, "host") => {
+                    let address: IpAddr = value
+                        .parse()
+                        .map_err(|_| eyre!("`{}` is not an IP address", value))?;
+                    match direction {
+                        Some("src") => filter.src_host = Some(address),
+                        Some("dst") => filter.dst_host = Some(address),
+                        _ => filter.host = Some(address),
+                    }
+                }
+                (direction, "port") => {
+                    let port: u16 = value
+                        .parse()
+                        .map_err(|_| eyre!("`{}` is not a port", value))?;
+                    match direction {
+                        Some("src") => filter.src_port = Some(port),
+                        Some("dst") => filter.dst_port = Some(port),
+                        _ => filter.port = Some(port),
+                    }
+                }
+                (_, other) => bail!(
+                    "Unsupported filter term `{}`, use `[src|dst] host ADDR` or `[src|dst] port PORT`",
+                    other
+                ),
+            }
+        }
+
+        Ok(filter)
+    }
+}
+
+impl Filter {
+    fn matches(&self, source: SocketAddr, destination: SocketAddr) -> bool {
+        fn either<T: PartialEq>(wanted: Option<T>, a: T, b: T) -> bool {
+            wanted.is_none_or(|w| w == a || w == b)
+        }
+        fn exact<T: PartialEq>(wanted: Option<T>, a: T) -> bool {
+            wanted.is_none_or(|w| w == a)
+        }
+
+        either(self.host, source.ip(), destination.ip())
+            && exact(self.src_host, source.ip())
+            && exact(self.dst_host, destination.ip())
+            && either(self.port, source.port(), destination.port())
+            && exact(self.src_port, source.port())
+            && exact(self.dst_port, destination.port())
+    }
+}
+
+/// Where sequence numbers are read from to count lost and reordered datagrams.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum Sequence {
+    /// Only inter-packet gaps are reported
+    #[default]
+    None,
+    /// 16-bit sequence number of the RTP header
+    Rtp,
+}
+
+/// A UDP flow in one direction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct FlowKey {
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+}
+
+impl fmt::Display for FlowKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} → {}", self.source, self.destination)
+    }
+}
+
+/// What was observed of one flow.
+pub(crate) struct Flow {
+    pub packets: u64,
+    pub bytes: u64,
+    first: Instant,
+    last: Instant,
+    /// Time between consecutive datagrams in nanoseconds
+    pub gaps: Histogram<u64>,
+    /// Highest sequence number seen, extended past 16-bit wrap-arounds
+    highest: Option<u64>,
+    pub lost: u64,
+    pub reordered: u64,
+    pub duplicates: u64,
+
+    /// Counters at the last progress report
+    reported: (u64, u64),
+}
+
+impl Flow {
+    fn new(now: Instant) -> Self {
+        Self {
+            packets: 0,
+            bytes: 0,
+            first: now,
+            last: now,
+            gaps: Histogram::new_with_bounds(1, Duration::from_secs(3600).as_nanos() as u64, 3)
+                .expect("valid histogram bounds"),
+            highest: None,
+            lost: 0,
+            reordered: 0,
+            duplicates: 0,
+            reported: (0, 0),
+        }
+    }
+
+    fn record(&mut self, now: Instant, size: usize, sequence: Option<u16>) {
+        if self.packets > 0 {
+            let gap = (now - self.last).as_nanos().clamp(1, u64::MAX as u128) as u64;
+            self.gaps.saturating_record(gap);
+        }
+        self.packets += 1;
+        self.bytes += size as u64;
+        self.last = now;
+
+        let (Some(sequence), Some(highest)) = (sequence, self.highest) else {
+            self.highest = sequence.map(u64::from).or(self.highest);
+            return;
+        };
+
+        // Distance from the highest number so far, interpreted across wrap-arounds
+        let delta = sequence.wrapping_sub(highest as u16) as i16;
+        match delta {
+            1.. => {
+                self.lost += delta as u64 - 1;
+                self.highest = Some(highest + delta as u64);
+            }
+            0 => self.duplicates += 1,
+            _ => {
+                // A late datagram that was counted as lost when the gap opened
+                self.reordered += 1;
+                self.lost = self.lost.saturating_sub(1);
+            }
+        }
+    }
+
+    pub(crate) fn duration(&self) -> Duration {
+        self.last - self.first
+    }
+
+    /// Share of the datagrams the sequence numbers show missing, `None` without
+    /// sequence numbers.
+    pub(crate) fn loss(&self) -> Option<f64> {
+        self.highest?;
+        Some(self.lost as f64 / (self.packets + self.lost).max(1) as f64)
+    }
+
+    fn gap(&self, quantile: f64) -> Duration {
+        Duration::from_nanos(self.gaps.value_at_quantile(quantile))
+    }
+}
+
+/// Observes existing UDP traffic on an interface without sending anything.
+pub(crate) struct PassiveMonitor {
+    interface: Option<String>,
+    filter: Filter,
+    sequence: Sequence,
+    report_interval: Duration,
+    duration: Option<Duration>,
+    format: DisplayFormat,
+}
+
+impl PassiveMonitor {
+    pub(crate) fn new(filter: Filter) -> Self {
+        Self {
+            interface: None,
+            filter,
+            sequence: Sequence::default(),
+            report_interval: Duration::from_secs(5),
+            duration: None,
+            format: DisplayFormat::default(),
+        }
+    }
+
+    /// Only capture on this interface instead of all of them.
+    pub(crate) fn with_interface(mut self, interface: String) -> Self {
+        self.interface = Some(interface);
+        self
+    }
+
+    pub(crate) fn with_sequence(mut self, sequence: Sequence) -> Self {
+        self.sequence = sequence;
+        self
+    }
+
+    /// Log per-flow progress this often, zero disables it.
+    pub(crate) fn with_report_interval(mut self, interval: Duration) -> Self {
+        self.report_interval = interval;
+        self
+    }
+
+    pub(crate) fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = Some(duration);
+        self
+    }
+
+    pub(crate) fn with_display_format(mut self, format: DisplayFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Captures until the duration is over or `cancel` fires, then logs a
+    /// summary of every flow.
+    pub(crate) async fn run(&self, cancel: CancellationToken) -> Result<()> {
+        let socket = PacketSocket::open(self.interface.as_deref())?;
+        let mut flows: HashMap<FlowKey, Flow> = HashMap::new();
+        let mut buf = vec![0; 65536];
+
+        let period = match self.report_interval.is_zero() {
+            true => Duration::from_secs(3600),
+            false => self.report_interval,
+        };
+        let mut ticker = time::interval_at(Instant::now() + period, period);
+        let end = self.duration.map(|d| Instant::now() + d);
+
+        info!(
+            "Watching UDP traffic on {}",
+            self.interface.as_deref().unwrap_or("all interfaces")
+        );
+
+        loop {
+            tokio::select! {
+                received = socket.recv(&mut buf) => {
+                    let size = received?;
+                    let now = Instant::now();
+                    let Some((key, payload)) = parse_udp(&buf[..size]) else { continue };
+                    if !self.filter.matches(key.source, key.destination) {
+                        continue;
+                    }
+
+                    let sequence = match self.sequence {
+                        Sequence::None => None,
+                        Sequence::Rtp => rtp_sequence(payload),
+                    };
+                    flows
+                        .entry(key)
+                        .or_insert_with(|| {
+                            info!("New flow {}", key);
+                            Flow::new(now)
+                        })
+                        .record(now, payload.len(), sequence);
+                }
+                _ = ticker.tick(), if !self.report_interval.is_zero() => {
+                    self.report_progress(&mut flows, period);
+                }
+                _ = async { time::sleep_until(end.unwrap()).await }, if end.is_some() => break,
+                _ = cancel.cancelled() => break,
+            }
+        }
+
+        self.report(&flows);
+        Ok(())
+    }
+
+    fn report_progress(&self, flows: &mut HashMap<FlowKey, Flow>, period: Duration) {
+        for (key, flow) in flows.iter_mut() {
+            let packets = flow.packets - flow.reported.0;
+            let lost = flow.lost.saturating_sub(flow.reported.1);
+            flow.reported = (flow.packets, flow.lost);
+            if packets == 0 && lost == 0 {
+                continue;
+            }
+
+            let mut text = format!(
+                "{}: {:.1} pkt/s, gap p50 {}, max {}",
+                key,
+                packets as f64 / period.as_secs_f64(),
+                self.format.duration(flow.gap(0.5)),
+                self.format.duration(Duration::from_nanos(flow.gaps.max()))
+            );
+            if flow.highest.is_some() {
+                text += &format!(", {} lost", lost);
+            }
+            info!("{}", text);
+        }
+    }
+
+    fn report(&self, flows: &HashMap<FlowKey, Flow>) {
+        if flows.is_empty() {
+            info!("No datagram matched the filter");
+            return;
+        }
+
+        let mut flows: Vec<_> = flows.iter().collect();
+        flows.sort_by_key(|(_, f)| std::cmp::Reverse(f.packets));
+        for (key, flow) in flows {
+            info!("Flow: {}", key);
+            info!(
+                "  Datagrams: {} ({} payload) over {:.1?}",
+                flow.packets,
+                crate::network::bandwidth::format_bytes(flow.bytes),
+                flow.duration()
+            );
+            if flow.packets > 1 {
+                info!(
+                    "  Gaps: min {}, p50 {}, p99 {}, max {}",
+                    self.format.duration(Duration::from_nanos(flow.gaps.min())),
+                    self.format.duration(flow.gap(0.5)),
+                    self.format.duration(flow.gap(0.99)),
+                    self.format.duration(Duration::from_nanos(flow.gaps.max()))
+                );
+            }
+            if let Some(loss) = flow.loss() {
+                info!(
+                    "  Lost: {} ({}), reordered: {}, duplicates: {}",
+                    flow.lost,
+                    self.format.percent(loss),
+                    flow.reordered,
+                    flow.duplicates
+                );
+            }
+        }
+    }
+}
+
+/// Flow and UDP payload of an IPv4 or IPv6 packet, `None` for anything else,
+/// including fragments after the first and IPv6 extension headers.
+fn parse_udp(packet: &[u8]) -> Option<(FlowKey, &[u8])> {
+    let (source, destination, udp): (IpAddr, IpAddr, &[u8]) = match packet.first()? >> 4 {
+        4 => {
+            let header = (packet[0] & 0x0f) as usize * 4;
+            let fragment_offset = u16::from_be_bytes([*packet.get(6)?, *packet.get(7)?]) & 0x1fff;
+            if *packet.get(9)? != libc::IPPROTO_UDP as u8 || fragment_offset != 0 {
+                return None;
+            }
+            let source: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+            let destination: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+            (
+                Ipv4Addr::from(source).into(),
+                Ipv4Addr::from(destination).into(),
+                packet.get(header..)?,
+            )
+        }
+        6 => {
+            if *packet.get(6)? != libc::IPPROTO_UDP as u8 {
+                return None;
+            }
+            let source: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+            let destination: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+            (
+                Ipv6Addr::from(source).into(),
+                Ipv6Addr::from(destination).into(),
+                packet.get(40..)?,
+            )
+        }
+        _ => return None,
+    };
+
+    let source_port = u16::from_be_bytes([*udp.first()?, *udp.get(1)?]);
+    let destination_port = u16::from_be_bytes([*udp.get(2)?, *udp.get(3)?]);
+    let length = u16::from_be_bytes([*udp.get(4)?, *udp.get(5)?]) as usize;
+    let payload = udp.get(8..length.clamp(8, udp.len()))?;
+
+    Some((
+        FlowKey {
+            source: SocketAddr::new(source, source_port),
+            destination: SocketAddr::new(destination, destination_port),
+        },
+        payload,
+    ))
+}
+
+/// Sequence number of an RTP packet, `None` when the payload is not RTP version 2.
+fn rtp_sequence(payload: &[u8]) -> Option<u16> {
+    if payload.len() < RTP_HEADER || payload[0] >> 6 != 2 {
+        return None;
+    }
+    Some(u16::from_be_bytes([payload[2], payload[3]]))
+}
+
+/// Packet socket that sees the IP packets of all, or one, interface, including
+/// the ones sent by this host.
+#[cfg(target_os = "linux")]
+struct PacketSocket {
+    socket: tokio::io::unix::AsyncFd<std::os::fd::OwnedFd>,
+}
+
+#[cfg(target_os = "linux")]
+impl PacketSocket {
+    fn open(interface: Option<&str>) -> Result<Self> {
+        use std::{
+            ffi::CString,
+            io,
+            os::fd::{FromRawFd, OwnedFd},
+        };
+
+        let index = match interface {
+            Some(name) => {
+                let c_name = CString::new(name)?;
+                match unsafe { libc::if_nametoindex(c_name.as_ptr()) } {
+                    0 => bail!("No interface named {}", name),
+                    index => index as i32,
+                }
+            }
+            None => 0,
+        };
+
+        let protocol = (libc::ETH_P_ALL as u16).to_be();
+        let socket = unsafe {
+            // Datagram packet sockets strip the link layer header
+            let fd = libc::socket(
+                libc::AF_PACKET,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK,
+                protocol as i32,
+            );
+            if fd < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::PermissionDenied {
+                    bail!(
+                        "Passive monitoring needs packet sockets: run as root or grant the \
+                         capability with `setcap cap_net_raw+ep`"
+                    );
+                }
+                return Err(e.into());
+            }
+            let socket = OwnedFd::from_raw_fd(fd);
+
+            let mut address: libc::sockaddr_ll = std::mem::zeroed();
+            address.sll_family = libc::AF_PACKET as u16;
+            address.sll_protocol = protocol;
+            address.sll_ifindex = index;
+            let bound = libc::bind(
+                fd,
+                &address as *const libc::sockaddr_ll as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            );
+            if bound < 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            socket
+        };
+
+        Ok(Self {
+            socket: tokio::io::unix::AsyncFd::new(socket)?,
+        })
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> std::io::Result<usize> {
+        use std::{io, os::fd::AsRawFd};
+
+        loop {
+            let mut guard = self.socket.readable().await?;
+            let received = guard.try_io(|socket| {
+                let mut address: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+                let mut length = std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t;
+                let received = unsafe {
+                    libc::recvfrom(
+                        socket.as_raw_fd(),
+                        buf.as_mut_ptr().cast(),
+                        buf.len(),
+                        0,
+                        &mut address as *mut libc::sockaddr_ll as *mut libc::sockaddr,
+                        &mut length,
+                    )
+                };
+                match received {
+                    n if n >= 0 => Ok((n as usize, address)),
+                    _ => Err(io::Error::last_os_error()),
+                }
+            });
+
+            match received {
+                // Loopback shows every packet twice, once leaving and once arriving
+                Ok(Ok((_, address)))
+                    if address.sll_hatype == libc::ARPHRD_LOOPBACK
+                        && address.sll_pkttype == libc::PACKET_OUTGOING =>
+                {
+                    continue
+                }
+                Ok(result) => return result.map(|(size, _)| size),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+struct PacketSocket;
+
+#[cfg(not(target_os = "linux"))]
+impl PacketSocket {
+    fn open(_interface: Option<&str>) -> Result<Self> {
+        bail!("Passive monitoring is only supported on Linux")
+    }
+
+    async fn recv(&self, _buf: &mut [u8]) -> std::io::Result<usize> {
+        std::future::pending().await
+    }
+}
@@ -0,0 +1,163 @@
+use std::fmt;
+
+/// First bytes of every probe and echo, datagrams without them are not bwlat
+/// traffic and are ignored.
+pub(crate) const MAGIC: &[u8; 4] = b"BWLT";
+
+/// Version of the probe layout, bumped on every change an older peer would
+/// misparse. Magic and version keep their place in all versions.
+pub(crate) const VERSION: u8 = 1;
+
+/// Magic, version, flags, two reserved bytes, sequence number and the send and
+/// receive times.
+pub(crate) const HEADER_LEN: usize = 32;
+
+/// Magic and version, all a peer of another version can rely on.
+const VERSION_LEN: usize = MAGIC.len() + 1;
+
+/// The probe asks for a reply that differs from it, described right after the
+/// header.
+pub(crate) const FLAG_REPLY: u8 = 1 << 0;
+/// The probe asks the server to write its receive time into the echo.
+pub(crate) const FLAG_TIMESTAMP: u8 = 1 << 1;
+/// The probe ends with a tag for the server to echo back, see [`write_tag`].
+pub(crate) const FLAG_TAG: u8 = 1 << 2;
+
+/// Longest tag a probe carries.
+pub(crate) const MAX_TAG_LEN: usize = 64;
+
+/// Header of the latency probes and their echoes. All fields are big-endian:
+///
+/// ```text
+///  0      4        5      6          8          16     24         32
+///  | magic | version | flags | reserved | sequence | sent | received |
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Header {
+    pub flags: u8,
+    pub sequence: u64,
+    /// When the client sent the probe, nanoseconds since the Unix epoch
+    pub sent: i64,
+    /// When the server received the probe, nanoseconds since the Unix epoch.
+    /// Zero unless the probe asked for it with [`FLAG_TIMESTAMP`]
+    pub received: i64,
+}
+
+/// Why a datagram is not a probe or echo this version understands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum DecodeError {
+    /// Too short or without the magic
+    Foreign,
+    /// Sent by a peer speaking another version
+    Version(u8),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Foreign => write!(f, "not a bwlat datagram"),
+            DecodeError::Version(version) => write!(
+                f,
+                "the peer speaks version {} of the bwlat protocol, this is version {}; \
+                 update the older of client and server",
+                version, VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl Header {
+    pub(crate) fn has(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// Writes the header to the start of `buf`, which needs [`HEADER_LEN`] bytes.
+    pub(crate) fn encode(&self, buf: &mut [u8]) {
+        let header = &mut buf[..HEADER_LEN];
+        header[..MAGIC.len()].copy_from_slice(MAGIC);
+        header[4] = VERSION;
+        header[5] = self.flags;
+        header[6..8].fill(0);
+        header[8..16].copy_from_slice(&self.sequence.to_be_bytes());
+        header[16..24].copy_from_slice(&self.sent.to_be_bytes());
+        header[24..32].copy_from_slice(&self.received.to_be_bytes());
+    }
+
+    pub(crate) fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        if buf.len() < VERSION_LEN || !buf.starts_with(MAGIC) {
+            return Err(DecodeError::Foreign);
+        }
+        if buf[4] != VERSION {
+            return Err(DecodeError::Version(buf[4]));
+        }
+        let Some(header) = buf.get(..HEADER_LEN) else {
+            return Err(DecodeError::Foreign);
+        };
+
+        Ok(Self {
+            flags: header[5],
+            sequence: u64::from_be_bytes(header[8..16].try_into().unwrap()),
+            sent: i64::from_be_bytes(header[16..24].try_into().unwrap()),
+            received: i64::from_be_bytes(header[24..32].try_into().unwrap()),
+        })
+    }
+}
+
+/// Sets `flag` in the encoded header at the start of `buf`.
+pub(crate) fn set_flag(buf: &mut [u8], flag: u8) {
+    buf[5] |= flag;
+}
+
+/// Clears `flag` in the encoded header at the start of `buf`.
+pub(crate) fn clear_flag(buf: &mut [u8], flag: u8) {
+    buf[5] &= !flag;
+}
+
+/// Writes the receive time into the encoded header at the start of `buf`.
+pub(crate) fn write_received(buf: &mut [u8], at: i64) {
+    buf[24..32].copy_from_slice(&at.to_be_bytes());
+}
+
+/// Answer to a probe of another version: the magic and this version, which
+/// peers of any version can read.
+pub(crate) fn version_answer() -> [u8; VERSION_LEN] {
+    let mut answer = [0; VERSION_LEN];
+    answer[..MAGIC.len()].copy_from_slice(MAGIC);
+    answer[4] = VERSION;
+    answer
+}
+
+/// Bytes a tag of `len` bytes takes at the end of a probe.
+pub(crate) fn tag_len(len: usize) -> usize {
+    len + 1
+}
+
+/// Writes `tag` and its length to the end of `probe` and flags it, `probe` needs
+/// [`tag_len`] bytes behind everything else in it.
+pub(crate) fn write_tag(probe: &mut [u8], tag: &[u8]) {
+    let trailer = probe.len() - tag_len(tag.len());
+    probe[trailer..][..tag.len()].copy_from_slice(tag);
+    probe[probe.len() - 1] = tag.len() as u8;
+    set_flag(probe, FLAG_TAG);
+}
+
+/// The tag at the end of `probe`, `None` when it carries none.
+pub(crate) fn read_tag<'a>(header: &Header, probe: &'a [u8]) -> Option<&'a [u8]> {
+    if !header.has(FLAG_TAG) {
+        return None;
+    }
+    let len_at = probe.len().checked_sub(1)?;
+    let start = len_at.checked_sub(probe[len_at] as usize)?;
+    (start >= HEADER_LEN).then(|| &probe[start..len_at])
+}
+
+/// Parses a probe tag given on the command line.
+pub(crate) fn parse_tag(s: &str) -> Result<String, String> {
+    match s.len() {
+        0 => Err("the tag is empty".to_string()),
+        len if len > MAX_TAG_LEN => Err(format!("tags are at most {} bytes", MAX_TAG_LEN)),
+        _ => Ok(s.to_string()),
+    }
+}
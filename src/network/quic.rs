@@ -0,0 +1,287 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use color_eyre::eyre::{eyre, Result};
+use quinn::{
+    crypto::rustls::{QuicClientConfig, QuicServerConfig},
+    rustls::{
+        self,
+        client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        crypto::CryptoProvider,
+        pki_types::{CertificateDer, PrivatePkcs8KeyDer, ServerName, UnixTime},
+        DigitallySignedStruct, SignatureScheme,
+    },
+    ClientConfig, Connection, Endpoint, EndpointConfig, RecvStream, SendStream, ServerConfig,
+    TokioRuntime,
+};
+use tokio::{io, time::Instant};
+use tracing::{debug, info, warn};
+
+use super::{bandwidth::format_bitrate, listen};
+use crate::pairing::Authenticator;
+
+/// Application protocol of the connections, the streams carry bwlat probes and
+/// bulk data rather than HTTP/3.
+const ALPN: &[u8] = b"bwlat";
+
+/// Name the self-signed certificate of the server is issued for.
+const SERVER_NAME: &str = "bwlat";
+
+/// Bytes a QUIC packet adds to its UDP payload: a short header with an 8 byte
+/// connection ID and up to 4 bytes of packet number, the 16 byte AEAD tag and
+/// the header of the STREAM frame. Probes that share a packet share it too.
+pub(crate) const QUIC_OVERHEAD: u64 = 1 + 8 + 4 + 16 + 12;
+
+/// How long the bulk sender waits for the server to acknowledge what is still
+/// in flight when the test ends.
+const FINISH_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// Client endpoint on `socket`, which the caller bound and configured.
+pub(crate) fn client(socket: std::net::UdpSocket) -> Result<Endpoint> {
+    let mut tls = rustls::ClientConfig::builder_with_provider(provider())
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AnyCertificate(provider())))
+        .with_no_client_auth();
+    tls.alpn_protocols = vec![ALPN.to_vec()];
+
+    let mut endpoint = Endpoint::new(
+        EndpointConfig::default(),
+        None,
+        socket,
+        Arc::new(TokioRuntime),
+    )?;
+    endpoint.set_default_client_config(ClientConfig::new(Arc::new(QuicClientConfig::try_from(
+        tls,
+    )?)));
+    Ok(endpoint)
+}
+
+/// Connects to the QUIC listener of a bwlat server, returning how long the
+/// handshake took.
+pub(crate) async fn connect(
+    endpoint: &Endpoint,
+    server: SocketAddr,
+) -> Result<(Connection, Duration)> {
+    let start = Instant::now();
+    let connection = endpoint
+        .connect(server, SERVER_NAME)?
+        .await
+        .map_err(|e| eyre!("QUIC handshake with {} failed: {}", server, e))?;
+    Ok((connection, start.elapsed()))
+}
+
+/// Takes any certificate, the server makes up a new self-signed one on every
+/// start. Encryption is part of what is measured, authenticating the server is
+/// not. The handshake signatures are still checked.
+#[derive(Debug)]
+struct AnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// QUIC listener of the server. Echoes every bidirectional stream, the probes
+/// of a latency test, and drains every unidirectional one, a bandwidth test.
+pub(crate) struct QuicSink {
+    port: u16,
+    bind: Option<IpAddr>,
+    auth: Option<Arc<Authenticator>>,
+}
+
+impl QuicSink {
+    pub(crate) fn new(port: u16) -> Self {
+        Self {
+            port,
+            bind: None,
+            auth: None,
+        }
+    }
+
+    /// Listen on this address only instead of all IPv6 and IPv4 addresses.
+    pub(crate) fn with_bind_address(mut self, address: IpAddr) -> Self {
+        self.bind = Some(address);
+        self
+    }
+
+    /// Only accept connections from clients that authenticated over TCP.
+    pub(crate) fn with_authenticator(mut self, auth: Arc<Authenticator>) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    pub(crate) async fn run(&self) -> Result<()> {
+        let endpoint = self.endpoint()?;
+        info!("QUIC listening on port {}", self.port);
+
+        while let Some(incoming) = endpoint.accept().await {
+            let peer = listen::canonical(incoming.remote_address());
+            if self
+                .auth
+                .as_ref()
+                .is_some_and(|auth| !auth.admit(peer.ip()))
+            {
+                debug!("Refusing QUIC connection from unauthenticated {}", peer);
+                incoming.refuse();
+                continue;
+            }
+
+            tokio::spawn(async move {
+                let served = match incoming.await {
+                    Ok(connection) => serve(connection, peer).await,
+                    Err(e) => Err(e.into()),
+                };
+                if let Err(e) = served {
+                    warn!("QUIC connection from {} failed: {}", peer, e);
+                }
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Endpoint with a fresh self-signed certificate.
+    fn endpoint(&self) -> Result<Endpoint> {
+        let certified = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_string()])?;
+        let certificate = CertificateDer::from(certified.cert);
+        let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+
+        let mut tls = rustls::ServerConfig::builder_with_provider(provider())
+            .with_protocol_versions(&[&rustls::version::TLS13])?
+            .with_no_client_auth()
+            .with_single_cert(vec![certificate], key.into())?;
+        tls.alpn_protocols = vec![ALPN.to_vec()];
+        let config = ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(tls)?));
+
+        Ok(Endpoint::new(
+            EndpointConfig::default(),
+            Some(config),
+            listen::quic(self.bind, self.port)?,
+            Arc::new(TokioRuntime),
+        )?)
+    }
+}
+
+/// Serves the streams of one connection until the client closes it.
+async fn serve(connection: Connection, peer: SocketAddr) -> Result<()> {
+    debug!("QUIC connection from {}", peer);
+    loop {
+        tokio::select! {
+            stream = connection.accept_bi() => {
+                let Some((send, recv)) = accepted(stream)? else { break };
+                tokio::spawn(async move {
+                    if let Err(e) = echo(send, recv, peer).await {
+                        warn!("QUIC latency test from {} failed: {}", peer, e);
+                    }
+                });
+            }
+            stream = connection.accept_uni() => {
+                let Some(recv) = accepted(stream)? else { break };
+                tokio::spawn(async move {
+                    if let Err(e) = drain(recv, peer).await {
+                        warn!("QUIC bandwidth test from {} failed: {}", peer, e);
+                    }
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The accepted stream, or `None` once the client closed the connection.
+fn accepted<T>(stream: Result<T, quinn::ConnectionError>) -> Result<Option<T>> {
+    match stream {
+        Ok(stream) => Ok(Some(stream)),
+        Err(quinn::ConnectionError::ApplicationClosed(_))
+        | Err(quinn::ConnectionError::LocallyClosed)
+        | Err(quinn::ConnectionError::TimedOut) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn echo(mut send: SendStream, mut recv: RecvStream, peer: SocketAddr) -> Result<()> {
+    debug!("QUIC latency test from {}", peer);
+    // Latency tests end by closing the connection rather than the stream
+    match io::copy(&mut recv, &mut send).await {
+        Ok(echoed) => debug!("Echoed {} bytes to {}", echoed, peer),
+        Err(e) if e.kind() == io::ErrorKind::NotConnected => {
+            debug!("QUIC latency test from {} ended", peer)
+        }
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
+
+async fn drain(mut recv: RecvStream, peer: SocketAddr) -> Result<()> {
+    debug!("QUIC bandwidth test from {}", peer);
+    let start = Instant::now();
+    let total = io::copy(&mut recv, &mut io::sink()).await?;
+
+    let elapsed = start.elapsed();
+    info!(
+        "Received {} bytes over QUIC from {} in {:.2?} ({})",
+        total,
+        peer,
+        elapsed,
+        format_bitrate(total as f64 * 8.0 / elapsed.as_secs_f64().max(f64::EPSILON))
+    );
+    Ok(())
+}
+
+/// Ends a bulk transfer: waits for the server to acknowledge the data still in
+/// flight, up to [`FINISH_TIMEOUT`], then closes the connection.
+pub(crate) async fn finish(endpoint: &Endpoint, connection: &Connection, mut stream: SendStream) {
+    if stream.finish().is_ok() {
+        let _ = tokio::time::timeout(FINISH_TIMEOUT, stream.stopped()).await;
+    }
+    connection.close(0u32.into(), b"done");
+    endpoint.wait_idle().await;
+}
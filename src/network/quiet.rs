@@ -0,0 +1,103 @@
+use std::{fmt, time::Duration};
+
+/// How often the engines check whether a quiet window began or ended.
+pub(crate) const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// What quiet hours hold back.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum QuietPolicy {
+    /// Pause the latency probes, their send slots are recorded as skipped, and
+    /// skip bandwidth tests
+    #[default]
+    Pause,
+    /// Keep probing latency, only skip bandwidth tests
+    SkipBandwidth,
+}
+
+/// A daily window of local time, e.g. a backup window from 02:00 to 03:00.
+/// Windows ending before they start, like 23:00-01:00, span midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct QuietWindow {
+    /// Minutes since midnight
+    start: u32,
+    end: u32,
+}
+
+impl QuietWindow {
+    pub(crate) fn contains(&self, minute: u32) -> bool {
+        match self.start <= self.end {
+            true => (self.start..self.end).contains(&minute),
+            false => minute >= self.start || minute < self.end,
+        }
+    }
+}
+
+impl fmt::Display for QuietWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60
+        )
+    }
+}
+
+/// Parses a window given as `HH:MM-HH:MM`.
+pub(crate) fn parse_window(s: &str) -> Result<QuietWindow, String> {
+    let minute = |time: &str| {
+        let (hours, minutes) = time
+            .trim()
+            .split_once(':')
+            .ok_or_else(|| format!("{} is not a time of day like 02:30", time))?;
+        let hours: u32 = hours.parse().map_err(|_| format!("bad hour in {}", time))?;
+        let minutes: u32 = minutes
+            .parse()
+            .map_err(|_| format!("bad minute in {}", time))?;
+        match hours <= 24 && minutes < 60 && hours * 60 + minutes <= MINUTES_PER_DAY {
+            true => Ok((hours * 60 + minutes) % MINUTES_PER_DAY),
+            false => Err(format!("{} is not a time of day", time)),
+        }
+    };
+    let (start, end) = s
+        .split_once('-')
+        .ok_or_else(|| "quiet hours are given as HH:MM-HH:MM".to_string())?;
+    let window = QuietWindow {
+        start: minute(start)?,
+        end: minute(end)?,
+    };
+    match window.start == window.end {
+        true => Err("the window is empty".to_string()),
+        false => Ok(window),
+    }
+}
+
+/// The window local time is in right now, if any.
+pub(crate) fn active(windows: &[QuietWindow]) -> Option<QuietWindow> {
+    let minute = local_minute()?;
+    windows.iter().copied().find(|w| w.contains(minute))
+}
+
+/// Minutes since local midnight.
+#[cfg(unix)]
+fn local_minute() -> Option<u32> {
+    let now = unsafe { libc::time(std::ptr::null_mut()) };
+    let mut local: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&now, &mut local) }.is_null() {
+        return None;
+    }
+    Some(local.tm_hour as u32 * 60 + local.tm_min as u32)
+}
+
+/// Windows are read as UTC where the local time zone is not known.
+#[cfg(not(unix))]
+fn local_minute() -> Option<u32> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?;
+    Some((now.as_secs() / 60 % MINUTES_PER_DAY as u64) as u32)
+}
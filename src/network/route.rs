@@ -0,0 +1,293 @@
+use std::{fmt, net::IpAddr};
+
+use color_eyre::eyre::Result;
+
+/// Route the kernel selected for a destination.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Route {
+    pub gateway: Option<IpAddr>,
+    pub interface: Option<String>,
+    pub source: Option<IpAddr>,
+    /// The interface is a VPN or other tunnel
+    pub tunnel: bool,
+}
+
+impl fmt::Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.gateway {
+            Some(gateway) => write!(f, "via {}", gateway)?,
+            None => write!(f, "direct")?,
+        }
+        if let Some(ref interface) = self.interface {
+            write!(f, " dev {}", interface)?;
+        }
+        if let Some(source) = self.source {
+            write!(f, " src {}", source)?;
+        }
+        if self.tunnel {
+            write!(f, " (tunnel)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Asks the kernel which route it would use for `destination` (`ip route get`).
+#[cfg(target_os = "linux")]
+pub(crate) fn lookup(destination: IpAddr) -> Result<Route> {
+    use std::{
+        io,
+        net::{Ipv4Addr, Ipv6Addr},
+    };
+
+    use color_eyre::eyre::bail;
+
+    const NLMSG_HDRLEN: usize = std::mem::size_of::<libc::nlmsghdr>();
+    // struct rtmsg: family, dst_len, src_len, tos, table, protocol, scope, type, flags
+    const RTMSG_LEN: usize = 12;
+
+    let (family, address) = match destination {
+        IpAddr::V4(a) => (libc::AF_INET, a.octets().to_vec()),
+        IpAddr::V6(a) => (libc::AF_INET6, a.octets().to_vec()),
+    };
+
+    let rta_len = 4 + address.len();
+    let len = NLMSG_HDRLEN + RTMSG_LEN + align(rta_len);
+
+    let mut request = Vec::with_capacity(len);
+    request.extend_from_slice(&(len as u32).to_ne_bytes());
+    request.extend_from_slice(&libc::RTM_GETROUTE.to_ne_bytes());
+    request.extend_from_slice(&(libc::NLM_F_REQUEST as u16).to_ne_bytes());
+    request.extend_from_slice(&1u32.to_ne_bytes()); // sequence
+    request.extend_from_slice(&0u32.to_ne_bytes()); // port id
+    request.extend_from_slice(&[family as u8, address.len() as u8 * 8, 0, 0]);
+    request.extend_from_slice(&[0; RTMSG_LEN - 4]);
+    request.extend_from_slice(&(rta_len as u16).to_ne_bytes());
+    request.extend_from_slice(&libc::RTA_DST.to_ne_bytes());
+    request.extend_from_slice(&address);
+    request.resize(len, 0);
+
+    let mut response = vec![0u8; 8192];
+    let received = unsafe {
+        let fd = libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+            libc::NETLINK_ROUTE,
+        );
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let sent = libc::send(fd, request.as_ptr().cast(), request.len(), 0);
+        let received = if sent < 0 {
+            -1
+        } else {
+            libc::recv(fd, response.as_mut_ptr().cast(), response.len(), 0)
+        };
+        let error = io::Error::last_os_error();
+        libc::close(fd);
+
+        if received < 0 {
+            return Err(error.into());
+        }
+        received as usize
+    };
+
+    let response = &response[..received];
+    if response.len() < NLMSG_HDRLEN + 4 {
+        bail!("Truncated netlink response");
+    }
+
+    let message_type = u16::from_ne_bytes([response[4], response[5]]);
+    if message_type == libc::NLMSG_ERROR as u16 {
+        let code = i32::from_ne_bytes(response[NLMSG_HDRLEN..NLMSG_HDRLEN + 4].try_into()?);
+        return Err(io::Error::from_raw_os_error(-code).into());
+    }
+
+    let to_address = |data: &[u8]| -> Option<IpAddr> {
+        match data.len() {
+            4 => Some(Ipv4Addr::from(<[u8; 4]>::try_from(data).ok()?).into()),
+            16 => Some(Ipv6Addr::from(<[u8; 16]>::try_from(data).ok()?).into()),
+            _ => None,
+        }
+    };
+
+    let mut route = Route {
+        gateway: None,
+        interface: None,
+        source: None,
+        tunnel: false,
+    };
+
+    let mut attributes = &response[(NLMSG_HDRLEN + RTMSG_LEN).min(response.len())..];
+    while attributes.len() >= 4 {
+        let attr_len = u16::from_ne_bytes([attributes[0], attributes[1]]) as usize;
+        let attr_type = u16::from_ne_bytes([attributes[2], attributes[3]]);
+        if attr_len < 4 || attr_len > attributes.len() {
+            break;
+        }
+
+        let data = &attributes[4..attr_len];
+        match attr_type {
+            libc::RTA_GATEWAY => route.gateway = to_address(data),
+            libc::RTA_PREFSRC => route.source = to_address(data),
+            libc::RTA_OIF if data.len() == 4 => {
+                let index = u32::from_ne_bytes(data.try_into()?);
+                route.interface = interface_name(index);
+            }
+            _ => {}
+        }
+
+        attributes = &attributes[align(attr_len).min(attributes.len())..];
+    }
+    route.tunnel = route.interface.as_deref().is_some_and(is_tunnel);
+
+    Ok(route)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn lookup(_destination: IpAddr) -> Result<Route> {
+    color_eyre::eyre::bail!("Route lookup is only supported on Linux")
+}
+
+/// Notices route and link changes through netlink as they happen, instead of at
+/// the next poll.
+#[cfg(target_os = "linux")]
+pub(crate) struct RouteWatch {
+    socket: tokio::io::unix::AsyncFd<std::os::fd::OwnedFd>,
+}
+
+#[cfg(target_os = "linux")]
+impl RouteWatch {
+    pub(crate) fn new() -> Result<Self> {
+        use std::{
+            io,
+            os::fd::{FromRawFd, OwnedFd},
+        };
+
+        let socket = unsafe {
+            let fd = libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK,
+                libc::NETLINK_ROUTE,
+            );
+            if fd < 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            let socket = OwnedFd::from_raw_fd(fd);
+
+            let mut address: libc::sockaddr_nl = std::mem::zeroed();
+            address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            address.nl_groups =
+                (libc::RTMGRP_LINK | libc::RTMGRP_IPV4_ROUTE | libc::RTMGRP_IPV6_ROUTE) as u32;
+            let bound = libc::bind(
+                fd,
+                &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            );
+            if bound < 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            socket
+        };
+
+        Ok(Self {
+            socket: tokio::io::unix::AsyncFd::new(socket)?,
+        })
+    }
+
+    /// Waits until routes or links changed. Notifications that arrive together
+    /// are consumed at once, one lookup covers all of them.
+    pub(crate) async fn changed(&self) -> std::io::Result<()> {
+        use std::{io, os::fd::AsRawFd};
+
+        let mut buf = [0u8; 8192];
+        loop {
+            let mut guard = self.socket.readable().await?;
+            let mut changed = false;
+            loop {
+                let received = unsafe {
+                    libc::recv(
+                        self.socket.get_ref().as_raw_fd(),
+                        buf.as_mut_ptr().cast(),
+                        buf.len(),
+                        0,
+                    )
+                };
+                if received >= 0 {
+                    changed = true;
+                    continue;
+                }
+
+                let error = io::Error::last_os_error();
+                match error.kind() {
+                    io::ErrorKind::WouldBlock => {
+                        guard.clear_ready();
+                        break;
+                    }
+                    // The kernel dropped notifications, something changed anyway
+                    _ if error.raw_os_error() == Some(libc::ENOBUFS) => changed = true,
+                    _ => return Err(error),
+                }
+            }
+
+            if changed {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) struct RouteWatch;
+
+#[cfg(not(target_os = "linux"))]
+impl RouteWatch {
+    pub(crate) fn new() -> Result<Self> {
+        color_eyre::eyre::bail!("Watching routes is only supported on Linux")
+    }
+
+    pub(crate) async fn changed(&self) -> std::io::Result<()> {
+        std::future::pending().await
+    }
+}
+
+/// Whether an interface is a VPN or other tunnel. Tun devices and WireGuard have
+/// no link layer, the others are PPP, IP-in-IP and GRE devices.
+#[cfg(target_os = "linux")]
+fn is_tunnel(interface: &str) -> bool {
+    let device = std::path::Path::new("/sys/class/net").join(interface);
+    // Tap devices look like Ethernet, but are backed by a VPN process all the same
+    if device.join("tun_flags").exists() {
+        return true;
+    }
+
+    let Ok(kind) = std::fs::read_to_string(device.join("type")) else {
+        return false;
+    };
+    matches!(
+        kind.trim().parse::<u16>(),
+        Ok(libc::ARPHRD_NONE
+            | libc::ARPHRD_PPP
+            | libc::ARPHRD_TUNNEL
+            | libc::ARPHRD_TUNNEL6
+            | libc::ARPHRD_SIT
+            | libc::ARPHRD_IPGRE)
+    )
+}
+
+#[cfg(target_os = "linux")]
+fn interface_name(index: u32) -> Option<String> {
+    let mut buf = [0 as libc::c_char; libc::IF_NAMESIZE];
+    let name = unsafe { libc::if_indextoname(index, buf.as_mut_ptr()) };
+    if name.is_null() {
+        return None;
+    }
+
+    let name = unsafe { std::ffi::CStr::from_ptr(name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+#[cfg(target_os = "linux")]
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
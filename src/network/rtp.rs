@@ -0,0 +1,159 @@
+use std::time::Duration;
+
+use super::latency::{PacketStatus, State, StreamProfile};
+
+/// Transmission rating of a connection without impairments, ITU-T G.107.
+const R_DEFAULT: f64 = 93.2;
+/// Mouth-to-ear delay beyond which conversation suffers noticeably.
+const DELAY_KNEE_MS: f64 = 177.3;
+
+/// What the E-model needs to know about a codec.
+struct Codec {
+    /// Time the encoder looks ahead before it can emit a frame
+    lookahead: Duration,
+    /// Equipment impairment factor and packet-loss robustness of ITU-T G.113
+    impairment: f64,
+    robustness: f64,
+}
+
+impl Codec {
+    /// `None` for profiles that do not carry voice.
+    fn of(profile: StreamProfile) -> Option<Self> {
+        match profile {
+            // With packet loss concealment
+            StreamProfile::RtpG711 => Some(Codec {
+                lookahead: Duration::ZERO,
+                impairment: 0.0,
+                robustness: 25.1,
+            }),
+            StreamProfile::RtpG729 => Some(Codec {
+                lookahead: Duration::from_millis(5),
+                impairment: 11.0,
+                robustness: 19.0,
+            }),
+            StreamProfile::Game => None,
+        }
+    }
+}
+
+/// What a call with a fixed jitter buffer would have experienced on the probed
+/// path. One-way figures are half the round trip, assuming a symmetric path.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CallQuality {
+    /// Frames that were due, excluding skipped send slots
+    pub frames: u32,
+    pub lost: u32,
+    /// Frames that arrived after their playout time and were discarded
+    pub late: u32,
+    /// Times the buffer ran dry, consecutive missing frames count once
+    pub underruns: u32,
+    /// Interarrival jitter of RFC 3550
+    pub jitter: Duration,
+    pub mouth_to_ear: Duration,
+    /// E-model transmission rating, 0-100
+    pub r_factor: f64,
+    /// Mean opinion score estimated from `r_factor`, 1-4.5
+    pub mos: f64,
+}
+
+impl CallQuality {
+    /// Plays out the received probes through a jitter buffer of `buffer` on top of
+    /// the fastest one-way delay. `None` until a probe was answered, or for
+    /// profiles other than RTP.
+    pub(crate) fn estimate(
+        state: &State,
+        profile: StreamProfile,
+        buffer: Duration,
+    ) -> Option<Self> {
+        let codec = Codec::of(profile)?;
+        if state.received_packets == 0 {
+            return None;
+        }
+        let base = state.min_latency / 2;
+
+        let mut quality = CallQuality {
+            frames: 0,
+            lost: 0,
+            late: 0,
+            underruns: 0,
+            jitter: Duration::ZERO,
+            mouth_to_ear: base + buffer + profile.interval() + codec.lookahead,
+            r_factor: 0.0,
+            mos: 0.0,
+        };
+
+        let mut jitter = 0.0;
+        let mut previous_transit: Option<f64> = None;
+        let mut missing = false;
+        for packet in state.packets.iter() {
+            let played = match *packet {
+                PacketStatus::Skipped(_) | PacketStatus::Invalid(_) | PacketStatus::Warmup(_) => {
+                    continue
+                }
+                PacketStatus::Sent(_) => {
+                    quality.lost += 1;
+                    false
+                }
+                PacketStatus::Received { latency, .. } => {
+                    let transit = (latency / 2).as_secs_f64();
+                    if let Some(previous) = previous_transit {
+                        jitter += ((transit - previous).abs() - jitter) / 16.0;
+                    }
+                    previous_transit = Some(transit);
+
+                    let on_time = latency / 2 <= base + buffer;
+                    if !on_time {
+                        quality.late += 1;
+                    }
+                    on_time
+                }
+            };
+
+            quality.frames += 1;
+            if !played && !missing {
+                quality.underruns += 1;
+            }
+            missing = !played;
+        }
+        quality.jitter = Duration::from_secs_f64(jitter);
+
+        let loss = quality.effective_loss() * 100.0;
+        let effective_impairment =
+            codec.impairment + (95.0 - codec.impairment) * loss / (loss + codec.robustness);
+
+        let delay = quality.mouth_to_ear.as_secs_f64() * 1000.0;
+        let delay_impairment = 0.024 * delay + 0.11 * (delay - DELAY_KNEE_MS).max(0.0);
+
+        quality.r_factor = (R_DEFAULT - delay_impairment - effective_impairment).clamp(0.0, 100.0);
+        quality.mos = mos(quality.r_factor);
+
+        Some(quality)
+    }
+
+    /// Share of the frames the listener did not hear, lost or too late.
+    pub(crate) fn effective_loss(&self) -> f64 {
+        (self.lost + self.late) as f64 / self.frames.max(1) as f64
+    }
+
+    /// Rating of the call by the user satisfaction bands of ITU-T G.109.
+    pub(crate) fn rating(&self) -> &'static str {
+        match self.r_factor {
+            r if r >= 90.0 => "best",
+            r if r >= 80.0 => "high",
+            r if r >= 70.0 => "medium",
+            r if r >= 60.0 => "low",
+            _ => "poor",
+        }
+    }
+}
+
+/// Conversion of the E-model rating to a mean opinion score, ITU-T G.107 Annex B.
+fn mos(r: f64) -> f64 {
+    if r <= 0.0 {
+        return 1.0;
+    }
+    if r >= 100.0 {
+        return 4.5;
+    }
+    1.0 + 0.035 * r + 7e-6 * r * (r - 60.0) * (100.0 - r)
+}
@@ -0,0 +1,101 @@
+use std::{fmt, time::Duration};
+
+use super::bandwidth::{format_bitrate, BandwidthSample};
+
+/// An interval counts as saturated once less than this share of the offered
+/// traffic arrives.
+const SATURATED_DELIVERY: f64 = 0.9;
+
+/// Queueing delay above the baseline RTT that points to a shaper's buffer.
+const MIN_QUEUEING: Duration = Duration::from_millis(5);
+
+/// How the path limits a UDP sender, judged from the bandwidth samples.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum RateLimit {
+    /// Everything offered arrived, up to this rate in bits per second
+    None { max_offered: u64 },
+    /// Excess traffic is dropped without queueing
+    Policer { rate: u64, loss: f64 },
+    /// Excess traffic is queued, delaying it, before anything is dropped
+    Shaper {
+        rate: u64,
+        queueing: Duration,
+        loss: f64,
+    },
+}
+
+impl RateLimit {
+    /// Classifies the samples of a UDP test, ideally a ramp that crossed the
+    /// limit. `None` without UDP samples.
+    pub(crate) fn detect(samples: &[BandwidthSample]) -> Option<Self> {
+        let samples: Vec<_> = samples
+            .iter()
+            .filter(|s| s.offered.is_some_and(|o| o > 0))
+            .collect();
+        let offered = |s: &BandwidthSample| s.offered.unwrap_or_default();
+
+        let baseline = samples.iter().map(|s| s.rtt).min()?;
+        let saturated: Vec<_> = samples
+            .iter()
+            .copied()
+            .filter(|s| (s.bits_per_second as f64) < offered(s) as f64 * SATURATED_DELIVERY)
+            .collect();
+
+        if saturated.is_empty() {
+            return Some(RateLimit::None {
+                max_offered: samples.iter().map(|s| offered(s)).max()?,
+            });
+        }
+
+        let rate = median(saturated.iter().map(|s| s.bits_per_second).collect());
+        let loss = 1.0
+            - saturated.iter().map(|s| s.bits_per_second).sum::<u64>() as f64
+                / saturated.iter().map(|s| offered(s)).sum::<u64>() as f64;
+        let queueing = median(saturated.iter().map(|s| s.rtt).collect()).saturating_sub(baseline);
+
+        // A shaper's queue adds delay before it overflows, a policer drops right away
+        if queueing >= MIN_QUEUEING.max(baseline) {
+            Some(RateLimit::Shaper {
+                rate,
+                queueing,
+                loss,
+            })
+        } else {
+            Some(RateLimit::Policer { rate, loss })
+        }
+    }
+}
+
+impl fmt::Display for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            RateLimit::None { max_offered } => write!(
+                f,
+                "no rate limit up to {}, ramp higher to find one",
+                format_bitrate(max_offered as f64)
+            ),
+            RateLimit::Policer { rate, loss } => write!(
+                f,
+                "policer at about {}, excess traffic is dropped ({:.1}% loss) without added delay",
+                format_bitrate(rate as f64),
+                loss * 100.0
+            ),
+            RateLimit::Shaper {
+                rate,
+                queueing,
+                loss,
+            } => write!(
+                f,
+                "shaper at about {}, excess traffic is queued ({:.1?} added delay, {:.1}% loss)",
+                format_bitrate(rate as f64),
+                queueing,
+                loss * 100.0
+            ),
+        }
+    }
+}
+
+fn median<T: Ord + Copy>(mut values: Vec<T>) -> T {
+    values.sort();
+    values[values.len() / 2]
+}
@@ -0,0 +1,137 @@
+use std::{io, os::fd::AsRawFd, time::Duration};
+
+/// Subset of the kernel's `struct tcp_info` (linux/tcp.h), laid out up to
+/// `tcpi_delivery_rate`. Older kernels fill in less, the rest stays zeroed.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+#[allow(dead_code)]
+struct RawTcpInfo {
+    state: u8,
+    ca_state: u8,
+    retransmits: u8,
+    probes: u8,
+    backoff: u8,
+    options: u8,
+    wscale: u8,
+    flags: u8,
+
+    rto: u32,
+    ato: u32,
+    snd_mss: u32,
+    rcv_mss: u32,
+
+    unacked: u32,
+    sacked: u32,
+    lost: u32,
+    retrans: u32,
+    fackets: u32,
+
+    last_data_sent: u32,
+    last_ack_sent: u32,
+    last_data_recv: u32,
+    last_ack_recv: u32,
+
+    pmtu: u32,
+    rcv_ssthresh: u32,
+    rtt: u32,
+    rttvar: u32,
+    snd_ssthresh: u32,
+    snd_cwnd: u32,
+    advmss: u32,
+    reordering: u32,
+
+    rcv_rtt: u32,
+    rcv_space: u32,
+
+    total_retrans: u32,
+
+    pacing_rate: u64,
+    max_pacing_rate: u64,
+    bytes_acked: u64,
+    bytes_received: u64,
+    segs_out: u32,
+    segs_in: u32,
+
+    notsent_bytes: u32,
+    min_rtt: u32,
+    data_segs_in: u32,
+    data_segs_out: u32,
+
+    delivery_rate: u64,
+}
+
+/// Kernel view of a TCP connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TcpInfo {
+    pub rtt: Duration,
+    pub rttvar: Duration,
+    /// Congestion window in segments.
+    pub cwnd: u32,
+    pub total_retransmits: u32,
+    pub bytes_acked: u64,
+    /// Kernel estimate of the delivery rate in bytes per second.
+    pub delivery_rate: u64,
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn tcp_info(socket: &impl AsRawFd) -> io::Result<TcpInfo> {
+    let mut raw = RawTcpInfo::default();
+    let mut len = std::mem::size_of::<RawTcpInfo>() as libc::socklen_t;
+
+    let res = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            (&mut raw as *mut RawTcpInfo).cast(),
+            &mut len,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(TcpInfo {
+        rtt: Duration::from_micros(raw.rtt as u64),
+        rttvar: Duration::from_micros(raw.rttvar as u64),
+        cwnd: raw.snd_cwnd,
+        total_retransmits: raw.total_retrans,
+        bytes_acked: raw.bytes_acked,
+        delivery_rate: raw.delivery_rate,
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn tcp_info(_socket: &impl AsRawFd) -> io::Result<TcpInfo> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "TCP_INFO is only supported on Linux",
+    ))
+}
+
+/// Selects the congestion control algorithm (e.g. `bbr`, `cubic`) for a socket.
+#[cfg(target_os = "linux")]
+pub(crate) fn set_congestion_control(socket: &impl AsRawFd, algorithm: &str) -> io::Result<()> {
+    let res = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_CONGESTION,
+            algorithm.as_ptr().cast(),
+            algorithm.len() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn set_congestion_control(_socket: &impl AsRawFd, _algorithm: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Selecting the congestion control is only supported on Linux",
+    ))
+}
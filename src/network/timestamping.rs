@@ -0,0 +1,186 @@
+use std::{io, time::Duration};
+
+use tokio::net::UdpSocket;
+
+use super::protocol::{Header, MAGIC};
+
+/// Not exported by libc for Linux, the value of all architectures but MIPS,
+/// PA-RISC and SPARC.
+#[cfg(target_os = "linux")]
+const SO_TIMESTAMPING: libc::c_int = 37;
+
+/// Where the send and receive times of UDP probes are taken instead of in the
+/// client after the scheduler woke it up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum Timestamping {
+    /// By the kernel as the datagram passes the network stack
+    Software,
+    /// By the network card, and by the kernel for probes the card did not stamp.
+    /// Stamping has to be enabled on the interface, e.g. with hwstamp_ctl
+    Hardware,
+}
+
+/// Times the kernel took of one datagram, nanoseconds since the Unix epoch for
+/// software and on the card's clock for hardware timestamps.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct KernelTimestamp {
+    pub software: Option<i64>,
+    pub hardware: Option<i64>,
+}
+
+impl KernelTimestamp {
+    /// Time from `sent` to `self`, from the card's clock when it stamped both.
+    pub(crate) fn since(&self, sent: &KernelTimestamp) -> Option<Duration> {
+        let nanos = match (sent.hardware, self.hardware) {
+            (Some(sent), Some(received)) => received - sent,
+            _ => self.software? - sent.software?,
+        };
+        u64::try_from(nanos).ok().map(Duration::from_nanos)
+    }
+
+    /// Reads the payload of a `SCM_TIMESTAMPING` control message.
+    ///
+    /// # Safety
+    ///
+    /// `data` has to point to the data of a `SCM_TIMESTAMPING` control message.
+    #[cfg(target_os = "linux")]
+    unsafe fn read(data: *const u8) -> Self {
+        let stamps = std::ptr::read_unaligned(data as *const [libc::timespec; 3]);
+        let nanos = |ts: &libc::timespec| {
+            let nanos = Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32).as_nanos() as i64;
+            (nanos != 0).then_some(nanos)
+        };
+        Self {
+            software: nanos(&stamps[0]),
+            hardware: nanos(&stamps[2]),
+        }
+    }
+}
+
+/// Has the kernel stamp the datagrams `socket` sends and receives. Transmit
+/// timestamps are queued with the ICMP errors, [`icmp_error::drain`] returns
+/// both.
+///
+/// [`icmp_error::drain`]: super::icmp_error::drain
+#[cfg(target_os = "linux")]
+pub(crate) fn enable(socket: &UdpSocket, timestamping: Timestamping) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let mut flags = libc::SOF_TIMESTAMPING_SOFTWARE
+        | libc::SOF_TIMESTAMPING_RX_SOFTWARE
+        | libc::SOF_TIMESTAMPING_TX_SOFTWARE;
+    if timestamping == Timestamping::Hardware {
+        flags |= libc::SOF_TIMESTAMPING_RAW_HARDWARE
+            | libc::SOF_TIMESTAMPING_RX_HARDWARE
+            | libc::SOF_TIMESTAMPING_TX_HARDWARE;
+    }
+
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            SO_TIMESTAMPING,
+            &flags as *const libc::c_uint as *const libc::c_void,
+            std::mem::size_of::<libc::c_uint>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn enable(_socket: &UdpSocket, _timestamping: Timestamping) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "kernel timestamps need Linux",
+    ))
+}
+
+/// Receives a datagram into `buf` with the time the kernel received it, and
+/// tells whether the kernel cut it short because it did not fit.
+pub(crate) async fn recv(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, bool, Option<KernelTimestamp>)> {
+    loop {
+        socket.readable().await?;
+        match socket.try_io(tokio::io::Interest::READABLE, || try_recv(socket, buf)) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            received => return received,
+        }
+    }
+}
+
+/// Like [`recv`], without waiting for a datagram. Fails with `WouldBlock`
+/// when none is there.
+#[cfg(target_os = "linux")]
+pub(crate) fn try_recv(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, bool, Option<KernelTimestamp>)> {
+    use std::os::fd::AsRawFd;
+
+    let mut control = [0u8; 128];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let mut message: libc::msghdr = unsafe { std::mem::zeroed() };
+    message.msg_iov = &mut iov;
+    message.msg_iovlen = 1;
+    message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    message.msg_controllen = control.len() as _;
+
+    let size = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut message, libc::MSG_DONTWAIT) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut timestamp = None;
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&message) };
+    while !cmsg.is_null() {
+        let header = unsafe { &*cmsg };
+        if header.cmsg_level == libc::SOL_SOCKET && header.cmsg_type == SO_TIMESTAMPING {
+            timestamp = Some(unsafe { KernelTimestamp::read(libc::CMSG_DATA(cmsg)) });
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(&message, cmsg) };
+    }
+    let truncated = message.msg_flags & libc::MSG_TRUNC != 0;
+    Ok((size as usize, truncated, timestamp))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn try_recv(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, bool, Option<KernelTimestamp>)> {
+    let size = socket.try_recv(buf)?;
+    Ok((size, false, None))
+}
+
+/// Reads a transmit timestamp's control message, `None` for other messages.
+///
+/// # Safety
+///
+/// `cmsg` has to point to a valid control message.
+#[cfg(target_os = "linux")]
+pub(crate) unsafe fn read_sent(cmsg: *const libc::cmsghdr) -> Option<KernelTimestamp> {
+    let header = &*cmsg;
+    (header.cmsg_level == libc::SOL_SOCKET && header.cmsg_type == SO_TIMESTAMPING)
+        .then(|| KernelTimestamp::read(libc::CMSG_DATA(cmsg)))
+}
+
+/// Sequence number of the probe a transmit timestamp was looped back with. The
+/// kernel returns the datagram from the link layer header on, the probe starts
+/// at its magic.
+pub(crate) fn sent_probe(looped: &[u8]) -> Option<u64> {
+    looped
+        .windows(MAGIC.len())
+        .enumerate()
+        .filter(|(_, window)| window == MAGIC)
+        .find_map(|(start, _)| Header::decode(&looped[start..]).ok())
+        .map(|header| header.sequence)
+}
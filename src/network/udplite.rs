@@ -0,0 +1,74 @@
+use std::{io, net::SocketAddr};
+
+use socket2::{Domain, Socket, Type};
+use tokio::net::UdpSocket;
+
+/// IP protocol number of UDP-Lite.
+pub(crate) const IPPROTO_UDPLITE: libc::c_int = 136;
+
+/// UDP-Lite header, the part of a datagram the checksum always covers.
+pub(crate) const HEADER: u16 = 8;
+
+/// Coverage of the echoes the server sends: the header and the sequence number
+/// of the probe, so damaged payloads still arrive and can be matched.
+pub(crate) const ECHO_COVERAGE: u16 = HEADER + 8;
+
+#[cfg(target_os = "linux")]
+const SOL_UDPLITE: libc::c_int = IPPROTO_UDPLITE;
+#[cfg(target_os = "linux")]
+const UDPLITE_SEND_CSCOV: libc::c_int = 10;
+
+/// Opens a UDP-Lite socket whose datagrams have the first `coverage` bytes,
+/// header included, protected by the checksum. Damage past them is delivered
+/// instead of dropped. `None` covers the whole datagram like UDP.
+#[cfg(target_os = "linux")]
+pub(crate) fn bind(address: SocketAddr, coverage: Option<u16>) -> io::Result<UdpSocket> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::DGRAM,
+        Some(socket2::Protocol::from(IPPROTO_UDPLITE)),
+    )?;
+    if let Some(coverage) = coverage {
+        set_coverage(&socket, coverage)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+    UdpSocket::from_std(socket.into())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn bind(_address: SocketAddr, _coverage: Option<u16>) -> io::Result<UdpSocket> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "UDP-Lite is only supported on Linux",
+    ))
+}
+
+/// Changes the checksum coverage of the datagrams `socket` sends.
+#[cfg(target_os = "linux")]
+pub(crate) fn set_coverage(socket: &Socket, coverage: u16) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let value = coverage as libc::c_int;
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            SOL_UDPLITE,
+            UDPLITE_SEND_CSCOV,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn set_coverage(_socket: &Socket, _coverage: u16) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "UDP-Lite is only supported on Linux",
+    ))
+}
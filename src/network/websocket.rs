@@ -0,0 +1,108 @@
+use std::net::SocketAddr;
+
+use color_eyre::eyre::{bail, eyre, Result};
+use futures::{SinkExt, StreamExt};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tokio_tungstenite::{
+    tungstenite::{error::ProtocolError, Error, Message},
+    WebSocketStream,
+};
+use tracing::debug;
+
+/// Path of the upgrade request. The server upgrades any path, so a reverse
+/// proxy in front of it can route by its own.
+const PATH: &str = "/bwlat";
+
+/// First bytes of an upgrade request, which tell it apart from the other
+/// connections to the server's TCP port.
+pub(crate) const UPGRADE_METHOD: &[u8] = b"GET ";
+
+/// Bytes a binary frame adds to a probe of up to 125 bytes: the 2 byte header
+/// and the 4 byte mask of client frames, echoes are not masked.
+pub(crate) const WEBSOCKET_OVERHEAD: u64 = 2 + 4;
+
+/// Longest proxy response to a CONNECT request that is read.
+const MAX_PROXY_RESPONSE: usize = 8192;
+
+/// Asks the HTTP proxy at the other end of `stream` for a tunnel to `target`,
+/// the way browsers reach HTTPS sites through it.
+pub(crate) async fn tunnel(stream: &mut TcpStream, target: SocketAddr) -> Result<()> {
+    let request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\n", target);
+    stream.write_all(request.as_bytes()).await?;
+
+    // Byte by byte, whatever follows the headers already belongs to the tunnel
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() > MAX_PROXY_RESPONSE {
+            bail!("The proxy sent no complete response");
+        }
+        response.push(
+            stream
+                .read_u8()
+                .await
+                .map_err(|e| eyre!("The proxy closed the connection: {}", e))?,
+        );
+    }
+    let response = String::from_utf8_lossy(&response);
+    let status = response
+        .split_whitespace()
+        .nth(1)
+        .ok_or_else(|| eyre!("No HTTP response from the proxy"))?;
+    if !status.starts_with('2') {
+        let reason = response.lines().next().unwrap_or_default();
+        bail!("The proxy refused a tunnel to {}: {}", target, reason);
+    }
+
+    Ok(())
+}
+
+/// Upgrades `stream`, connected to the server or tunneled to it, to a
+/// WebSocket that carries the probes.
+pub(crate) async fn connect(
+    stream: TcpStream,
+    server: SocketAddr,
+) -> Result<WebSocketStream<TcpStream>> {
+    let url = format!("ws://{}{}", server, PATH);
+    let (websocket, _) = tokio_tungstenite::client_async(url, stream)
+        .await
+        .map_err(|e| eyre!("WebSocket upgrade with {} failed: {}", server, e))?;
+    Ok(websocket)
+}
+
+/// The socket error behind a WebSocket error, so network changes are still
+/// recognised as such.
+pub(crate) fn io_error(e: Error) -> std::io::Error {
+    match e {
+        Error::Io(e) => e,
+        e => std::io::Error::other(e),
+    }
+}
+
+/// Echoes every binary message of a WebSocket latency test back.
+pub(crate) async fn echo(stream: TcpStream, peer: SocketAddr) -> Result<()> {
+    stream.set_nodelay(true)?;
+    let mut websocket = tokio_tungstenite::accept_async(stream).await?;
+
+    debug!("WebSocket latency test from {}", peer);
+    let mut echoed = 0;
+    while let Some(message) = websocket.next().await {
+        match message {
+            Ok(Message::Binary(payload)) => {
+                echoed += payload.len();
+                websocket.send(Message::Binary(payload)).await?;
+            }
+            Ok(Message::Close(_)) => break,
+            Ok(_) => {}
+            // Clients end the test by dropping the connection
+            Err(Error::Protocol(ProtocolError::ResetWithoutClosingHandshake))
+            | Err(Error::ConnectionClosed) => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    debug!("Echoed {} bytes to {}", echoed, peer);
+
+    Ok(())
+}
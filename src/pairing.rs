@@ -0,0 +1,525 @@
+use std::{
+    collections::HashMap,
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+use color_eyre::eyre::{bail, eyre, Result};
+use curve25519_dalek::{
+    ristretto::{CompressedRistretto, RistrettoPoint},
+    traits::IsIdentity,
+    Scalar,
+};
+use directories::ProjectDirs;
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time,
+};
+use tracing::{info, warn};
+
+use crate::signing::{from_hex, to_hex};
+
+/// First bytes of a pairing or authentication connection to the TCP port.
+pub(crate) const PAIR_MAGIC: &[u8; 10] = b"bwlat-pair";
+pub(crate) const AUTH_MAGIC: &[u8; 10] = b"bwlat-auth";
+
+/// Letters and digits that cannot be mistaken for each other when read aloud.
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKMNPQRSTUVWXYZ23456789";
+const CODE_LENGTH: usize = 6;
+const CODE_VALIDITY: Duration = Duration::from_secs(10 * 60);
+/// Wrong guesses after which a pairing code is discarded.
+const CODE_ATTEMPTS: u32 = 3;
+
+/// An authenticated client may probe until it has been idle for this long.
+const LEASE: Duration = Duration::from_secs(5 * 60);
+
+const EXCHANGE_TIMEOUT: Duration = Duration::from_secs(5);
+
+const SERVERS_FILE: &str = "paired_servers.toml";
+const CLIENTS_FILE: &str = "paired_clients.toml";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Secret shared with a server, as remembered by the client.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct PairedServer {
+    /// `host:port` as given on the command line
+    pub server: String,
+    pub id: String,
+    pub secret: String,
+}
+
+/// Secret shared with a client, as remembered by the server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PairedClient {
+    id: String,
+    secret: String,
+    /// Address the client paired from, for reference
+    peer: String,
+    paired: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ServersFile {
+    #[serde(default)]
+    servers: Vec<PairedServer>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ClientsFile {
+    #[serde(default)]
+    clients: Vec<PairedClient>,
+}
+
+struct PendingCode {
+    code: String,
+    expires: Instant,
+    attempts: u32,
+}
+
+/// Server side of pairing and authentication. Hands out one-time pairing codes,
+/// remembers paired clients and tracks which addresses authenticated recently.
+pub(crate) struct Authenticator {
+    required: bool,
+    code: Mutex<Option<PendingCode>>,
+    clients: Mutex<Vec<PairedClient>>,
+    allowed: Mutex<HashMap<IpAddr, Instant>>,
+}
+
+impl Authenticator {
+    pub(crate) fn load(required: bool) -> Result<Self> {
+        let clients = match config_path(CLIENTS_FILE) {
+            Some(path) if path.exists() => {
+                toml::from_str::<ClientsFile>(&fs::read_to_string(&path)?)
+                    .map_err(|e| eyre!("Invalid {}: {}", path.display(), e))?
+                    .clients
+            }
+            _ => Vec::new(),
+        };
+        Ok(Self {
+            required,
+            code: Mutex::new(None),
+            clients: Mutex::new(clients),
+            allowed: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub(crate) fn has_clients(&self) -> bool {
+        !self.clients.lock().unwrap().is_empty()
+    }
+
+    /// Generates a new one-time pairing code, replacing any previous one.
+    pub(crate) fn new_code(&self) -> String {
+        let mut rng = OsRng;
+        let code: String = (0..CODE_LENGTH)
+            .map(|_| CODE_ALPHABET[rng.gen_range(0..CODE_ALPHABET.len())] as char)
+            .collect();
+
+        *self.code.lock().unwrap() = Some(PendingCode {
+            code: code.clone(),
+            expires: Instant::now() + CODE_VALIDITY,
+            attempts: 0,
+        });
+
+        format!("{}-{}", &code[..3], &code[3..])
+    }
+
+    /// Whether traffic from `ip` is served, refreshing its lease.
+    pub(crate) fn admit(&self, ip: IpAddr) -> bool {
+        if !self.required {
+            return true;
+        }
+
+        let mut allowed = self.allowed.lock().unwrap();
+        match allowed.get_mut(&ip) {
+            Some(seen) if seen.elapsed() < LEASE => {
+                *seen = Instant::now();
+                true
+            }
+            Some(_) => {
+                allowed.remove(&ip);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Whether `ip` paired or authenticated within its lease, also when
+    /// authentication is not required.
+    pub(crate) fn authenticated(&self, ip: IpAddr) -> bool {
+        self.allowed
+            .lock()
+            .unwrap()
+            .get(&ip)
+            .is_some_and(|seen| seen.elapsed() < LEASE)
+    }
+
+    /// Serves a connection that started with [`PAIR_MAGIC`] or [`AUTH_MAGIC`].
+    pub(crate) async fn handle(&self, mut stream: TcpStream, peer: SocketAddr) -> Result<()> {
+        let mut magic = [0; 10];
+        stream.read_exact(&mut magic).await?;
+
+        time::timeout(EXCHANGE_TIMEOUT, async {
+            match &magic {
+                PAIR_MAGIC => self.pair(&mut stream, peer).await,
+                AUTH_MAGIC => self.authenticate(&mut stream, peer).await,
+                _ => bail!("Unknown request"),
+            }
+        })
+        .await
+        .map_err(|_| eyre!("Timed out"))?
+    }
+
+    async fn pair(&self, stream: &mut TcpStream, peer: SocketAddr) -> Result<()> {
+        let mut client_public = [0; 32];
+        stream.read_exact(&mut client_public).await?;
+
+        // The attempt counts before the client can prove anything, so parallel
+        // connections cannot guess more often than allowed
+        let code = match *self.code.lock().unwrap() {
+            Some(ref mut pending)
+                if pending.expires > Instant::now() && pending.attempts < CODE_ATTEMPTS =>
+            {
+                pending.attempts += 1;
+                pending.code.clone()
+            }
+            _ => bail!("No pairing code is active"),
+        };
+
+        let exchange = Cpace::new(&code);
+        let secret = exchange.finish(&client_public);
+        // The server confirms first, the client learns nothing about the code
+        // from it beyond whether its one guess was right
+        let confirmation = match secret {
+            Some(ref secret) => mac(secret, b"server"),
+            None => [0; 32],
+        };
+        stream
+            .write_all(&[&exchange.public[..], &confirmation].concat())
+            .await?;
+
+        let mut client_confirmation = [0; 32];
+        stream.read_exact(&mut client_confirmation).await?;
+        let secret = match secret {
+            Some(secret) if verify(&secret, b"client", &client_confirmation) => secret,
+            _ => {
+                let mut pending = self.code.lock().unwrap();
+                if pending
+                    .as_ref()
+                    .is_some_and(|p| p.code == code && p.attempts >= CODE_ATTEMPTS)
+                {
+                    warn!("Too many wrong pairing codes, the code is no longer valid");
+                    *pending = None;
+                }
+                bail!("Wrong pairing code");
+            }
+        };
+        {
+            let mut pending = self.code.lock().unwrap();
+            match pending.take() {
+                Some(p) if p.code == code => {}
+                other => {
+                    *pending = other;
+                    bail!("The pairing code was already used");
+                }
+            }
+        }
+
+        let mut id = [0; 8];
+        OsRng.fill_bytes(&mut id);
+        stream.write_all(&id).await?;
+
+        let client = PairedClient {
+            id: to_hex(&id),
+            secret: to_hex(&secret),
+            peer: peer.to_string(),
+            paired: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        };
+        let clients = {
+            let mut clients = self.clients.lock().unwrap();
+            clients.push(client);
+            ClientsFile {
+                clients: clients.clone(),
+            }
+        };
+        save(CLIENTS_FILE, &toml::to_string(&clients)?)?;
+        self.allowed
+            .lock()
+            .unwrap()
+            .insert(peer.ip(), Instant::now());
+
+        info!("Paired with client {} ({})", to_hex(&id), peer);
+
+        Ok(())
+    }
+
+    async fn authenticate(&self, stream: &mut TcpStream, peer: SocketAddr) -> Result<()> {
+        let mut id = [0; 8];
+        stream.read_exact(&mut id).await?;
+
+        let mut challenge = [0; 32];
+        OsRng.fill_bytes(&mut challenge);
+        stream.write_all(&challenge).await?;
+
+        let mut response = [0; 32];
+        stream.read_exact(&mut response).await?;
+
+        let id = to_hex(&id);
+        let secret = self
+            .clients
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|c| c.id == id)
+            .and_then(|c| from_hex::<32>(&c.secret).ok());
+
+        let accepted = secret.is_some_and(|secret| verify(&secret, &challenge, &response));
+        stream.write_all(&[accepted as u8]).await?;
+
+        if !accepted {
+            bail!("Authentication of {} failed", id);
+        }
+        self.allowed
+            .lock()
+            .unwrap()
+            .insert(peer.ip(), Instant::now());
+
+        Ok(())
+    }
+}
+
+/// Pairs with the server using the code it printed and remembers the resulting
+/// secret under `server`.
+pub(crate) async fn pair(address: SocketAddr, server: &str, code: &str) -> Result<PairedServer> {
+    let code: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    if code.len() != CODE_LENGTH {
+        bail!("A pairing code has {} letters and digits", CODE_LENGTH);
+    }
+
+    let mut stream = connect(address).await?;
+    let exchange = async {
+        let exchange = Cpace::new(&code);
+        // In one write, the server tells pairing from bandwidth tests by peeking
+        stream
+            .write_all(&[&PAIR_MAGIC[..], &exchange.public].concat())
+            .await?;
+
+        let mut server_public = [0; 32];
+        stream
+            .read_exact(&mut server_public)
+            .await
+            .map_err(|_| eyre!("The server is not accepting pairing, start it with --pair"))?;
+        let mut confirmation = [0; 32];
+        stream.read_exact(&mut confirmation).await?;
+
+        let secret = match exchange.finish(&server_public) {
+            Some(secret) if verify(&secret, b"server", &confirmation) => secret,
+            _ => bail!("Pairing was rejected, check the code"),
+        };
+        stream.write_all(&mac(&secret, b"client")).await?;
+
+        let mut id = [0; 8];
+        stream
+            .read_exact(&mut id)
+            .await
+            .map_err(|_| eyre!("Pairing was rejected by the server"))?;
+
+        Ok(PairedServer {
+            server: server.to_string(),
+            id: to_hex(&id),
+            secret: to_hex(&secret),
+        })
+    };
+    let paired = time::timeout(EXCHANGE_TIMEOUT, exchange)
+        .await
+        .map_err(|_| eyre!("Pairing with {} timed out", address))??;
+
+    let mut file = load_servers()?;
+    file.servers.retain(|s| s.server != paired.server);
+    file.servers.push(paired.clone());
+    save(SERVERS_FILE, &toml::to_string(&file)?)?;
+
+    Ok(paired)
+}
+
+/// The secret remembered for `server`, if the client paired with it.
+pub(crate) fn paired_server(server: &str) -> Result<Option<PairedServer>> {
+    Ok(load_servers()?
+        .servers
+        .into_iter()
+        .find(|s| s.server == server))
+}
+
+/// Proves to the server that this client knows the paired secret, after which
+/// the server serves its probes.
+pub(crate) async fn authenticate(address: SocketAddr, paired: &PairedServer) -> Result<()> {
+    let id = from_hex::<8>(&paired.id)?;
+    let secret = from_hex::<32>(&paired.secret)?;
+
+    let mut stream = connect(address).await?;
+    let exchange = async {
+        stream.write_all(&[&AUTH_MAGIC[..], &id].concat()).await?;
+
+        let mut challenge = [0; 32];
+        stream.read_exact(&mut challenge).await?;
+        stream.write_all(&mac(&secret, &challenge)).await?;
+
+        let mut accepted = [0; 1];
+        stream.read_exact(&mut accepted).await?;
+        Ok::<_, color_eyre::eyre::Report>(accepted[0] == 1)
+    };
+
+    let accepted = time::timeout(EXCHANGE_TIMEOUT, exchange)
+        .await
+        .map_err(|_| eyre!("Authentication with {} timed out", address))??;
+    if !accepted {
+        bail!(
+            "{} did not accept the paired secret, pair again with --pair",
+            address
+        );
+    }
+
+    Ok(())
+}
+
+async fn connect(address: SocketAddr) -> Result<TcpStream> {
+    time::timeout(EXCHANGE_TIMEOUT, TcpStream::connect(address))
+        .await
+        .map_err(|_| eyre!("Could not connect to {}", address))?
+        .map_err(|e| eyre!("Could not connect to {}: {}", address, e))
+}
+
+/// One side of a CPace exchange. The code picks the generator, so only parties
+/// that know it end up with the same secret, and a wrong guess can only be
+/// tested against the live peer rather than offline from a transcript.
+struct Cpace {
+    scalar: Scalar,
+    public: [u8; 32],
+}
+
+impl Cpace {
+    fn new(code: &str) -> Self {
+        let generator = RistrettoPoint::from_uniform_bytes(
+            &Sha512::new()
+                .chain_update(b"bwlat-pairing-v2")
+                .chain_update(code.as_bytes())
+                .finalize()
+                .into(),
+        );
+        let mut wide = [0; 64];
+        OsRng.fill_bytes(&mut wide);
+        let scalar = Scalar::from_bytes_mod_order_wide(&wide);
+
+        Self {
+            public: (generator * scalar).compress().to_bytes(),
+            scalar,
+        }
+    }
+
+    /// The secret shared with the peer that sent `peer`, bound to both public
+    /// values of the exchange. None if `peer` is not a valid point.
+    fn finish(&self, peer: &[u8; 32]) -> Option<[u8; 32]> {
+        let shared = CompressedRistretto(*peer).decompress()? * self.scalar;
+        if shared.is_identity() {
+            return None;
+        }
+
+        // Both sides hash the public values in the same order
+        let (first, second) = match self.public < *peer {
+            true => (&self.public, peer),
+            false => (peer, &self.public),
+        };
+        Some(
+            Sha256::new()
+                .chain_update(b"bwlat-pairing-v2")
+                .chain_update(shared.compress().as_bytes())
+                .chain_update(first)
+                .chain_update(second)
+                .finalize()
+                .into(),
+        )
+    }
+}
+
+fn mac(secret: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(message);
+    mac.finalize().into_bytes().into()
+}
+
+fn verify(secret: &[u8], message: &[u8], tag: &[u8]) -> bool {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(message);
+    mac.verify_slice(tag).is_ok()
+}
+
+fn load_servers() -> Result<ServersFile> {
+    match config_path(SERVERS_FILE) {
+        Some(path) if path.exists() => toml::from_str(&fs::read_to_string(&path)?)
+            .map_err(|e| eyre!("Invalid {}: {}", path.display(), e)),
+        _ => Ok(ServersFile::default()),
+    }
+}
+
+/// Writes a file with secrets to the config directory, readable only by the user.
+fn save(name: &str, contents: &str) -> Result<()> {
+    let path = config_path(name).ok_or_else(|| eyre!("No config directory"))?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // A file left by an older version may still be readable by others
+    restrict_permissions(&path)?;
+    let mut file = secret_file().open(&path)?;
+    std::io::Write::write_all(&mut file, contents.as_bytes())?;
+
+    Ok(())
+}
+
+/// Opens a file that is created readable only by the user, never with the
+/// permissions of the umask in between.
+#[cfg(unix)]
+fn secret_file() -> fs::OpenOptions {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true).mode(0o600);
+    options
+}
+
+#[cfg(not(unix))]
+fn secret_file() -> fs::OpenOptions {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    options
+}
+
+#[cfg(unix)]
+fn restrict_permissions(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if path.exists() {
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn restrict_permissions(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+fn config_path(name: &str) -> Option<PathBuf> {
+    ProjectDirs::from("", "", "bwlat").map(|dirs| dirs.config_dir().join(name))
+}
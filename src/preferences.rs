@@ -0,0 +1,83 @@
+use std::{fs, path::PathBuf, time::Duration};
+
+use color_eyre::eyre::{eyre, Result};
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::components::latency::ChartLines;
+
+const FILE_NAME: &str = "tui.toml";
+
+/// View settings of the client TUI that are remembered across runs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Preferences {
+    pub show_help: bool,
+    pub chart_lines: ChartLines,
+    /// Time span shown by the latency chart
+    #[serde(with = "duration_format")]
+    pub chart_window: Duration,
+    pub show_histogram: bool,
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Self {
+            show_help: false,
+            chart_lines: ChartLines::default(),
+            chart_window: Duration::from_secs(60),
+            show_histogram: true,
+        }
+    }
+}
+
+impl Preferences {
+    /// Loads the saved preferences, falling back to the defaults if there are none
+    /// or they cannot be read.
+    pub(crate) fn load() -> Self {
+        let Some(path) = path() else {
+            return Self::default();
+        };
+
+        match fs::read_to_string(&path)
+            .map_err(|e| eyre!(e))
+            .and_then(|s| toml::from_str(&s).map_err(|e| eyre!(e)))
+        {
+            Ok(preferences) => preferences,
+            Err(e) => {
+                debug!("Not using preferences from {}: {}", path.display(), e);
+                Self::default()
+            }
+        }
+    }
+
+    pub(crate) fn save(&self) -> Result<()> {
+        let path = path().ok_or_else(|| eyre!("No config directory"))?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, toml::to_string(self)?)?;
+
+        Ok(())
+    }
+}
+
+fn path() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "bwlat").map(|dirs| dirs.config_dir().join(FILE_NAME))
+}
+
+mod duration_format {
+    use std::time::Duration;
+
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&humantime::format_duration(*duration))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        humantime::parse_duration(&s).map_err(D::Error::custom)
+    }
+}
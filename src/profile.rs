@@ -0,0 +1,123 @@
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tracing::{info, span, Subscriber};
+use tracing_subscriber::{
+    filter::{filter_fn, Filtered},
+    layer::{Context, Layer},
+    registry::LookupSpan,
+};
+
+use crate::units::DisplayFormat;
+
+/// Timings of the spans closed so far, by span name.
+static TIMINGS: Mutex<BTreeMap<&'static str, Timing>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Timing {
+    count: u64,
+    total: Duration,
+    max: Duration,
+}
+
+/// Records how long the spans of this crate stay open for `--profile-self`.
+pub(crate) struct Profiler;
+
+struct Opened(Instant);
+
+impl Profiler {
+    /// The profiler as a layer that only sees this crate's spans, independent of
+    /// the log level.
+    pub(crate) fn layer<S>() -> Filtered<Self, impl tracing_subscriber::layer::Filter<S>, S>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        Profiler.with_filter(filter_fn(|metadata| {
+            metadata.is_span() && metadata.target().starts_with(env!("CARGO_CRATE_NAME"))
+        }))
+    }
+}
+
+impl<S> Layer<S> for Profiler
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, _attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(Opened(Instant::now()));
+        }
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else { return };
+        let Some(elapsed) = span.extensions().get::<Opened>().map(|o| o.0.elapsed()) else {
+            return;
+        };
+
+        let mut timings = TIMINGS.lock().unwrap();
+        let timing = timings.entry(span.name()).or_default();
+        timing.count += 1;
+        timing.total += elapsed;
+        timing.max = timing.max.max(elapsed);
+    }
+}
+
+/// Timings of a span in microseconds, as kept for bug reports.
+#[derive(Debug, Serialize)]
+pub(crate) struct SpanTiming {
+    pub name: &'static str,
+    pub count: u64,
+    pub average: u64,
+    pub max: u64,
+    pub total: u64,
+}
+
+/// The recorded timings, slowest in total first.
+fn sorted() -> Vec<(&'static str, Timing)> {
+    let mut timings: Vec<_> = TIMINGS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(&name, &timing)| (name, timing))
+        .collect();
+    timings.sort_by_key(|(_, t)| std::cmp::Reverse(t.total));
+    timings
+}
+
+pub(crate) fn timings() -> Vec<SpanTiming> {
+    sorted()
+        .into_iter()
+        .map(|(name, timing)| SpanTiming {
+            name,
+            count: timing.count,
+            average: (timing.total / timing.count.max(1) as u32).as_micros() as u64,
+            max: timing.max.as_micros() as u64,
+            total: timing.total.as_micros() as u64,
+        })
+        .collect()
+}
+
+/// Logs the recorded timings, slowest in total first.
+pub(crate) fn report(format: &DisplayFormat) {
+    let timings = sorted();
+    if timings.is_empty() {
+        info!("Self-profile: nothing recorded");
+        return;
+    }
+
+    info!("Self-profile:");
+    for (name, timing) in timings {
+        info!(
+            "  {}: {} times, average {}, max {}, total {}",
+            name,
+            timing.count,
+            format.duration(timing.total / timing.count.max(1) as u32),
+            format.duration(timing.max),
+            format.duration(timing.total)
+        );
+    }
+}
@@ -0,0 +1,215 @@
+use std::{collections::HashMap, fs, path::Path, time::Duration};
+
+use clap::ValueEnum;
+use color_eyre::eyre::{eyre, Result};
+use serde::Deserialize;
+
+use crate::{network::link::LinkType, units::DisplayFormat};
+
+/// How a figure compares to what links of a type usually achieve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Grade {
+    Good,
+    Typical,
+    Poor,
+}
+
+impl Grade {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Grade::Good => "good",
+            Grade::Typical => "typical",
+            Grade::Poor => "poor",
+        }
+    }
+}
+
+/// Values up to `good` beat most links of a type, up to `typical` are common
+/// and anything above is poor. Values below `floor` are out of reach of the
+/// type, such a result was not measured over it.
+#[derive(Debug, Clone, Copy)]
+struct Range {
+    floor: f64,
+    good: f64,
+    typical: f64,
+}
+
+impl Range {
+    const fn new(floor: f64, good: f64, typical: f64) -> Self {
+        Self {
+            floor,
+            good,
+            typical,
+        }
+    }
+
+    fn fits(&self, value: f64) -> bool {
+        (self.floor..=self.typical).contains(&value)
+    }
+
+    fn grade(&self, value: f64) -> Grade {
+        match value {
+            v if v <= self.good => Grade::Good,
+            v if v <= self.typical => Grade::Typical,
+            _ => Grade::Poor,
+        }
+    }
+}
+
+/// Reference ranges of a link type, to a server in the same region. Latencies
+/// in milliseconds, loss as a share of the probes.
+struct Reference {
+    median: Range,
+    tail: Range,
+    loss: Range,
+}
+
+/// Ranges from published broadband measurement campaigns such as the FCC's
+/// Measuring Broadband America and Ookla's regional reports, rounded.
+fn reference(link: LinkType) -> Reference {
+    match link {
+        LinkType::Fiber => Reference {
+            median: Range::new(0.0, 10.0, 25.0),
+            tail: Range::new(0.0, 20.0, 50.0),
+            loss: Range::new(0.0, 0.001, 0.005),
+        },
+        LinkType::Cable => Reference {
+            median: Range::new(5.0, 15.0, 35.0),
+            tail: Range::new(8.0, 40.0, 100.0),
+            loss: Range::new(0.0, 0.005, 0.01),
+        },
+        LinkType::Dsl => Reference {
+            median: Range::new(8.0, 20.0, 45.0),
+            tail: Range::new(10.0, 50.0, 120.0),
+            loss: Range::new(0.0, 0.005, 0.01),
+        },
+        LinkType::Lte => Reference {
+            median: Range::new(15.0, 40.0, 80.0),
+            tail: Range::new(25.0, 100.0, 250.0),
+            loss: Range::new(0.0, 0.01, 0.02),
+        },
+        LinkType::Satellite => Reference {
+            median: Range::new(480.0, 620.0, 750.0),
+            tail: Range::new(500.0, 900.0, 1500.0),
+            loss: Range::new(0.0, 0.01, 0.03),
+        },
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Results {
+    metadata: Metadata,
+    summary: Summary,
+}
+
+#[derive(Debug, Deserialize)]
+struct Metadata {
+    #[serde(default)]
+    parameters: HashMap<String, String>,
+}
+
+/// The figures of a latency export that are rated, latencies in microseconds.
+#[derive(Debug, Deserialize)]
+struct Summary {
+    sent: u32,
+    lost: u32,
+    p50_latency: Option<u64>,
+    p99_latency: Option<u64>,
+}
+
+/// What a figure was compared to.
+pub(crate) enum Against {
+    Link(LinkType, Grade),
+    /// No link type was known, these are the ones the figure is typical for
+    Typical(Vec<LinkType>),
+}
+
+/// Rating of one figure of a run.
+pub(crate) struct Verdict {
+    /// What was rated, e.g. "p99 latency of 38 ms"
+    pub figure: String,
+    pub against: Against,
+}
+
+impl Verdict {
+    fn new(
+        figure: String,
+        link: Option<LinkType>,
+        range: impl Fn(&Reference) -> Range,
+        value: f64,
+    ) -> Self {
+        let against = match link {
+            Some(link) => Against::Link(link, range(&reference(link)).grade(value)),
+            None => Against::Typical(
+                LinkType::value_variants()
+                    .iter()
+                    .copied()
+                    .filter(|&l| range(&reference(l)).fits(value))
+                    .collect(),
+            ),
+        };
+        Self { figure, against }
+    }
+
+    fn grade(&self) -> Option<Grade> {
+        match self.against {
+            Against::Link(_, grade) => Some(grade),
+            Against::Typical(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Verdict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.against {
+            Against::Link(link, grade) => {
+                write!(f, "{} is {} for {}", self.figure, grade.name(), link.name())
+            }
+            Against::Typical(ref links) if links.is_empty() => {
+                write!(f, "{} is not typical for any link type", self.figure)
+            }
+            Against::Typical(ref links) => {
+                let names: Vec<_> = links.iter().map(|l| l.name()).collect();
+                write!(f, "{} is typical for {}", self.figure, names.join(", "))
+            }
+        }
+    }
+}
+
+/// Rates the latency JSON export at `path` against the reference ranges of
+/// `link`, of the link type recorded in the export, or of every type.
+pub(crate) fn rate(
+    path: &Path,
+    link: Option<LinkType>,
+    format: &DisplayFormat,
+) -> Result<(Vec<Verdict>, Option<Grade>)> {
+    let results: Results = serde_json::from_str(&fs::read_to_string(path)?)
+        .map_err(|e| eyre!("{} is not a latency JSON export: {}", path.display(), e))?;
+
+    let link = link.or_else(|| {
+        let name = results.metadata.parameters.get("link_type")?;
+        LinkType::from_str(name, true).ok()
+    });
+
+    let summary = results.summary;
+    let latency = |micros: u64| Duration::from_micros(micros);
+    let millis = |micros: u64| micros as f64 / 1e3;
+
+    let mut verdicts = Vec::new();
+    if let Some(p50) = summary.p50_latency {
+        let figure = format!("Median latency of {}", format.duration(latency(p50)));
+        verdicts.push(Verdict::new(figure, link, |r| r.median, millis(p50)));
+    }
+    if let Some(p99) = summary.p99_latency {
+        let figure = format!("p99 latency of {}", format.duration(latency(p99)));
+        verdicts.push(Verdict::new(figure, link, |r| r.tail, millis(p99)));
+    }
+    if summary.sent > 0 {
+        let loss = summary.lost as f64 / summary.sent as f64;
+        let figure = format!("Loss of {}", format.percent(loss));
+        verdicts.push(Verdict::new(figure, link, |r| r.loss, loss));
+    }
+
+    let overall = verdicts.iter().filter_map(|v| v.grade()).max();
+    Ok((verdicts, overall))
+}
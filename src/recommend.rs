@@ -0,0 +1,258 @@
+use std::time::Duration;
+
+use crate::{
+    network::{
+        icmp_error::IcmpErrorKind,
+        latency::{self, Event, Payload, State},
+    },
+    units::DisplayFormat,
+};
+
+/// Loss above which a run counts as lossy.
+const HIGH_LOSS: f64 = 0.02;
+
+/// Probes from this size on come close to the MTU of common links and tunnels.
+const LARGE_PROBE: usize = 1400;
+
+/// A phase whose average latency is this many times that of the first phase
+/// points at queues filling up.
+const LOADED_LATENCY_FACTOR: f64 = 2.0;
+
+/// A p99 this many times the median, and at least [`MIN_SPIKE`] above it,
+/// counts as latency spikes.
+const SPIKE_FACTOR: f64 = 4.0;
+const MIN_SPIKE: Duration = Duration::from_millis(20);
+
+/// A finding of a run and what to do about it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Recommendation {
+    pub finding: String,
+    pub action: String,
+}
+
+impl Recommendation {
+    fn new(finding: String, action: impl Into<String>) -> Self {
+        Self {
+            finding,
+            action: action.into(),
+        }
+    }
+}
+
+/// What the rules look at: the results of a run and how it was probed.
+pub(crate) struct Run<'a> {
+    pub state: &'a State,
+    /// Bytes of UDP payload per probe
+    pub probe_size: usize,
+    pub payload: Payload,
+    pub format: DisplayFormat,
+}
+
+impl Run<'_> {
+    fn loss(&self) -> f64 {
+        self.state.packet_loss as f64 / self.state.sent_packets().max(1) as f64
+    }
+
+    fn icmp_errors(&self, matches: impl Fn(&IcmpErrorKind) -> bool) -> u32 {
+        self.state
+            .icmp_error_counts()
+            .iter()
+            .filter(|(error, _)| matches(&error.kind))
+            .map(|&(_, probes)| probes)
+            .sum()
+    }
+}
+
+type Rule = fn(&Run<'_>) -> Option<Recommendation>;
+
+/// Checked in order, so the most specific explanation of loss comes first.
+const RULES: &[Rule] = &[
+    rejected,
+    fragmentation,
+    large_probes,
+    loaded_latency,
+    latency_spikes,
+    sources,
+    compression,
+    corruption,
+    behind_schedule,
+    suspended,
+    loss,
+];
+
+/// Suggestions for the results of a run, empty when nothing stands out.
+pub(crate) fn recommend(run: &Run<'_>) -> Vec<Recommendation> {
+    RULES.iter().filter_map(|rule| rule(run)).collect()
+}
+
+/// The server port is closed or filtered.
+fn rejected(run: &Run<'_>) -> Option<Recommendation> {
+    let probes = run.icmp_errors(|kind| {
+        matches!(
+            kind,
+            IcmpErrorKind::PortUnreachable | IcmpErrorKind::Prohibited
+        )
+    });
+    (probes > 0).then(|| {
+        Recommendation::new(
+            format!("{} probe(s) were rejected with ICMP errors", probes),
+            "check that bwlat server is running and that firewalls admit its UDP port",
+        )
+    })
+}
+
+/// A link on the path has a smaller MTU than the probes need.
+fn fragmentation(run: &Run<'_>) -> Option<Recommendation> {
+    let mtu = run
+        .state
+        .icmp_error_counts()
+        .iter()
+        .find_map(|(error, _)| match error.kind {
+            IcmpErrorKind::FragmentationNeeded(mtu) => Some(mtu),
+            _ => None,
+        })?;
+    Some(Recommendation::new(
+        format!(
+            "probes of {} B exceed a link with an MTU of {}",
+            run.probe_size, mtu
+        ),
+        "check the MTU of tunnels and PPPoE links on the path, bwlat pmtu finds the largest size that passes",
+    ))
+}
+
+/// Loss of large probes without an ICMP error, often a tunnel dropping what it
+/// cannot fragment.
+fn large_probes(run: &Run<'_>) -> Option<Recommendation> {
+    if run.probe_size < LARGE_PROBE
+        || run.loss() < HIGH_LOSS
+        || run.icmp_errors(|kind| matches!(kind, IcmpErrorKind::FragmentationNeeded(_))) > 0
+    {
+        return None;
+    }
+    Some(Recommendation::new(
+        format!(
+            "{} loss with probes of {} B",
+            run.format.percent(run.loss()),
+            run.probe_size
+        ),
+        "loss of large packets hints at an MTU problem: compare with --packet-size 64 and check the path MTU with bwlat pmtu",
+    ))
+}
+
+/// A later phase, e.g. one marked while a download ran, is much slower than the
+/// first.
+fn loaded_latency(run: &Run<'_>) -> Option<Recommendation> {
+    let phases = run.state.phase_statistics();
+    let first = phases.first().filter(|p| p.received > 0)?;
+    let baseline = first.average_latency.as_secs_f64();
+    let loaded = phases[1..]
+        .iter()
+        .filter(|p| p.received > 0)
+        .max_by_key(|p| p.average_latency)?;
+    let factor = loaded.average_latency.as_secs_f64() / baseline;
+    (factor >= LOADED_LATENCY_FACTOR).then(|| {
+        Recommendation::new(
+            format!(
+                "latency is {:.1}x higher during \"{}\" ({} vs {})",
+                factor,
+                loaded.name,
+                run.format.duration(loaded.average_latency),
+                run.format.duration(first.average_latency)
+            ),
+            "queues fill up under load (bufferbloat): enable SQM such as fq_codel or cake on the router, shaped slightly below the line rate",
+        )
+    })
+}
+
+/// The tail is far above the median.
+fn latency_spikes(run: &Run<'_>) -> Option<Recommendation> {
+    let p = run.state.percentiles()?;
+    let factor = p.p99.as_secs_f64() / p.p50.as_secs_f64().max(f64::EPSILON);
+    (factor >= SPIKE_FACTOR && p.p99 - p.p50 >= MIN_SPIKE).then(|| {
+        Recommendation::new(
+            format!(
+                "p99 latency of {} is {:.0}x the median of {}",
+                run.format.duration(p.p99),
+                factor,
+                run.format.duration(p.p50)
+            ),
+            "spikes like these come from bufferbloat, Wi-Fi retransmissions or power saving: test over a cable and enable SQM on the router",
+        )
+    })
+}
+
+/// Sources of the socket pool or --source-addresses are treated differently.
+fn sources(run: &Run<'_>) -> Option<Recommendation> {
+    let sources = run.state.source_statistics();
+    if sources.len() < 2 || !latency::sources_differ(&sources) {
+        return None;
+    }
+    Some(Recommendation::new(
+        format!("results differ between the {} source(s)", sources.len()),
+        "the path treats flows differently: look for per-flow policing, NAT limits, ECMP or source-based routing",
+    ))
+}
+
+/// Compressible and incompressible probes see different latency or loss.
+fn compression(run: &Run<'_>) -> Option<Recommendation> {
+    if run.payload != Payload::Alternate {
+        return None;
+    }
+    let comparison = run.state.payload_comparison();
+    (comparison.latency_differs() || comparison.loss_differs()).then(|| {
+        Recommendation::new(
+            "compressible and random payloads fare differently".to_string(),
+            "a link compresses or inspects traffic, e.g. a VPN, PPP or WAN optimizer: measure with --payload random for figures that hold for encrypted traffic",
+        )
+    })
+}
+
+fn corruption(run: &Run<'_>) -> Option<Recommendation> {
+    let corrupted = run.state.corrupted_packets;
+    (corrupted > 0).then(|| {
+        Recommendation::new(
+            format!("{} echo(es) came back with a different payload", corrupted),
+            "a faulty cable, NIC offload or middlebox alters packets: swap cables and disable offloads with ethtool to narrow it down",
+        )
+    })
+}
+
+/// The results understate loss and latency when the client itself lagged.
+fn behind_schedule(run: &Run<'_>) -> Option<Recommendation> {
+    let rate = run.state.send_rate();
+    let pps = rate.packets_per_second()?;
+    (!rate.on_schedule()).then(|| {
+        Recommendation::new(
+            format!(
+                "the client sent {:.1} of {:.1} probes per second",
+                pps,
+                rate.expected_packets_per_second()
+            ),
+            "this host cannot keep the interval: use a longer --interval, or check its timers and CPU power saving with bwlat doctor",
+        )
+    })
+}
+
+fn suspended(run: &Run<'_>) -> Option<Recommendation> {
+    run.state
+        .events
+        .iter()
+        .any(|(_, event)| matches!(event, Event::Suspended { .. }))
+        .then(|| {
+            Recommendation::new(
+                "the host was suspended during the run".to_string(),
+                "disable sleep for unattended runs, probes in flight while suspended are not counted",
+            )
+        })
+}
+
+/// Loss none of the rules above explain.
+fn loss(run: &Run<'_>) -> Option<Recommendation> {
+    let explained = run.icmp_errors(|_| true) > 0 || run.probe_size >= LARGE_PROBE;
+    (run.loss() >= HIGH_LOSS && !explained).then(|| {
+        Recommendation::new(
+            format!("{} of the probes were lost", run.format.percent(run.loss())),
+            "check the Wi-Fi signal and cabling first; loss that only shows under load is solved by SQM on the router",
+        )
+    })
+}
@@ -0,0 +1,89 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use color_eyre::eyre::{bail, eyre, Result};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::signing;
+
+/// When the probes of an earlier run were sent, to offer the exact same pattern
+/// again, e.g. before and after a change of the network.
+#[derive(Debug, Clone)]
+pub(crate) struct SendSchedule {
+    pub path: PathBuf,
+    /// SHA-256 of the export, so a run can be matched to the schedule it replayed
+    pub digest: String,
+    /// Start of the recorded run
+    pub started: String,
+    /// Configured interval of the recorded run
+    pub interval: Duration,
+    /// Send times since the start of the run, in order
+    pub offsets: Arc<[Duration]>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Results {
+    metadata: Metadata,
+    summary: Summary,
+    packets: Vec<Packet>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Metadata {
+    started: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Summary {
+    interval: u64,
+}
+
+/// Times in microseconds since the start of the run.
+#[derive(Debug, Deserialize)]
+struct Packet {
+    sent: u64,
+    status: String,
+}
+
+impl SendSchedule {
+    /// Reads the send times of the probes in the latency JSON export at `path`.
+    /// Slots the recorded run skipped were not offered, so they are left out.
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let contents =
+            fs::read(path).map_err(|e| eyre!("Cannot read {}: {}", path.display(), e))?;
+        let results: Results = serde_json::from_slice(&contents)
+            .map_err(|e| eyre!("{} is not a latency JSON export: {}", path.display(), e))?;
+
+        let mut offsets: Vec<_> = results
+            .packets
+            .iter()
+            .filter(|p| p.status != "skipped")
+            .map(|p| Duration::from_micros(p.sent))
+            .collect();
+        if offsets.is_empty() {
+            bail!(
+                "{} has no probes to replay, export it with all probes retained",
+                path.display()
+            );
+        }
+        // Probes are exported by sequence, which follows the send times
+        offsets.sort();
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            digest: signing::to_hex(&Sha256::digest(&contents)),
+            started: results.metadata.started,
+            interval: Duration::from_micros(results.summary.interval),
+            offsets: offsets.into(),
+        })
+    }
+
+    pub(crate) fn len(&self) -> u32 {
+        self.offsets.len().min(u32::MAX as usize) as u32
+    }
+}
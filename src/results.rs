@@ -0,0 +1,441 @@
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::{
+    metadata::RunMetadata,
+    network::{
+        bandwidth::{BandwidthSample, BandwidthState},
+        clock::DelayRange,
+        game::GameQuality,
+        icmp_error::IcmpError,
+        latency::{PacketStatus, State},
+        rtp::CallQuality,
+    },
+};
+
+/// Results of a latency run as written by `--json`. Times are in microseconds
+/// relative to the start of the run, like in the CSV export.
+#[derive(Debug, Serialize)]
+pub(crate) struct LatencyResults<'a> {
+    pub metadata: RunMetadata,
+    pub address: String,
+    pub summary: LatencySummary,
+    /// With `--profile` only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub call_quality: Option<CallQualitySummary>,
+    /// With `--profile game` only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub game_quality: Option<GameQualitySummary>,
+    /// Lost probes a router or the server rejected with an ICMP error
+    pub icmp_errors: Vec<IcmpErrorSummary>,
+    pub phases: Vec<PhaseSummary>,
+    pub events: Vec<EventRecord>,
+    /// With `--protocol tcp` only
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tcp_info: Vec<TcpInfoRecord>,
+    pub packets: Vec<PacketRecord<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct LatencySummary {
+    pub sent: u32,
+    pub received: u32,
+    pub lost: u32,
+    pub skipped: u32,
+    pub invalid: u32,
+    /// Probes of the warm-up, left out of every other count
+    pub warmup: u32,
+    /// Echoes larger than the receive buffer
+    pub truncated: u32,
+    /// Echoes whose payload differs from the probe, included in `received`
+    pub corrupted: u32,
+    pub min_latency: u64,
+    pub average_latency: u64,
+    pub max_latency: u64,
+    pub p50_latency: Option<u64>,
+    pub p90_latency: Option<u64>,
+    pub p99_latency: Option<u64>,
+    pub p999_latency: Option<u64>,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub wire_bytes_sent: u64,
+    pub wire_bytes_received: u64,
+    /// Parts per million, with `--clock-drift` only
+    pub clock_drift: Option<f64>,
+    /// Server clock minus client clock the one-way delays are corrected by
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clock_offset: Option<i64>,
+    /// With `--protocol quic` only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handshake: Option<u64>,
+    /// With `--protocol tcp` only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kernel_rtt: Option<KernelRttSummary>,
+    /// With `--one-way` only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<DelaySummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub downstream: Option<DelaySummary>,
+    /// Configured time between probes
+    pub interval: u64,
+    pub packets_per_second: Option<f64>,
+    /// Bitrate put on the wire by the probes, headers included
+    pub offered_bits_per_second: Option<f64>,
+}
+
+/// One direction of the one-way delays, signed as they depend on the clock offset.
+#[derive(Debug, Serialize)]
+pub(crate) struct DelaySummary {
+    pub min: i64,
+    pub average: i64,
+    pub max: i64,
+}
+
+impl From<DelayRange> for DelaySummary {
+    fn from(range: DelayRange) -> Self {
+        Self {
+            min: range.min / 1000,
+            average: range.average / 1000,
+            max: range.max / 1000,
+        }
+    }
+}
+
+/// The kernel's round trip of the probe connection next to the one the probes
+/// measured.
+#[derive(Debug, Serialize)]
+pub(crate) struct KernelRttSummary {
+    pub rtt: u64,
+    pub rttvar: u64,
+    /// Average latency of the probes minus the kernel's RTT, signed
+    pub application_difference: i64,
+    pub retransmits: u32,
+    /// Bits per second
+    pub delivery_rate: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct TcpInfoRecord {
+    pub at: u64,
+    pub rtt: u64,
+    pub rttvar: u64,
+    pub retransmits: u32,
+    pub delivery_rate: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct CallQualitySummary {
+    pub frames: u32,
+    pub lost: u32,
+    pub late: u32,
+    pub underruns: u32,
+    pub jitter: u64,
+    pub mouth_to_ear: u64,
+    pub r_factor: f64,
+    pub mos: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct GameQualitySummary {
+    pub ping: u64,
+    pub jitter: u64,
+    pub loss: f64,
+    pub spikes: u32,
+    pub spikes_per_minute: f64,
+    pub verdict: String,
+    pub limited_by: Vec<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct IcmpErrorSummary {
+    pub error: String,
+    /// Router or host that sent the error, absent for errors of this host
+    pub from: Option<String>,
+    pub probes: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct PhaseSummary {
+    pub name: String,
+    pub first_packet: usize,
+    pub sent: u32,
+    pub received: u32,
+    pub min_latency: u64,
+    pub average_latency: u64,
+    pub max_latency: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct EventRecord {
+    pub at: u64,
+    pub description: String,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct PacketRecord<'a> {
+    pub packet: usize,
+    pub sent: u64,
+    pub received: Option<u64>,
+    pub latency: Option<u64>,
+    pub status: &'static str,
+    pub icmp_error: Option<String>,
+    /// With `--one-way` only, signed as they depend on the clock offset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub downstream: Option<i64>,
+    pub burst: Option<usize>,
+    pub phase: Option<&'a str>,
+    /// With `--probe-tag` only, as echoed back by the server
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    /// How long the probe was waited for before it counted as lost, in
+    /// microseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub loss_timeout: Option<u64>,
+}
+
+impl<'a> LatencyResults<'a> {
+    /// Collects the results of one target, `events` already formatted for display.
+    pub(crate) fn new(
+        metadata: RunMetadata,
+        address: String,
+        state: &'a State,
+        events: Vec<(Duration, String)>,
+    ) -> Self {
+        let percentiles = state.percentiles();
+        let rate = state.send_rate();
+        let one_way = state.one_way_delay_ranges();
+        let summary = LatencySummary {
+            sent: state.sent_packets(),
+            received: state.received_packets,
+            lost: state.packet_loss,
+            skipped: state.skipped_packets,
+            invalid: state.invalid_packets,
+            warmup: state.warmup_packets,
+            truncated: state.truncated_packets,
+            corrupted: state.corrupted_packets,
+            min_latency: micros(state.min_latency),
+            average_latency: micros(state.average_latency),
+            max_latency: micros(state.max_latency),
+            p50_latency: percentiles.map(|p| micros(p.p50)),
+            p90_latency: percentiles.map(|p| micros(p.p90)),
+            p99_latency: percentiles.map(|p| micros(p.p99)),
+            p999_latency: percentiles.map(|p| micros(p.p999)),
+            bytes_sent: state.traffic_sent.payload,
+            bytes_received: state.traffic_received.payload,
+            wire_bytes_sent: state.traffic_sent.wire,
+            wire_bytes_received: state.traffic_received.wire,
+            clock_drift: state.clock_drift().map(|d| d.ppm),
+            clock_offset: state.clock_offset.map(|o| o.offset / 1000),
+            handshake: state.handshake.map(micros),
+            kernel_rtt: state.kernel_rtt().map(|kernel| KernelRttSummary {
+                rtt: micros(kernel.rtt),
+                rttvar: micros(kernel.rttvar),
+                application_difference: micros(state.average_latency) as i64
+                    - micros(kernel.rtt) as i64,
+                retransmits: kernel.retransmits,
+                delivery_rate: kernel.delivery_rate,
+            }),
+            upstream: one_way.map(|(up, _)| up.into()),
+            downstream: one_way.map(|(_, down)| down.into()),
+            interval: micros(rate.interval),
+            packets_per_second: rate.packets_per_second(),
+            offered_bits_per_second: rate.bits_per_second(),
+        };
+
+        let phases = state
+            .phase_statistics()
+            .into_iter()
+            .map(|p| PhaseSummary {
+                name: p.name,
+                first_packet: p.packets.start,
+                sent: p.sent,
+                received: p.received,
+                min_latency: micros(p.min_latency),
+                average_latency: micros(p.average_latency),
+                max_latency: micros(p.max_latency),
+            })
+            .collect();
+
+        let packets = state
+            .packets
+            .numbered()
+            .map(|(i, packet)| {
+                let (sent, echo, status) = match *packet {
+                    PacketStatus::Skipped(s) => (s, None, "skipped"),
+                    PacketStatus::Invalid(s) => (s, None, "invalid"),
+                    PacketStatus::Warmup(s) => (s, None, "warmup"),
+                    PacketStatus::Sent(s) => (s, None, "lost"),
+                    PacketStatus::Received {
+                        start,
+                        stop,
+                        latency,
+                    } if state.corrupted.contains(&i) => {
+                        (start, Some((stop, latency)), "corrupted")
+                    }
+                    PacketStatus::Received {
+                        start,
+                        stop,
+                        latency,
+                    } => (start, Some((stop, latency)), "received"),
+                };
+                PacketRecord {
+                    packet: i,
+                    sent: micros(sent),
+                    received: echo.map(|(stop, _)| micros(stop)),
+                    latency: echo.map(|(_, latency)| micros(latency)),
+                    status,
+                    icmp_error: state.icmp_error(i).map(|e| e.kind.to_string()),
+                    upstream: state.one_way_delays.get(&i).map(|d| d.upstream / 1000),
+                    downstream: state.one_way_delays.get(&i).map(|d| d.downstream / 1000),
+                    burst: state.bursts.iter().position(|b| b.contains(&i)),
+                    phase: state.phase_of(i),
+                    tag: state.tag(i),
+                    loss_timeout: (status != "skipped").then(|| micros(state.grace(i))),
+                }
+            })
+            .collect();
+
+        Self {
+            metadata,
+            address,
+            summary,
+            call_quality: None,
+            game_quality: None,
+            icmp_errors: Vec::new(),
+            phases,
+            events: events
+                .into_iter()
+                .map(|(at, description)| EventRecord {
+                    at: micros(at),
+                    description,
+                })
+                .collect(),
+            tcp_info: state
+                .tcp_info
+                .iter()
+                .map(|sample| TcpInfoRecord {
+                    at: micros(sample.at),
+                    rtt: micros(sample.rtt),
+                    rttvar: micros(sample.rttvar),
+                    retransmits: sample.retransmits,
+                    delivery_rate: sample.delivery_rate,
+                })
+                .collect(),
+            packets,
+        }
+    }
+
+    pub(crate) fn with_call_quality(mut self, quality: &CallQuality) -> Self {
+        self.call_quality = Some(CallQualitySummary {
+            frames: quality.frames,
+            lost: quality.lost,
+            late: quality.late,
+            underruns: quality.underruns,
+            jitter: micros(quality.jitter),
+            mouth_to_ear: micros(quality.mouth_to_ear),
+            r_factor: quality.r_factor,
+            mos: quality.mos,
+        });
+        self
+    }
+
+    /// Takes each error with its sender already formatted for display.
+    pub(crate) fn with_icmp_errors(
+        mut self,
+        errors: impl IntoIterator<Item = (IcmpError, Option<String>, u32)>,
+    ) -> Self {
+        self.icmp_errors = errors
+            .into_iter()
+            .map(|(error, from, probes)| IcmpErrorSummary {
+                error: error.kind.to_string(),
+                from,
+                probes,
+            })
+            .collect();
+        self
+    }
+
+    pub(crate) fn with_game_quality(mut self, quality: &GameQuality) -> Self {
+        self.game_quality = Some(GameQualitySummary {
+            ping: micros(quality.ping),
+            jitter: micros(quality.jitter),
+            loss: quality.loss,
+            spikes: quality.spikes,
+            spikes_per_minute: quality.spikes_per_minute,
+            verdict: quality.verdict.to_string(),
+            limited_by: quality.limited_by.clone(),
+        });
+        self
+    }
+}
+
+/// Results of a bandwidth run as written by `--json`.
+#[derive(Debug, Serialize)]
+pub(crate) struct BandwidthResults {
+    pub metadata: RunMetadata,
+    pub summary: BandwidthSummary,
+    pub samples: Vec<SampleRecord>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct BandwidthSummary {
+    pub bytes: u64,
+    pub elapsed: u64,
+    pub bits_per_second: f64,
+    pub retransmits: u32,
+    pub congestion: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct SampleRecord {
+    pub at: u64,
+    pub bytes: u64,
+    pub bits_per_second: u64,
+    pub retransmits: u32,
+    pub lost: Option<u64>,
+    pub offered: Option<u64>,
+    pub cwnd: u32,
+    pub rtt: u64,
+    pub rttvar: u64,
+    pub delivery_rate: u64,
+}
+
+impl BandwidthResults {
+    pub(crate) fn new(metadata: RunMetadata, state: &BandwidthState) -> Self {
+        Self {
+            metadata,
+            summary: BandwidthSummary {
+                bytes: state.total_bytes,
+                elapsed: micros(state.elapsed),
+                bits_per_second: state.bits_per_second(),
+                retransmits: state.retransmits,
+                congestion: state.congestion.clone(),
+            },
+            samples: state.samples.iter().map(SampleRecord::from).collect(),
+        }
+    }
+}
+
+impl From<&BandwidthSample> for SampleRecord {
+    fn from(sample: &BandwidthSample) -> Self {
+        Self {
+            at: micros(sample.at),
+            bytes: sample.bytes,
+            bits_per_second: sample.bits_per_second,
+            retransmits: sample.retransmits,
+            lost: sample.lost,
+            offered: sample.offered,
+            cwnd: sample.cwnd,
+            rtt: micros(sample.rtt),
+            rttvar: micros(sample.rttvar),
+            delivery_rate: sample.delivery_rate,
+        }
+    }
+}
+
+fn micros(duration: Duration) -> u64 {
+    duration.as_micros() as u64
+}
@@ -0,0 +1,457 @@
+use std::{fs, net::SocketAddr, ops::Range, path::Path, sync::Arc, time::Duration};
+
+use color_eyre::eyre::{bail, eyre, Result};
+use serde::{de::Error as _, Deserialize, Deserializer};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::{
+    cli::parse_bitrate,
+    network::{
+        bandwidth::{format_bitrate, BandwidthState, TcpBandwidth, UdpBandwidth},
+        dns::{DnsProbe, DnsResults},
+        game::GameQuality,
+        latency::{Latency, PacketStatus, State, StreamProfile},
+        rtp::CallQuality,
+    },
+    test_plan::{duration, optional_duration},
+    units::DisplayFormat,
+};
+
+/// Flows that run at the same time against one server, to see how they affect
+/// each other.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Scenario {
+    #[serde(default = "default_name")]
+    pub name: String,
+    /// `host:port` of the bwlat server, DNS flows query their own server
+    pub target: String,
+    /// Length of flows that do not set their own
+    #[serde(deserialize_with = "duration")]
+    pub duration: Duration,
+    pub flows: Vec<Flow>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Flow {
+    pub name: String,
+    /// Delay after the start of the scenario
+    #[serde(default, deserialize_with = "optional_duration")]
+    pub start: Option<Duration>,
+    #[serde(default, deserialize_with = "optional_duration")]
+    pub duration: Option<Duration>,
+    #[serde(flatten)]
+    pub kind: FlowKind,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum FlowKind {
+    Latency(LatencyFlow),
+    Bandwidth(BandwidthFlow),
+    Dns(DnsFlow),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct LatencyFlow {
+    /// Takes the place of interval and size
+    pub profile: Option<StreamProfile>,
+    #[serde(deserialize_with = "duration")]
+    pub interval: Duration,
+    pub size: usize,
+    /// Jitter buffer of the RTP profiles
+    #[serde(deserialize_with = "duration")]
+    pub jitter_buffer: Duration,
+}
+
+impl Default for LatencyFlow {
+    fn default() -> Self {
+        Self {
+            profile: None,
+            interval: Duration::from_millis(20),
+            size: 64,
+            jitter_buffer: Duration::from_millis(40),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct BandwidthFlow {
+    /// UDP at this bitrate, e.g. "50M", instead of a TCP bulk transfer
+    #[serde(deserialize_with = "bitrate")]
+    pub rate: Option<u64>,
+    pub congestion: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct DnsFlow {
+    /// `address:port` of the resolver
+    pub server: SocketAddr,
+    #[serde(default = "default_query")]
+    pub query: String,
+    #[serde(default = "default_dns_interval", deserialize_with = "duration")]
+    pub interval: Duration,
+}
+
+/// What a flow measured, with its time span relative to the start of the scenario.
+struct FlowOutcome {
+    active: Range<Duration>,
+    result: FlowResult,
+}
+
+enum FlowResult {
+    Latency(Box<State>),
+    Bandwidth(BandwidthState),
+    Dns(DnsResults),
+}
+
+impl FlowResult {
+    /// Send times relative to the flow's start and round trips of the answered
+    /// probes.
+    fn samples(&self) -> Option<Vec<(Duration, Option<Duration>)>> {
+        match self {
+            FlowResult::Latency(state) => Some(
+                state
+                    .packets
+                    .iter()
+                    .filter_map(|packet| match *packet {
+                        PacketStatus::Sent(start) => Some((start, None)),
+                        PacketStatus::Received { start, latency, .. } => {
+                            Some((start, Some(latency)))
+                        }
+                        PacketStatus::Skipped(_)
+                        | PacketStatus::Invalid(_)
+                        | PacketStatus::Warmup(_) => None,
+                    })
+                    .collect(),
+            ),
+            FlowResult::Dns(results) => Some(results.samples.clone()),
+            FlowResult::Bandwidth(_) => None,
+        }
+    }
+}
+
+/// Latency of one flow while no bulk flow ran and while one did.
+#[derive(Debug, Default)]
+struct Interaction {
+    alone: Vec<Duration>,
+    alone_sent: u32,
+    loaded: Vec<Duration>,
+    loaded_sent: u32,
+}
+
+impl Scenario {
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let scenario: Self = serde_yaml::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| eyre!("Invalid scenario {}: {}", path.display(), e))?;
+
+        if scenario.flows.is_empty() {
+            bail!("The scenario has no flows");
+        }
+        for flow in scenario.flows.iter() {
+            if scenario
+                .flows
+                .iter()
+                .filter(|f| f.name == flow.name)
+                .count()
+                > 1
+            {
+                bail!("Flow name '{}' is used more than once", flow.name);
+            }
+            if let FlowKind::Latency(ref latency) = flow.kind {
+                if latency.interval.is_zero() && latency.profile.is_none() {
+                    bail!("Flow '{}' needs a non-zero interval", flow.name);
+                }
+            }
+        }
+
+        Ok(scenario)
+    }
+
+    /// Starts every flow at its offset, waits for all of them and reports each
+    /// flow and how the bulk flows changed the latency of the others.
+    pub(crate) async fn run(&self, format: &DisplayFormat) -> Result<()> {
+        let address = tokio::net::lookup_host(&self.target)
+            .await?
+            .next()
+            .ok_or_else(|| eyre!("{} did not resolve to any address", self.target))?;
+
+        let cancel = CancellationToken::new();
+        let interrupt = cancel.clone();
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                interrupt.cancel();
+            }
+        });
+
+        info!(
+            "Running scenario {} with {} flows against {}",
+            self.name,
+            self.flows.len(),
+            self.target
+        );
+        let outcomes = futures::future::join_all(
+            self.flows
+                .iter()
+                .map(|flow| self.run_flow(flow, address, cancel.child_token())),
+        )
+        .await;
+
+        for (flow, outcome) in self.flows.iter().zip(outcomes.iter()) {
+            match outcome {
+                Ok(outcome) => report_flow(flow, &outcome.result, format),
+                Err(e) => warn!("{}: failed: {}", flow.name, e),
+            }
+        }
+
+        let bulk: Vec<_> = outcomes
+            .iter()
+            .filter_map(|o| o.as_ref().ok())
+            .filter(|o| matches!(o.result, FlowResult::Bandwidth(_)))
+            .map(|o| o.active.clone())
+            .collect();
+        if !bulk.is_empty() {
+            info!("Interaction with the bandwidth flows:");
+            for (flow, outcome) in self.flows.iter().zip(outcomes.iter()) {
+                let Ok(outcome) = outcome else { continue };
+                if let Some(interaction) = interaction(outcome, &bulk) {
+                    report_interaction(&flow.name, &interaction, format);
+                }
+            }
+        }
+
+        if outcomes.iter().any(|o| o.is_err()) {
+            bail!("Not every flow of the scenario could run");
+        }
+
+        Ok(())
+    }
+
+    async fn run_flow(
+        &self,
+        flow: &Flow,
+        address: SocketAddr,
+        quit: CancellationToken,
+    ) -> Result<FlowOutcome> {
+        let start = flow.start.unwrap_or_default();
+        let duration = flow.duration.unwrap_or(self.duration);
+        tokio::select! {
+            _ = tokio::time::sleep(start) => {}
+            _ = quit.cancelled() => bail!("Interrupted before the flow started"),
+        }
+
+        // The engines report progress for the TUI, nothing listens here
+        let (notify, mut actions) = mpsc::unbounded_channel();
+        let drain = tokio::spawn(async move { while actions.recv().await.is_some() {} });
+
+        let result = match flow.kind {
+            FlowKind::Latency(ref options) => {
+                let (interval, size) = match options.profile {
+                    Some(profile) => (profile.interval(), profile.packet_size()),
+                    None => (options.interval, options.size),
+                };
+                let count = (duration.as_nanos() / interval.as_nanos()).max(1) as u32;
+
+                let mut latency =
+                    Latency::new_with_count(address.ip(), address.port(), count, notify, quit)
+                        .with_interval(interval)
+                        .with_packet_size(size as u16);
+                if let Some(dscp) = options.profile.and_then(|p| p.dscp()) {
+                    latency = latency.with_dscp(dscp);
+                }
+                if let Some(reply) = options.profile.and_then(|p| p.reply()) {
+                    latency = latency.with_reply(reply);
+                }
+
+                let state = latency.run().await?;
+                drop(latency);
+                let state = Arc::try_unwrap(state)
+                    .map_err(|_| eyre!("Latency state of {} is still in use", flow.name))?;
+                FlowResult::Latency(Box::new(state.into_inner()))
+            }
+            FlowKind::Bandwidth(ref options) => {
+                let state = match options.rate {
+                    Some(rate) => {
+                        UdpBandwidth::new(
+                            address.ip(),
+                            address.port(),
+                            duration,
+                            rate,
+                            notify,
+                            quit,
+                        )
+                        .run()
+                        .await?
+                    }
+                    None => {
+                        let mut bandwidth =
+                            TcpBandwidth::new(address.ip(), address.port(), duration, notify, quit);
+                        if let Some(ref congestion) = options.congestion {
+                            bandwidth = bandwidth.with_congestion(congestion.clone());
+                        }
+                        bandwidth.run().await?
+                    }
+                };
+                FlowResult::Bandwidth(state)
+            }
+            FlowKind::Dns(ref options) => {
+                drop(notify);
+                let count = (duration.as_nanos() / options.interval.as_nanos()).max(1) as u32;
+                let probe = DnsProbe::new(
+                    options.server,
+                    options.query.clone(),
+                    options.interval,
+                    count,
+                );
+                FlowResult::Dns(probe.run(quit).await?)
+            }
+        };
+
+        drain.await?;
+
+        Ok(FlowOutcome {
+            active: start..start + duration,
+            result,
+        })
+    }
+}
+
+/// Splits the samples of a latency or DNS flow by whether a bulk flow was running
+/// when they were sent. `None` for bulk flows and when one of the parts is empty.
+fn interaction(outcome: &FlowOutcome, bulk: &[Range<Duration>]) -> Option<Interaction> {
+    let samples = outcome.result.samples()?;
+
+    let mut interaction = Interaction::default();
+    for (at, latency) in samples {
+        let at = outcome.active.start + at;
+        let loaded = bulk.iter().any(|active| active.contains(&at));
+        let (sent, latencies) = match loaded {
+            true => (&mut interaction.loaded_sent, &mut interaction.loaded),
+            false => (&mut interaction.alone_sent, &mut interaction.alone),
+        };
+        *sent += 1;
+        latencies.extend(latency);
+    }
+
+    (interaction.alone_sent > 0 && interaction.loaded_sent > 0).then_some(interaction)
+}
+
+fn report_flow(flow: &Flow, result: &FlowResult, format: &DisplayFormat) {
+    match result {
+        FlowResult::Latency(state) => {
+            let mut text = format!(
+                "{}: latency avg {}, max {}, loss {} ({}/{})",
+                flow.name,
+                format.duration(state.average_latency),
+                format.duration(state.max_latency),
+                format.percent(state.packet_loss as f64 / state.sent_packets().max(1) as f64),
+                state.packet_loss,
+                state.sent_packets()
+            );
+            if let Some(p) = state.percentiles() {
+                text += &format!(
+                    ", p50 {}, p99 {}",
+                    format.duration(p.p50),
+                    format.duration(p.p99)
+                );
+            }
+            info!("{}", text);
+
+            let FlowKind::Latency(ref options) = flow.kind else {
+                return;
+            };
+            let Some(profile) = options.profile else {
+                return;
+            };
+            if let Some(quality) = CallQuality::estimate(state, profile, options.jitter_buffer) {
+                info!(
+                    "  call quality: MOS {:.2}, R-factor {:.1} ({}), {} late frame(s)",
+                    quality.mos,
+                    quality.r_factor,
+                    quality.rating(),
+                    quality.late
+                );
+            }
+            if profile == StreamProfile::Game {
+                if let Some(quality) = GameQuality::estimate(state) {
+                    info!(
+                        "  game: jitter {}, {} spike(s), verdict {}",
+                        format.duration(quality.jitter),
+                        quality.spikes,
+                        quality.verdict
+                    );
+                }
+            }
+        }
+        FlowResult::Bandwidth(state) => info!(
+            "{}: {}, {} retransmits",
+            flow.name,
+            format_bitrate(state.bits_per_second()),
+            state.retransmits
+        ),
+        FlowResult::Dns(results) => {
+            let mut latencies: Vec<_> = results.samples.iter().filter_map(|&(_, l)| l).collect();
+            latencies.sort();
+            info!(
+                "{}: {}/{} queries answered, {} failed, median {}, max {}",
+                flow.name,
+                results.answered,
+                results.sent,
+                results.failed,
+                format.duration(median(&latencies)),
+                format.duration(latencies.last().copied().unwrap_or_default())
+            );
+        }
+    }
+}
+
+fn report_interaction(name: &str, interaction: &Interaction, format: &DisplayFormat) {
+    let mut alone = interaction.alone.clone();
+    alone.sort();
+    let mut loaded = interaction.loaded.clone();
+    loaded.sort();
+
+    let (alone_median, loaded_median) = (median(&alone), median(&loaded));
+    let loss = |sent: u32, answered: usize| (sent as usize - answered) as f64 / sent as f64;
+    info!(
+        "  {}: median {} alone, {} under load ({}{}), loss {} alone, {} under load",
+        name,
+        format.duration(alone_median),
+        format.duration(loaded_median),
+        if loaded_median >= alone_median {
+            "+"
+        } else {
+            "-"
+        },
+        format.duration(loaded_median.abs_diff(alone_median)),
+        format.percent(loss(interaction.alone_sent, alone.len())),
+        format.percent(loss(interaction.loaded_sent, loaded.len()))
+    );
+}
+
+/// Median of sorted latencies, zero if there are none.
+fn median(sorted: &[Duration]) -> Duration {
+    sorted.get(sorted.len() / 2).copied().unwrap_or_default()
+}
+
+fn default_name() -> String {
+    "scenario".to_string()
+}
+
+fn default_query() -> String {
+    "example.com".to_string()
+}
+
+fn default_dns_interval() -> Duration {
+    Duration::from_secs(1)
+}
+
+fn bitrate<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    parse_bitrate(&s).map(Some).map_err(D::Error::custom)
+}
@@ -0,0 +1,62 @@
+#[cfg(target_os = "linux")]
+use tracing::info;
+use tracing::warn;
+
+/// SCHED_FIFO priority of `--realtime`, the middle of the range: above normal
+/// tasks, below the kernel's threaded interrupts and watchdogs.
+#[cfg(target_os = "linux")]
+const REALTIME_PRIORITY: libc::c_int = 50;
+
+/// Gives every thread of the process real-time priority and pins them to
+/// `cpu`, so other work on a busy host delays the probes less. Threads started
+/// later inherit both. Runs on with normal scheduling, and a warning, where the
+/// process lacks the privilege.
+#[cfg(target_os = "linux")]
+pub(crate) fn apply(realtime: bool, cpu: Option<usize>) {
+    let threads: Vec<libc::pid_t> = match std::fs::read_dir("/proc/self/task") {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok()?.file_name().to_str()?.parse().ok())
+            .collect(),
+        Err(e) => {
+            warn!("Could not list the threads to schedule: {}", e);
+            return;
+        }
+    };
+
+    if let Some(cpu) = cpu {
+        let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+        let failed = threads.iter().find_map(|&tid| {
+            let ret = unsafe { libc::sched_setaffinity(tid, std::mem::size_of_val(&set), &set) };
+            (ret != 0).then(std::io::Error::last_os_error)
+        });
+        match failed {
+            None => info!("Pinned to CPU {}", cpu),
+            Some(e) => warn!("Could not pin to CPU {} ({}), running on all CPUs", cpu, e),
+        }
+    }
+
+    if realtime {
+        let param = libc::sched_param {
+            sched_priority: REALTIME_PRIORITY,
+        };
+        let failed = threads.iter().find_map(|&tid| {
+            let ret = unsafe { libc::sched_setscheduler(tid, libc::SCHED_FIFO, &param) };
+            (ret != 0).then(std::io::Error::last_os_error)
+        });
+        match failed {
+            None => info!("Running with SCHED_FIFO priority {}", REALTIME_PRIORITY),
+            Some(e) => warn!(
+                "Could not switch to real-time priority ({}), it needs root or CAP_SYS_NICE",
+                e
+            ),
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn apply(realtime: bool, cpu: Option<usize>) {
+    if realtime || cpu.is_some() {
+        warn!("--realtime and --cpu need Linux, running with normal scheduling");
+    }
+}
@@ -1,18 +1,210 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
 use color_eyre::eyre::Result;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
 
-use crate::network::echo::Echo;
+use crate::{
+    app::App,
+    components::server_view::ServerView,
+    metrics::{MetricsExporter, Source},
+    network::{
+        bandwidth::TcpSink,
+        buffers::SocketBuffers,
+        echo::{Echo, EchoCounters},
+        quic::QuicSink,
+    },
+    pairing::Authenticator,
+};
 
 pub(crate) struct Server {
     port: u16,
+    bind_address: Option<IpAddr>,
+    stats_interval: Option<Duration>,
+    stats_csv: Option<PathBuf>,
+    pairing: bool,
+    require_auth: bool,
+    amplification: bool,
+    tui: bool,
+    metrics: Option<SocketAddr>,
+    udplite: bool,
+    #[cfg(feature = "udp-no-checksum")]
+    no_udp_checksum: bool,
+    batch: Option<usize>,
+    buffers: SocketBuffers,
+    quic_port: Option<u16>,
 }
 
 impl Server {
     pub(crate) fn new(port: u16) -> Self {
-        Self { port }
+        Self {
+            port,
+            bind_address: None,
+            stats_interval: None,
+            stats_csv: None,
+            pairing: false,
+            require_auth: false,
+            amplification: false,
+            tui: false,
+            metrics: None,
+            udplite: false,
+            #[cfg(feature = "udp-no-checksum")]
+            no_udp_checksum: false,
+            batch: None,
+            buffers: SocketBuffers::default(),
+            quic_port: None,
+        }
+    }
+
+    /// Listen on this address only instead of all IPv6 and IPv4 addresses.
+    pub(crate) fn set_bind_address(&mut self, address: IpAddr) {
+        self.bind_address = Some(address);
+    }
+
+    /// Serve Prometheus metrics of the echoed traffic on this address.
+    pub(crate) fn enable_metrics(&mut self, listen: SocketAddr) {
+        self.metrics = Some(listen);
+    }
+
+    /// Show the clients and their traffic in a TUI instead of logging statistics.
+    pub(crate) fn enable_tui(&mut self) {
+        self.tui = true;
+    }
+
+    pub(crate) fn enable_stats(&mut self, interval: Duration) {
+        self.stats_interval = Some(interval);
+    }
+
+    pub(crate) fn enable_stats_csv(&mut self, path: PathBuf) {
+        self.stats_csv = Some(path);
+    }
+
+    /// Print a one-time code a client can pair with.
+    pub(crate) fn enable_pairing(&mut self) {
+        self.pairing = true;
+    }
+
+    /// Only serve clients that paired and authenticated.
+    pub(crate) fn require_auth(&mut self) {
+        self.require_auth = true;
+    }
+
+    /// Send clients that did not authenticate the larger or repeated replies
+    /// they ask for, rate limited per address.
+    pub(crate) fn allow_amplification(&mut self) {
+        self.amplification = true;
+    }
+
+    /// Also echo UDP-Lite probes on the UDP port.
+    pub(crate) fn enable_udplite(&mut self) {
+        self.udplite = true;
+    }
+
+    /// Accepts IPv6 probes sent without UDP checksums.
+    #[cfg(feature = "udp-no-checksum")]
+    pub(crate) fn accept_missing_udp_checksums(&mut self) {
+        self.no_udp_checksum = true;
+    }
+
+    /// Reads up to `size` datagrams per system call.
+    pub(crate) fn set_batch(&mut self, size: usize) {
+        self.batch = Some(size);
+    }
+
+    /// Sizes the kernel buffers of the UDP sockets.
+    pub(crate) fn set_socket_buffers(&mut self, buffers: SocketBuffers) {
+        self.buffers = buffers;
+    }
+
+    /// Also accept QUIC latency and bandwidth tests on this UDP port.
+    pub(crate) fn enable_quic(&mut self, port: u16) {
+        self.quic_port = Some(port);
     }
 
     pub(crate) async fn run(&self) -> Result<()> {
-        let mut echo = Echo::new(self.port);
-        echo.run().await
+        let auth = Arc::new(Authenticator::load(self.require_auth)?);
+        let pairing_code = self.pairing.then(|| auth.new_code());
+        if let Some(ref code) = pairing_code {
+            info!(
+                "Pairing code: {}, pair a client with --pair within 10 minutes",
+                code
+            );
+        } else if self.require_auth && !auth.has_clients() {
+            warn!("Authentication is required but no client is paired yet, use --pair");
+        }
+
+        let mut echo = Echo::new(self.port).with_authenticator(auth.clone());
+        if let Some(interval) = self.stats_interval {
+            echo = echo.with_stats(interval);
+            if let Some(ref path) = self.stats_csv {
+                echo = echo.with_stats_csv(path.clone());
+            }
+        }
+        if self.amplification {
+            echo = echo.with_amplification();
+        }
+        if self.udplite {
+            echo = echo.with_udplite();
+        }
+        #[cfg(feature = "udp-no-checksum")]
+        if self.no_udp_checksum {
+            echo = echo.with_missing_checksums();
+        }
+        if let Some(size) = self.batch {
+            echo = echo.with_batch(size);
+        }
+        if !self.buffers.is_default() {
+            echo = echo.with_socket_buffers(self.buffers);
+        }
+        let mut sink = TcpSink::new(self.port).with_authenticator(auth.clone());
+        let mut quic = self
+            .quic_port
+            .map(|port| QuicSink::new(port).with_authenticator(auth));
+        if let Some(address) = self.bind_address {
+            echo = echo.with_bind_address(address);
+            sink = sink.with_bind_address(address);
+            quic = quic.map(|quic| quic.with_bind_address(address));
+        }
+        let quic = async move {
+            match quic {
+                Some(quic) => quic.run().await,
+                None => Ok(()),
+            }
+        };
+
+        let cancel = CancellationToken::new();
+        let _cancel = cancel.clone().drop_guard();
+        if let Some(listen) = self.metrics {
+            let counters = Arc::new(EchoCounters::default());
+            echo = echo.with_counters(counters.clone());
+            let exporter = MetricsExporter::new(listen, Source::Server(counters), cancel);
+            tokio::spawn(async move {
+                if let Err(e) = exporter.run().await {
+                    error!("Metrics exporter failed: {:?}", e);
+                }
+            });
+        }
+
+        if !self.tui {
+            tokio::try_join!(echo.run(), sink.run(), quic)?;
+            return Ok(());
+        }
+
+        let (action_tx, action_rx) = mpsc::unbounded_channel();
+        let mut echo = echo.with_notify(action_tx.clone());
+        App::new(1.0, 10.0)?
+            .with_component(Box::new(ServerView::new(self.port, pairing_code)))
+            .with_task(tokio::spawn(async move {
+                tokio::try_join!(echo.run(), sink.run(), quic)?;
+                Ok(())
+            }))
+            .run(action_tx, action_rx, |_| false)
+            .await
     }
 }
@@ -0,0 +1,165 @@
+use std::{
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{bail, eyre, Result};
+use ed25519_dalek::{
+    pkcs8::{DecodePrivateKey, DecodePublicKey},
+    Signature, Signer, SigningKey, Verifier, VerifyingKey,
+};
+use sha2::{Digest, Sha256};
+
+const SIGNATURE_HEADER: &str = "bwlat-signature-v1";
+
+/// Detached signature over the canonicalized contents of an exported report.
+pub(crate) struct ReportSignature {
+    pub digest: [u8; 32],
+    pub public_key: VerifyingKey,
+    pub signature: Signature,
+}
+
+/// Loads an Ed25519 private key from a PKCS#8 PEM file, e.g. one generated with
+/// `openssl genpkey -algorithm ed25519`.
+pub(crate) fn load_signing_key(path: &Path) -> Result<SigningKey> {
+    let pem = fs::read_to_string(path)?;
+    SigningKey::from_pkcs8_pem(&pem)
+        .map_err(|e| eyre!("Invalid signing key {}: {}", path.display(), e))
+}
+
+pub(crate) fn load_verifying_key(path: &Path) -> Result<VerifyingKey> {
+    let pem = fs::read_to_string(path)?;
+    VerifyingKey::from_public_key_pem(&pem)
+        .map_err(|e| eyre!("Invalid public key {}: {}", path.display(), e))
+}
+
+/// Path of the detached signature written next to a report.
+pub(crate) fn signature_path(report: &Path) -> PathBuf {
+    let mut path = report.as_os_str().to_owned();
+    path.push(".sig");
+    PathBuf::from(path)
+}
+
+/// SHA-256 of the report with line endings normalized, so a report that went
+/// through a CRLF conversion still verifies.
+fn canonical_digest(contents: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    let mut iter = contents.iter().peekable();
+
+    while let Some(&b) = iter.next() {
+        if b == b'\r' && iter.peek() == Some(&&b'\n') {
+            continue;
+        }
+        hasher.update([b]);
+    }
+
+    hasher.finalize().into()
+}
+
+pub(crate) fn sign_report(report: &Path, key: &SigningKey) -> Result<PathBuf> {
+    let digest = canonical_digest(&fs::read(report)?);
+    let signature = ReportSignature {
+        digest,
+        public_key: key.verifying_key(),
+        signature: key.sign(&digest),
+    };
+
+    let path = signature_path(report);
+    fs::write(&path, signature.encode())?;
+
+    Ok(path)
+}
+
+/// Verifies `report` against its detached signature. When `trusted_key` is given
+/// the signature must also have been made with that key.
+pub(crate) fn verify_report(
+    report: &Path,
+    signature: &Path,
+    trusted_key: Option<&VerifyingKey>,
+) -> Result<ReportSignature> {
+    let signature = ReportSignature::decode(&fs::read_to_string(signature)?)?;
+
+    if canonical_digest(&fs::read(report)?) != signature.digest {
+        bail!("Report contents do not match the signed digest");
+    }
+
+    if let Some(trusted_key) = trusted_key {
+        if *trusted_key != signature.public_key {
+            bail!("Report was signed with a different key");
+        }
+    }
+
+    signature
+        .public_key
+        .verify(&signature.digest, &signature.signature)
+        .map_err(|_| eyre!("Invalid signature"))?;
+
+    Ok(signature)
+}
+
+impl ReportSignature {
+    fn encode(&self) -> String {
+        format!(
+            "{}\nalgorithm: ed25519\ndigest: sha256:{}\npublic-key: {}\nsignature: {}\n",
+            SIGNATURE_HEADER,
+            to_hex(&self.digest),
+            to_hex(self.public_key.as_bytes()),
+            to_hex(&self.signature.to_bytes()),
+        )
+    }
+
+    fn decode(s: &str) -> Result<Self> {
+        let mut lines = s.lines();
+        if lines.next() != Some(SIGNATURE_HEADER) {
+            bail!("Not a bwlat signature file");
+        }
+
+        let (mut digest, mut public_key, mut signature) = (None, None, None);
+        for line in lines {
+            match line.split_once(": ") {
+                Some(("algorithm", "ed25519")) => {}
+                Some(("algorithm", other)) => bail!("Unsupported signature algorithm: {}", other),
+                Some(("digest", value)) => {
+                    let value = value
+                        .strip_prefix("sha256:")
+                        .ok_or_else(|| eyre!("Unsupported digest: {}", value))?;
+                    digest = Some(from_hex::<32>(value)?);
+                }
+                Some(("public-key", value)) => public_key = Some(from_hex::<32>(value)?),
+                Some(("signature", value)) => signature = Some(from_hex::<64>(value)?),
+                _ => bail!("Malformed signature line: {}", line),
+            }
+        }
+
+        let public_key = public_key.ok_or_else(|| eyre!("Signature is missing the public key"))?;
+
+        Ok(Self {
+            digest: digest.ok_or_else(|| eyre!("Signature is missing the digest"))?,
+            public_key: VerifyingKey::from_bytes(&public_key)?,
+            signature: Signature::from_bytes(
+                &signature.ok_or_else(|| eyre!("Signature is missing the signature"))?,
+            ),
+        })
+    }
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut s, b| {
+        let _ = write!(s, "{:02x}", b);
+        s
+    })
+}
+
+pub(crate) fn from_hex<const N: usize>(s: &str) -> Result<[u8; N]> {
+    if s.len() != N * 2 {
+        bail!("Expected {} hex characters, got {}", N * 2, s.len());
+    }
+
+    let mut out = [0; N];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16)?;
+    }
+
+    Ok(out)
+}
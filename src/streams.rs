@@ -0,0 +1,238 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use color_eyre::eyre::Result;
+use hdrhistogram::Histogram;
+use tokio::{
+    sync::{
+        mpsc::{UnboundedReceiver, UnboundedSender},
+        Mutex,
+    },
+    time::Instant,
+};
+
+use crate::{
+    action::Action,
+    network::latency::{Percentiles, SendRate, SourceStatistics, State, Traffic},
+};
+
+/// How often the TUI gets percentiles merged over all streams.
+const PERCENTILE_UPDATE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Latest figures one stream reported.
+#[derive(Debug, Default, Clone, Copy)]
+struct StreamProgress {
+    total: u32,
+    sent: u32,
+    skipped: u32,
+    received: u32,
+    min: Duration,
+    average: Duration,
+    max: Duration,
+    traffic: (u64, u64),
+    rate: Option<SendRate>,
+    converged: bool,
+}
+
+/// Combines the actions of the concurrent streams to one target, each tagged
+/// with its stream index, into actions for the target as if a single engine
+/// sent all probes.
+pub(crate) struct StreamRelay {
+    target: usize,
+    states: Vec<Arc<Mutex<State>>>,
+    streams: Vec<StreamProgress>,
+    percentiles_updated: Option<Instant>,
+}
+
+impl StreamRelay {
+    pub(crate) fn new(target: usize, states: Vec<Arc<Mutex<State>>>) -> Self {
+        Self {
+            target,
+            streams: vec![StreamProgress::default(); states.len()],
+            states,
+            percentiles_updated: None,
+        }
+    }
+
+    /// Relays until every stream dropped its sender.
+    pub(crate) async fn run(
+        mut self,
+        mut streams: UnboundedReceiver<Action>,
+        notify: UnboundedSender<Action>,
+    ) -> Result<()> {
+        while let Some(action) = streams.recv().await {
+            if let Some(action) = self.combine(action).await {
+                // The run may already be over and nobody listening
+                if notify.send(action).is_err() {
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn combine(&mut self, action: Action) -> Option<Action> {
+        let t = self.target;
+        Some(match action {
+            Action::LatencyPacketTotal(s, total) => {
+                self.streams[s].total = total;
+                Action::LatencyPacketTotal(t, self.sum(|p| p.total))
+            }
+            Action::LatencyPacketsSent(s, sent) => {
+                self.streams[s].sent = sent;
+                Action::LatencyPacketsSent(t, self.sum(|p| p.sent))
+            }
+            Action::LatencyPacketsSkipped(s, skipped) => {
+                self.streams[s].skipped = skipped;
+                Action::LatencyPacketsSkipped(t, self.sum(|p| p.skipped))
+            }
+            Action::LatencyPacketsReceived(s, received, min, average, max) => {
+                let stream = &mut self.streams[s];
+                stream.received = received;
+                stream.min = min;
+                stream.average = average;
+                stream.max = max;
+
+                let answered = || self.streams.iter().filter(|p| p.received > 0);
+                let received = self.sum(|p| p.received);
+                let total: Duration = answered().map(|p| p.average * p.received).sum();
+                Action::LatencyPacketsReceived(
+                    t,
+                    received,
+                    answered().map(|p| p.min).min().unwrap_or_default(),
+                    total / received.max(1),
+                    answered().map(|p| p.max).max().unwrap_or_default(),
+                )
+            }
+            Action::LatencyTraffic(s, sent, received) => {
+                self.streams[s].traffic = (sent, received);
+                Action::LatencyTraffic(
+                    t,
+                    self.streams.iter().map(|p| p.traffic.0).sum(),
+                    self.streams.iter().map(|p| p.traffic.1).sum(),
+                )
+            }
+            Action::LatencySendRate(s, rate) => {
+                self.streams[s].rate = Some(rate);
+                let rates: Vec<_> = self.streams.iter().filter_map(|p| p.rate).collect();
+                Action::LatencySendRate(
+                    t,
+                    SendRate {
+                        // The streams together send this often
+                        interval: rate.interval / self.streams.len() as u32,
+                        packets: rates.iter().map(|r| r.packets).sum(),
+                        span: rates.iter().map(|r| r.span).max().unwrap_or_default(),
+                        wire_bytes: rates.iter().map(|r| r.wire_bytes).sum(),
+                    },
+                )
+            }
+            Action::LatencyPercentiles(..) => {
+                if self
+                    .percentiles_updated
+                    .is_some_and(|at| at.elapsed() < PERCENTILE_UPDATE_INTERVAL)
+                {
+                    return None;
+                }
+                self.percentiles_updated = Some(Instant::now());
+
+                let mut states = Vec::with_capacity(self.states.len());
+                for state in self.states.iter() {
+                    states.push(state.lock().await);
+                }
+                let states: Vec<&State> = states.iter().map(|s| &**s).collect();
+                Action::LatencyPercentiles(t, StreamsSummary::of(&states).percentiles()?)
+            }
+            Action::LatencyConverged(s) => {
+                self.streams[s].converged = true;
+                if !self.streams.iter().all(|p| p.converged) {
+                    return None;
+                }
+                Action::LatencyConverged(t)
+            }
+            Action::LatencyEvent(_, at, event) => Action::LatencyEvent(t, at, event),
+            Action::LatencySample(_, at, latency) => Action::LatencySample(t, at, latency),
+            Action::LatencyLost(_, sent) => Action::LatencyLost(t, sent),
+            action => action,
+        })
+    }
+
+    fn sum(&self, value: impl Fn(&StreamProgress) -> u32) -> u32 {
+        self.streams.iter().map(value).sum()
+    }
+}
+
+/// Results of all streams to one target taken together.
+pub(crate) struct StreamsSummary {
+    pub sent: u32,
+    pub lost: u32,
+    pub min_latency: Duration,
+    pub average_latency: Duration,
+    pub max_latency: Duration,
+    pub histogram: Histogram<u64>,
+    pub traffic_sent: Traffic,
+    pub traffic_received: Traffic,
+    /// Each stream by its source port
+    pub streams: Vec<SourceStatistics>,
+}
+
+impl StreamsSummary {
+    pub(crate) fn of(states: &[&State]) -> Self {
+        let answered = || states.iter().filter(|s| s.received_packets > 0);
+        let received: u32 = states.iter().map(|s| s.received_packets).sum();
+        let total: Duration = answered()
+            .map(|s| s.average_latency * s.received_packets)
+            .sum();
+
+        let mut histogram = Histogram::new_from(&states[0].histogram);
+        for state in states {
+            // Same bounds in every stream, adding cannot fail
+            let _ = histogram.add(&state.histogram);
+        }
+
+        let mut traffic_sent = Traffic::default();
+        let mut traffic_received = Traffic::default();
+        for state in states {
+            traffic_sent.payload += state.traffic_sent.payload;
+            traffic_sent.wire += state.traffic_sent.wire;
+            traffic_received.payload += state.traffic_received.payload;
+            traffic_received.wire += state.traffic_received.wire;
+        }
+
+        Self {
+            sent: states.iter().map(|s| s.sent_packets()).sum(),
+            lost: states.iter().map(|s| s.packet_loss).sum(),
+            min_latency: answered().map(|s| s.min_latency).min().unwrap_or_default(),
+            average_latency: total / received.max(1),
+            max_latency: answered().map(|s| s.max_latency).max().unwrap_or_default(),
+            histogram,
+            traffic_sent,
+            traffic_received,
+            streams: states
+                .iter()
+                .map(|s| SourceStatistics {
+                    source: s
+                        .sources
+                        .first()
+                        .copied()
+                        .unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 0))),
+                    sent: s.sent_packets(),
+                    received: s.received_packets,
+                    average_latency: s.average_latency,
+                })
+                .collect(),
+        }
+    }
+
+    pub(crate) fn percentiles(&self) -> Option<Percentiles> {
+        if self.histogram.is_empty() {
+            return None;
+        }
+        let at = |p| Duration::from_nanos(self.histogram.value_at_percentile(p));
+        Some(Percentiles {
+            p50: at(50.0),
+            p90: at(90.0),
+            p99: at(99.0),
+            p999: at(99.9),
+        })
+    }
+}
@@ -0,0 +1,509 @@
+use std::{
+    fmt::Write as _,
+    fs,
+    net::SocketAddr,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use color_eyre::eyre::{bail, eyre, Result};
+use serde::{de::Error as _, Deserialize, Deserializer};
+use tokio::{sync::mpsc, time::Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::network::{
+    bandwidth::{format_bitrate, BandwidthState, TcpBandwidth},
+    latency::{Latency, State},
+    mtu::{self, PathMtu},
+};
+
+/// Sequence of measurements with pass/fail criteria, run against every target.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TestPlan {
+    #[serde(default = "default_name")]
+    pub name: String,
+    /// `host:port` of bwlat servers
+    pub targets: Vec<String>,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Step {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: StepKind,
+    #[serde(default, rename = "assert")]
+    pub assertions: Assertions,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum StepKind {
+    Latency(LatencyStep),
+    Bandwidth(BandwidthStep),
+    Mtu(MtuStep),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct LatencyStep {
+    pub count: u32,
+    #[serde(deserialize_with = "duration")]
+    pub interval: Duration,
+    pub size: u16,
+}
+
+impl Default for LatencyStep {
+    fn default() -> Self {
+        Self {
+            count: 100,
+            interval: Duration::from_millis(20),
+            size: 64,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct BandwidthStep {
+    #[serde(deserialize_with = "duration")]
+    pub duration: Duration,
+    pub congestion: Option<String>,
+}
+
+impl Default for BandwidthStep {
+    fn default() -> Self {
+        Self {
+            duration: Duration::from_secs(10),
+            congestion: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct MtuStep {
+    /// Largest MTU to try
+    pub max: u16,
+}
+
+impl Default for MtuStep {
+    fn default() -> Self {
+        Self { max: 1500 }
+    }
+}
+
+/// Criteria a step has to meet, unset ones are not checked.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Assertions {
+    /// Packet loss in percent
+    pub max_loss: Option<f64>,
+    #[serde(deserialize_with = "optional_duration")]
+    pub max_average_latency: Option<Duration>,
+    #[serde(deserialize_with = "optional_duration")]
+    pub max_latency: Option<Duration>,
+
+    /// Average goodput in bits per second
+    pub min_bitrate: Option<f64>,
+    pub max_retransmits: Option<u32>,
+    /// Time until a one second interval first reaches `min_bitrate`
+    #[serde(deserialize_with = "optional_duration")]
+    pub max_ramp_up: Option<Duration>,
+
+    pub min_mtu: Option<u16>,
+}
+
+impl Assertions {
+    /// Names of the assertions that do not apply to `kind`.
+    fn unsupported(&self, kind: &StepKind) -> Vec<&'static str> {
+        let latency = matches!(kind, StepKind::Latency(_));
+        let bandwidth = matches!(kind, StepKind::Bandwidth(_));
+        let mtu = matches!(kind, StepKind::Mtu(_));
+
+        [
+            ("max_loss", self.max_loss.is_some(), latency),
+            (
+                "max_average_latency",
+                self.max_average_latency.is_some(),
+                latency,
+            ),
+            ("max_latency", self.max_latency.is_some(), latency),
+            ("min_bitrate", self.min_bitrate.is_some(), bandwidth),
+            ("max_retransmits", self.max_retransmits.is_some(), bandwidth),
+            ("max_ramp_up", self.max_ramp_up.is_some(), bandwidth),
+            ("min_mtu", self.min_mtu.is_some(), mtu),
+        ]
+        .into_iter()
+        .filter(|&(_, set, applies)| set && !applies)
+        .map(|(name, ..)| name)
+        .collect()
+    }
+
+    fn check_latency(&self, state: &State) -> Vec<String> {
+        let mut failures = Vec::new();
+
+        let loss = 100.0 * state.packet_loss as f64 / state.sent_packets().max(1) as f64;
+        if let Some(max) = self.max_loss.filter(|&max| loss > max) {
+            failures.push(format!("packet loss {:.2}% exceeds {}%", loss, max));
+        }
+
+        let bounded = self.max_average_latency.is_some() || self.max_latency.is_some();
+        if bounded && state.received_packets == 0 {
+            failures.push("no probe was answered".to_string());
+            return failures;
+        }
+        if let Some(max) = self
+            .max_average_latency
+            .filter(|&max| state.average_latency > max)
+        {
+            failures.push(format!(
+                "average latency {:.3?} exceeds {:?}",
+                state.average_latency, max
+            ));
+        }
+        if let Some(max) = self.max_latency.filter(|&max| state.max_latency > max) {
+            failures.push(format!(
+                "max latency {:.3?} exceeds {:?}",
+                state.max_latency, max
+            ));
+        }
+
+        failures
+    }
+
+    fn check_bandwidth(&self, state: &BandwidthState) -> Vec<String> {
+        let mut failures = Vec::new();
+
+        if let Some(min) = self
+            .min_bitrate
+            .filter(|&min| state.bits_per_second() < min)
+        {
+            failures.push(format!(
+                "bitrate {} below {}",
+                format_bitrate(state.bits_per_second()),
+                format_bitrate(min)
+            ));
+        }
+        if let Some(max) = self.max_retransmits.filter(|&max| state.retransmits > max) {
+            failures.push(format!("{} retransmits exceed {}", state.retransmits, max));
+        }
+        if let (Some(max), Some(target)) = (self.max_ramp_up, self.min_bitrate) {
+            let ramp_up = state
+                .samples
+                .iter()
+                .find(|s| s.bits_per_second as f64 >= target)
+                .map(|s| s.at);
+            match ramp_up {
+                Some(at) if at <= max => {}
+                Some(at) => failures.push(format!(
+                    "reached {} after {:?}, allowed {:?}",
+                    format_bitrate(target),
+                    at,
+                    max
+                )),
+                None => failures.push(format!("never reached {}", format_bitrate(target))),
+            }
+        }
+
+        failures
+    }
+
+    fn check_mtu(&self, result: &PathMtu) -> Vec<String> {
+        match self.min_mtu {
+            Some(min) if result.mtu < min => {
+                vec![format!("path MTU {} below {}", result.mtu, min)]
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Result of one step against one target.
+#[derive(Debug)]
+enum Outcome {
+    Passed,
+    Failed(Vec<String>),
+    /// The step could not be run
+    Error(String),
+}
+
+#[derive(Debug)]
+struct TestCase {
+    step: String,
+    target: String,
+    outcome: Outcome,
+    summary: String,
+    time: Duration,
+}
+
+impl TestPlan {
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let plan: Self = serde_yaml::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| eyre!("Invalid test plan {}: {}", path.display(), e))?;
+
+        if plan.targets.is_empty() {
+            bail!("The test plan has no targets");
+        }
+        for step in plan.steps.iter() {
+            let unsupported = step.assertions.unsupported(&step.kind);
+            if !unsupported.is_empty() {
+                bail!(
+                    "Step '{}' asserts {}, which its measurement does not provide",
+                    step.name,
+                    unsupported.join(", ")
+                );
+            }
+            if step.assertions.max_ramp_up.is_some() && step.assertions.min_bitrate.is_none() {
+                bail!(
+                    "Step '{}' needs min_bitrate to assert max_ramp_up",
+                    step.name
+                );
+            }
+        }
+
+        Ok(plan)
+    }
+
+    /// Runs every step against every target in order, optionally writing a JUnit
+    /// XML report, and fails if any assertion did not hold.
+    pub(crate) async fn run(&self, junit: Option<&Path>) -> Result<()> {
+        let started = SystemTime::now();
+        let mut cases = Vec::new();
+
+        for target in self.targets.iter() {
+            let address = tokio::net::lookup_host(target)
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|mut a| a.next().ok_or_else(|| "no address".to_string()));
+
+            for step in self.steps.iter() {
+                let start = Instant::now();
+                let (outcome, summary) = match address {
+                    Ok(address) => match self.run_step(step, address).await {
+                        Ok((failures, summary)) if failures.is_empty() => {
+                            (Outcome::Passed, summary)
+                        }
+                        Ok((failures, summary)) => (Outcome::Failed(failures), summary),
+                        Err(e) => (Outcome::Error(e.to_string()), String::new()),
+                    },
+                    Err(ref e) => (
+                        Outcome::Error(format!("Could not resolve {}: {}", target, e)),
+                        String::new(),
+                    ),
+                };
+
+                let case = TestCase {
+                    step: step.name.clone(),
+                    target: target.clone(),
+                    outcome,
+                    summary,
+                    time: start.elapsed(),
+                };
+                match case.outcome {
+                    Outcome::Passed => info!("PASS {} on {}: {}", case.step, target, case.summary),
+                    Outcome::Failed(ref failures) => {
+                        warn!("FAIL {} on {}: {}", case.step, target, failures.join("; "))
+                    }
+                    Outcome::Error(ref e) => warn!("ERROR {} on {}: {}", case.step, target, e),
+                }
+                cases.push(case);
+            }
+        }
+
+        if let Some(path) = junit {
+            fs::write(path, self.junit(&cases, started))?;
+            info!("Wrote JUnit report to {}", path.display());
+        }
+
+        let unsuccessful = cases
+            .iter()
+            .filter(|c| !matches!(c.outcome, Outcome::Passed))
+            .count();
+        if unsuccessful > 0 {
+            bail!(
+                "{} of {} test cases did not pass",
+                unsuccessful,
+                cases.len()
+            );
+        }
+        info!("All {} test cases passed", cases.len());
+
+        Ok(())
+    }
+
+    /// Runs a single measurement, returning the failed assertions and a summary of
+    /// the results.
+    async fn run_step(&self, step: &Step, address: SocketAddr) -> Result<(Vec<String>, String)> {
+        // The engines report progress for the TUI, nothing listens here
+        let (notify, mut actions) = mpsc::unbounded_channel();
+        let drain = tokio::spawn(async move { while actions.recv().await.is_some() {} });
+
+        let result = match step.kind {
+            StepKind::Latency(ref options) => {
+                let mut latency = Latency::new_with_count(
+                    address.ip(),
+                    address.port(),
+                    options.count,
+                    notify,
+                    CancellationToken::new(),
+                )
+                .with_interval(options.interval)
+                .with_packet_size(options.size);
+
+                let state = latency.run().await?;
+                let state = state.lock().await;
+                let summary = format!(
+                    "loss {}/{}, latency min {:.3?} avg {:.3?} max {:.3?}",
+                    state.packet_loss,
+                    state.sent_packets(),
+                    state.min_latency,
+                    state.average_latency,
+                    state.max_latency
+                );
+                (step.assertions.check_latency(&state), summary)
+            }
+            StepKind::Bandwidth(ref options) => {
+                let mut bandwidth = TcpBandwidth::new(
+                    address.ip(),
+                    address.port(),
+                    options.duration,
+                    notify,
+                    CancellationToken::new(),
+                );
+                if let Some(ref congestion) = options.congestion {
+                    bandwidth = bandwidth.with_congestion(congestion.clone());
+                }
+
+                let state = bandwidth.run().await?;
+                let summary = format!(
+                    "{}, {} retransmits",
+                    format_bitrate(state.bits_per_second()),
+                    state.retransmits
+                );
+                (step.assertions.check_bandwidth(&state), summary)
+            }
+            StepKind::Mtu(ref options) => {
+                drop(notify);
+                let result = mtu::discover(address.ip(), address.port(), options.max).await?;
+                let summary = format!("path MTU {} ({} probes)", result.mtu, result.probes);
+                (step.assertions.check_mtu(&result), summary)
+            }
+        };
+
+        drain.await?;
+
+        Ok(result)
+    }
+
+    fn junit(&self, cases: &[TestCase], started: SystemTime) -> String {
+        let failures = |cases: &[&TestCase]| {
+            cases
+                .iter()
+                .filter(|c| matches!(c.outcome, Outcome::Failed(_)))
+                .count()
+        };
+        let errors = |cases: &[&TestCase]| {
+            cases
+                .iter()
+                .filter(|c| matches!(c.outcome, Outcome::Error(_)))
+                .count()
+        };
+        let time = |cases: &[&TestCase]| cases.iter().map(|c| c.time).sum::<Duration>();
+
+        let all: Vec<_> = cases.iter().collect();
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let _ = writeln!(
+            xml,
+            "<testsuites name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">",
+            escape(&self.name),
+            all.len(),
+            failures(&all),
+            errors(&all),
+            time(&all).as_secs_f64()
+        );
+
+        for target in self.targets.iter() {
+            let suite: Vec<_> = cases.iter().filter(|c| &c.target == target).collect();
+            let _ = writeln!(
+                xml,
+                "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\" timestamp=\"{}\">",
+                escape(target),
+                suite.len(),
+                failures(&suite),
+                errors(&suite),
+                time(&suite).as_secs_f64(),
+                humantime::format_rfc3339_seconds(started)
+            );
+
+            for case in suite {
+                let _ = write!(
+                    xml,
+                    "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\">",
+                    escape(&case.step),
+                    escape(&format!("{}.{}", self.name, target)),
+                    case.time.as_secs_f64()
+                );
+                match case.outcome {
+                    Outcome::Passed => {}
+                    Outcome::Failed(ref failures) => {
+                        let _ = write!(
+                            xml,
+                            "\n      <failure message=\"{}\">{}</failure>",
+                            escape(&failures.join("; ")),
+                            escape(&failures.join("\n"))
+                        );
+                    }
+                    Outcome::Error(ref e) => {
+                        let _ = write!(xml, "\n      <error message=\"{}\"/>", escape(e));
+                    }
+                }
+                if !case.summary.is_empty() {
+                    let _ = write!(
+                        xml,
+                        "\n      <system-out>{}</system-out>",
+                        escape(&case.summary)
+                    );
+                }
+                xml.push_str("\n    </testcase>\n");
+            }
+
+            xml.push_str("  </testsuite>\n");
+        }
+
+        xml.push_str("</testsuites>\n");
+        xml
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn default_name() -> String {
+    "bwlat".to_string()
+}
+
+pub(crate) fn duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    humantime::parse_duration(&s).map_err(D::Error::custom)
+}
+
+pub(crate) fn optional_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    duration(deserializer).map(Some)
+}
@@ -24,6 +24,7 @@ pub enum TuiEvent {
     Render,
     FocusGained,
     FocusLost,
+    #[allow(dead_code)]
     Paste(String),
     Key(KeyEvent),
     Mouse(MouseEvent),
@@ -0,0 +1,128 @@
+use std::{env, time::Duration};
+
+/// Unit latencies are displayed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub(crate) enum Units {
+    /// Pick µs, ms or s depending on the magnitude
+    #[default]
+    Auto,
+    Ms,
+    Us,
+}
+
+/// How durations and numbers are shown in the TUI and summaries. Exports keep
+/// their fixed machine-readable format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DisplayFormat {
+    pub units: Units,
+    pub decimal: char,
+    pub grouping: char,
+    /// Spell units out, e.g. "milliseconds" instead of "ms", for screen readers
+    pub unit_names: bool,
+}
+
+impl Default for DisplayFormat {
+    fn default() -> Self {
+        Self {
+            units: Units::default(),
+            decimal: '.',
+            grouping: ',',
+            unit_names: false,
+        }
+    }
+}
+
+impl DisplayFormat {
+    /// Picks the separators from the numeric locale in the environment.
+    pub(crate) fn from_env(units: Units) -> Self {
+        let locale = ["LC_ALL", "LC_NUMERIC", "LANG"]
+            .iter()
+            .filter_map(|var| env::var(var).ok())
+            .find(|value| !value.is_empty())
+            .unwrap_or_default();
+
+        let (decimal, grouping) = separators(&locale);
+        Self {
+            units,
+            decimal,
+            grouping,
+            unit_names: false,
+        }
+    }
+
+    /// Spells units out, see [`DisplayFormat::unit_names`].
+    pub(crate) fn with_unit_names(mut self) -> Self {
+        self.unit_names = true;
+        self
+    }
+
+    pub(crate) fn duration(&self, duration: Duration) -> String {
+        let micros = duration.as_secs_f64() * 1e6;
+        let (us, ms, s) = match self.unit_names {
+            true => ("microseconds", "milliseconds", "seconds"),
+            false => ("µs", "ms", "s"),
+        };
+        match self.units {
+            Units::Us => format!("{} {}", self.number(micros, 1), us),
+            Units::Ms => format!("{} {}", self.number(micros / 1e3, 3), ms),
+            Units::Auto if micros < 1e3 => format!("{} {}", self.number(micros, 1), us),
+            Units::Auto if micros < 1e6 => format!("{} {}", self.number(micros / 1e3, 2), ms),
+            Units::Auto => format!("{} {}", self.number(micros / 1e6, 3), s),
+        }
+    }
+
+    /// Formats a difference of clock readings in nanoseconds, which can be negative.
+    pub(crate) fn signed_duration(&self, nanos: i64) -> String {
+        let duration = self.duration(Duration::from_nanos(nanos.unsigned_abs()));
+        match nanos < 0 {
+            true => format!("-{}", duration),
+            false => duration,
+        }
+    }
+
+    /// Formats `value` with a fixed number of decimals and grouped thousands.
+    pub(crate) fn number(&self, value: f64, decimals: usize) -> String {
+        let formatted = format!("{:.*}", decimals, value.abs());
+        let (integer, fraction) = match formatted.split_once('.') {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (formatted.as_str(), None),
+        };
+
+        let mut result = String::new();
+        if value < 0.0 && formatted.bytes().any(|b| b.is_ascii_digit() && b != b'0') {
+            result.push('-');
+        }
+        for (i, digit) in integer.chars().enumerate() {
+            if i > 0 && (integer.len() - i) % 3 == 0 {
+                result.push(self.grouping);
+            }
+            result.push(digit);
+        }
+        if let Some(fraction) = fraction {
+            result.push(self.decimal);
+            result.push_str(fraction);
+        }
+
+        result
+    }
+
+    pub(crate) fn percent(&self, ratio: f64) -> String {
+        match self.unit_names {
+            true => format!("{} percent", self.number(ratio * 100.0, 2)),
+            false => format!("{}%", self.number(ratio * 100.0, 2)),
+        }
+    }
+}
+
+/// Decimal and grouping separators for a POSIX locale name like `de_DE.UTF-8`.
+fn separators(locale: &str) -> (char, char) {
+    let language = locale.split(['_', '.', '@']).next().unwrap_or_default();
+
+    match language {
+        "de" | "da" | "es" | "id" | "it" | "nl" | "pt" | "tr" | "el" => (',', '.'),
+        "fr" | "ru" | "pl" | "cs" | "sv" | "fi" | "nb" | "no" | "uk" | "sk" | "hu" => {
+            (',', '\u{202f}')
+        }
+        _ => ('.', ','),
+    }
+}
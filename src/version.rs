@@ -0,0 +1,88 @@
+use std::{fs, net::IpAddr};
+
+use serde::Serialize;
+
+use crate::network::{route, tcp};
+
+/// What this binary is and what it can do on the current machine.
+#[derive(Debug, Serialize)]
+pub(crate) struct VersionInfo {
+    pub name: &'static str,
+    pub version: &'static str,
+    pub target: &'static str,
+    pub profile: &'static str,
+    /// Optional functionality compiled into this build
+    pub features: Vec<&'static str>,
+    pub capabilities: Capabilities,
+}
+
+/// Results of probing the running system.
+#[derive(Debug, Serialize)]
+pub(crate) struct Capabilities {
+    pub os: &'static str,
+    pub kernel: Option<String>,
+    pub ipv6: bool,
+    pub tcp_info: bool,
+    pub route_lookup: bool,
+    pub congestion_control: Vec<String>,
+}
+
+impl VersionInfo {
+    pub(crate) fn detect() -> Self {
+        let mut features = Vec::new();
+        if cfg!(target_os = "linux") {
+            features.extend(["tcp-info", "congestion-control", "route-tracking"]);
+        }
+        if cfg!(feature = "udp-no-checksum") {
+            features.push("udp-no-checksum");
+        }
+
+        Self {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            target: env!("BWLAT_TARGET"),
+            profile: if cfg!(debug_assertions) {
+                "debug"
+            } else {
+                "release"
+            },
+            features,
+            capabilities: Capabilities::detect(),
+        }
+    }
+}
+
+impl Capabilities {
+    pub(crate) fn detect() -> Self {
+        Self {
+            os: std::env::consts::OS,
+            kernel: kernel_release(),
+            ipv6: std::net::UdpSocket::bind("[::1]:0").is_ok(),
+            tcp_info: std::net::TcpListener::bind("127.0.0.1:0")
+                .map(|listener| tcp::tcp_info(&listener).is_ok())
+                .unwrap_or(false),
+            route_lookup: route::lookup(IpAddr::from([127, 0, 0, 1])).is_ok(),
+            congestion_control: fs::read_to_string(
+                "/proc/sys/net/ipv4/tcp_available_congestion_control",
+            )
+            .map(|s| s.split_whitespace().map(str::to_string).collect())
+            .unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn kernel_release() -> Option<String> {
+    let mut uts: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return None;
+    }
+
+    let release = unsafe { std::ffi::CStr::from_ptr(uts.release.as_ptr()) };
+    Some(release.to_string_lossy().into_owned())
+}
+
+#[cfg(not(unix))]
+fn kernel_release() -> Option<String> {
+    None
+}
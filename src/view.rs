@@ -0,0 +1,230 @@
+use std::{fs, path::Path, time::Duration};
+
+use color_eyre::eyre::{eyre, Result};
+use serde::Deserialize;
+
+use crate::units::DisplayFormat;
+
+#[derive(Debug, Deserialize)]
+struct Results {
+    metadata: Metadata,
+    address: String,
+    summary: Summary,
+    packets: Vec<Packet>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Metadata {
+    started: String,
+}
+
+/// The figures of a latency export that are shown, latencies in microseconds.
+#[derive(Debug, Deserialize)]
+struct Summary {
+    sent: u32,
+    received: u32,
+    lost: u32,
+    min_latency: u64,
+    average_latency: u64,
+    max_latency: u64,
+    p50_latency: Option<u64>,
+    p90_latency: Option<u64>,
+    p99_latency: Option<u64>,
+    p999_latency: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Packet {
+    sent: u64,
+    latency: Option<u64>,
+    status: String,
+}
+
+/// A latency run loaded back from a `client --json` export.
+pub(crate) struct RecordedRun {
+    /// Target and start of the run
+    pub name: String,
+    pub sent: u32,
+    pub received: u32,
+    pub lost: u32,
+    pub min_latency: Duration,
+    pub average_latency: Duration,
+    pub max_latency: Duration,
+    pub percentiles: [Option<Duration>; 4],
+    /// Answered probes, seconds since the start and milliseconds
+    pub latencies: Vec<(f64, f64)>,
+    /// Send times of the lost probes, seconds since the start
+    pub lost_at: Vec<f64>,
+}
+
+impl RecordedRun {
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| eyre!("Could not read {}: {}", path.display(), e))?;
+        let results: Results = serde_json::from_str(&contents).map_err(|e| {
+            eyre!(
+                "{} is not a latency export of `client --json`: {}",
+                path.display(),
+                e
+            )
+        })?;
+
+        let seconds = |us: u64| us as f64 / 1e6;
+        let mut latencies = Vec::new();
+        let mut lost_at = Vec::new();
+        for packet in results.packets.iter() {
+            match (packet.status.as_str(), packet.latency) {
+                ("received" | "corrupted", Some(latency)) => {
+                    latencies.push((seconds(packet.sent), latency as f64 / 1000.0))
+                }
+                ("lost", _) => lost_at.push(seconds(packet.sent)),
+                _ => {}
+            }
+        }
+
+        let summary = results.summary;
+        Ok(Self {
+            name: format!("{}, {}", results.address, results.metadata.started),
+            sent: summary.sent,
+            received: summary.received,
+            lost: summary.lost,
+            min_latency: Duration::from_micros(summary.min_latency),
+            average_latency: Duration::from_micros(summary.average_latency),
+            max_latency: Duration::from_micros(summary.max_latency),
+            percentiles: [
+                summary.p50_latency,
+                summary.p90_latency,
+                summary.p99_latency,
+                summary.p999_latency,
+            ]
+            .map(|p| p.map(Duration::from_micros)),
+            latencies,
+            lost_at,
+        })
+    }
+
+    pub(crate) fn loss(&self) -> f64 {
+        self.lost as f64 / self.sent.max(1) as f64
+    }
+
+    /// Seconds from the first to the last probe.
+    pub(crate) fn span(&self) -> f64 {
+        let last = self.latencies.last().map_or(0.0, |&(t, _)| t);
+        self.lost_at.last().map_or(last, |&t| t.max(last))
+    }
+}
+
+/// Whether a figure got better or worse in the run compared to the other one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Change {
+    Better,
+    Worse,
+    Same,
+}
+
+/// One line of the stats table, the figure of the run, of the compared run and
+/// the difference between them.
+pub(crate) struct FigureRow {
+    pub figure: &'static str,
+    pub value: String,
+    pub compared: Option<String>,
+    pub delta: Option<(String, Change)>,
+}
+
+/// Figures of `run` and, with `compared`, the difference to that run. Higher
+/// latency and loss count as worse.
+pub(crate) fn figure_rows(
+    run: &RecordedRun,
+    compared: Option<&RecordedRun>,
+    format: &DisplayFormat,
+) -> Vec<FigureRow> {
+    let count = |figure, value: fn(&RecordedRun) -> u32| FigureRow {
+        figure,
+        value: value(run).to_string(),
+        compared: compared.map(|c| value(c).to_string()),
+        delta: compared.map(|c| {
+            let delta = value(run) as i64 - value(c) as i64;
+            (format!("{:+}", delta), Change::Same)
+        }),
+    };
+
+    let latency = |figure, value: Option<Duration>, other: Option<Option<Duration>>| {
+        let show = |v: Option<Duration>| v.map_or_else(|| "-".to_string(), |v| format.duration(v));
+        FigureRow {
+            figure,
+            value: show(value),
+            compared: other.map(show),
+            delta: match (value, other) {
+                (Some(value), Some(Some(other))) => {
+                    let nanos = value.as_nanos() as i64 - other.as_nanos() as i64;
+                    let relative = nanos as f64 / other.as_nanos().max(1) as f64;
+                    let sign = if nanos < 0 { "-" } else { "+" };
+                    Some((
+                        format!(
+                            "{}{} ({}{})",
+                            sign,
+                            format.duration(Duration::from_nanos(nanos.unsigned_abs())),
+                            sign,
+                            format.percent(relative.abs())
+                        ),
+                        change(nanos as f64),
+                    ))
+                }
+                _ => None,
+            },
+        }
+    };
+
+    let mut rows = vec![
+        count("Sent", |r| r.sent),
+        count("Received", |r| r.received),
+        FigureRow {
+            figure: "Loss",
+            value: format.percent(run.loss()),
+            compared: compared.map(|c| format.percent(c.loss())),
+            delta: compared.map(|c| {
+                let delta = run.loss() - c.loss();
+                (
+                    format!(
+                        "{}{} points",
+                        if delta < 0.0 { "-" } else { "+" },
+                        format.number(delta.abs() * 100.0, 2)
+                    ),
+                    change(delta),
+                )
+            }),
+        },
+        latency(
+            "Min",
+            Some(run.min_latency),
+            compared.map(|c| Some(c.min_latency)),
+        ),
+        latency(
+            "Avg",
+            Some(run.average_latency),
+            compared.map(|c| Some(c.average_latency)),
+        ),
+        latency(
+            "Max",
+            Some(run.max_latency),
+            compared.map(|c| Some(c.max_latency)),
+        ),
+    ];
+    for (i, figure) in ["p50", "p90", "p99", "p99.9"].into_iter().enumerate() {
+        rows.push(latency(
+            figure,
+            run.percentiles[i],
+            compared.map(|c| c.percentiles[i]),
+        ));
+    }
+
+    rows
+}
+
+fn change(delta: f64) -> Change {
+    match delta {
+        d if d > 0.0 => Change::Worse,
+        d if d < 0.0 => Change::Better,
+        _ => Change::Same,
+    }
+}
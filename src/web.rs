@@ -0,0 +1,182 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use color_eyre::eyre::Result;
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::Mutex,
+    time,
+};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info};
+
+use crate::network::latency::{PacketStatus, State};
+
+/// Number of most recent packets included in the dashboard chart.
+const CHART_POINTS: usize = 300;
+
+/// How often the stats of every target are pushed over the WebSocket feed.
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+const INDEX_HTML: &str = include_str!("web/index.html");
+
+type Targets = Arc<Vec<(String, Arc<Mutex<State>>)>>;
+
+/// Minimal HTTP dashboard serving a single page, a WebSocket feed pushing the
+/// stats of every target and a JSON stats endpoint.
+pub(crate) struct Dashboard {
+    listen: SocketAddr,
+    targets: Targets,
+    quit: CancellationToken,
+}
+
+impl Dashboard {
+    pub(crate) fn new(
+        listen: SocketAddr,
+        targets: Vec<(String, Arc<Mutex<State>>)>,
+        quit: CancellationToken,
+    ) -> Self {
+        Self {
+            listen,
+            targets: Arc::new(targets),
+            quit,
+        }
+    }
+
+    pub(crate) async fn run(self) -> Result<()> {
+        let listener = TcpListener::bind(self.listen).await?;
+        info!("Dashboard listening on http://{}", listener.local_addr()?);
+
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let (stream, peer) = accepted?;
+                    let targets = self.targets.clone();
+                    let quit = self.quit.clone();
+
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_connection(stream, targets, quit).await {
+                            debug!("Dashboard connection from {} failed: {:?}", peer, e);
+                        }
+                    });
+                }
+                _ = self.quit.cancelled() => break,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    targets: Targets,
+    quit: CancellationToken,
+) -> Result<()> {
+    // Peek so the WebSocket handshake can still read the upgrade request
+    let mut buf = [0; 1024];
+    let n = stream.peek(&mut buf).await?;
+    let request = String::from_utf8_lossy(&buf[..n]);
+
+    let path = request
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .unwrap_or("/")
+        .to_string();
+
+    if path == "/ws" {
+        return push_stats(stream, targets, quit).await;
+    }
+    let _ = stream.read(&mut buf).await?;
+
+    let (status, content_type, body) = match path.as_str() {
+        "/" | "/index.html" => ("200 OK", "text/html; charset=utf-8", INDEX_HTML.to_string()),
+        "/stats" => (
+            "200 OK",
+            "application/json",
+            stats_json(&targets).await.to_string(),
+        ),
+        _ => ("404 Not Found", "text/plain", "Not found".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+
+    Ok(())
+}
+
+/// Pushes the stats of every target until the page goes away or the client
+/// quits.
+async fn push_stats(stream: TcpStream, targets: Targets, quit: CancellationToken) -> Result<()> {
+    let mut websocket = tokio_tungstenite::accept_async(stream).await?;
+    let mut interval = time::interval(UPDATE_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let stats = stats_json(&targets).await.to_string();
+                websocket.send(Message::Text(stats)).await?;
+            }
+            message = websocket.next() => match message {
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Err(e)) => return Err(e.into()),
+                Some(Ok(_)) => {}
+            },
+            _ = quit.cancelled() => {
+                let _ = websocket.close(None).await;
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn stats_json(targets: &Targets) -> Value {
+    let mut stats = Vec::with_capacity(targets.len());
+    for (label, state) in targets.iter() {
+        stats.push(target_json(label, &*state.lock().await));
+    }
+
+    json!({ "targets": stats })
+}
+
+fn target_json(label: &str, state: &State) -> Value {
+    let sent = state.sent_packets() as usize;
+    let loss = if sent > 0 {
+        state.packet_loss as f64 / sent as f64 * 100.0
+    } else {
+        0.0
+    };
+
+    let recent = state.packets.len().saturating_sub(CHART_POINTS)..state.packets.len();
+    let points: Vec<_> = state
+        .packets
+        .range(recent)
+        .filter_map(|(i, packet)| match packet {
+            PacketStatus::Received { latency, .. } => Some([i as u64, latency.as_micros() as u64]),
+            _ => None,
+        })
+        .collect();
+
+    json!({
+        "label": label,
+        "sent": sent,
+        "received": state.received_packets,
+        "loss_percent": loss,
+        "min_us": state.min_latency.as_micros() as u64,
+        "avg_us": state.average_latency.as_micros() as u64,
+        "max_us": state.max_latency.as_micros() as u64,
+        "latencies": points,
+    })
+}